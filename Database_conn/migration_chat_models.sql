@@ -0,0 +1,31 @@
+-- Registry of the chat models, replacing the hard-coded model of rust_openai_answer and the
+-- OPENAI_API_URL/OPENAI_MODEL of rust_compute_metadata
+-- A function uses the model named by its CHAT_MODEL environment variable, a request may name another
+-- active one. chat_backend is openai (or a compatible server), azure, ollama or anthropic, chat_endpoint
+-- the full URL of its chat API. chat_api_key is the name of the field of the database secret holding
+-- the key, NULL for a server without key.
+CREATE TABLE IF NOT EXISTS document_library.chat_models (
+    chat_model_uuid text NOT NULL,
+    chat_model_name text NOT NULL,
+    chat_backend text NOT NULL,
+    chat_model text NOT NULL,
+    chat_endpoint text NOT NULL,
+    chat_api_key text NULL,
+    temperature double precision NOT NULL DEFAULT 0.7,
+    max_tokens integer NOT NULL DEFAULT 2000,
+    is_active boolean NOT NULL DEFAULT false,
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (chat_model_uuid)
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_chat_models_name ON document_library.chat_models USING btree (chat_model_name);
+
+-- The settings used so far: the answers of rust_openai_answer, and a deterministic variant for the
+-- metadata extraction of rust_compute_metadata
+INSERT INTO document_library.chat_models (chat_model_uuid, chat_model_name, chat_backend, chat_model, chat_endpoint, chat_api_key, temperature, max_tokens, is_active, creation_date, created_by, updated_date, updated_by, comments) VALUES
+	 ('5d2e9a41-8c3f-4b7a-a6e1-2f9b0c4d7e58', 'gpt-4o-mini', 'openai', 'gpt-4o-mini', 'https://api.openai.com/v1/chat/completions', 'OPENAI_API_KEY', 0.7, 2000, true, now(), 'system', now(), 'system', NULL),
+	 ('a8c41f07-3e6d-4d92-b5f8-71e2d3c9a046', 'gpt-4o-mini-extraction', 'openai', 'gpt-4o-mini', 'https://api.openai.com/v1/chat/completions', 'OPENAI_API_KEY', 0, 500, true, now(), 'system', now(), 'system', 'metadata extraction')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,34 @@
+-- Conversations of the users with rust_ask and rust_openai_answer, created by rust_add_chat_session
+-- A session belongs to the email of its creator (created_by). Its title is the first question asked in
+-- it unless one is given.
+CREATE TABLE IF NOT EXISTS document_library.chat_sessions (
+    chat_session_uuid text NOT NULL,
+    session_title text NULL,
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (chat_session_uuid)
+);
+CREATE INDEX IF NOT EXISTS idx_chat_sessions_created_by_date ON document_library.chat_sessions USING btree (created_by, updated_date DESC);
+
+-- Messages of a session in their order (message_index from 1), a user question followed by the answer
+-- of the assistant. standalone_question is the question rewritten without the conversation for the
+-- search, NULL when it was not rewritten. query_ledger_uuid is the entry of the answer in the query ledger.
+CREATE TABLE IF NOT EXISTS document_library.chat_messages (
+    chat_message_uuid text NOT NULL,
+    chat_session_uuid text NOT NULL REFERENCES document_library.chat_sessions(chat_session_uuid),
+    message_index integer NOT NULL,
+    message_role text NOT NULL,
+    message_content text NOT NULL,
+    standalone_question text NULL,
+    query_ledger_uuid text NULL REFERENCES document_library.query_ledgers(query_ledger_uuid),
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (chat_message_uuid)
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_chat_messages_session_index ON document_library.chat_messages USING btree (chat_session_uuid, message_index);
@@ -0,0 +1,3 @@
+-- Record the kind of content of each chunk: running text, or a table with its header row
+ALTER TABLE document_library.document_chunks ADD COLUMN IF NOT EXISTS chunck_kind text NULL;
+UPDATE document_library.document_chunks SET chunck_kind = 'text' WHERE chunck_kind IS NULL;
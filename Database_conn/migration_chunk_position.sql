@@ -0,0 +1,6 @@
+-- Record where each chunk comes from in its document
+-- Pages are only set for paged formats (PDF), char offsets are in the extracted text
+ALTER TABLE document_library.document_chunks ADD COLUMN IF NOT EXISTS chunck_page_start integer NULL;
+ALTER TABLE document_library.document_chunks ADD COLUMN IF NOT EXISTS chunck_page_end integer NULL;
+ALTER TABLE document_library.document_chunks ADD COLUMN IF NOT EXISTS chunck_char_start integer NULL;
+ALTER TABLE document_library.document_chunks ADD COLUMN IF NOT EXISTS chunck_char_end integer NULL;
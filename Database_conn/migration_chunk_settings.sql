@@ -0,0 +1,4 @@
+-- Record the chunking settings used for each chunk (CHUNK_SIZE, CHUNK_OVERLAP, CHUNK_UNIT)
+-- chunck_overlap already exists, chunck_lenght is now a number of characters
+ALTER TABLE document_library.document_chunks ADD COLUMN IF NOT EXISTS chunck_size integer NULL;
+ALTER TABLE document_library.document_chunks ADD COLUMN IF NOT EXISTS chunck_unit text NULL;
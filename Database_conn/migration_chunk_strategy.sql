@@ -0,0 +1,4 @@
+-- Record the chunking strategy (window, recursive, markdown, semantic) used for each chunk
+-- chunck_parameters holds the size, overlap, unit and strategy parameters to re-chunk the same way
+ALTER TABLE document_library.document_chunks ADD COLUMN IF NOT EXISTS chunck_strategy text NULL;
+ALTER TABLE document_library.document_chunks ADD COLUMN IF NOT EXISTS chunck_parameters jsonb NULL;
@@ -0,0 +1,22 @@
+-- Outcome of each run of rust_file_vectorisation, one row per document and embedding model
+-- failed_chunk_uuids lists the chunks whose embedding still failed after the retries, so that
+-- they can be found and vectorised again; last_error is the error of the last failed batch
+CREATE TABLE IF NOT EXISTS document_library.document_vectorisations (
+    document_vectorisation_uuid text NOT NULL,
+    document_uuid text NULL,
+    embedding_model_name text NULL,
+    total_chunks integer NULL,
+    processed_chunks integer NULL,
+    skipped_chunks integer NULL,
+    failed_chunks integer NULL,
+    failed_chunk_uuids text[] NULL,
+    last_error text NULL,
+    vectorisation_time double precision NULL,
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (document_vectorisation_uuid)
+);
+CREATE INDEX IF NOT EXISTS idx_document_vectorisations_document ON document_library.document_vectorisations USING btree (document_uuid);
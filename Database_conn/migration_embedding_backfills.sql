@@ -0,0 +1,26 @@
+-- Progress of the backfills of rust_file_vectorisation, which embed every chunk of the corpus into
+-- the table of one model of document_library.embedding_models (event {"backfill": {"embedding_model": ...}})
+-- last_chunk_uuid is the checkpoint: chunks are processed in document_chunk_uuid order and an invocation
+-- resumes after it. backfill_time adds up the time of every invocation, for the throughput.
+CREATE TABLE IF NOT EXISTS document_library.embedding_backfills (
+    embedding_backfill_uuid text NOT NULL,
+    embedding_model_name text NOT NULL,
+    backfill_status text NOT NULL,
+    reembed boolean NOT NULL DEFAULT false,
+    last_chunk_uuid text NULL,
+    total_chunks integer NULL,
+    processed_chunks integer NOT NULL DEFAULT 0,
+    skipped_chunks integer NOT NULL DEFAULT 0,
+    failed_chunks integer NOT NULL DEFAULT 0,
+    failed_chunk_uuids text[] NOT NULL DEFAULT '{}',
+    backfill_time double precision NOT NULL DEFAULT 0,
+    completion_date timestamp with time zone NULL,
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (embedding_backfill_uuid)
+);
+-- At most one running backfill per model
+CREATE UNIQUE INDEX IF NOT EXISTS idx_embedding_backfills_running ON document_library.embedding_backfills USING btree (embedding_model_name) WHERE backfill_status = 'running';
@@ -0,0 +1,30 @@
+-- Registry of the embedding models, replacing the EMBEDDING_TABLE environment variable
+-- Searches choose a model by embedding_model_name (the is_default one otherwise), the vectorisation
+-- embeds chunks for every active model. embedding_endpoint is the URL of the ollama/openai API, or the
+-- directory of a local model. embedding_table must be a table of document_library with an embedding column.
+CREATE TABLE IF NOT EXISTS document_library.embedding_models (
+    embedding_model_uuid text NOT NULL,
+    embedding_model_name text NOT NULL,
+    embedding_backend text NOT NULL,
+    embedding_model text NOT NULL,
+    embedding_endpoint text NULL,
+    embedding_dimension integer NOT NULL,
+    distance_metric text NOT NULL,
+    embedding_table text NOT NULL,
+    is_active boolean NOT NULL DEFAULT false,
+    is_default boolean NOT NULL DEFAULT false,
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (embedding_model_uuid)
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_embedding_models_name ON document_library.embedding_models USING btree (embedding_model_name);
+-- At most one default model
+CREATE UNIQUE INDEX IF NOT EXISTS idx_embedding_models_default ON document_library.embedding_models USING btree (is_default) WHERE is_default;
+
+-- The model used so far by rust_file_vectorisation and rust_get_chunks
+INSERT INTO document_library.embedding_models (embedding_model_uuid, embedding_model_name, embedding_backend, embedding_model, embedding_endpoint, embedding_dimension, distance_metric, embedding_table, is_active, is_default, creation_date, created_by, updated_date, updated_by, comments) VALUES
+	 ('0b6f3c52-6a2e-4b8e-9d51-3f0c8e2a7d14', 'nomic-embed-text', 'ollama', 'nomic-embed-text', 'http://35.180.65.102:11434/api/embeddings', 768, 'cosine', 'document_embeding_mistral_generic', true, true, now(), 'system', now(), 'system', NULL)
+ON CONFLICT DO NOTHING;
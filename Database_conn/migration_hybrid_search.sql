@@ -0,0 +1,5 @@
+-- Full-text search on the chunk text, for the keyword and hybrid search modes of rust_get_chunks
+-- The 'simple' configuration keeps every word as written, without stemming or stop words, so that
+-- clause numbers, product codes and names match exactly whatever the language of the document
+ALTER TABLE document_library.document_chunks ADD COLUMN IF NOT EXISTS embebed_tsv tsvector GENERATED ALWAYS AS (to_tsvector('simple'::regconfig, COALESCE(embebed_text, ''::text))) STORED;
+CREATE INDEX IF NOT EXISTS idx_document_chunks_embebed_tsv ON document_library.document_chunks USING gin (embebed_tsv);
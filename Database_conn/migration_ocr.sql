@@ -0,0 +1,4 @@
+-- Record whether the text of a document was read by OCR, and the average OCR confidence (0-100) of the words of each chunk
+-- Documents without usable text get document_status = 'needs_review' and no chunks
+ALTER TABLE document_library.documents ADD COLUMN IF NOT EXISTS ocr_used boolean NULL;
+ALTER TABLE document_library.document_chunks ADD COLUMN IF NOT EXISTS chunck_ocr_confidence real NULL;
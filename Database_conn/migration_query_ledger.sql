@@ -0,0 +1,28 @@
+-- Ledger of the searches and answers of rust_get_chunks, rust_openai_answer and rust_ask, listed and
+-- reopened by rust_get_query_history
+-- query_type is search, answer or ask. query_filters holds the search parameters of the request,
+-- query_citations the citations of the answer and the numbers it cited without a chunk. latency_ms is
+-- the time from the request to the response. Entries are listed by created_by, the email of the caller,
+-- as callers that are not registered users have no user_uuid.
+ALTER TABLE document_library.query_ledgers ADD COLUMN IF NOT EXISTS search_query text NULL;
+ALTER TABLE document_library.query_ledgers ADD COLUMN IF NOT EXISTS query_filters jsonb NULL;
+ALTER TABLE document_library.query_ledgers ADD COLUMN IF NOT EXISTS embedding_model_name text NULL;
+ALTER TABLE document_library.query_ledgers ADD COLUMN IF NOT EXISTS chat_model_name text NULL;
+ALTER TABLE document_library.query_ledgers ADD COLUMN IF NOT EXISTS input_tokens integer NULL;
+ALTER TABLE document_library.query_ledgers ADD COLUMN IF NOT EXISTS output_tokens integer NULL;
+ALTER TABLE document_library.query_ledgers ADD COLUMN IF NOT EXISTS latency_ms integer NULL;
+ALTER TABLE document_library.query_ledgers ADD COLUMN IF NOT EXISTS query_citations jsonb NULL;
+CREATE INDEX IF NOT EXISTS idx_query_ledgers_created_by_date ON document_library.query_ledgers USING btree (created_by, creation_date DESC);
+
+-- Chunks of a search or answer in their order, with the scores they were found with (NULL for the
+-- chunks given by the client of rust_openai_answer). query_answer_uuid is the query_ledger_uuid of the
+-- entry, which has a single answer.
+ALTER TABLE document_library.query_answer_chunks ADD COLUMN IF NOT EXISTS chunk_rank integer NULL;
+ALTER TABLE document_library.query_answer_chunks ADD COLUMN IF NOT EXISTS similarity double precision NULL;
+ALTER TABLE document_library.query_answer_chunks ADD COLUMN IF NOT EXISTS rerank_score double precision NULL;
+CREATE INDEX IF NOT EXISTS idx_query_answer_chunks_ledger ON document_library.query_answer_chunks USING btree (query_ledger_uuid);
+
+-- Documents of the chunks of an entry
+ALTER TABLE document_library.query_answer_documents ADD COLUMN IF NOT EXISTS query_ledger_uuid text NULL REFERENCES document_library.query_ledgers(query_ledger_uuid);
+CREATE INDEX IF NOT EXISTS idx_query_answer_documents_ledger ON document_library.query_answer_documents USING btree (query_ledger_uuid);
+CREATE INDEX IF NOT EXISTS idx_query_metadatas_ledger ON document_library.query_metadatas USING btree (query_ledger_uuid);
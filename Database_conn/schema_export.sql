@@ -8,14 +8,73 @@ BEGIN;
 CREATE SCHEMA IF NOT EXISTS "document_library";
 
 -- Tables
+-- Table: document_library.chat_messages
+CREATE TABLE IF NOT EXISTS document_library.chat_messages (
+    chat_message_uuid text NOT NULL,
+    chat_session_uuid text NOT NULL,
+    message_index integer NOT NULL,
+    message_role text NOT NULL,
+    message_content text NOT NULL,
+    standalone_question text NULL,
+    query_ledger_uuid text NULL,
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (chat_message_uuid)
+);
+
+-- Table: document_library.chat_models
+CREATE TABLE IF NOT EXISTS document_library.chat_models (
+    chat_model_uuid text NOT NULL,
+    chat_model_name text NOT NULL,
+    chat_backend text NOT NULL,
+    chat_model text NOT NULL,
+    chat_endpoint text NOT NULL,
+    chat_api_key text NULL,
+    temperature double precision NOT NULL DEFAULT 0.7,
+    max_tokens integer NOT NULL DEFAULT 2000,
+    is_active boolean NOT NULL DEFAULT false,
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (chat_model_uuid)
+);
+
+-- Table: document_library.chat_sessions
+CREATE TABLE IF NOT EXISTS document_library.chat_sessions (
+    chat_session_uuid text NOT NULL,
+    session_title text NULL,
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (chat_session_uuid)
+);
+
 -- Table: document_library.document_chunks
 CREATE TABLE IF NOT EXISTS document_library.document_chunks (
     document_chunk_uuid text NOT NULL,
     document_uuid text NULL,
     chunck_lenght integer NULL,
     chunck_overlap integer NULL,
+    chunck_size integer NULL,
+    chunck_unit text NULL,
+    chunck_strategy text NULL,
+    chunck_parameters jsonb NULL,
+    chunck_kind text NULL,
+    chunck_page_start integer NULL,
+    chunck_page_end integer NULL,
+    chunck_char_start integer NULL,
+    chunck_char_end integer NULL,
+    chunck_ocr_confidence real NULL,
     chunck_hash text NULL,
     embebed_text text NULL,
+    embebed_tsv tsvector NULL GENERATED ALWAYS AS (to_tsvector('simple'::regconfig, COALESCE(embebed_text, ''::text))) STORED,
     creation_date timestamp with time zone NULL,
     created_by text NULL,
     updated_date timestamp with time zone NULL,
@@ -31,7 +90,7 @@ CREATE TABLE IF NOT EXISTS document_library.document_embeding_mistral (
     embeder_type text NULL,
     embedding_token integer NULL,
     embedding_time double precision NULL,
-    embedding vector NULL,
+    embedding vector(1024) NULL,
     creation_date timestamp with time zone NULL,
     created_by text NULL,
     updated_date timestamp with time zone NULL,
@@ -47,7 +106,7 @@ CREATE TABLE IF NOT EXISTS document_library.document_embeding_mistral_generic (
     embeder_type text NULL,
     embedding_token integer NULL,
     embedding_time double precision NULL,
-    embedding vector NULL,
+    embedding vector(768) NULL,
     creation_date timestamp with time zone NULL,
     created_by text NULL,
     updated_date timestamp with time zone NULL,
@@ -63,7 +122,7 @@ CREATE TABLE IF NOT EXISTS document_library.document_embeding_openai (
     embeder_type text NULL,
     embedding_token integer NULL,
     embedding_time double precision NULL,
-    embedding vector NULL,
+    embedding vector(1536) NULL,
     creation_date timestamp with time zone NULL,
     created_by text NULL,
     updated_date timestamp with time zone NULL,
@@ -104,6 +163,26 @@ CREATE TABLE IF NOT EXISTS document_library.document_security_groups (
     PRIMARY KEY (document_security_group_uuid)
 );
 
+-- Table: document_library.document_vectorisations
+CREATE TABLE IF NOT EXISTS document_library.document_vectorisations (
+    document_vectorisation_uuid text NOT NULL,
+    document_uuid text NULL,
+    embedding_model_name text NULL,
+    total_chunks integer NULL,
+    processed_chunks integer NULL,
+    skipped_chunks integer NULL,
+    failed_chunks integer NULL,
+    failed_chunk_uuids text[] NULL,
+    last_error text NULL,
+    vectorisation_time double precision NULL,
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (document_vectorisation_uuid)
+);
+
 -- Table: document_library.documents
 CREATE TABLE IF NOT EXISTS document_library.documents (
     document_uuid text NOT NULL,
@@ -114,6 +193,7 @@ CREATE TABLE IF NOT EXISTS document_library.documents (
     document_lenght integer NULL,
     document_size double precision NULL,
     document_status text NULL,
+    ocr_used boolean NULL,
     chunk_time double precision NULL,
     creation_date timestamp with time zone NULL,
     created_by text NULL,
@@ -124,6 +204,48 @@ CREATE TABLE IF NOT EXISTS document_library.documents (
     PRIMARY KEY (document_uuid)
 );
 
+-- Table: document_library.embedding_backfills
+CREATE TABLE IF NOT EXISTS document_library.embedding_backfills (
+    embedding_backfill_uuid text NOT NULL,
+    embedding_model_name text NOT NULL,
+    backfill_status text NOT NULL,
+    reembed boolean NOT NULL DEFAULT false,
+    last_chunk_uuid text NULL,
+    total_chunks integer NULL,
+    processed_chunks integer NOT NULL DEFAULT 0,
+    skipped_chunks integer NOT NULL DEFAULT 0,
+    failed_chunks integer NOT NULL DEFAULT 0,
+    failed_chunk_uuids text[] NOT NULL DEFAULT '{}'::text[],
+    backfill_time double precision NOT NULL DEFAULT 0,
+    completion_date timestamp with time zone NULL,
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (embedding_backfill_uuid)
+);
+
+-- Table: document_library.embedding_models
+CREATE TABLE IF NOT EXISTS document_library.embedding_models (
+    embedding_model_uuid text NOT NULL,
+    embedding_model_name text NOT NULL,
+    embedding_backend text NOT NULL,
+    embedding_model text NOT NULL,
+    embedding_endpoint text NULL,
+    embedding_dimension integer NOT NULL,
+    distance_metric text NOT NULL,
+    embedding_table text NOT NULL,
+    is_active boolean NOT NULL DEFAULT false,
+    is_default boolean NOT NULL DEFAULT false,
+    creation_date timestamp with time zone NULL,
+    created_by text NULL,
+    updated_date timestamp with time zone NULL,
+    updated_by text NULL,
+    comments text NULL,
+    PRIMARY KEY (embedding_model_uuid)
+);
+
 -- Table: document_library.metadatas
 CREATE TABLE IF NOT EXISTS document_library.metadatas (
     metadata_uuid text NOT NULL,
@@ -149,6 +271,9 @@ CREATE TABLE IF NOT EXISTS document_library.query_answer_chunks (
     updated_date timestamp with time zone NULL,
     updated_by text NULL,
     comments text NULL,
+    chunk_rank integer NULL,
+    similarity double precision NULL,
+    rerank_score double precision NULL,
     PRIMARY KEY (query_answer_chunk_uuid)
 );
 
@@ -162,6 +287,7 @@ CREATE TABLE IF NOT EXISTS document_library.query_answer_documents (
     updated_date timestamp with time zone NULL,
     updated_by text NULL,
     comments text NULL,
+    query_ledger_uuid text NULL,
     PRIMARY KEY (query_answer_document_uuid)
 );
 
@@ -180,6 +306,14 @@ CREATE TABLE IF NOT EXISTS document_library.query_ledgers (
     updated_date timestamp with time zone NULL,
     updated_by text NULL,
     comments text NULL,
+    search_query text NULL,
+    query_filters jsonb NULL,
+    embedding_model_name text NULL,
+    chat_model_name text NULL,
+    input_tokens integer NULL,
+    output_tokens integer NULL,
+    latency_ms integer NULL,
+    query_citations jsonb NULL,
     PRIMARY KEY (query_ledger_uuid)
 );
 
@@ -319,14 +453,32 @@ CREATE TABLE IF NOT EXISTS document_library.users (
 );
 
 -- Indexes
-CREATE INDEX idx_documents_name ON document_library.documents USING btree (document_name)
+CREATE UNIQUE INDEX idx_chat_messages_session_index ON document_library.chat_messages USING btree (chat_session_uuid, message_index);
+CREATE UNIQUE INDEX idx_chat_models_name ON document_library.chat_models USING btree (chat_model_name);
+CREATE INDEX idx_chat_sessions_created_by_date ON document_library.chat_sessions USING btree (created_by, updated_date DESC);
+CREATE INDEX idx_document_chunks_embebed_tsv ON document_library.document_chunks USING gin (embebed_tsv);
+CREATE INDEX idx_document_embeding_mistral_embedding ON document_library.document_embeding_mistral USING hnsw (embedding vector_cosine_ops) WITH (m='16', ef_construction='64');
+CREATE INDEX idx_document_embeding_mistral_generic_embedding ON document_library.document_embeding_mistral_generic USING hnsw (embedding vector_cosine_ops) WITH (m='16', ef_construction='64');
+CREATE INDEX idx_document_embeding_openai_embedding ON document_library.document_embeding_openai USING hnsw (embedding vector_cosine_ops) WITH (m='16', ef_construction='64');
+CREATE INDEX idx_document_vectorisations_document ON document_library.document_vectorisations USING btree (document_uuid);
+CREATE INDEX idx_documents_name ON document_library.documents USING btree (document_name);
+CREATE UNIQUE INDEX idx_embedding_backfills_running ON document_library.embedding_backfills USING btree (embedding_model_name) WHERE (backfill_status = 'running'::text);
+CREATE UNIQUE INDEX idx_embedding_models_default ON document_library.embedding_models USING btree (is_default) WHERE is_default;
+CREATE UNIQUE INDEX idx_embedding_models_name ON document_library.embedding_models USING btree (embedding_model_name);
+CREATE INDEX idx_query_answer_chunks_ledger ON document_library.query_answer_chunks USING btree (query_ledger_uuid);
+CREATE INDEX idx_query_answer_documents_ledger ON document_library.query_answer_documents USING btree (query_ledger_uuid);
+CREATE INDEX idx_query_ledgers_created_by_date ON document_library.query_ledgers USING btree (created_by, creation_date DESC);
+CREATE INDEX idx_query_metadatas_ledger ON document_library.query_metadatas USING btree (query_ledger_uuid);
 
 -- Foreign Key Constraints
+ALTER TABLE document_library.chat_messages ADD CONSTRAINT chat_messages_chat_session_uuid_fkey FOREIGN KEY (chat_session_uuid) REFERENCES document_library.chat_sessions (chat_session_uuid) ON UPDATE NO ACTION ON DELETE NO ACTION;
+ALTER TABLE document_library.chat_messages ADD CONSTRAINT chat_messages_query_ledger_uuid_fkey FOREIGN KEY (query_ledger_uuid) REFERENCES document_library.query_ledgers (query_ledger_uuid) ON UPDATE NO ACTION ON DELETE NO ACTION;
 ALTER TABLE document_library.document_metadatas ADD CONSTRAINT document_metadatas_document_uuid_fkey FOREIGN KEY (document_uuid) REFERENCES document_library.documents (document_uuid) ON UPDATE NO ACTION ON DELETE NO ACTION;
 ALTER TABLE document_library.document_metadatas ADD CONSTRAINT document_metadatas_metadata_uuid_fkey FOREIGN KEY (metadata_uuid) REFERENCES document_library.metadatas (metadata_uuid) ON UPDATE NO ACTION ON DELETE NO ACTION;
 ALTER TABLE document_library.document_security_groups ADD CONSTRAINT document_security_groups_document_uuid_fkey FOREIGN KEY (document_uuid) REFERENCES document_library.documents (document_uuid) ON UPDATE NO ACTION ON DELETE NO ACTION;
 ALTER TABLE document_library.document_security_groups ADD CONSTRAINT document_security_groups_security_group_uuid_fkey FOREIGN KEY (security_group_uuid) REFERENCES document_library.security_groups (security_group_uuid) ON UPDATE NO ACTION ON DELETE NO ACTION;
 ALTER TABLE document_library.query_answer_documents ADD CONSTRAINT query_answer_documents_document_uuid_fkey FOREIGN KEY (document_uuid) REFERENCES document_library.documents (document_uuid) ON UPDATE NO ACTION ON DELETE NO ACTION;
+ALTER TABLE document_library.query_answer_documents ADD CONSTRAINT query_answer_documents_query_ledger_uuid_fkey FOREIGN KEY (query_ledger_uuid) REFERENCES document_library.query_ledgers (query_ledger_uuid) ON UPDATE NO ACTION ON DELETE NO ACTION;
 ALTER TABLE document_library.query_ledgers ADD CONSTRAINT query_ledgers_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES document_library.users (user_uuid) ON UPDATE NO ACTION ON DELETE NO ACTION;
 ALTER TABLE document_library.query_ledgers_extended ADD CONSTRAINT query_ledgers_extended_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES document_library.users (user_uuid) ON UPDATE NO ACTION ON DELETE NO ACTION;
 ALTER TABLE document_library.query_metadatas ADD CONSTRAINT query_metadatas_metadata_uuid_fkey FOREIGN KEY (metadata_uuid) REFERENCES document_library.metadatas (metadata_uuid) ON UPDATE NO ACTION ON DELETE NO ACTION;
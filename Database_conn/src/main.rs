@@ -13,6 +13,10 @@ use std::time::Instant;
 use chrono::Local;
 use dotenv::dotenv;
 
+mod vector_index;
+
+use vector_index::{IndexType, VectorIndexSpec};
+
 // Document struct to map query results
 #[derive(Debug, Serialize, Deserialize)]
 struct DocumentChunkCount {
@@ -27,6 +31,7 @@ struct Column {
     data_type: String,
     is_nullable: bool,
     default_value: Option<String>,
+    generation_expression: Option<String>,
 }
 
 #[derive(Debug)]
@@ -103,6 +108,39 @@ struct Args {
     /// Run the original document chunk query
     #[arg(long)]
     run_chunk_query: bool,
+
+    /// Create or update the vector index of these embedding tables (comma-separated, or "all"),
+    /// in the first schema of --schemas, instead of exporting the schema
+    #[arg(long)]
+    vector_index_tables: Option<String>,
+
+    /// Vector index type: hnsw or ivfflat
+    #[arg(long, default_value = "hnsw")]
+    index_type: String,
+
+    /// Distance metric the index serves: l2, cosine or inner_product (must match the distance_metric of the model in document_library.embedding_models)
+    #[arg(long, default_value = "cosine")]
+    index_metric: String,
+
+    /// HNSW maximum number of connections per node
+    #[arg(long, default_value_t = 16)]
+    hnsw_m: u32,
+
+    /// HNSW size of the candidate list while building the index
+    #[arg(long, default_value_t = 64)]
+    hnsw_ef_construction: u32,
+
+    /// IVFFlat number of lists (default: rows / 1000, at least 10)
+    #[arg(long)]
+    ivfflat_lists: Option<u32>,
+
+    /// Dimension to give to embedding columns declared without one
+    #[arg(long)]
+    dimensions: Option<i32>,
+
+    /// Rebuild the vector indexes even when their settings did not change
+    #[arg(long)]
+    reindex: bool,
 }
 
 #[tokio::main]
@@ -179,6 +217,29 @@ async fn main() -> Result<(), anyhow::Error> {
         // You can now work with the strongly-typed documents collection
         println!("Query executed in {:?}", start_time.elapsed());
         println!("Total documents: {}", documents.len());
+    } else if let Some(tables) = &args.vector_index_tables {
+        // Create or update the ANN indexes of the embedding tables
+        let schema = args.schemas.split(',').next().unwrap_or("public");
+        let spec = VectorIndexSpec {
+            index_type: IndexType::parse(&args.index_type)?,
+            metric: args.index_metric.clone(),
+            hnsw_m: args.hnsw_m,
+            hnsw_ef_construction: args.hnsw_ef_construction,
+            ivfflat_lists: args.ivfflat_lists,
+            dimensions: args.dimensions,
+            reindex: args.reindex,
+        };
+        let tables: Vec<String> = if tables.trim() == "all" {
+            vector_index::embedding_tables(&client, schema).await?
+        } else {
+            tables.split(',').map(|table| table.trim().to_string()).collect()
+        };
+        println!("Vector indexes of {:?} in schema '{}': {:?}", tables, schema, spec);
+
+        for table in &tables {
+            vector_index::ensure_vector_index(&client, schema, table, &spec).await
+                .with_context(|| format!("Failed to index {}.{}", schema, table))?;
+        }
     } else {
         // Export database schema to SQL file
         let schemas: Vec<&str> = args.schemas.split(',').collect();
@@ -255,7 +316,7 @@ async fn export_schema_to_sql(
     if !indexes.is_empty() {
         writeln!(file, "-- Indexes")?;
         for index in &indexes {
-            writeln!(file, "{}", index.definition)?;
+            writeln!(file, "{};", index.definition)?;
         }
         writeln!(file)?;
     }
@@ -347,10 +408,11 @@ async fn get_table_columns(client: &Client, schema: &str, table: &str) -> Result
                 WHEN c.udt_name = 'USER-DEFINED' THEN
                     c.data_type || '_' || c.udt_schema || '_' || c.udt_name
                 ELSE 
-                    pg_catalog.format_type(t.oid, c.character_maximum_length)
+                    pg_catalog.format_type(t.oid, a.atttypmod)
             END as data_type,
             c.is_nullable,
-            c.column_default
+            c.column_default,
+            CASE WHEN c.is_generated = 'ALWAYS' THEN c.generation_expression END as generation_expression
         FROM 
             information_schema.columns c
             JOIN pg_catalog.pg_namespace n ON n.nspname = c.table_schema
@@ -373,6 +435,7 @@ async fn get_table_columns(client: &Client, schema: &str, table: &str) -> Result
             data_type: row.get("data_type"),
             is_nullable: row.get::<_, String>("is_nullable") == "YES",
             default_value: row.get("column_default"),
+            generation_expression: row.get("generation_expression"),
         });
     }
     
@@ -573,9 +636,11 @@ fn write_table_definition(file: &mut File, table: &Table) -> Result<(), anyhow::
     // Write column definitions
     for (i, column) in table.columns.iter().enumerate() {
         let nullable = if column.is_nullable { "NULL" } else { "NOT NULL" };
-        let default = match &column.default_value {
-            Some(default_val) => format!(" DEFAULT {}", default_val),
-            None => String::new(),
+        // Generated columns, such as the tsvector of the chunks, have an expression instead of a default
+        let default = match (&column.generation_expression, &column.default_value) {
+            (Some(expression), _) => format!(" GENERATED ALWAYS AS ({}) STORED", expression),
+            (None, Some(default_val)) => format!(" DEFAULT {}", default_val),
+            (None, None) => String::new(),
         };
         
         let comma = if i < table.columns.len() - 1 || table.primary_key.is_some() { "," } else { "" };
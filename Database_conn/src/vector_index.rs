@@ -0,0 +1,228 @@
+//! Approximate nearest-neighbour indexes of the embedding tables
+//!
+//! Each embedding table gets one pgvector index on its `embedding` column, named
+//! `idx_<table>_embedding`: HNSW (parameters `m` and `ef_construction`) or IVFFlat
+//! (parameter `lists`), with the operator class of the distance metric used by the search.
+//! The index is left alone when it already has the requested settings and is valid. It is
+//! rebuilt otherwise, concurrently and under a temporary name before it replaces the old
+//! one, so that searches keep using the old index for the whole build. An index left
+//! invalid by a failed concurrent build is rebuilt the same way.
+
+use anyhow::{bail, Context, Result};
+use tokio_postgres::Client;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexType {
+    Hnsw,
+    IvfFlat,
+}
+
+impl IndexType {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "hnsw" => Ok(IndexType::Hnsw),
+            "ivfflat" => Ok(IndexType::IvfFlat),
+            _ => bail!("Invalid index type '{}', expected hnsw or ivfflat", value),
+        }
+    }
+
+    fn access_method(self) -> &'static str {
+        match self {
+            IndexType::Hnsw => "hnsw",
+            IndexType::IvfFlat => "ivfflat",
+        }
+    }
+}
+
+/// Operator class of each distance metric, it must match the operator of the search
+/// (`<->`, `<=>` or `<#>`) for the index to be used
+fn operator_class(metric: &str) -> Result<&'static str> {
+    match metric.trim().to_lowercase().as_str() {
+        "l2" => Ok("vector_l2_ops"),
+        "cosine" => Ok("vector_cosine_ops"),
+        "inner_product" => Ok("vector_ip_ops"),
+        _ => bail!("Invalid metric '{}', expected l2, cosine or inner_product", metric),
+    }
+}
+
+// Settings of the index of one table
+#[derive(Debug)]
+pub struct VectorIndexSpec {
+    pub index_type: IndexType,
+    pub metric: String,
+    pub hnsw_m: u32,
+    pub hnsw_ef_construction: u32,
+    // IVFFlat lists, rows / 1000 of the table (at least 10) when not given
+    pub ivfflat_lists: Option<u32>,
+    // Dimension given to an `embedding` column declared as a plain `vector`, which pgvector cannot index
+    pub dimensions: Option<i32>,
+    // Rebuild the index even when its settings did not change, e.g. IVFFlat lists after a large import
+    pub reindex: bool,
+}
+
+/// Tables of the schema with an `embedding` column of type vector
+pub async fn embedding_tables(client: &Client, schema: &str) -> Result<Vec<String>> {
+    let rows = client
+        .query(
+            "SELECT c.table_name
+             FROM information_schema.columns c
+             WHERE c.table_schema = $1::text AND c.column_name = 'embedding' AND c.udt_name = 'vector'
+             ORDER BY c.table_name",
+            &[&schema],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get("table_name")).collect())
+}
+
+/// Create or update the index of `table` so that it has the settings of `spec`
+pub async fn ensure_vector_index(client: &Client, schema: &str, table: &str, spec: &VectorIndexSpec) -> Result<()> {
+    // The table name ends up in DDL, only accept the embedding tables of the schema
+    if !embedding_tables(client, schema).await?.iter().any(|name| name == table) {
+        bail!("{}.{} is not a table with an embedding vector column", schema, table);
+    }
+    let index_name = format!("idx_{}_embedding", table);
+    let opclass = operator_class(&spec.metric)?;
+
+    ensure_dimensions(client, schema, table, spec.dimensions).await?;
+
+    let options = match spec.index_type {
+        IndexType::Hnsw => vec![format!("m={}", spec.hnsw_m), format!("ef_construction={}", spec.hnsw_ef_construction)],
+        IndexType::IvfFlat => {
+            let lists = match spec.ivfflat_lists {
+                Some(lists) => lists,
+                None => default_lists(client, schema, table).await?,
+            };
+            vec![format!("lists={}", lists)]
+        }
+    };
+
+    // Settings of the existing index, if any
+    let existing = client
+        .query_opt(
+            "SELECT am.amname, opc.opcname, COALESCE(i.reloptions, '{}') as reloptions, x.indisvalid
+             FROM pg_class i
+             JOIN pg_namespace n ON n.oid = i.relnamespace
+             JOIN pg_am am ON am.oid = i.relam
+             JOIN pg_index x ON x.indexrelid = i.oid
+             JOIN pg_opclass opc ON opc.oid = x.indclass[0]
+             WHERE n.nspname = $1::text AND i.relname = $2::text",
+            &[&schema, &index_name],
+        )
+        .await?;
+
+    if let Some(row) = &existing {
+        let access_method: String = row.get("amname");
+        let existing_opclass: String = row.get("opcname");
+        let mut existing_options: Vec<String> = row.get("reloptions");
+        let valid: bool = row.get("indisvalid");
+        let mut wanted_options = options.clone();
+        existing_options.sort();
+        wanted_options.sort();
+
+        let unchanged = access_method == spec.index_type.access_method() && existing_opclass == opclass && existing_options == wanted_options;
+        if !valid {
+            println!("Index {}.{} is invalid, its build failed, replacing it", schema, index_name);
+        } else if unchanged && !spec.reindex {
+            println!("Index {}.{} is up to date ({} {} {:?})", schema, index_name, access_method, opclass, existing_options);
+            return Ok(());
+        } else if unchanged {
+            println!("Rebuilding index {}.{}", schema, index_name);
+            let start_time = std::time::Instant::now();
+            client
+                .batch_execute(&format!("REINDEX INDEX CONCURRENTLY \"{}\".\"{}\"", schema, index_name))
+                .await
+                .context("Failed to rebuild the index")?;
+            println!("Index {}.{} rebuilt in {:?}", schema, index_name, start_time.elapsed());
+            return Ok(());
+        } else {
+            println!(
+                "Index {}.{} has other settings ({} {} {:?}), replacing it",
+                schema, index_name, access_method, existing_opclass, existing_options
+            );
+        }
+    }
+
+    // A replacement is built next to the old index, which is only dropped once it is ready
+    let build_name = if existing.is_some() { format!("{}_new", index_name) } else { index_name.clone() };
+    if existing.is_some() {
+        // Left over by a replacement that did not complete
+        client
+            .batch_execute(&format!("DROP INDEX CONCURRENTLY IF EXISTS \"{}\".\"{}\"", schema, build_name))
+            .await
+            .context("Failed to drop the previous replacement of the index")?;
+    }
+
+    let definition = format!(
+        "CREATE INDEX CONCURRENTLY \"{}\" ON \"{}\".\"{}\" USING {} (embedding {}) WITH ({})",
+        build_name,
+        schema,
+        table,
+        spec.index_type.access_method(),
+        opclass,
+        options.join(", ")
+    );
+    println!("{}", definition);
+    let start_time = std::time::Instant::now();
+    client.batch_execute(&definition).await.context("Failed to create the index")?;
+    println!("Index {}.{} created in {:?}", schema, build_name, start_time.elapsed());
+
+    if existing.is_some() {
+        client
+            .batch_execute(&format!("DROP INDEX CONCURRENTLY \"{}\".\"{}\"", schema, index_name))
+            .await
+            .context("Failed to drop the old index")?;
+        client
+            .batch_execute(&format!(
+                "ALTER INDEX \"{}\".\"{}\" RENAME TO \"{}\"",
+                schema, build_name, index_name
+            ))
+            .await
+            .context("Failed to rename the new index")?;
+        println!("Index {}.{} replaced", schema, index_name);
+    }
+
+    Ok(())
+}
+
+/// pgvector only indexes columns with a dimension: give one to a plain `vector` column
+async fn ensure_dimensions(client: &Client, schema: &str, table: &str, dimensions: Option<i32>) -> Result<()> {
+    let row = client
+        .query_one(
+            "SELECT a.atttypmod
+             FROM pg_attribute a
+             JOIN pg_class c ON c.oid = a.attrelid
+             JOIN pg_namespace n ON n.oid = c.relnamespace
+             WHERE n.nspname = $1::text AND c.relname = $2::text AND a.attname = 'embedding'",
+            &[&schema, &table],
+        )
+        .await?;
+    let current: i32 = row.get("atttypmod");
+    if current > 0 {
+        if let Some(dimensions) = dimensions.filter(|&dimensions| dimensions != current) {
+            bail!("{}.{}.embedding already has {} dimensions, not {}", schema, table, current, dimensions);
+        }
+        return Ok(());
+    }
+
+    let Some(dimensions) = dimensions else {
+        bail!("{}.{}.embedding has no dimension, give it one with --dimensions", schema, table);
+    };
+    println!("Setting {}.{}.embedding to vector({})", schema, table, dimensions);
+    client
+        .batch_execute(&format!(
+            "ALTER TABLE \"{}\".\"{}\" ALTER COLUMN embedding TYPE vector({})",
+            schema, table, dimensions
+        ))
+        .await
+        .context("Failed to set the dimension of the embedding column, every row must have that many dimensions")?;
+    Ok(())
+}
+
+/// pgvector advises rows / 1000 lists up to a million rows
+async fn default_lists(client: &Client, schema: &str, table: &str) -> Result<u32> {
+    let row = client
+        .query_one(&format!("SELECT COUNT(*) FROM \"{}\".\"{}\"", schema, table), &[])
+        .await?;
+    let rows: i64 = row.get(0);
+    Ok(((rows / 1000) as u32).max(10))
+}
@@ -0,0 +1,20 @@
+#!/bin/sh
+# Build the poppler-tesseract layer and publish a new version of it. The printed ARN is the
+# OCR_LAYER_ARN of serverless.yaml.
+set -e
+cd "$(dirname "$0")"
+
+docker build --platform linux/amd64 -t poppler-tesseract-layer .
+container=$(docker create poppler-tesseract-layer)
+docker cp "$container:/layer.zip" layer.zip
+docker rm "$container" > /dev/null
+
+aws lambda publish-layer-version \
+    --layer-name poppler-tesseract \
+    --description "pdftoppm (poppler) and tesseract with eng and fra" \
+    --compatible-runtimes provided.al2023 \
+    --compatible-architectures x86_64 \
+    --zip-file fileb://layer.zip \
+    --region "${REGION:-ap-southeast-1}" \
+    --query LayerVersionArn \
+    --output text
@@ -0,0 +1,101 @@
+//! Answers of the chat models grounded in retrieved chunks.
+//!
+//! The chunks are numbered from 1 with their document, pages and metadata in a context
+//! assembled within a token budget by [`crate::context::assemble`], [`chat_messages`] asks
+//! the chat model to answer from them citing their numbers, and [`ground`] resolves the
+//! citations of the answer to the chunks for [`answer_json`].
+
+use serde_json::{json, Value};
+
+use crate::chat::{ChatMessage, ChatUsage};
+use crate::citations::{parse_citations, GroundedAnswer, Source, CITATION_INSTRUCTIONS};
+use crate::context::AnswerContext;
+
+/// Instructions given to the model before the context and the question.
+const SYSTEM_PROMPT: &str = "You are a helpful assistant that answers questions based on the provided document context and metadata. \
+Provide clear and concise answers based on both the content and metadata of the provided documents. \
+Content marked as a table holds rows of a table under its header row: read each value with the header of its column. \
+If the answer cannot be found in the context or metadata, state that you don't have enough information to answer.";
+
+/// Messages asking the chat model to answer a question from its numbered sources, citing them,
+/// after the previous turns of the conversation in `history` (see
+/// [`crate::conversation::history_messages`]).
+pub fn chat_messages(question: &str, context: &str, history: &[ChatMessage]) -> Vec<ChatMessage> {
+    let mut messages = vec![ChatMessage::system(format!("{} {}", SYSTEM_PROMPT, CITATION_INSTRUCTIONS))];
+    messages.extend_from_slice(history);
+    messages.push(ChatMessage::user(format!("Context: {}\n\nQuestion: {}", context, question)));
+    messages
+}
+
+/// Sources of the context as numbered in it, each one with the document and pages of its
+/// first chunk and the text of all its chunks.
+fn context_sources<'a>(context: &'a AnswerContext) -> Vec<Source<'a>> {
+    context
+        .sources
+        .iter()
+        .map(|source| Source {
+            document_chunk_uuid: &source.first().document_chunk_uuid,
+            document_uuid: &source.first().document_uuid,
+            document_name: &source.first().document_name,
+            page_start: source.page_start(),
+            page_end: source.page_end(),
+            text: &source.text,
+        })
+        .collect()
+}
+
+/// `answer` with its citations checked against the sources of its context. Citations of
+/// sources that were not in the context are removed from the answer. A citation of merged
+/// chunks is resolved to the chunk holding its quote, or to the first of them.
+pub fn ground(answer: &str, context: &AnswerContext) -> GroundedAnswer {
+    let mut grounded = parse_citations(answer, &context_sources(context));
+    for citation in &mut grounded.citations {
+        let source = &context.sources[citation.number - 1];
+        if source.chunks.len() < 2 {
+            continue;
+        }
+        let quoted = citation
+            .quote
+            .as_deref()
+            .and_then(|quote| source.chunks.iter().find(|chunk| chunk.embebed_text.contains(quote)));
+        if let Some(chunk) = quoted {
+            citation.document_chunk_uuid = chunk.document_chunk_uuid.clone();
+            citation.page_start = chunk.page_start;
+            citation.page_end = chunk.page_end;
+        }
+    }
+
+    if !grounded.rejected_citations.is_empty() {
+        println!(
+            "Warning: rejected citations {:?} of the answer, only {} sources were provided",
+            grounded.rejected_citations,
+            context.sources.len()
+        );
+    }
+    grounded
+}
+
+/// Token usage of an answer in the format of the responses.
+pub fn token_usage_json(usage: &Option<ChatUsage>) -> Value {
+    match usage {
+        Some(usage) => json!({
+            "input_tokens": usage.input_tokens,
+            "output_tokens": usage.output_tokens,
+            "total_tokens": usage.total_tokens()
+        }),
+        None => json!(null),
+    }
+}
+
+/// Body of an answer: the answer with its citations resolved to chunks, the citations of
+/// sources that were not in its context, and what was left out of the context.
+pub fn answer_json(grounded: &GroundedAnswer, context: &AnswerContext, usage: &Option<ChatUsage>, chat_model: &str) -> Value {
+    json!({
+        "answer": grounded.answer,
+        "chat_model": chat_model,
+        "token_usage": token_usage_json(usage),
+        "citations": grounded.citations,
+        "rejected_citations": grounded.rejected_citations,
+        "context": context.to_json()
+    })
+}
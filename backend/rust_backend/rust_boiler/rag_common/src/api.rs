@@ -0,0 +1,72 @@
+//! Responses and pagination shared by the HTTP handlers.
+//!
+//! The responses are generic over their body, so that they serve both the buffered
+//! `lambda_http::Body` of the APIs and the streamed body of the function URLs. Errors have
+//! the standard body of the APIs, `{"statusAPI": "ERROR", "message": ...}`.
+
+use http::Response;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::auth::AuthError;
+use crate::Error;
+
+/// Number of rows listed when the request has no limit.
+pub const DEFAULT_LIMIT: i64 = 20;
+/// Most rows that can be listed at once.
+pub const MAX_LIMIT: i64 = 100;
+
+/// JSON response with `body`.
+pub fn json_response<B: From<String>>(status: u16, body: &Value) -> Result<Response<B>, Error> {
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body.to_string().into())
+        .map_err(Box::new)?)
+}
+
+/// Error response with `message`.
+pub fn error_response<B: From<String>>(status: u16, message: impl Into<String>) -> Result<Response<B>, Error> {
+    json_response(status, &json!({"statusAPI": "ERROR", "message": message.into()}))
+}
+
+/// Response to a request whose Cognito token failed the verification: 401, or 500 when
+/// Cognito could not be reached.
+pub fn auth_error_response<B: From<String>>(e: &AuthError) -> Result<Response<B>, Error> {
+    println!("Failed to authenticate the request: {}", e);
+    json_response(e.status_code(), &e.to_json())
+}
+
+/// Page of a listing, from the `limit` and `offset` of the request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Page {
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl Page {
+    /// Validate the `limit` (1 to [`MAX_LIMIT`], [`DEFAULT_LIMIT`] if absent) and the
+    /// `offset` (not negative, 0 if absent). The error is the message of a bad request.
+    pub fn new(limit: Option<i64>, offset: Option<i64>) -> Result<Self, String> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_LIMIT));
+        }
+        let offset = offset.unwrap_or(0);
+        if offset < 0 {
+            return Err("offset must not be negative".to_string());
+        }
+        Ok(Page { limit, offset })
+    }
+
+    /// Body of the listing: the `items` of the page under `key`, with the `total` number of
+    /// rows and the page.
+    pub fn listing(&self, key: &str, items: impl Serialize, total: i64) -> Value {
+        json!({
+            key: items,
+            "total": total,
+            "limit": self.limit,
+            "offset": self.offset
+        })
+    }
+}
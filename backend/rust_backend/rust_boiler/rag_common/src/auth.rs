@@ -0,0 +1,148 @@
+use std::env;
+use std::fmt;
+
+use jsonwebtokens_cognito::KeySet;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::OnceCell;
+
+use crate::secrets::get_secret_json;
+
+/// Content of the Cognito secret (named by `COGNITO_SECRET`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct CognitoConfig {
+    #[serde(rename = "USER_POOL_ID")]
+    pub user_pool_id: String,
+    #[serde(rename = "APP_CLIENT_ID")]
+    pub app_client_id: String,
+    #[serde(rename = "REGION")]
+    pub region: String,
+}
+
+impl CognitoConfig {
+    /// Load the Cognito configuration from the secret named by `COGNITO_SECRET`.
+    pub async fn from_env() -> Result<Self, AuthError> {
+        let secret_name = env::var("COGNITO_SECRET").expect("COGNITO_SECRET environment variable not set");
+        get_secret_json(&secret_name)
+            .await
+            .map_err(|e| AuthError::Configuration(e.to_string()))
+    }
+}
+
+/// Cognito configuration and its JWKs, kept for the lifetime of the Lambda instance.
+struct Cognito {
+    config: CognitoConfig,
+    key_set: KeySet,
+}
+
+static COGNITO: OnceCell<Cognito> = OnceCell::const_new();
+
+async fn load_cognito() -> Result<Cognito, AuthError> {
+    let config = CognitoConfig::from_env().await?;
+    let key_set = KeySet::new(config.region.clone(), config.user_pool_id.clone())
+        .map_err(|e| AuthError::Configuration(format!("Failed to create KeySet: {}", e)))?;
+
+    // Prefetch the JWKs from Cognito - this is necessary before verification
+    key_set
+        .prefetch_jwks()
+        .await
+        .map_err(|e| AuthError::Configuration(format!("Failed to fetch JWKs: {}", e)))?;
+
+    Ok(Cognito { config, key_set })
+}
+
+async fn cognito() -> Result<&'static Cognito, AuthError> {
+    COGNITO.get_or_try_init(load_cognito).await
+}
+
+/// Return the cached Cognito configuration, loading it on the first call.
+pub async fn cognito_config() -> Result<&'static CognitoConfig, AuthError> {
+    Ok(&cognito().await?.config)
+}
+
+/// The caller of an API, as found in a verified Cognito ID token.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub email: String,
+    /// All the claims of the token.
+    pub claims: Value,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    /// No `Authorization` header in the request.
+    MissingHeader,
+    /// The header is not of the form `Bearer <token>`.
+    InvalidHeader,
+    /// The token failed the verification (signature, expiry, audience...).
+    InvalidToken(String),
+    /// The token is valid but has no `email` claim.
+    MissingEmail,
+    /// The Cognito configuration or the JWKs could not be loaded.
+    Configuration(String),
+}
+
+impl AuthError {
+    /// HTTP status to answer with: 401 for a client problem, 500 for ours.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            AuthError::Configuration(_) => 500,
+            _ => 401,
+        }
+    }
+
+    /// Standard error body of the APIs.
+    pub fn to_json(&self) -> Value {
+        json!({"statusAPI": "ERROR", "message": self.to_string()})
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::MissingHeader => write!(f, "Authorization header not provided"),
+            AuthError::InvalidHeader => write!(f, "Invalid authorization header format"),
+            AuthError::InvalidToken(e) => write!(f, "Failed to verify token: {}", e),
+            AuthError::MissingEmail => write!(f, "Email claim not found in token"),
+            AuthError::Configuration(e) => write!(f, "Authentication configuration error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Extract the token from an `Authorization: Bearer <token>` header value.
+pub fn bearer_token(authorization: Option<&str>) -> Result<&str, AuthError> {
+    let header = authorization.ok_or(AuthError::MissingHeader)?;
+    match header.strip_prefix("Bearer ") {
+        Some(token) if !token.trim().is_empty() => Ok(token.trim()),
+        _ => Err(AuthError::InvalidHeader),
+    }
+}
+
+/// Verify a Cognito ID token and return its claims. The `KeySet` is reused across warm
+/// invocations; it fetches the JWKs again by itself when it meets an unknown key id.
+pub async fn verify_claims(token: &str) -> Result<Value, AuthError> {
+    let cognito = cognito().await?;
+
+    let verifier = cognito
+        .key_set
+        .new_id_token_verifier(&[cognito.config.app_client_id.as_str()])
+        .build()
+        .map_err(|e| AuthError::Configuration(format!("Failed to create verifier: {}", e)))?;
+
+    cognito
+        .key_set
+        .verify(token, &verifier)
+        .await
+        .map_err(|e| AuthError::InvalidToken(e.to_string()))
+}
+
+/// Verify the `Authorization` header value of a request and return the caller.
+pub async fn authenticate(authorization: Option<&str>) -> Result<AuthenticatedUser, AuthError> {
+    let token = bearer_token(authorization)?;
+    let claims = verify_claims(token).await?;
+
+    let email = claims["email"].as_str().ok_or(AuthError::MissingEmail)?.to_string();
+    Ok(AuthenticatedUser { email, claims })
+}
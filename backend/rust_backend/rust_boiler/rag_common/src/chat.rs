@@ -0,0 +1,760 @@
+//! Chat models generating the answers and extracting the metadata.
+//!
+//! A [`ChatModel`] completes a conversation, at once or token by token. Chat models are
+//! built by [`new_chat_model`] from a model of the [`crate::registry`], which gives the
+//! backend, the endpoint, the key and the generation settings:
+//!
+//! - `openai`: the chat completions API of OpenAI or of any compatible server (vLLM,
+//!   LiteLLM, Mistral...), with a bearer key. The endpoint is the full URL of
+//!   `/v1/chat/completions`.
+//! - `azure`: the chat completions API of an Azure OpenAI deployment. The endpoint is the
+//!   full URL of the deployment, with its `api-version`, the key is sent as `api-key`.
+//! - `ollama`: the Ollama `/api/chat` API at the endpoint, without key.
+//! - `anthropic`: the Anthropic `/v1/messages` API or a compatible one, the key is sent as
+//!   `x-api-key`.
+//!
+//! Streams are read line by line, whatever the size of the network chunks: server-sent
+//! events for OpenAI, Azure and Anthropic, JSON lines for Ollama.
+
+use async_trait::async_trait;
+use reqwest::{Client as HttpClient, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::Error;
+
+/// Version of the Anthropic API the requests are written for.
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// One message of a conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// `system`, `user` or `assistant`.
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        ChatMessage { role: "system".to_string(), content: content.into() }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        ChatMessage { role: "user".to_string(), content: content.into() }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        ChatMessage { role: "assistant".to_string(), content: content.into() }
+    }
+}
+
+/// Tokens of a completion, as counted by the backend.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ChatUsage {
+    /// Tokens of the prompt.
+    pub input_tokens: u32,
+    /// Tokens of the completion.
+    pub output_tokens: u32,
+}
+
+impl ChatUsage {
+    pub fn total_tokens(&self) -> u32 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+/// Completion of a conversation.
+#[derive(Debug, Clone, Default)]
+pub struct ChatCompletion {
+    pub content: String,
+    /// `None` when the backend does not report it.
+    pub usage: Option<ChatUsage>,
+}
+
+/// Generation settings of a chat model, from its row of the registry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatSettings {
+    /// Model of the backend, such as `gpt-4o-mini` or `llama3.1`.
+    pub model: String,
+    pub temperature: f64,
+    /// Longest completion, in tokens.
+    pub max_tokens: u32,
+}
+
+/// Completes conversations.
+#[async_trait]
+pub trait ChatModel: Send + Sync {
+    /// Name of the backend and model, for the logs and the responses.
+    fn name(&self) -> String;
+
+    /// Completion of `messages`, returned once generated.
+    async fn complete(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, Error>;
+
+    /// Completion of `messages`, sending each piece of it to `tokens` as soon as it is
+    /// generated. Backends that cannot stream send the whole completion at once.
+    async fn stream(&self, messages: &[ChatMessage], tokens: &UnboundedSender<String>) -> Result<ChatCompletion, Error> {
+        let completion = self.complete(messages).await?;
+        send_token(tokens, completion.content.clone())?;
+        Ok(completion)
+    }
+}
+
+/// Build the chat model served by `backend` (`openai`, `azure`, `ollama` or `anthropic`)
+/// at `endpoint`, with the key `api_key` when the backend needs one.
+pub fn new_chat_model(
+    backend: &str,
+    settings: ChatSettings,
+    endpoint: String,
+    api_key: Option<String>,
+) -> Result<Box<dyn ChatModel>, Error> {
+    let backend = backend.trim().to_lowercase();
+    let api_key = || api_key.clone().ok_or_else(|| format!("No API key for the {} chat model {}", backend, settings.model));
+
+    match backend.as_str() {
+        "openai" => {
+            // Servers compatible with OpenAI may run without key
+            let auth = api_key().ok().map(|key| ("Authorization", format!("Bearer {}", key)));
+            Ok(Box::new(OpenAiChat::new(endpoint, settings, auth)))
+        }
+        "azure" => {
+            let auth = Some(("api-key", api_key()?));
+            Ok(Box::new(OpenAiChat::new(endpoint, settings, auth)))
+        }
+        "ollama" => Ok(Box::new(OllamaChat::new(endpoint, settings))),
+        "anthropic" => {
+            let key = api_key()?;
+            Ok(Box::new(AnthropicChat::new(endpoint, settings, key)))
+        }
+        _ => Err(format!("Invalid chat backend '{}', expected openai, azure, ollama or anthropic", backend).into()),
+    }
+}
+
+/// Send a piece of a streamed completion, failing when nobody listens anymore.
+fn send_token(tokens: &UnboundedSender<String>, token: String) -> Result<(), Error> {
+    tokens.send(token).map_err(|_| "The receiver of the streamed completion is closed".into())
+}
+
+/// Send `request` and fail with the body of the response when its status is an error.
+async fn send(request: RequestBuilder, api: &str) -> Result<Response, Error> {
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(format!("{} API error {}: {}", api, status, error_text).into());
+    }
+    Ok(response)
+}
+
+/// Lines of a body received in network chunks. A chunk can end in the middle of a line, or
+/// of a UTF-8 character, so lines are only decoded once complete.
+#[derive(Default)]
+struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    fn push(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+    }
+
+    /// Next complete line, without its line feed.
+    fn next_line(&mut self) -> Option<String> {
+        let end = self.pending.iter().position(|byte| *byte == b'\n')?;
+        let line: Vec<u8> = self.pending.drain(..=end).collect();
+        Some(String::from_utf8_lossy(&line[..end]).into_owned())
+    }
+
+    /// Last line of the body, when it does not end with a line feed.
+    fn rest(self) -> Option<String> {
+        (!self.pending.is_empty()).then(|| String::from_utf8_lossy(&self.pending).into_owned())
+    }
+}
+
+/// Call `on_line` with each trimmed line of the body of `response` as it arrives, until the
+/// body ends or `on_line` returns false.
+async fn for_each_line(mut response: Response, mut on_line: impl FnMut(&str) -> Result<bool, Error>) -> Result<(), Error> {
+    let mut lines = LineBuffer::default();
+    while let Some(bytes) = response.chunk().await? {
+        lines.push(&bytes);
+        while let Some(line) = lines.next_line() {
+            if !on_line(line.trim())? {
+                return Ok(());
+            }
+        }
+    }
+    if let Some(line) = lines.rest() {
+        on_line(line.trim())?;
+    }
+    Ok(())
+}
+
+/// Data of a server-sent event line, `None` for the other lines of the event.
+fn event_data(line: &str) -> Option<&str> {
+    line.strip_prefix("data:").map(str::trim)
+}
+
+/// Chat completions API of OpenAI, of a compatible server or of Azure OpenAI.
+pub struct OpenAiChat {
+    client: HttpClient,
+    url: String,
+    settings: ChatSettings,
+    /// Header and value authenticating the requests, if the server needs a key.
+    auth: Option<(&'static str, String)>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiResponse {
+    choices: Vec<OpenAiChoice>,
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    message: OpenAiMessage,
+}
+
+#[derive(Deserialize)]
+struct OpenAiMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+impl From<OpenAiUsage> for ChatUsage {
+    fn from(usage: OpenAiUsage) -> Self {
+        ChatUsage { input_tokens: usage.prompt_tokens, output_tokens: usage.completion_tokens }
+    }
+}
+
+/// Event of a streamed completion, the last one only carries the token usage.
+#[derive(Deserialize)]
+struct OpenAiStreamEvent {
+    #[serde(default)]
+    choices: Vec<OpenAiStreamChoice>,
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiStreamChoice {
+    delta: OpenAiMessage,
+}
+
+impl OpenAiChat {
+    pub fn new(url: String, settings: ChatSettings, auth: Option<(&'static str, String)>) -> Self {
+        OpenAiChat { client: HttpClient::new(), url, settings, auth }
+    }
+
+    fn request(&self, messages: &[ChatMessage], stream: bool) -> RequestBuilder {
+        let mut body = json!({
+            "model": self.settings.model,
+            "messages": messages,
+            "temperature": self.settings.temperature,
+            "max_tokens": self.settings.max_tokens,
+        });
+        if stream {
+            body["stream"] = json!(true);
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+
+        let request = self.client.post(&self.url).json(&body);
+        match &self.auth {
+            Some((header, value)) => request.header(*header, value),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl ChatModel for OpenAiChat {
+    fn name(&self) -> String {
+        format!("openai:{}", self.settings.model)
+    }
+
+    async fn complete(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, Error> {
+        let response: OpenAiResponse = send(self.request(messages, false), "OpenAI").await?.json().await?;
+        let content = response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .unwrap_or_default();
+        Ok(ChatCompletion { content, usage: response.usage.map(ChatUsage::from) })
+    }
+
+    async fn stream(&self, messages: &[ChatMessage], tokens: &UnboundedSender<String>) -> Result<ChatCompletion, Error> {
+        let response = send(self.request(messages, true), "OpenAI").await?;
+        let mut completion = ChatCompletion::default();
+        for_each_line(response, |line| {
+            let data = match event_data(line) {
+                Some(data) => data,
+                None => return Ok(true),
+            };
+            if data == "[DONE]" {
+                return Ok(false);
+            }
+
+            let event: OpenAiStreamEvent =
+                serde_json::from_str(data).map_err(|e| format!("Invalid OpenAI stream event '{}': {}", data, e))?;
+            if let Some(usage) = event.usage {
+                completion.usage = Some(usage.into());
+            }
+            for choice in event.choices {
+                if let Some(token) = choice.delta.content.filter(|token| !token.is_empty()) {
+                    completion.content.push_str(&token);
+                    send_token(tokens, token)?;
+                }
+            }
+            Ok(true)
+        })
+        .await?;
+        Ok(completion)
+    }
+}
+
+/// Chat model served by Ollama.
+pub struct OllamaChat {
+    client: HttpClient,
+    url: String,
+    settings: ChatSettings,
+}
+
+/// Response of `/api/chat`, or one line of its stream.
+#[derive(Deserialize)]
+struct OllamaResponse {
+    message: Option<OpenAiMessage>,
+    #[serde(default)]
+    done: bool,
+    prompt_eval_count: Option<u32>,
+    eval_count: Option<u32>,
+}
+
+impl OllamaResponse {
+    fn usage(&self) -> Option<ChatUsage> {
+        match (self.prompt_eval_count, self.eval_count) {
+            (None, None) => None,
+            (input, output) => Some(ChatUsage {
+                input_tokens: input.unwrap_or_default(),
+                output_tokens: output.unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+impl OllamaChat {
+    pub fn new(url: String, settings: ChatSettings) -> Self {
+        OllamaChat { client: HttpClient::new(), url, settings }
+    }
+
+    fn request(&self, messages: &[ChatMessage], stream: bool) -> RequestBuilder {
+        let body = json!({
+            "model": self.settings.model,
+            "messages": messages,
+            "stream": stream,
+            "options": {
+                "temperature": self.settings.temperature,
+                "num_predict": self.settings.max_tokens,
+            },
+        });
+        self.client.post(&self.url).json(&body)
+    }
+}
+
+#[async_trait]
+impl ChatModel for OllamaChat {
+    fn name(&self) -> String {
+        format!("ollama:{}", self.settings.model)
+    }
+
+    async fn complete(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, Error> {
+        let response: OllamaResponse = send(self.request(messages, false), "Ollama").await?.json().await?;
+        let usage = response.usage();
+        let content = response.message.and_then(|message| message.content).unwrap_or_default();
+        Ok(ChatCompletion { content, usage })
+    }
+
+    async fn stream(&self, messages: &[ChatMessage], tokens: &UnboundedSender<String>) -> Result<ChatCompletion, Error> {
+        let response = send(self.request(messages, true), "Ollama").await?;
+        let mut completion = ChatCompletion::default();
+        for_each_line(response, |line| {
+            if line.is_empty() {
+                return Ok(true);
+            }
+
+            let event: OllamaResponse =
+                serde_json::from_str(line).map_err(|e| format!("Invalid Ollama stream line '{}': {}", line, e))?;
+            if let Some(token) = event.message.as_ref().and_then(|message| message.content.clone()).filter(|token| !token.is_empty()) {
+                completion.content.push_str(&token);
+                send_token(tokens, token)?;
+            }
+            // The last line carries the token counts
+            if event.done {
+                completion.usage = event.usage();
+            }
+            Ok(!event.done)
+        })
+        .await?;
+        Ok(completion)
+    }
+}
+
+/// Messages API of Anthropic, or of a compatible server.
+pub struct AnthropicChat {
+    client: HttpClient,
+    url: String,
+    settings: ChatSettings,
+    api_key: String,
+}
+
+#[derive(Deserialize)]
+struct AnthropicResponse {
+    #[serde(default)]
+    content: Vec<AnthropicContent>,
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicContent {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    output_tokens: u32,
+}
+
+impl AnthropicChat {
+    pub fn new(url: String, settings: ChatSettings, api_key: String) -> Self {
+        AnthropicChat { client: HttpClient::new(), url, settings, api_key }
+    }
+
+    fn request(&self, messages: &[ChatMessage], stream: bool) -> RequestBuilder {
+        // The system prompt is a field of the request, not a message
+        let system: Vec<&str> = messages
+            .iter()
+            .filter(|message| message.role == "system")
+            .map(|message| message.content.as_str())
+            .collect();
+        let conversation: Vec<&ChatMessage> = messages.iter().filter(|message| message.role != "system").collect();
+
+        let mut body = json!({
+            "model": self.settings.model,
+            "messages": conversation,
+            "temperature": self.settings.temperature,
+            "max_tokens": self.settings.max_tokens,
+            "stream": stream,
+        });
+        if !system.is_empty() {
+            body["system"] = json!(system.join("\n\n"));
+        }
+
+        self.client
+            .post(&self.url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&body)
+    }
+}
+
+#[async_trait]
+impl ChatModel for AnthropicChat {
+    fn name(&self) -> String {
+        format!("anthropic:{}", self.settings.model)
+    }
+
+    async fn complete(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, Error> {
+        let response: AnthropicResponse = send(self.request(messages, false), "Anthropic").await?.json().await?;
+        let content = response
+            .content
+            .iter()
+            .filter(|block| block.kind == "text")
+            .map(|block| block.text.as_str())
+            .collect();
+        let usage = response.usage.map(|usage| ChatUsage {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+        });
+        Ok(ChatCompletion { content, usage })
+    }
+
+    async fn stream(&self, messages: &[ChatMessage], tokens: &UnboundedSender<String>) -> Result<ChatCompletion, Error> {
+        let response = send(self.request(messages, true), "Anthropic").await?;
+        let mut completion = ChatCompletion::default();
+        let mut usage = ChatUsage::default();
+        for_each_line(response, |line| {
+            let data = match event_data(line) {
+                Some(data) => data,
+                None => return Ok(true),
+            };
+
+            let event: Value =
+                serde_json::from_str(data).map_err(|e| format!("Invalid Anthropic stream event '{}': {}", data, e))?;
+            match event["type"].as_str().unwrap_or_default() {
+                // Tokens of the prompt come first, those of the completion with its end
+                "message_start" => {
+                    usage.input_tokens = event["message"]["usage"]["input_tokens"].as_u64().unwrap_or_default() as u32;
+                }
+                "content_block_delta" if event["delta"]["type"] == "text_delta" => {
+                    if let Some(token) = event["delta"]["text"].as_str().filter(|token| !token.is_empty()) {
+                        completion.content.push_str(token);
+                        send_token(tokens, token.to_string())?;
+                    }
+                }
+                "message_delta" => {
+                    usage.output_tokens = event["usage"]["output_tokens"].as_u64().unwrap_or_default() as u32;
+                }
+                "message_stop" => return Ok(false),
+                "error" => return Err(format!("Anthropic stream error: {}", event["error"]).into()),
+                _ => {}
+            }
+            Ok(true)
+        })
+        .await?;
+        completion.usage = Some(usage);
+        Ok(completion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+    use crate::stub_server::StubServer;
+
+    fn settings() -> ChatSettings {
+        ChatSettings { model: "model".to_string(), temperature: 0.0, max_tokens: 100 }
+    }
+
+    fn messages() -> Vec<ChatMessage> {
+        vec![ChatMessage::system("Answer from the sources."), ChatMessage::user("What is the notice period?")]
+    }
+
+    /// Completion streamed by `chat`, with the pieces sent to the receiver.
+    async fn streamed(chat: &dyn ChatModel) -> (ChatCompletion, Vec<String>) {
+        let (sender, mut receiver) = unbounded_channel();
+        let completion = chat.stream(&messages(), &sender).await.unwrap();
+        drop(sender);
+        let mut tokens = Vec::new();
+        while let Some(token) = receiver.recv().await {
+            tokens.push(token);
+        }
+        (completion, tokens)
+    }
+
+    fn usage(completion: &ChatCompletion) -> Option<(u32, u32)> {
+        completion.usage.map(|usage| (usage.input_tokens, usage.output_tokens))
+    }
+
+    #[test]
+    fn reassembles_the_lines_split_across_chunks() {
+        let body = "data: {\"a\": 1}\r\n\ndata: préavis\nlast";
+        let bytes = body.as_bytes();
+        // "é" is two bytes, cut between them
+        let cut = body.find('é').unwrap() + 1;
+
+        let mut buffer = LineBuffer::default();
+        let mut lines = Vec::new();
+        for chunk in [&bytes[..4], &bytes[4..cut], &bytes[cut..cut + 1], &bytes[cut + 1..]] {
+            buffer.push(chunk);
+            while let Some(line) = buffer.next_line() {
+                lines.push(line);
+            }
+        }
+
+        assert_eq!(lines, vec!["data: {\"a\": 1}\r", "", "data: préavis"]);
+        assert_eq!(buffer.rest().as_deref(), Some("last"));
+    }
+
+    #[test]
+    fn a_body_ending_with_a_line_feed_has_no_rest() {
+        let mut buffer = LineBuffer::default();
+        buffer.push(b"one\n");
+
+        assert_eq!(buffer.next_line().as_deref(), Some("one"));
+        assert_eq!(buffer.next_line(), None);
+        assert_eq!(buffer.rest(), None);
+    }
+
+    #[test]
+    fn reads_the_data_of_server_sent_events() {
+        assert_eq!(event_data("data: {\"a\": 1}"), Some("{\"a\": 1}"));
+        assert_eq!(event_data("data:[DONE]"), Some("[DONE]"));
+        assert_eq!(event_data("event: message_stop"), None);
+        assert_eq!(event_data(": keep-alive"), None);
+    }
+
+    #[tokio::test]
+    async fn openai_completes_with_the_usage() {
+        let server = StubServer::json(
+            200,
+            &json!({
+                "choices": [{"message": {"role": "assistant", "content": "Three months [1]."}}],
+                "usage": {"prompt_tokens": 120, "completion_tokens": 8, "total_tokens": 128}
+            }),
+        );
+        let chat = OpenAiChat::new(server.url.clone(), settings(), Some(("Authorization", "Bearer key".to_string())));
+
+        let completion = chat.complete(&messages()).await.unwrap();
+
+        assert_eq!(completion.content, "Three months [1].");
+        assert_eq!(usage(&completion), Some((120, 8)));
+        let (_, request) = server.request();
+        assert_eq!(request["messages"][0]["role"], "system");
+        assert_eq!(request["max_tokens"], 100);
+        assert!(request.get("stream").is_none());
+    }
+
+    #[tokio::test]
+    async fn openai_streams_until_done_with_the_usage_of_the_last_event() {
+        let body = "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":\"\"}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"Trois \"}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"mois de préavis.\"}}]}\n\n\
+            data: {\"choices\":[],\"usage\":{\"prompt_tokens\":120,\"completion_tokens\":6}}\n\n\
+            data: [DONE]\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"after the end\"}}]}\n\n";
+        let cut = body.find('é').unwrap() + 1;
+        let server = StubServer::start(200, "text/event-stream", vec![&body.as_bytes()[..cut], &body.as_bytes()[cut..]]);
+        let chat = OpenAiChat::new(server.url.clone(), settings(), None);
+
+        let (completion, tokens) = streamed(&chat).await;
+
+        assert_eq!(tokens, vec!["Trois ", "mois de préavis."]);
+        assert_eq!(completion.content, "Trois mois de préavis.");
+        assert_eq!(usage(&completion), Some((120, 6)));
+        let (_, request) = server.request();
+        assert_eq!(request["stream"], true);
+        assert_eq!(request["stream_options"]["include_usage"], true);
+    }
+
+    #[tokio::test]
+    async fn openai_fails_on_an_invalid_event_or_an_error_status() {
+        let server = StubServer::start(200, "text/event-stream", vec![b"data: {not json}\n\n"]);
+        let chat = OpenAiChat::new(server.url.clone(), settings(), None);
+        let (sender, _receiver) = unbounded_channel();
+        let error = chat.stream(&messages(), &sender).await.unwrap_err();
+        assert!(error.to_string().starts_with("Invalid OpenAI stream event '{not json}'"), "{}", error);
+
+        let server = StubServer::json(429, &json!({"error": {"message": "Rate limit reached"}}));
+        let chat = OpenAiChat::new(server.url.clone(), settings(), None);
+        let error = chat.complete(&messages()).await.unwrap_err();
+        assert!(error.to_string().starts_with("OpenAI API error 429"), "{}", error);
+        assert!(error.to_string().contains("Rate limit reached"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn ollama_completes_with_the_eval_counts() {
+        let server = StubServer::json(
+            200,
+            &json!({"message": {"role": "assistant", "content": "Three months."}, "done": true, "prompt_eval_count": 90, "eval_count": 4}),
+        );
+        let chat = OllamaChat::new(server.url.clone(), settings());
+
+        let completion = chat.complete(&messages()).await.unwrap();
+
+        assert_eq!(completion.content, "Three months.");
+        assert_eq!(usage(&completion), Some((90, 4)));
+        let (_, request) = server.request();
+        assert_eq!(request["stream"], false);
+        assert_eq!(request["options"]["num_predict"], 100);
+    }
+
+    #[tokio::test]
+    async fn ollama_streams_json_lines_until_done() {
+        let body = "{\"message\":{\"role\":\"assistant\",\"content\":\"Trois \"},\"done\":false}\n\
+            {\"message\":{\"role\":\"assistant\",\"content\":\"mois de préavis.\"},\"done\":false}\n\
+            {\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"prompt_eval_count\":90,\"eval_count\":6}";
+        let cut = body.find('é').unwrap() + 1;
+        let server = StubServer::start(200, "application/x-ndjson", vec![&body.as_bytes()[..20], &body.as_bytes()[20..cut], &body.as_bytes()[cut..]]);
+        let chat = OllamaChat::new(server.url.clone(), settings());
+
+        let (completion, tokens) = streamed(&chat).await;
+
+        assert_eq!(tokens, vec!["Trois ", "mois de préavis."]);
+        assert_eq!(completion.content, "Trois mois de préavis.");
+        assert_eq!(usage(&completion), Some((90, 6)));
+    }
+
+    #[tokio::test]
+    async fn anthropic_completes_with_the_text_blocks() {
+        let server = StubServer::json(
+            200,
+            &json!({
+                "content": [{"type": "text", "text": "Three "}, {"type": "tool_use", "id": "t1"}, {"type": "text", "text": "months."}],
+                "usage": {"input_tokens": 150, "output_tokens": 5}
+            }),
+        );
+        let chat = AnthropicChat::new(server.url.clone(), settings(), "key".to_string());
+
+        let completion = chat.complete(&messages()).await.unwrap();
+
+        assert_eq!(completion.content, "Three months.");
+        assert_eq!(usage(&completion), Some((150, 5)));
+        let (_, request) = server.request();
+        assert_eq!(request["system"], "Answer from the sources.");
+        assert_eq!(request["messages"], json!([{"role": "user", "content": "What is the notice period?"}]));
+    }
+
+    #[tokio::test]
+    async fn anthropic_streams_the_text_deltas_with_the_usage() {
+        let body = "event: message_start\n\
+            data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":150,\"output_tokens\":1}}}\n\n\
+            event: ping\n\
+            data: {\"type\":\"ping\"}\n\n\
+            event: content_block_delta\n\
+            data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Trois \"}}\n\n\
+            event: content_block_delta\n\
+            data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"mois de préavis.\"}}\n\n\
+            event: message_delta\n\
+            data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":7}}\n\n\
+            event: message_stop\n\
+            data: {\"type\":\"message_stop\"}\n\n";
+        let cut = body.find('é').unwrap() + 1;
+        let server = StubServer::start(200, "text/event-stream", vec![&body.as_bytes()[..cut], &body.as_bytes()[cut..]]);
+        let chat = AnthropicChat::new(server.url.clone(), settings(), "key".to_string());
+
+        let (completion, tokens) = streamed(&chat).await;
+
+        assert_eq!(tokens, vec!["Trois ", "mois de préavis."]);
+        assert_eq!(completion.content, "Trois mois de préavis.");
+        assert_eq!(usage(&completion), Some((150, 7)));
+    }
+
+    #[tokio::test]
+    async fn anthropic_fails_on_an_error_event() {
+        let body = "event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n";
+        let server = StubServer::start(200, "text/event-stream", vec![body.as_bytes()]);
+        let chat = AnthropicChat::new(server.url.clone(), settings(), "key".to_string());
+        let (sender, _receiver) = unbounded_channel();
+
+        let error = chat.stream(&messages(), &sender).await.unwrap_err();
+
+        assert!(error.to_string().starts_with("Anthropic stream error"), "{}", error);
+        assert!(error.to_string().contains("Overloaded"), "{}", error);
+    }
+
+    #[test]
+    fn builds_the_backend_of_the_registry() {
+        let model = |backend: &str, key: Option<&str>| {
+            new_chat_model(backend, settings(), "http://localhost".to_string(), key.map(str::to_string)).map(|chat| chat.name())
+        };
+
+        assert_eq!(model("OpenAI", None).unwrap(), "openai:model");
+        assert_eq!(model("azure", Some("key")).unwrap(), "openai:model");
+        assert_eq!(model("ollama", None).unwrap(), "ollama:model");
+        assert_eq!(model("anthropic", Some("key")).unwrap(), "anthropic:model");
+        assert!(model("azure", None).is_err());
+        assert!(model("anthropic", None).is_err());
+        assert!(model("bedrock", Some("key")).is_err());
+    }
+}
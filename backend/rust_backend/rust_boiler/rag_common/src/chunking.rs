@@ -0,0 +1,932 @@
+//! Split extracted document text into overlapping chunks for embedding.
+//!
+//! Chunks are cut on char boundaries only, so multi-byte text is never split inside a
+//! character. Inside the second half of each window the chunker prefers, in order, a
+//! paragraph break, the end of a sentence, then a space; a word is cut only when the
+//! window holds no space at all. The next chunk starts `overlap` units before the end
+//! of the previous one, on a word start.
+//!
+//! Besides this fixed window, a [`ChunkStrategy`] can split recursively on paragraphs,
+//! lines, sentences and words, follow the headings of a Markdown document, or break
+//! where the embeddings of neighbouring sentences stop being similar. The strategy is
+//! chosen per `document_type` or at upload time, see [`ChunkStrategy::select`].
+//!
+//! Tables found in a document are chunked apart with [`chunk_table`], as Markdown or CSV
+//! with their header row repeated in every chunk, and stored with the
+//! [`ChunkKind::Table`] kind.
+
+use std::env;
+use std::future::Future;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use serde_json::{json, Value};
+use tiktoken_rs::CoreBPE;
+
+use crate::Error;
+
+static TOKENIZER: OnceLock<CoreBPE> = OnceLock::new();
+
+/// The `cl100k_base` tokenizer of the OpenAI embedding models.
+fn tokenizer() -> &'static CoreBPE {
+    TOKENIZER.get_or_init(|| tiktoken_rs::cl100k_base().expect("Failed to load the cl100k_base tokenizer"))
+}
+
+/// Unit in which the chunk size and overlap are measured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkUnit {
+    Chars,
+    /// Tokens of the `cl100k_base` tokenizer.
+    Tokens,
+}
+
+impl ChunkUnit {
+    /// Parse the values of `CHUNK_UNIT`: `chars` or `tokens`.
+    pub fn parse(unit: &str) -> Option<Self> {
+        match unit.trim().to_lowercase().as_str() {
+            "chars" | "characters" => Some(ChunkUnit::Chars),
+            "tokens" => Some(ChunkUnit::Tokens),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChunkUnit::Chars => "chars",
+            ChunkUnit::Tokens => "tokens",
+        }
+    }
+
+    /// Length of `text` in this unit.
+    pub fn measure(self, text: &str) -> usize {
+        match self {
+            ChunkUnit::Chars => text.chars().count(),
+            ChunkUnit::Tokens => tokenizer().encode_ordinary(text).len(),
+        }
+    }
+
+    /// Largest char boundary `end` such that `text[..end]` measures at most `max`.
+    fn prefix_end(self, text: &str, max: usize) -> usize {
+        match self {
+            ChunkUnit::Chars => text.char_indices().nth(max).map_or(text.len(), |(i, _)| i),
+            ChunkUnit::Tokens => {
+                // A token is seldom longer than 16 chars, which bounds the search
+                let bound = ChunkUnit::Chars.prefix_end(text, max.saturating_mul(16));
+                let boundaries = char_boundaries(text, 0, bound);
+                let count = boundaries.partition_point(|&end| self.measure(&text[..end]) <= max);
+                boundaries[count.saturating_sub(1)]
+            }
+        }
+    }
+
+    /// Smallest char boundary `start` such that `text[start..]` measures at most `max`.
+    fn suffix_start(self, text: &str, max: usize) -> usize {
+        match self {
+            ChunkUnit::Chars if max == 0 => text.len(),
+            ChunkUnit::Chars => text.char_indices().rev().nth(max - 1).map_or(0, |(i, _)| i),
+            ChunkUnit::Tokens => {
+                let bound = ChunkUnit::Chars.suffix_start(text, max.saturating_mul(16));
+                let boundaries = char_boundaries(text, bound, text.len());
+                let count = boundaries.partition_point(|&start| self.measure(&text[start..]) > max);
+                boundaries.get(count).copied().unwrap_or(text.len())
+            }
+        }
+    }
+}
+
+/// The char boundaries of `text` between `from` and `to`, both included.
+fn char_boundaries(text: &str, from: usize, to: usize) -> Vec<usize> {
+    text[from..to]
+        .char_indices()
+        .map(|(i, _)| from + i)
+        .chain(std::iter::once(to))
+        .collect()
+}
+
+/// Move `index` back to the closest char boundary.
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Chunk size and overlap of a deployment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkConfig {
+    pub size: usize,
+    pub overlap: usize,
+    pub unit: ChunkUnit,
+}
+
+impl ChunkConfig {
+    pub fn new(size: usize, overlap: usize, unit: ChunkUnit) -> Result<Self, Error> {
+        if size == 0 {
+            return Err("CHUNK_SIZE must be greater than 0".into());
+        }
+        if overlap >= size {
+            return Err(format!("CHUNK_OVERLAP ({}) must be smaller than CHUNK_SIZE ({})", overlap, size).into());
+        }
+        Ok(ChunkConfig { size, overlap, unit })
+    }
+
+    /// Read `CHUNK_SIZE`, `CHUNK_OVERLAP` and `CHUNK_UNIT` (`chars` or `tokens`).
+    pub fn from_env() -> Result<Self, Error> {
+        let size = env::var("CHUNK_SIZE").expect("CHUNK_SIZE environment variable not set");
+        let overlap = env::var("CHUNK_OVERLAP").expect("CHUNK_OVERLAP environment variable not set");
+        let unit = env::var("CHUNK_UNIT").expect("CHUNK_UNIT environment variable not set");
+
+        let size = size
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid CHUNK_SIZE '{}': {}", size, e))?;
+        let overlap = overlap
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid CHUNK_OVERLAP '{}': {}", overlap, e))?;
+        let unit = ChunkUnit::parse(&unit).ok_or_else(|| format!("Invalid CHUNK_UNIT '{}', expected chars or tokens", unit))?;
+        ChunkConfig::new(size, overlap, unit)
+    }
+}
+
+/// A chunk of text with its length in the unit of the [`ChunkConfig`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextChunk {
+    pub text: String,
+    pub length: usize,
+    /// Byte range of the chunk in the chunked text. The headings the `markdown` strategy
+    /// repeats at the start of a chunk are not part of it.
+    pub range: Range<usize>,
+}
+
+impl TextChunk {
+    /// The chunk of `text[range]` without its surrounding whitespace, None when it is blank.
+    fn trimmed(text: &str, range: Range<usize>, unit: ChunkUnit) -> Option<Self> {
+        let slice = &text[range.clone()];
+        let chunk = slice.trim();
+        if chunk.is_empty() {
+            return None;
+        }
+
+        let start = range.start + (slice.len() - slice.trim_start().len());
+        Some(TextChunk {
+            text: chunk.to_string(),
+            length: unit.measure(chunk),
+            range: start..start + chunk.len(),
+        })
+    }
+}
+
+/// Where to end a chunk within `text[..window]`: the last paragraph break, sentence end or
+/// space of the second half of the window, or the window itself.
+fn break_position(text: &str, window: usize) -> usize {
+    let min = floor_char_boundary(text, window / 2);
+    let region = &text[min..window];
+
+    if let Some(pos) = region.rfind("\n\n") {
+        return min + pos + 2;
+    }
+
+    let chars = region.char_indices().rev();
+    let mut next_is_space = false;
+    for (pos, c) in chars {
+        if next_is_space && matches!(c, '.' | '!' | '?' | '…') {
+            return min + pos + c.len_utf8();
+        }
+        next_is_space = c.is_whitespace();
+    }
+
+    match region.rfind(char::is_whitespace) {
+        Some(pos) if min + pos > 0 => min + pos,
+        _ => window,
+    }
+}
+
+/// Split `text` into chunks of at most `config.size` units overlapping by about
+/// `config.overlap` units.
+pub fn chunk_text(text: &str, config: &ChunkConfig) -> Vec<TextChunk> {
+    let mut chunks = Vec::new();
+    let mut start = text.len() - text.trim_start().len();
+
+    while start < text.len() {
+        let rest = &text[start..];
+        let window = config.unit.prefix_end(rest, config.size);
+
+        let mut end = if window == rest.len() { window } else { break_position(rest, window) };
+        if end == 0 {
+            // A single char longer than the size, take it anyway to make progress
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+
+        chunks.extend(TextChunk::trimmed(text, start..start + end, config.unit));
+
+        if end == rest.len() {
+            break;
+        }
+
+        // Start the next chunk `overlap` units back, on the start of a word
+        let mut next = config.unit.suffix_start(&rest[..end], config.overlap);
+        if next > 0 && next < end && !rest[..next].ends_with(char::is_whitespace) {
+            if let Some(space) = rest[next..end].find(char::is_whitespace) {
+                next += space;
+            }
+        }
+        if next == 0 {
+            next = end;
+        }
+
+        start += next;
+        start += text[start..].len() - text[start..].trim_start().len();
+    }
+
+    chunks
+}
+
+/// Default deepest heading level that starts a section of the `markdown` strategy.
+const DEFAULT_MARKDOWN_LEVEL: usize = 3;
+
+/// Default similarity under which the `semantic` strategy starts a new chunk.
+const DEFAULT_SEMANTIC_THRESHOLD: f64 = 0.75;
+
+/// Number of separator levels of the recursive splitter: paragraphs, lines, sentences, words.
+const SPLIT_LEVELS: usize = 4;
+
+/// How a document is cut into chunks.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkStrategy {
+    /// Sliding window of [`chunk_text`].
+    Window,
+    /// Split on paragraphs, then lines, sentences and words until every piece fits, and pack
+    /// the pieces back into chunks.
+    Recursive,
+    /// One section per heading up to `max_level`; the later chunks of a long section repeat
+    /// the headings it sits under.
+    Markdown { max_level: usize },
+    /// Pack sentences, starting a new chunk where the cosine similarity of two neighbouring
+    /// sentences falls below `threshold`.
+    Semantic { threshold: f64 },
+}
+
+impl ChunkStrategy {
+    /// Parse `window`, `recursive`, `markdown[:max_level]` or `semantic[:threshold]`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_lowercase();
+        let (name, param) = match value.split_once(':') {
+            Some((name, param)) => (name.trim(), Some(param.trim())),
+            None => (value.as_str(), None),
+        };
+
+        match (name, param) {
+            ("window", None) => Some(ChunkStrategy::Window),
+            ("recursive", None) => Some(ChunkStrategy::Recursive),
+            ("markdown", None) => Some(ChunkStrategy::Markdown { max_level: DEFAULT_MARKDOWN_LEVEL }),
+            ("markdown", Some(level)) => level
+                .parse()
+                .ok()
+                .filter(|level| (1..=6).contains(level))
+                .map(|max_level| ChunkStrategy::Markdown { max_level }),
+            ("semantic", None) => Some(ChunkStrategy::Semantic { threshold: DEFAULT_SEMANTIC_THRESHOLD }),
+            ("semantic", Some(threshold)) => threshold
+                .parse()
+                .ok()
+                .filter(|threshold| (-1.0..=1.0).contains(threshold))
+                .map(|threshold| ChunkStrategy::Semantic { threshold }),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChunkStrategy::Window => "window",
+            ChunkStrategy::Recursive => "recursive",
+            ChunkStrategy::Markdown { .. } => "markdown",
+            ChunkStrategy::Semantic { .. } => "semantic",
+        }
+    }
+
+    /// Parameters stored with each chunk, enough to chunk the document again the same way.
+    pub fn parameters(&self, config: &ChunkConfig) -> Value {
+        let mut parameters = json!({
+            "size": config.size,
+            "overlap": config.overlap,
+            "unit": config.unit.as_str(),
+        });
+        match self {
+            ChunkStrategy::Markdown { max_level } => parameters["max_level"] = json!(max_level),
+            ChunkStrategy::Semantic { threshold } => parameters["threshold"] = json!(threshold),
+            ChunkStrategy::Window | ChunkStrategy::Recursive => {}
+        }
+        parameters
+    }
+
+    /// Strategy of a document: the option given at upload time when it is valid, else the
+    /// strategy of its type in `CHUNK_STRATEGY_BY_TYPE` (e.g. `MARKDOWN=markdown:2,TXT=recursive`),
+    /// else `CHUNK_STRATEGY`.
+    pub fn select(document_type: &str, upload_option: Option<&str>) -> Result<Self, Error> {
+        if let Some(option) = upload_option {
+            match ChunkStrategy::parse(option) {
+                Some(strategy) => return Ok(strategy),
+                None => println!("Warning: ignoring invalid chunk strategy '{}' given at upload", option),
+            }
+        }
+
+        let by_type = env::var("CHUNK_STRATEGY_BY_TYPE").expect("CHUNK_STRATEGY_BY_TYPE environment variable not set");
+        for entry in by_type.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let (entry_type, strategy) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid CHUNK_STRATEGY_BY_TYPE entry '{}', expected TYPE=strategy", entry))?;
+            if entry_type.trim().eq_ignore_ascii_case(document_type) {
+                return ChunkStrategy::parse(strategy)
+                    .ok_or_else(|| format!("Invalid chunk strategy '{}' for {} in CHUNK_STRATEGY_BY_TYPE", strategy, entry_type).into());
+            }
+        }
+
+        let strategy = env::var("CHUNK_STRATEGY").expect("CHUNK_STRATEGY environment variable not set");
+        ChunkStrategy::parse(&strategy).ok_or_else(|| {
+            format!("Invalid CHUNK_STRATEGY '{}', expected window, recursive, markdown or semantic", strategy).into()
+        })
+    }
+
+    /// Chunk `text` with this strategy. `embed` is only called by the semantic strategy, with
+    /// the sentences of the text, and must return one embedding per sentence.
+    pub async fn chunk<F, Fut>(&self, text: &str, config: &ChunkConfig, embed: F) -> Result<Vec<TextChunk>, Error>
+    where
+        F: FnOnce(Vec<String>) -> Fut,
+        Fut: Future<Output = Result<Vec<Vec<f32>>, Error>>,
+    {
+        match self {
+            ChunkStrategy::Window => Ok(chunk_text(text, config)),
+            ChunkStrategy::Recursive => Ok(chunk_recursive(text, config)),
+            ChunkStrategy::Markdown { max_level } => Ok(chunk_markdown(text, config, *max_level)),
+            ChunkStrategy::Semantic { threshold } => chunk_semantic(text, config, *threshold, embed).await,
+        }
+    }
+}
+
+/// Positions just after the sentence ends of `text`: `.`, `!`, `?` or `…` followed by whitespace.
+pub(crate) fn sentence_ends(text: &str) -> Vec<usize> {
+    text.char_indices()
+        .zip(text.chars().skip(1))
+        .filter(|&((_, c), next)| matches!(c, '.' | '!' | '?' | '…') && next.is_whitespace())
+        .map(|((pos, c), _)| pos + c.len_utf8())
+        .collect()
+}
+
+/// Cut positions of `text` at one level of the recursive splitter, coarsest level first.
+fn split_points(text: &str, level: usize) -> Vec<usize> {
+    match level {
+        0 => text.match_indices("\n\n").map(|(pos, _)| pos + 2).collect(),
+        1 => text.match_indices('\n').map(|(pos, _)| pos + 1).collect(),
+        2 => sentence_ends(text),
+        _ => text.match_indices(char::is_whitespace).map(|(pos, s)| pos + s.len()).collect(),
+    }
+}
+
+/// Split `text[range]` into pieces of at most `size` units, using the coarsest separators
+/// that make each piece fit.
+fn split_recursive(text: &str, range: Range<usize>, level: usize, size: usize, unit: ChunkUnit, pieces: &mut Vec<Range<usize>>) {
+    let slice = &text[range.clone()];
+    if unit.measure(slice) <= size {
+        pieces.push(range);
+        return;
+    }
+
+    if level == SPLIT_LEVELS {
+        // No separator left, cut inside the word
+        let mut start = range.start;
+        while start < range.end {
+            let rest = &text[start..range.end];
+            let mut end = unit.prefix_end(rest, size);
+            if end == 0 {
+                end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+            }
+            pieces.push(start..start + end);
+            start += end;
+        }
+        return;
+    }
+
+    let mut start = range.start;
+    for point in split_points(slice, level) {
+        let point = range.start + point;
+        if point > start {
+            split_recursive(text, start..point, level + 1, size, unit, pieces);
+            start = point;
+        }
+    }
+    if start < range.end {
+        split_recursive(text, start..range.end, level + 1, size, unit, pieces);
+    }
+}
+
+/// Pack consecutive pieces of `text` into chunks of at most `config.size` units. A chunk
+/// closed because it is full hands its last pieces, up to `config.overlap` units, over to
+/// the next one; a chunk closed because `is_break` holds for the next piece does not.
+fn merge_pieces(text: &str, pieces: &[Range<usize>], config: &ChunkConfig, is_break: impl Fn(usize) -> bool) -> Vec<TextChunk> {
+    let span = |first: usize, last: usize| &text[pieces[first].start..pieces[last].end];
+    let mut chunks = Vec::new();
+    let mut first = 0;
+
+    while first < pieces.len() {
+        let mut last = first;
+        while last + 1 < pieces.len() && !is_break(last + 1) && config.unit.measure(span(first, last + 1)) <= config.size {
+            last += 1;
+        }
+
+        chunks.extend(TextChunk::trimmed(text, pieces[first].start..pieces[last].end, config.unit));
+
+        let next = last + 1;
+        if next == pieces.len() || is_break(next) {
+            first = next;
+            continue;
+        }
+
+        // Take back the last pieces that fit in the overlap, as long as the next piece still fits
+        let mut start = next;
+        while start > first + 1 && config.unit.measure(span(start - 1, last)) <= config.overlap {
+            start -= 1;
+        }
+        while start < next && config.unit.measure(span(start, next)) > config.size {
+            start += 1;
+        }
+        first = start;
+    }
+
+    chunks
+}
+
+/// Split `text` on paragraphs, lines, sentences and words until every piece fits in
+/// `config.size` units, then pack the pieces into chunks overlapping by about `config.overlap`.
+pub fn chunk_recursive(text: &str, config: &ChunkConfig) -> Vec<TextChunk> {
+    let mut pieces = Vec::new();
+    split_recursive(text, 0..text.len(), 0, config.size, config.unit, &mut pieces);
+    merge_pieces(text, &pieces, config, |_| false)
+}
+
+/// A section of a Markdown document with the heading lines it sits under, its own included.
+struct Section {
+    headings: Vec<String>,
+    range: Range<usize>,
+}
+
+/// Level of an ATX heading line such as `## Title`.
+fn heading_level(line: &str) -> Option<usize> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let rest = &line[level..];
+    ((1..=6).contains(&level) && (rest.is_empty() || rest.starts_with(' '))).then_some(level)
+}
+
+/// Cut a Markdown document before each heading up to `max_level`, outside code fences.
+fn markdown_sections(text: &str, max_level: usize) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut headings: Vec<(usize, &str)> = Vec::new();
+    let mut start = 0;
+    let mut pos = 0;
+    let mut in_fence = false;
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        } else if let Some(level) = heading_level(trimmed).filter(|&level| !in_fence && level <= max_level) {
+            if pos > start {
+                sections.push(Section {
+                    headings: headings.iter().map(|(_, heading)| heading.to_string()).collect(),
+                    range: start..pos,
+                });
+            }
+            headings.retain(|&(parent, _)| parent < level);
+            headings.push((level, trimmed));
+            start = pos;
+        }
+        pos += line.len();
+    }
+
+    if start < text.len() {
+        sections.push(Section {
+            headings: headings.iter().map(|(_, heading)| heading.to_string()).collect(),
+            range: start..text.len(),
+        });
+    }
+
+    sections
+}
+
+/// Headings repeated at the start of the later chunks of a section, measuring at most `max`:
+/// the outer headings are dropped first, then the innermost one is cut.
+fn heading_prefix(headings: &[String], max: usize, unit: ChunkUnit) -> String {
+    for first in 0..headings.len() {
+        let prefix = format!("{}\n\n", headings[first..].join("\n"));
+        if unit.measure(&prefix) <= max {
+            return prefix;
+        }
+    }
+    let heading = headings.last().map_or("", String::as_str);
+    let heading = heading[..unit.prefix_end(heading, max.saturating_sub(unit.measure("\n\n")))].trim_end();
+    if heading.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n\n", heading)
+    }
+}
+
+/// Chunk a Markdown document section by section, never mixing two sections in a chunk.
+/// Sections too long for one chunk are split recursively, and every chunk after the first
+/// starts with the headings of the section, shortened to half a chunk at most.
+pub fn chunk_markdown(text: &str, config: &ChunkConfig, max_level: usize) -> Vec<TextChunk> {
+    let mut chunks = Vec::new();
+
+    for section in markdown_sections(text, max_level) {
+        let body = &text[section.range.clone()];
+        let offset = |chunk: TextChunk| TextChunk {
+            range: section.range.start + chunk.range.start..section.range.start + chunk.range.end,
+            ..chunk
+        };
+        if config.unit.measure(body) <= config.size || section.headings.is_empty() {
+            chunks.extend(chunk_recursive(body, config).into_iter().map(offset));
+            continue;
+        }
+
+        // Keep room for the headings repeated at the start of the later chunks
+        let prefix = heading_prefix(&section.headings, config.size / 2, config.unit);
+        let size = config.size.saturating_sub(config.unit.measure(&prefix)).max(1);
+        let section_config = ChunkConfig {
+            size,
+            overlap: config.overlap.min(size - 1),
+            unit: config.unit,
+        };
+
+        for (index, chunk) in chunk_recursive(body, &section_config).into_iter().map(offset).enumerate() {
+            let text = format!("{}{}", prefix, chunk.text);
+            let length = config.unit.measure(&text);
+            // The tokens of the headings and of the chunk can merge differently once joined
+            if index == 0 || prefix.is_empty() || length > config.size {
+                chunks.push(chunk);
+            } else {
+                chunks.push(TextChunk { length, text, range: chunk.range });
+            }
+        }
+    }
+
+    chunks
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| *x as f64 * *y as f64).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| *x as f64 * *x as f64).sum::<f64>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/// Pack the sentences of `text` into chunks of at most `config.size` units, starting a new
+/// chunk without overlap where the similarity of two neighbouring sentences is below
+/// `threshold`. `embed` receives every sentence at once and returns their embeddings.
+pub async fn chunk_semantic<F, Fut>(text: &str, config: &ChunkConfig, threshold: f64, embed: F) -> Result<Vec<TextChunk>, Error>
+where
+    F: FnOnce(Vec<String>) -> Fut,
+    Fut: Future<Output = Result<Vec<Vec<f32>>, Error>>,
+{
+    let mut ends = split_points(text, 0);
+    ends.extend(sentence_ends(text));
+    ends.sort_unstable();
+    ends.dedup();
+
+    // One piece per sentence, sentences longer than a chunk are split further
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    for end in ends.into_iter().chain(std::iter::once(text.len())) {
+        if end > start {
+            if !text[start..end].trim().is_empty() {
+                split_recursive(text, start..end, 1, config.size, config.unit, &mut pieces);
+            }
+            start = end;
+        }
+    }
+    if pieces.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let sentences = pieces.iter().map(|piece| text[piece.clone()].trim().to_string()).collect();
+    let embeddings = embed(sentences).await?;
+    if embeddings.len() != pieces.len() {
+        return Err(format!("Expected {} sentence embeddings, got {}", pieces.len(), embeddings.len()).into());
+    }
+
+    let breaks: Vec<bool> = (0..pieces.len())
+        .map(|i| i > 0 && cosine_similarity(&embeddings[i - 1], &embeddings[i]) < threshold)
+        .collect();
+    Ok(merge_pieces(text, &pieces, config, |i| breaks[i]))
+}
+
+/// Kind of content of a chunk, stored in `document_chunks.chunck_kind`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkKind {
+    /// Running text of the document.
+    Text,
+    /// Rows of a table with their header row, see [`chunk_table`].
+    Table,
+}
+
+impl ChunkKind {
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind.trim().to_lowercase().as_str() {
+            "text" => Some(ChunkKind::Text),
+            "table" => Some(ChunkKind::Table),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChunkKind::Text => "text",
+            ChunkKind::Table => "table",
+        }
+    }
+}
+
+/// How the rows of a table are written in its chunks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TableFormat {
+    /// A Markdown table, `|` in cells escaped.
+    Markdown,
+    /// Comma separated values, cells quoted when needed.
+    Csv,
+}
+
+impl TableFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format.trim().to_lowercase().as_str() {
+            "markdown" | "md" => Some(TableFormat::Markdown),
+            "csv" => Some(TableFormat::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TableFormat::Markdown => "markdown",
+            TableFormat::Csv => "csv",
+        }
+    }
+
+    /// Read `TABLE_FORMAT` (`markdown` or `csv`).
+    pub fn from_env() -> Result<Self, Error> {
+        let format = env::var("TABLE_FORMAT").expect("TABLE_FORMAT environment variable not set");
+        TableFormat::parse(&format).ok_or_else(|| format!("Invalid TABLE_FORMAT '{}', expected markdown or csv", format).into())
+    }
+
+    /// One row of the table as a line.
+    fn row(self, cells: &[String]) -> String {
+        match self {
+            TableFormat::Markdown => {
+                let cells: Vec<String> = cells
+                    .iter()
+                    .map(|cell| cell.split_whitespace().collect::<Vec<_>>().join(" ").replace('|', "\\|"))
+                    .collect();
+                format!("| {} |", cells.join(" | "))
+            }
+            TableFormat::Csv => {
+                let cells: Vec<String> = cells
+                    .iter()
+                    .map(|cell| {
+                        if cell.contains([',', '"', '\n', '\r']) {
+                            format!("\"{}\"", cell.replace('"', "\"\""))
+                        } else {
+                            cell.clone()
+                        }
+                    })
+                    .collect();
+                cells.join(",")
+            }
+        }
+    }
+
+    /// The header row, followed by the separator line of a Markdown table.
+    fn header(self, cells: &[String]) -> String {
+        match self {
+            TableFormat::Markdown => format!("{}\n|{}", self.row(cells), " --- |".repeat(cells.len())),
+            TableFormat::Csv => self.row(cells),
+        }
+    }
+}
+
+/// Write a table, header row first, as chunks of at most `config.size` units that all start
+/// with the header row. A row longer than a chunk gets a chunk of its own. Tables are not
+/// overlapped: the header already gives each chunk its context.
+pub fn chunk_table(rows: &[Vec<String>], config: &ChunkConfig, format: TableFormat) -> Vec<String> {
+    let Some((header, body)) = rows.split_first() else {
+        return Vec::new();
+    };
+    let header = format.header(header);
+    if body.is_empty() {
+        return vec![header];
+    }
+
+    let mut chunks = Vec::new();
+    let mut chunk = header.clone();
+    let mut has_rows = false;
+    for row in body {
+        let line = format.row(row);
+        let candidate = format!("{}\n{}", chunk, line);
+        if has_rows && config.unit.measure(&candidate) > config.size {
+            chunks.push(std::mem::replace(&mut chunk, format!("{}\n{}", header, line)));
+        } else {
+            chunk = candidate;
+        }
+        has_rows = true;
+    }
+    chunks.push(chunk);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(size: usize, overlap: usize) -> ChunkConfig {
+        ChunkConfig::new(size, overlap, ChunkUnit::Chars).unwrap()
+    }
+
+    #[test]
+    fn cuts_multi_byte_text_on_char_boundaries() {
+        let text = "Préavis de trois mois — résiliation écrite. Congés payés: 25 jours ouvrés. 日本語のテキストも分割されます。";
+        let chunks = chunk_text(text, &config(16, 4));
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.length <= 16, "{:?} is longer than the size", chunk.text);
+            assert_eq!(chunk.length, chunk.text.chars().count());
+            assert_eq!(&text[chunk.range.clone()], chunk.text);
+        }
+    }
+
+    #[test]
+    fn cuts_a_word_without_spaces_inside_the_window() {
+        let text = "ééééééééééééééééééééééééé";
+        let chunks = chunk_text(text, &config(10, 3));
+
+        assert!(chunks.iter().all(|chunk| chunk.length <= 10));
+        assert_eq!(chunks[0].text, "éééééééééé");
+        assert_eq!(chunks.last().unwrap().range.end, text.len());
+    }
+
+    #[test]
+    fn prefers_the_end_of_a_sentence_in_the_second_half_of_the_window() {
+        let text = "The notice is three months. It can be shortened by agreement.";
+        let chunks = chunk_text(text, &config(40, 0));
+
+        assert_eq!(chunks[0].text, "The notice is three months.");
+        assert_eq!(chunks[1].text, "It can be shortened by agreement.");
+    }
+
+    #[test]
+    fn overlapping_chunks_start_on_a_word_and_always_move_forward() {
+        let text = "one two three four five six seven eight nine ten eleven twelve thirteen fourteen fifteen";
+        let chunks = chunk_text(text, &config(20, 12));
+
+        for pair in chunks.windows(2) {
+            assert!(pair[1].range.start > pair[0].range.start);
+            assert!(pair[1].range.start < pair[0].range.end, "{:?} does not overlap {:?}", pair[1].text, pair[0].text);
+            assert!(text[..pair[1].range.start].ends_with(' '));
+        }
+        assert_eq!(chunks.last().unwrap().range.end, text.len());
+    }
+
+    #[test]
+    fn an_overlap_covering_the_whole_chunk_still_makes_progress() {
+        let text = "abcdefghij klmnopqrst uvwxyz";
+        let chunks = chunk_text(text, &config(11, 10));
+
+        assert!(chunks.windows(2).all(|pair| pair[1].range.start > pair[0].range.start));
+        assert_eq!(chunks.last().unwrap().range.end, text.len());
+    }
+
+    #[test]
+    fn measures_the_chunks_in_tokens() {
+        let text = "Le préavis est de trois mois. ".repeat(20);
+        let config = ChunkConfig::new(30, 5, ChunkUnit::Tokens).unwrap();
+        let chunks = chunk_text(&text, &config);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.length <= 30 && chunk.length == ChunkUnit::Tokens.measure(&chunk.text)));
+    }
+
+    #[test]
+    fn blank_text_has_no_chunk() {
+        assert!(chunk_text(" \n\n\t ", &config(10, 2)).is_empty());
+        assert!(chunk_text("", &config(10, 2)).is_empty());
+    }
+
+    #[test]
+    fn parses_the_strategies_and_their_parameters() {
+        assert_eq!(ChunkStrategy::parse(" Recursive "), Some(ChunkStrategy::Recursive));
+        assert_eq!(ChunkStrategy::parse("markdown"), Some(ChunkStrategy::Markdown { max_level: 3 }));
+        assert_eq!(ChunkStrategy::parse("markdown:2"), Some(ChunkStrategy::Markdown { max_level: 2 }));
+        assert_eq!(ChunkStrategy::parse("markdown:7"), None);
+        assert_eq!(ChunkStrategy::parse("semantic:0.5"), Some(ChunkStrategy::Semantic { threshold: 0.5 }));
+        assert_eq!(ChunkStrategy::parse("semantic:2"), None);
+        assert_eq!(ChunkStrategy::parse("window:1"), None);
+        assert_eq!(ChunkStrategy::parse("sentences"), None);
+    }
+
+    #[test]
+    fn recursive_keeps_the_paragraphs_that_fit_whole() {
+        let text = "The notice is three months.\n\nOvertime is paid at 125%.";
+        let chunks = chunk_recursive(text, &config(30, 0));
+
+        let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+        assert_eq!(texts, vec!["The notice is three months.", "Overtime is paid at 125%."]);
+    }
+
+    #[test]
+    fn recursive_chunks_stay_within_the_size() {
+        let text = format!("Short sentence. {} Another one.\n\nLast paragraph.", "x".repeat(45));
+        let chunks = chunk_recursive(&text, &config(20, 5));
+
+        assert!(chunks.iter().all(|chunk| chunk.length <= 20), "{:?}", chunks);
+        assert_eq!(chunks.last().unwrap().text, "Last paragraph.");
+    }
+
+    #[test]
+    fn markdown_never_mixes_two_sections() {
+        let text = "# Contract\n\nSigned in Paris.\n\n## Notice\n\nThree months.\n\n## Overtime\n\nPaid at 125%.\n";
+        let chunks = chunk_markdown(text, &config(200, 0), 3);
+
+        let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec!["# Contract\n\nSigned in Paris.", "## Notice\n\nThree months.", "## Overtime\n\nPaid at 125%."]
+        );
+    }
+
+    #[test]
+    fn markdown_repeats_the_headings_of_a_long_section() {
+        let text = format!("# Contract\n\n## Notice\n\n{}", "The notice is three months. ".repeat(6));
+        let chunks = chunk_markdown(&text, &config(80, 0), 3);
+
+        assert!(chunks.len() > 2);
+        assert!(chunks[1].text.starts_with("## Notice\n\nThe notice"));
+        for chunk in &chunks[2..] {
+            assert!(chunk.text.starts_with("# Contract\n## Notice\n\n"), "{:?}", chunk.text);
+            assert_eq!(chunk.length, chunk.text.chars().count());
+        }
+    }
+
+    #[test]
+    fn markdown_shortens_the_headings_to_stay_within_the_size() {
+        let body = "The notice is three months. ".repeat(6);
+        let text = format!("# Contract of the Paris office
+
+## Notice
+
+{}", body);
+        let chunks = chunk_markdown(&text, &config(40, 0), 3);
+
+        assert!(chunks.len() > 2);
+        for chunk in &chunks {
+            assert!(chunk.length <= 40, "{:?}", chunk.text);
+        }
+        // The outer heading is dropped, the inner one still fits in half a chunk
+        for chunk in &chunks[2..] {
+            assert!(chunk.text.starts_with("## Notice\n\nThe notice"), "{:?}", chunk.text);
+        }
+
+        let text = format!("## Notice period of the employees of the Paris office\n\n{}", body);
+        let chunks = chunk_markdown(&text, &config(40, 0), 3);
+
+        assert!(chunks.len() > 2);
+        for chunk in &chunks {
+            assert!(chunk.length <= 40, "{:?}", chunk.text);
+        }
+        // A single heading longer than half a chunk is cut
+        for chunk in &chunks[1..] {
+            assert!(chunk.text.starts_with("## Notice period o\n\n"), "{:?}", chunk.text);
+        }
+    }
+
+    #[tokio::test]
+    async fn semantic_breaks_where_neighbouring_sentences_differ() {
+        let text = "The notice is three months. It can be shortened. Overtime is paid at 125%.";
+        let embed = |sentences: Vec<String>| async move {
+            Ok(sentences
+                .iter()
+                .map(|sentence| if sentence.contains("Overtime") { vec![0.0, 1.0] } else { vec![1.0, 0.0] })
+                .collect())
+        };
+        let chunks = chunk_semantic(text, &config(200, 0), 0.75, embed).await.unwrap();
+
+        let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+        assert_eq!(texts, vec!["The notice is three months. It can be shortened.", "Overtime is paid at 125%."]);
+    }
+
+    #[tokio::test]
+    async fn semantic_needs_one_embedding_per_sentence() {
+        let embed = |_: Vec<String>| async { Ok(vec![vec![1.0]]) };
+
+        assert!(chunk_semantic("One sentence. Two sentences.", &config(200, 0), 0.75, embed).await.is_err());
+    }
+}
@@ -0,0 +1,347 @@
+//! Grounded answers: numbered sources and their `[n]` citations.
+//!
+//! The chunks given to the chat model are numbered from 1 in its context, and
+//! [`CITATION_INSTRUCTIONS`] asks it to back every statement with the numbers of its
+//! sources, such as `[2]` or `[1, 3]`. [`parse_citations`] then maps each citation of the
+//! answer to its chunk, with the sentence of the answer it supports and the sentence of
+//! the chunk sharing the most words with it.
+//!
+//! A citation of a number that was not given points at a chunk the model never saw: it
+//! is removed from the answer and reported apart, so that no statement is linked to a
+//! made-up source.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::chunking::sentence_ends;
+
+/// Instructions added to the system prompt of the grounded answers.
+pub const CITATION_INSTRUCTIONS: &str = "Each source of the context starts with its number in square brackets, such as [1]. \
+Cite the sources supporting each sentence of your answer by their numbers in square brackets at the end of the sentence, \
+such as [1] or [1, 3]. Only cite the numbers of the sources given in the context, and do not cite anything else.";
+
+/// Longest quote of a chunk returned with a citation, in chars.
+const MAX_QUOTE_CHARS: usize = 300;
+
+/// Words shorter than this are too common to tell which sentence of a chunk is quoted.
+const MIN_WORD_CHARS: usize = 3;
+
+/// A chunk given to the chat model, numbered by its position in `sources` plus one.
+#[derive(Debug, Clone)]
+pub struct Source<'a> {
+    pub document_chunk_uuid: &'a str,
+    pub document_uuid: &'a str,
+    pub document_name: &'a str,
+    pub page_start: Option<i32>,
+    pub page_end: Option<i32>,
+    /// Text of the chunk, where the quotes are taken from.
+    pub text: &'a str,
+}
+
+/// A citation of the answer, resolved to its chunk.
+#[derive(Debug, Clone, Serialize)]
+pub struct Citation {
+    /// Number of the source in the context.
+    pub number: usize,
+    pub document_chunk_uuid: String,
+    pub document_uuid: String,
+    pub document_name: String,
+    pub page_start: Option<i32>,
+    pub page_end: Option<i32>,
+    /// Sentence of the answer carrying the citation, without its citations.
+    pub answer_span: String,
+    /// Sentence of the chunk sharing the most words with `answer_span`, `None` if they
+    /// share none.
+    pub quote: Option<String>,
+}
+
+/// Answer whose citations were checked against the sources.
+#[derive(Debug, Clone, Serialize)]
+pub struct GroundedAnswer {
+    /// The answer, without the citations of sources that were not given.
+    pub answer: String,
+    /// Valid citations, in the order of the answer, once per sentence and source.
+    pub citations: Vec<Citation>,
+    /// Numbers cited by the answer that are not sources, sorted.
+    pub rejected_citations: Vec<usize>,
+}
+
+/// Citation marker of a text, such as `[1]` or `[1, 3]`.
+struct Marker {
+    range: Range<usize>,
+    numbers: Vec<usize>,
+}
+
+/// Citation markers of `text`. Brackets holding anything but numbers separated by commas,
+/// such as Markdown links, are not citations.
+fn markers(text: &str) -> Vec<Marker> {
+    let mut markers = Vec::new();
+    let mut search_from = 0;
+    while let Some(offset) = text[search_from..].find('[') {
+        let start = search_from + offset;
+        search_from = start + 1;
+        let end = match text[start..].find(']') {
+            Some(offset) => start + offset,
+            None => break,
+        };
+
+        let numbers: Option<Vec<usize>> = text[start + 1..end]
+            .split(',')
+            .map(|number| {
+                let number = number.trim();
+                if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) {
+                    number.parse().ok()
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if let Some(numbers) = numbers {
+            markers.push(Marker { range: start..end + 1, numbers });
+            search_from = end + 1;
+        }
+    }
+    markers
+}
+
+/// `text` without its citation markers, such as a previous answer of a conversation whose
+/// numbers were those of another context.
+pub fn strip_markers(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut last = 0;
+    for marker in markers(text) {
+        stripped.push_str(text[last..marker.range.start].trim_end());
+        last = marker.range.end;
+    }
+    stripped.push_str(&text[last..]);
+    stripped.trim().to_string()
+}
+
+/// Sentence ranges of `text`, cut after the sentence ends and at line breaks.
+fn sentences(text: &str) -> Vec<Range<usize>> {
+    let mut cuts: Vec<usize> = sentence_ends(text);
+    cuts.extend(text.match_indices('\n').map(|(pos, _)| pos + 1));
+    cuts.push(text.len());
+    cuts.sort_unstable();
+    cuts.dedup();
+
+    let mut ranges = Vec::with_capacity(cuts.len());
+    let mut start = 0;
+    for cut in cuts {
+        if !text[start..cut].trim().is_empty() {
+            ranges.push(start..cut);
+        }
+        start = cut;
+    }
+    ranges
+}
+
+/// Lowercased words of `text` long enough to be telling.
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_WORD_CHARS)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Sentence of `text` sharing the most words with `span`, cut to [`MAX_QUOTE_CHARS`].
+fn quote(text: &str, span: &str) -> Option<String> {
+    let span_words = words(span);
+    let (best, shared) = sentences(text)
+        .into_iter()
+        .map(|range| {
+            let shared = words(&text[range.clone()]).intersection(&span_words).count();
+            (range, shared)
+        })
+        // The first of the best sentences
+        .fold(None, |best: Option<(Range<usize>, usize)>, (range, shared)| match best {
+            Some((_, best_shared)) if best_shared >= shared => best,
+            _ => Some((range, shared)),
+        })?;
+    if shared == 0 {
+        return None;
+    }
+
+    let sentence = text[best].trim();
+    if sentence.chars().count() <= MAX_QUOTE_CHARS {
+        return Some(sentence.to_string());
+    }
+    let cut: String = sentence.chars().take(MAX_QUOTE_CHARS).collect();
+    Some(format!("{}…", cut.trim_end()))
+}
+
+/// Check the citations of `answer` against `sources` and resolve the valid ones.
+pub fn parse_citations(answer: &str, sources: &[Source]) -> GroundedAnswer {
+    let is_source = |number: usize| (1..=sources.len()).contains(&number);
+
+    // Rewrite the markers with their valid numbers only, dropping those left empty
+    let mut rejected: Vec<usize> = Vec::new();
+    let mut cleaned = String::with_capacity(answer.len());
+    let mut cited: Vec<(usize, Vec<usize>)> = Vec::new();
+    let mut last = 0;
+    for marker in markers(answer) {
+        let (valid, invalid): (Vec<usize>, Vec<usize>) = marker.numbers.iter().partition(|number| is_source(**number));
+        rejected.extend(invalid);
+
+        let before = &answer[last..marker.range.start];
+        if valid.is_empty() {
+            cleaned.push_str(before.trim_end_matches([' ', '\t']));
+            // A marker opening a line goes with the space after it
+            if cleaned.is_empty() || cleaned.ends_with('\n') {
+                let after = &answer[marker.range.end..];
+                last = marker.range.end + (after.len() - after.trim_start_matches([' ', '\t']).len());
+                continue;
+            }
+        } else {
+            cleaned.push_str(before);
+            cited.push((cleaned.len(), valid.clone()));
+            let numbers: Vec<String> = valid.iter().map(usize::to_string).collect();
+            cleaned.push_str(&format!("[{}]", numbers.join(", ")));
+        }
+        last = marker.range.end;
+    }
+    cleaned.push_str(&answer[last..]);
+    rejected.sort_unstable();
+    rejected.dedup();
+
+    // Each citation supports the sentence it ends, a citation opening a sentence the one before
+    let sentences = sentences(&cleaned);
+    let mut seen: HashSet<(usize, usize)> = HashSet::new();
+    let mut citations = Vec::new();
+    for (position, numbers) in cited {
+        let mut index = match sentences.iter().position(|range| range.contains(&position)) {
+            Some(index) => index,
+            None => continue,
+        };
+        if index > 0 && cleaned[sentences[index].start..position].trim().is_empty() {
+            index -= 1;
+        }
+        let answer_span = strip_markers(&cleaned[sentences[index].clone()]);
+
+        for number in numbers {
+            if !seen.insert((index, number)) {
+                continue;
+            }
+            let source = &sources[number - 1];
+            citations.push(Citation {
+                number,
+                document_chunk_uuid: source.document_chunk_uuid.to_string(),
+                document_uuid: source.document_uuid.to_string(),
+                document_name: source.document_name.to_string(),
+                page_start: source.page_start,
+                page_end: source.page_end,
+                quote: quote(source.text, &answer_span),
+                answer_span: answer_span.clone(),
+            });
+        }
+    }
+
+    GroundedAnswer { answer: cleaned, citations, rejected_citations: rejected }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXTS: [&str; 2] = [
+        "The notice period is three months. It can be shortened by agreement.",
+        "Overtime is paid at 125% for the first eight hours.",
+    ];
+    const UUIDS: [&str; 2] = ["chunk-1", "chunk-2"];
+
+    fn sources() -> Vec<Source<'static>> {
+        TEXTS
+            .iter()
+            .zip(UUIDS)
+            .map(|(text, uuid)| Source {
+                document_chunk_uuid: uuid,
+                document_uuid: "doc-1",
+                document_name: "contract.pdf",
+                page_start: Some(1),
+                page_end: None,
+                text,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resolves_the_citations_with_their_sentence_and_quote() {
+        let grounded = parse_citations("The notice is three months [1]. Overtime is paid at 125% [2].", &sources());
+
+        assert_eq!(grounded.answer, "The notice is three months [1]. Overtime is paid at 125% [2].");
+        assert!(grounded.rejected_citations.is_empty());
+        assert_eq!(grounded.citations.len(), 2);
+        assert_eq!(grounded.citations[0].number, 1);
+        assert_eq!(grounded.citations[0].document_chunk_uuid, "chunk-1");
+        assert_eq!(grounded.citations[0].answer_span, "The notice is three months.");
+        assert_eq!(grounded.citations[0].quote.as_deref(), Some("The notice period is three months."));
+        assert_eq!(grounded.citations[1].number, 2);
+        assert_eq!(grounded.citations[1].answer_span, "Overtime is paid at 125%.");
+    }
+
+    #[test]
+    fn removes_the_markers_of_numbers_that_are_not_sources() {
+        let grounded = parse_citations("The notice is three months [3]. Overtime is paid [0].", &sources());
+
+        assert_eq!(grounded.answer, "The notice is three months. Overtime is paid.");
+        assert!(grounded.citations.is_empty());
+        assert_eq!(grounded.rejected_citations, vec![0, 3]);
+    }
+
+    #[test]
+    fn keeps_the_valid_numbers_of_a_mixed_marker() {
+        let grounded = parse_citations("The notice is three months [4, 1, 7, 4].", &sources());
+
+        assert_eq!(grounded.answer, "The notice is three months [1].");
+        assert_eq!(grounded.rejected_citations, vec![4, 7]);
+        assert_eq!(grounded.citations.len(), 1);
+        assert_eq!(grounded.citations[0].number, 1);
+    }
+
+    #[test]
+    fn cites_a_source_once_per_sentence() {
+        let grounded = parse_citations("The notice is three months [1] by agreement [1, 2].", &sources());
+
+        let numbers: Vec<usize> = grounded.citations.iter().map(|citation| citation.number).collect();
+        assert_eq!(numbers, vec![1, 2]);
+    }
+
+    #[test]
+    fn markdown_links_and_other_brackets_are_not_citations() {
+        let answer = "See [the handbook](https://example.com/handbook) and [appendix A] [2].";
+        let grounded = parse_citations(answer, &sources());
+
+        assert_eq!(grounded.answer, answer);
+        assert!(grounded.rejected_citations.is_empty());
+        assert_eq!(grounded.citations.len(), 1);
+        assert_eq!(grounded.citations[0].number, 2);
+        assert_eq!(strip_markers(answer), "See [the handbook](https://example.com/handbook) and [appendix A].");
+    }
+
+    #[test]
+    fn a_rejected_marker_opening_a_line_goes_with_its_space() {
+        let grounded = parse_citations("[5] The notice is three months.\n[6] It can be shortened.", &sources());
+
+        assert_eq!(grounded.answer, "The notice is three months.\nIt can be shortened.");
+        assert_eq!(grounded.rejected_citations, vec![5, 6]);
+    }
+
+    #[test]
+    fn a_marker_opening_a_line_supports_the_sentence_before() {
+        let grounded = parse_citations("The notice is three months.\n[1] Overtime is paid at 125%.", &sources());
+
+        assert_eq!(grounded.answer, "The notice is three months.\n[1] Overtime is paid at 125%.");
+        assert_eq!(grounded.citations.len(), 1);
+        assert_eq!(grounded.citations[0].answer_span, "The notice is three months.");
+    }
+
+    #[test]
+    fn a_span_sharing_no_word_with_the_source_has_no_quote() {
+        let grounded = parse_citations("Yes [2].", &sources());
+
+        assert_eq!(grounded.citations.len(), 1);
+        assert_eq!(grounded.citations[0].quote, None);
+    }
+}
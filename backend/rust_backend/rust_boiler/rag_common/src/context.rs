@@ -0,0 +1,423 @@
+//! Context of the answers, assembled from the retrieved chunks within a token budget.
+//!
+//! [`assemble`] takes the chunks in their order of relevance and:
+//!
+//! - drops the chunks given twice, those with the text of another chunk of their document,
+//!   and those lying within the char range already covered by chunks of their document;
+//! - merges the chunks that follow each other in a document, whose char ranges overlap or
+//!   are only apart by the whitespace trimmed from them, into one [`ContextSource`],
+//!   without repeating the overlap of the chunking;
+//! - numbers the sources from 1 in the order of their most relevant chunk and keeps them
+//!   while they fit in the budget, in tokens of the `cl100k_base` tokenizer. A source that
+//!   does not fit is left out whole, as a truncated table or sentence would be misread,
+//!   and the next ones are still tried.
+//!
+//! Every chunk left out is reported in [`AnswerContext::dropped`] with the reason.
+
+use std::collections::HashSet;
+use std::env;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::chunking::{ChunkKind, ChunkUnit};
+use crate::search::Chunk;
+use crate::Error;
+
+/// Shortest text repeated at the end of a chunk and the start of the next one that is taken
+/// for their overlap, in chars. Shorter matches are common words rather than the overlap.
+const MIN_OVERLAP_CHARS: usize = 10;
+
+/// Longest gap between the char ranges of two chunks that follow each other, in chars: the
+/// whitespace trimmed from their ends, up to a paragraph break.
+const MAX_GAP_CHARS: i32 = 2;
+
+/// Why a chunk was left out of the context.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    /// Its text is already in the context, through another chunk.
+    Duplicate,
+    /// Its source did not fit in the token budget.
+    OverBudget,
+}
+
+/// A chunk left out of the context.
+#[derive(Debug, Clone, Serialize)]
+pub struct DroppedChunk {
+    pub document_chunk_uuid: String,
+    pub document_uuid: String,
+    pub reason: DropReason,
+    /// Tokens of the text of the chunk.
+    pub tokens: usize,
+}
+
+/// One numbered source of the context: a chunk, or chunks that follow each other in a document.
+#[derive(Debug, Clone)]
+pub struct ContextSource<'a> {
+    /// The chunks of the source, in their order in the document.
+    pub chunks: Vec<&'a Chunk>,
+    /// Text of the chunks, their overlaps removed.
+    pub text: String,
+    /// Tokens of the source in the context, with its number, document and metadata.
+    pub tokens: usize,
+}
+
+impl ContextSource<'_> {
+    /// The first chunk of the source, whose document and metadata are those of every chunk.
+    pub fn first(&self) -> &Chunk {
+        self.chunks[0]
+    }
+
+    pub fn page_start(&self) -> Option<i32> {
+        self.chunks.iter().filter_map(|chunk| chunk.page_start).min()
+    }
+
+    pub fn page_end(&self) -> Option<i32> {
+        self.chunks.iter().filter_map(|chunk| chunk.page_end.or(chunk.page_start)).max()
+    }
+}
+
+/// Context given to the chat model, with what was left out of it.
+#[derive(Debug, Clone)]
+pub struct AnswerContext<'a> {
+    /// Sources numbered by their position plus one.
+    pub sources: Vec<ContextSource<'a>>,
+    /// The sources as given to the chat model.
+    pub text: String,
+    /// Tokens of `text`.
+    pub tokens: usize,
+    pub budget: usize,
+    /// Chunks left out, in their order of relevance.
+    pub dropped: Vec<DroppedChunk>,
+}
+
+impl AnswerContext<'_> {
+    /// Report of the assembly in the format of the responses.
+    pub fn to_json(&self) -> Value {
+        let chunk_count: usize = self.sources.iter().map(|source| source.chunks.len()).sum();
+        json!({
+            "tokens": self.tokens,
+            "budget": self.budget,
+            "source_count": self.sources.len(),
+            "chunk_count": chunk_count,
+            "merged_chunk_count": chunk_count - self.sources.len(),
+            "dropped_chunks": self.dropped
+        })
+    }
+}
+
+/// Read `ANSWER_CONTEXT_TOKENS`, the most tokens of chunks given to the chat model.
+pub fn context_token_budget() -> Result<usize, Error> {
+    let budget = env::var("ANSWER_CONTEXT_TOKENS").expect("ANSWER_CONTEXT_TOKENS environment variable not set");
+    budget
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid ANSWER_CONTEXT_TOKENS '{}': {}", budget, e).into())
+}
+
+/// Chunks of a document covering one char range, in their order of relevance until they
+/// are sorted by position.
+struct Group<'a> {
+    chunks: Vec<&'a Chunk>,
+    /// Char range covered, `None` for a chunk without position, which is never merged.
+    range: Option<(i32, i32)>,
+}
+
+fn char_range(chunk: &Chunk) -> Option<(i32, i32)> {
+    match (chunk.char_start, chunk.char_end) {
+        (Some(start), Some(end)) if start <= end => Some((start, end)),
+        _ => None,
+    }
+}
+
+/// Index of the group of the document of `chunk` whose range overlaps `range` or is at most
+/// [`MAX_GAP_CHARS`] apart from it.
+fn adjacent_group(groups: &[Group], chunk: &Chunk, range: (i32, i32), skip: Option<usize>) -> Option<usize> {
+    groups.iter().enumerate().position(|(index, group)| {
+        Some(index) != skip
+            && group.chunks[0].document_uuid == chunk.document_uuid
+            && group.chunks[0].chunk_kind == chunk.chunk_kind
+            && group
+                .range
+                .is_some_and(|(start, end)| range.0 <= end + MAX_GAP_CHARS && range.1 + MAX_GAP_CHARS >= start)
+    })
+}
+
+/// Append `next` to `text`, without the start of `next` that `text` already ends with, or
+/// after `separator` when they do not overlap.
+fn append_without_overlap(text: &mut String, next: &str, separator: &str) {
+    let longest = text.len().min(next.len());
+    let overlap = next
+        .char_indices()
+        .map(|(index, _)| index)
+        .chain(std::iter::once(next.len()))
+        .filter(|&end| end <= longest)
+        .rev()
+        .find(|&end| next[..end].chars().count() >= MIN_OVERLAP_CHARS && text.ends_with(&next[..end]))
+        .unwrap_or(0);
+
+    if overlap == 0 {
+        text.push_str(separator);
+    }
+    text.push_str(&next[overlap..]);
+}
+
+/// `source` as it appears in the context under `number`.
+fn source_block(number: usize, source: &ContextSource) -> String {
+    let chunk = source.first();
+    let metadata = chunk
+        .document_metadata
+        .iter()
+        .map(|meta| format!("{}: {}", meta.metadata_name, meta.value_string()))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    // Page range of the source, when the document has pages
+    let pages = match (source.page_start(), source.page_end()) {
+        (Some(start), Some(end)) if start != end => format!("\nPages: {}-{}", start, end),
+        (Some(start), _) => format!("\nPage: {}", start),
+        _ => String::new(),
+    };
+
+    // Tables are announced so that their rows are read cell by cell under the header row
+    let content_label = match ChunkKind::parse(&chunk.chunk_kind) {
+        Some(ChunkKind::Table) => "Content (table, first row is the header)",
+        _ => "Content",
+    };
+
+    format!(
+        "[{}] Document: {}{}\nMetadata:\n{}\n{}: {}",
+        number, chunk.document_name, pages, metadata, content_label, source.text
+    )
+}
+
+/// The context of `chunks`, given in their order of relevance, within `budget` tokens.
+pub fn assemble(chunks: &[Chunk], budget: usize) -> AnswerContext<'_> {
+    let mut dropped = Vec::new();
+    let mut drop_chunk = |chunk: &Chunk, reason: DropReason| {
+        dropped.push(DroppedChunk {
+            document_chunk_uuid: chunk.document_chunk_uuid.clone(),
+            document_uuid: chunk.document_uuid.clone(),
+            reason,
+            tokens: ChunkUnit::Tokens.measure(&chunk.embebed_text),
+        })
+    };
+
+    // Duplicates are dropped and neighbours grouped, keeping the position of the most relevant chunk.
+    // The same text in two documents is kept, each is a source of its own
+    let mut uuids = HashSet::new();
+    let mut texts = HashSet::new();
+    let mut groups: Vec<Group> = Vec::new();
+    for chunk in chunks {
+        if !uuids.insert(chunk.document_chunk_uuid.as_str()) || !texts.insert((chunk.document_uuid.as_str(), chunk.embebed_text.trim())) {
+            drop_chunk(chunk, DropReason::Duplicate);
+            continue;
+        }
+        let range = match char_range(chunk) {
+            Some(range) => range,
+            None => {
+                groups.push(Group { chunks: vec![chunk], range: None });
+                continue;
+            }
+        };
+
+        let mut index = match adjacent_group(&groups, chunk, range, None) {
+            Some(index) => index,
+            None => {
+                groups.push(Group { chunks: vec![chunk], range: Some(range) });
+                continue;
+            }
+        };
+        let (start, end) = groups[index].range.unwrap_or(range);
+        if range.0 >= start && range.1 <= end {
+            drop_chunk(chunk, DropReason::Duplicate);
+            continue;
+        }
+        groups[index].chunks.push(chunk);
+        groups[index].range = Some((start.min(range.0), end.max(range.1)));
+
+        // The extended group may now join another group of the document, the merged group
+        // takes the position of the most relevant one
+        while let Some(other) = groups[index].range.and_then(|range| adjacent_group(&groups, chunk, range, Some(index))) {
+            let (kept, removed) = (index.min(other), index.max(other));
+            let removed = groups.remove(removed);
+            let group = &mut groups[kept];
+            let (start, end) = group.range.unwrap_or_default();
+            let (removed_start, removed_end) = removed.range.unwrap_or_default();
+            group.range = Some((start.min(removed_start), end.max(removed_end)));
+            group.chunks.extend(removed.chunks);
+            index = kept;
+        }
+    }
+
+    // Sources are kept in the order of their most relevant chunk while they fit
+    let mut sources: Vec<ContextSource> = Vec::new();
+    let mut blocks: Vec<String> = Vec::new();
+    let mut tokens = 0;
+    for mut group in groups {
+        group.chunks.sort_by_key(|chunk| chunk.char_start);
+        let mut text = group.chunks[0].embebed_text.clone();
+        for pair in group.chunks.windows(2) {
+            // Chunks cut right one after the other continue the same text, a gap was whitespace
+            let separator = if pair[0].char_end == pair[1].char_start { "" } else { "\n" };
+            append_without_overlap(&mut text, &pair[1].embebed_text, separator);
+        }
+
+        let mut source = ContextSource { chunks: group.chunks, text, tokens: 0 };
+        let block = source_block(sources.len() + 1, &source);
+        source.tokens = ChunkUnit::Tokens.measure(&block);
+        if tokens + source.tokens > budget {
+            source.chunks.iter().for_each(|chunk| drop_chunk(chunk, DropReason::OverBudget));
+            continue;
+        }
+        tokens += source.tokens;
+        blocks.push(block);
+        sources.push(source);
+    }
+
+    if !dropped.is_empty() {
+        println!(
+            "Context of {} sources in {} of {} tokens, {} chunks dropped",
+            sources.len(),
+            tokens,
+            budget,
+            dropped.len()
+        );
+    }
+    let text = blocks.join("\n\n");
+    AnswerContext { tokens: ChunkUnit::Tokens.measure(&text), sources, text, budget, dropped }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = "The notice period is three months. It can be shortened by agreement. Overtime is paid at 125%.";
+
+    fn chunk(document_uuid: &str, document_chunk_uuid: &str, text: &str, range: Option<(i32, i32)>) -> Chunk {
+        Chunk {
+            document_uuid: document_uuid.to_string(),
+            document_name: format!("{}.pdf", document_uuid),
+            document_location: String::new(),
+            document_hash: String::new(),
+            document_type: "pdf".to_string(),
+            document_status: "vectorised".to_string(),
+            document_chunk_uuid: document_chunk_uuid.to_string(),
+            embebed_text: text.to_string(),
+            chunk_kind: ChunkKind::Text.as_str().to_string(),
+            page_start: Some(1),
+            page_end: Some(1),
+            char_start: range.map(|(start, _)| start),
+            char_end: range.map(|(_, end)| end),
+            document_embeding_uuid: String::new(),
+            embeder_type: String::new(),
+            embedding_token: None,
+            embedding_time: None,
+            vector_distance: 0.0,
+            similarity: 0.0,
+            keyword_score: 0.0,
+            hybrid_score: None,
+            rerank_score: None,
+            document_metadata: Vec::new(),
+        }
+    }
+
+    // Chunk of DOCUMENT between the char offsets
+    fn part(document_chunk_uuid: &str, start: usize, end: usize) -> Chunk {
+        chunk("contract", document_chunk_uuid, &DOCUMENT[start..end], Some((start as i32, end as i32)))
+    }
+
+    fn uuids(source: &ContextSource) -> Vec<String> {
+        source.chunks.iter().map(|chunk| chunk.document_chunk_uuid.clone()).collect()
+    }
+
+    #[test]
+    fn merges_overlapping_chunks_without_repeating_the_overlap() {
+        let chunks = vec![part("c2", 25, 69), part("c1", 0, 45), part("c3", 69, DOCUMENT.len())];
+        let context = assemble(&chunks, 1000);
+
+        assert_eq!(context.sources.len(), 1);
+        assert_eq!(uuids(&context.sources[0]), vec!["c1", "c2", "c3"]);
+        assert_eq!(context.sources[0].text, DOCUMENT);
+        assert!(context.dropped.is_empty());
+    }
+
+    #[test]
+    fn separates_chunks_apart_by_trimmed_whitespace_with_a_line_break() {
+        let chunks = vec![part("c1", 0, 34), part("c2", 35, 68)];
+        let context = assemble(&chunks, 1000);
+
+        assert_eq!(context.sources.len(), 1);
+        assert_eq!(context.sources[0].text, "The notice period is three months.\nIt can be shortened by agreement.");
+    }
+
+    #[test]
+    fn drops_the_duplicates_of_a_document() {
+        let chunks = vec![
+            part("c1", 0, 68),
+            part("c1", 0, 68),
+            // Within the range of c1
+            part("c2", 35, 68),
+            // Text of c1 in another chunk of the document
+            chunk("contract", "c3", &DOCUMENT[0..68], None),
+        ];
+        let context = assemble(&chunks, 1000);
+
+        assert_eq!(context.sources.len(), 1);
+        let dropped: Vec<(&str, DropReason)> =
+            context.dropped.iter().map(|chunk| (chunk.document_chunk_uuid.as_str(), chunk.reason)).collect();
+        assert_eq!(
+            dropped,
+            vec![("c1", DropReason::Duplicate), ("c2", DropReason::Duplicate), ("c3", DropReason::Duplicate)]
+        );
+    }
+
+    #[test]
+    fn keeps_the_same_text_in_two_documents() {
+        let chunks = vec![
+            chunk("contract", "c1", "The notice period is three months.", None),
+            chunk("amendment", "c2", "The notice period is three months.", None),
+        ];
+        let context = assemble(&chunks, 1000);
+
+        assert_eq!(context.sources.len(), 2);
+        assert!(context.dropped.is_empty());
+    }
+
+    #[test]
+    fn skips_a_source_over_the_budget_and_keeps_trying_the_next_ones() {
+        let long_text = "The notice period is three months. ".repeat(40);
+        let chunks = vec![
+            chunk("handbook", "long", &long_text, None),
+            chunk("contract", "short", "Overtime is paid at 125%.", None),
+        ];
+        let context = assemble(&chunks, 100);
+
+        assert_eq!(context.sources.len(), 1);
+        assert_eq!(uuids(&context.sources[0]), vec!["short"]);
+        assert!(context.tokens <= 100);
+        assert_eq!(context.dropped.len(), 1);
+        assert_eq!(context.dropped[0].document_chunk_uuid, "long");
+        assert_eq!(context.dropped[0].reason, DropReason::OverBudget);
+        // The kept source is numbered from 1
+        assert!(context.text.starts_with("[1] Document: contract.pdf"));
+    }
+
+    #[test]
+    fn numbers_the_sources_in_the_order_of_their_most_relevant_chunk() {
+        let chunks = vec![
+            part("c3", 69, DOCUMENT.len()),
+            chunk("handbook", "h1", "Overtime needs the approval of a manager.", None),
+            // Joins the source of c3, which keeps the first number
+            part("c2", 25, 69),
+        ];
+        let context = assemble(&chunks, 1000);
+
+        assert_eq!(context.sources.len(), 2);
+        assert_eq!(uuids(&context.sources[0]), vec!["c2", "c3"]);
+        assert_eq!(uuids(&context.sources[1]), vec!["h1"]);
+        assert!(context.text.starts_with("[1] Document: contract.pdf"));
+        assert!(context.text.contains("\n\n[2] Document: handbook.pdf"));
+    }
+}
@@ -0,0 +1,220 @@
+//! Chat sessions: conversations of a user stored in `document_library.chat_sessions` and
+//! `document_library.chat_messages`.
+//!
+//! A session is a list of turns, a question of the user followed by the answer of the
+//! assistant, each answer linked to its entry of the [`crate::ledger`]. A follow-up
+//! question such as "and for the second one?" cannot be searched as it is:
+//! [`standalone_question`] rewrites it with the previous turns into a question that can.
+//! The previous turns are also given to the chat model answering it, by
+//! [`history_messages`], from the newest one and within `CHAT_HISTORY_TOKENS` tokens.
+
+use std::env;
+
+use tokio_postgres::Client;
+use uuid::Uuid;
+
+use crate::chat::{ChatMessage, ChatModel};
+use crate::chunking::ChunkUnit;
+use crate::citations::strip_markers;
+use crate::Error;
+
+/// Longest title given to a session from its first question, in chars.
+const MAX_TITLE_CHARS: usize = 200;
+
+/// Instructions of the rewriting of a follow-up question.
+const STANDALONE_PROMPT: &str = "Rewrite the follow-up question of the user into a standalone question \
+that can be understood without the conversation, replacing the pronouns and references to earlier messages \
+by what they refer to. Keep the language of the follow-up question. If it is already standalone, return it \
+unchanged. Answer with the question only.";
+
+/// A question of a session and its answer.
+#[derive(Debug, Clone)]
+pub struct ChatTurn {
+    /// The question as asked.
+    pub question: String,
+    /// The question rewritten without the conversation for the search, `None` when it was
+    /// searched as asked.
+    pub standalone_question: Option<String>,
+    pub answer: String,
+    /// Entry of the answer in the query ledger, `None` if it could not be recorded.
+    pub query_ledger_uuid: Option<String>,
+}
+
+/// Create a session of the user `user_email` and return its uuid. Without a title, the
+/// session is named after its first question.
+pub async fn create_session(client: &Client, user_email: &str, title: Option<&str>) -> Result<String, Error> {
+    let chat_session_uuid = Uuid::new_v4().to_string();
+    client
+        .execute(
+            "INSERT INTO document_library.chat_sessions
+             (chat_session_uuid, session_title, creation_date, created_by, updated_date, updated_by)
+             VALUES ($1, $2, now(), $3, now(), $3)",
+            &[&chat_session_uuid, &title, &user_email],
+        )
+        .await?;
+    Ok(chat_session_uuid)
+}
+
+/// Turns of the session `chat_session_uuid` in their order, `None` if `user_email` has no
+/// such session.
+pub async fn session_turns(client: &Client, chat_session_uuid: &str, user_email: &str) -> Result<Option<Vec<ChatTurn>>, Error> {
+    let owned = client
+        .query_opt(
+            "SELECT 1 FROM document_library.chat_sessions WHERE chat_session_uuid = $1 AND created_by = $2",
+            &[&chat_session_uuid, &user_email],
+        )
+        .await?;
+    if owned.is_none() {
+        return Ok(None);
+    }
+
+    let rows = client
+        .query(
+            "SELECT message_role, message_content, standalone_question, query_ledger_uuid
+             FROM document_library.chat_messages
+             WHERE chat_session_uuid = $1
+             ORDER BY message_index",
+            &[&chat_session_uuid],
+        )
+        .await?;
+
+    // Each question is followed by its answer, a question left without one is skipped
+    let mut turns = Vec::new();
+    let mut question: Option<(String, Option<String>)> = None;
+    for row in rows {
+        let role: String = row.get("message_role");
+        let content: String = row.get("message_content");
+        match role.as_str() {
+            "user" => question = Some((content, row.get("standalone_question"))),
+            "assistant" => {
+                if let Some((question, standalone_question)) = question.take() {
+                    turns.push(ChatTurn {
+                        question,
+                        standalone_question,
+                        answer: content,
+                        query_ledger_uuid: row.get("query_ledger_uuid"),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Some(turns))
+}
+
+/// Append `turn` to the session `chat_session_uuid`, after its last message. The session is
+/// named after the question if it has no title yet.
+pub async fn append_turn(client: &Client, chat_session_uuid: &str, user_email: &str, turn: &ChatTurn) -> Result<(), Error> {
+    let title: String = turn.question.trim().chars().take(MAX_TITLE_CHARS).collect();
+
+    // Both messages are written with the session in one statement, a concurrent turn of the
+    // same session fails on the unique message_index rather than interleaving
+    let query = "WITH last AS (
+            SELECT COALESCE(MAX(message_index), 0) AS message_index
+            FROM document_library.chat_messages WHERE chat_session_uuid = $1
+        ), messages AS (
+            INSERT INTO document_library.chat_messages
+            (chat_message_uuid, chat_session_uuid, message_index, message_role, message_content, standalone_question,
+             query_ledger_uuid, creation_date, created_by, updated_date, updated_by)
+            SELECT $2, $1, last.message_index + 1, 'user', $4, $5, $7, now(), $8, now(), $8 FROM last
+            UNION ALL
+            SELECT $3, $1, last.message_index + 2, 'assistant', $6, NULL, $7, now(), $8, now(), $8 FROM last
+        )
+        UPDATE document_library.chat_sessions
+        SET session_title = COALESCE(session_title, $9), updated_date = now(), updated_by = $8
+        WHERE chat_session_uuid = $1";
+
+    client
+        .execute(
+            query,
+            &[
+                &chat_session_uuid,
+                &Uuid::new_v4().to_string(),
+                &Uuid::new_v4().to_string(),
+                &turn.question,
+                &turn.standalone_question,
+                &turn.answer,
+                &turn.query_ledger_uuid,
+                &user_email,
+                &title,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Read `CHAT_HISTORY_TOKENS`, the most tokens of previous turns given to the chat model.
+pub fn history_token_budget() -> Result<usize, Error> {
+    let budget = env::var("CHAT_HISTORY_TOKENS").expect("CHAT_HISTORY_TOKENS environment variable not set");
+    budget
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid CHAT_HISTORY_TOKENS '{}': {}", budget, e).into())
+}
+
+/// The latest turns as alternating user and assistant messages, in their order, holding at
+/// most `budget` tokens of the `cl100k_base` tokenizer. Turns are kept whole, from the
+/// newest one, and the citations of the answers are removed as their numbers were those of
+/// another context.
+pub fn history_messages(turns: &[ChatTurn], budget: usize) -> Vec<ChatMessage> {
+    let mut kept: Vec<(String, String)> = Vec::new();
+    let mut tokens = 0;
+    for turn in turns.iter().rev() {
+        let answer = strip_markers(&turn.answer);
+        let turn_tokens = ChunkUnit::Tokens.measure(&turn.question) + ChunkUnit::Tokens.measure(&answer);
+        if tokens + turn_tokens > budget {
+            break;
+        }
+        tokens += turn_tokens;
+        kept.push((turn.question.clone(), answer));
+    }
+
+    if kept.len() < turns.len() {
+        println!("Kept {} of {} previous turns within {} tokens", kept.len(), turns.len(), budget);
+    }
+    kept.into_iter()
+        .rev()
+        .flat_map(|(question, answer)| [ChatMessage::user(question), ChatMessage::assistant(answer)])
+        .collect()
+}
+
+/// `question` rewritten by `chat_model` into a question that can be searched without the
+/// conversation `history`. A first question, or an empty rewriting, is returned as asked.
+pub async fn standalone_question(chat_model: &dyn ChatModel, history: &[ChatMessage], question: &str) -> Result<String, Error> {
+    if history.is_empty() {
+        return Ok(question.to_string());
+    }
+
+    let conversation = history
+        .iter()
+        .map(|message| match message.role.as_str() {
+            "assistant" => format!("Assistant: {}", message.content),
+            _ => format!("User: {}", message.content),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let messages = [
+        ChatMessage::system(STANDALONE_PROMPT),
+        ChatMessage::user(format!("Conversation:\n{}\n\nFollow-up question: {}", conversation, question)),
+    ];
+
+    let rewritten = chat_model.complete(&messages).await?;
+    let rewritten = rewritten.content.trim().trim_matches('"').trim();
+    if rewritten.is_empty() {
+        return Ok(question.to_string());
+    }
+    Ok(rewritten.to_string())
+}
+
+/// Delete the session `chat_session_uuid` of `user_email` with its messages, and return
+/// whether it existed. The ledger entries of its answers are kept.
+pub async fn delete_session(client: &Client, chat_session_uuid: &str, user_email: &str) -> Result<bool, Error> {
+    let query = "WITH messages AS (
+            DELETE FROM document_library.chat_messages m
+            USING document_library.chat_sessions s
+            WHERE m.chat_session_uuid = s.chat_session_uuid AND s.chat_session_uuid = $1 AND s.created_by = $2
+        )
+        DELETE FROM document_library.chat_sessions WHERE chat_session_uuid = $1 AND created_by = $2";
+    let deleted = client.execute(query, &[&chat_session_uuid, &user_email]).await?;
+    Ok(deleted > 0)
+}
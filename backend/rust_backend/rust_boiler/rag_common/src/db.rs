@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::env;
+
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
+use native_tls::TlsConnector;
+use postgres_native_tls::MakeTlsConnector;
+use serde::Deserialize;
+use tokio::sync::OnceCell;
+
+use crate::secrets::get_secret_json;
+use crate::Error;
+
+/// A client borrowed from the pool. It derefs to a `tokio_postgres::Client` and goes
+/// back to the pool when dropped.
+pub type PooledClient = deadpool_postgres::Object;
+
+/// Maximum number of connections kept by one Lambda instance. An instance serves one
+/// event at a time, so this only bounds the queries a handler runs concurrently.
+const POOL_MAX_SIZE: usize = 4;
+
+/// Process-wide pool, created on the first call and reused across warm invocations.
+static POOL: OnceCell<Pool> = OnceCell::const_new();
+
+/// Content of the database secret (named by `DATABASE_CONECTION_STRING`).
+/// All the values are stored as strings in Secrets Manager.
+#[derive(Clone, Deserialize)]
+pub struct DbCredentials {
+    #[serde(rename = "DB_HOST")]
+    pub host: String,
+    #[serde(rename = "DB_PORT")]
+    pub port: String,
+    #[serde(rename = "DB_NAME")]
+    pub name: String,
+    #[serde(rename = "DB_USER")]
+    pub user: String,
+    #[serde(rename = "DB_PASSWORD")]
+    pub password: String,
+    /// The OpenAI key is stored in the same secret for the functions calling OpenAI.
+    #[serde(rename = "OPENAI_API_KEY", default)]
+    pub openai_api_key: Option<String>,
+}
+
+impl DbCredentials {
+    /// Load the credentials from the secret named by `DATABASE_CONECTION_STRING`.
+    pub async fn from_env() -> Result<Self, Error> {
+        let secret_name = env::var("DATABASE_CONECTION_STRING")
+            .expect("DATABASE_CONECTION_STRING environment variable not set");
+        get_secret_json(&secret_name).await
+    }
+
+    /// Value of the field `field` of the database secret, such as the key of a chat
+    /// model, `None` if the secret has no such field.
+    pub async fn secret_field(field: &str) -> Result<Option<String>, Error> {
+        let secret_name = env::var("DATABASE_CONECTION_STRING")
+            .expect("DATABASE_CONECTION_STRING environment variable not set");
+        let mut fields: HashMap<String, String> = get_secret_json(&secret_name).await?;
+        Ok(fields.remove(field))
+    }
+
+    fn pg_config(&self) -> Result<tokio_postgres::Config, Error> {
+        let port: u16 = self
+            .port
+            .parse()
+            .map_err(|e| format!("Invalid DB_PORT '{}': {}", self.port, e))?;
+
+        let mut config = tokio_postgres::Config::new();
+        config
+            .host(&self.host)
+            .port(port)
+            .user(&self.user)
+            .password(&self.password)
+            .dbname(&self.name);
+        Ok(config)
+    }
+}
+
+async fn create_pool() -> Result<Pool, Error> {
+    let credentials = DbCredentials::from_env().await?;
+
+    let tls_connector = TlsConnector::builder()
+        .danger_accept_invalid_certs(true) // Disable certificate validation for development
+        .build()?;
+    let tls = MakeTlsConnector::new(tls_connector);
+
+    // Verified recycling runs a trivial query before handing out an idle connection,
+    // so a connection closed by the server is dropped and replaced by a new one.
+    let manager_config = ManagerConfig {
+        recycling_method: RecyclingMethod::Verified,
+    };
+    let manager = Manager::from_config(credentials.pg_config()?, tls, manager_config);
+
+    Pool::builder(manager)
+        .max_size(POOL_MAX_SIZE)
+        .build()
+        .map_err(|e| format!("Failed to create database pool: {}", e).into())
+}
+
+/// Return the process-wide pool, creating it on the first call.
+pub async fn pool() -> Result<&'static Pool, Error> {
+    POOL.get_or_try_init(create_pool).await
+}
+
+/// Borrow a healthy connection from the pool, opening one if needed.
+pub async fn get_client() -> Result<PooledClient, Error> {
+    let client = pool()
+        .await?
+        .get()
+        .await
+        .map_err(|e| format!("Failed to connect to database: {}", e))?;
+    Ok(client)
+}
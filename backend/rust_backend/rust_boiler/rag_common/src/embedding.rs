@@ -0,0 +1,328 @@
+//! Embeddings of the chunks and of the questions.
+//!
+//! An [`Embedder`] turns texts into embeddings. Embedders are built by [`new_embedder`]
+//! from the backend, model and endpoint of a model of the [`crate::registry`], so that the
+//! ingestion and the search embed chunks and questions with the same model:
+//!
+//! - `ollama`: the Ollama `/api/embeddings` API at the endpoint, one text per call.
+//! - `openai`: the OpenAI `/v1/embeddings` API at the endpoint, with the OpenAI key of
+//!   the database secret, texts sent in batches.
+//! - `local`: a BERT sentence-embedding model run in process with candle, loaded from the
+//!   `config.json`, `tokenizer.json` and `model.safetensors` of the endpoint directory.
+//!   Only available when the crate is built with the `local-embedding` feature.
+//!
+//! A [`DistanceMetric`] tells how two embeddings are compared: the pgvector operator
+//! that orders the search, and how its distance turns into a similarity between 0 and 1
+//! that clients can show or cut off with a threshold.
+
+use async_trait::async_trait;
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::db::DbCredentials;
+use crate::Error;
+
+/// Largest number of texts sent in one OpenAI request, the API accepts up to 2048.
+const OPENAI_BATCH_SIZE: usize = 512;
+
+/// Turns texts into embeddings.
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    /// Name of the model, stored as the `embeder_type` of the embeddings.
+    fn name(&self) -> String;
+
+    /// One embedding per text, in the order of `texts`.
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Error>;
+
+    /// Embedding of a single text, such as a question.
+    async fn embed_one(&self, text: &str) -> Result<Vec<f32>, Error> {
+        self.embed(&[text.to_string()])
+            .await?
+            .pop()
+            .ok_or_else(|| format!("{} returned no embedding", self.name()).into())
+    }
+}
+
+/// Build the embedder of `model` served by `backend` (`ollama`, `openai` or `local`) at
+/// `endpoint`, the URL of the API or the directory of the local model.
+pub async fn new_embedder(backend: &str, model: &str, endpoint: Option<&str>) -> Result<Box<dyn Embedder>, Error> {
+    let endpoint = endpoint
+        .map(str::to_string)
+        .ok_or_else(|| format!("No endpoint for the {} embedding model {}", backend, model))?;
+    let model = model.to_string();
+
+    match backend.trim().to_lowercase().as_str() {
+        "ollama" => Ok(Box::new(OllamaEmbedder::new(endpoint, model))),
+        "openai" => {
+            let api_key = DbCredentials::from_env()
+                .await?
+                .openai_api_key
+                .ok_or("OPENAI_API_KEY is missing from the database secret")?;
+            Ok(Box::new(OpenAiEmbedder::new(endpoint, model, api_key)))
+        }
+        "local" => local_embedder(&endpoint, model),
+        _ => Err(format!("Invalid embedding backend '{}', expected ollama, openai or local", backend).into()),
+    }
+}
+
+#[cfg(feature = "local-embedding")]
+fn local_embedder(path: &str, model: String) -> Result<Box<dyn Embedder>, Error> {
+    Ok(Box::new(local::LocalEmbedder::load(std::path::Path::new(path), model)?))
+}
+
+#[cfg(not(feature = "local-embedding"))]
+fn local_embedder(_path: &str, _model: String) -> Result<Box<dyn Embedder>, Error> {
+    Err("The local embedding backend needs rag_common built with the local-embedding feature".into())
+}
+
+/// Embedding model served by Ollama.
+pub struct OllamaEmbedder {
+    client: HttpClient,
+    url: String,
+    model: String,
+}
+
+impl OllamaEmbedder {
+    pub fn new(url: String, model: String) -> Self {
+        OllamaEmbedder { client: HttpClient::new(), url, model }
+    }
+}
+
+#[async_trait]
+impl Embedder for OllamaEmbedder {
+    fn name(&self) -> String {
+        self.model.clone()
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Error> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in texts {
+            let body = json!({ "model": self.model, "prompt": text });
+            let response = self.client.post(&self.url).json(&body).send().await?;
+            if !response.status().is_success() {
+                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                return Err(format!("Ollama API error: {}", error_text).into());
+            }
+
+            let response: Value = response.json().await?;
+            let embedding = response["embedding"]
+                .as_array()
+                .ok_or_else(|| format!("Embedding not found in response: {}", response))?
+                .iter()
+                .map(|value| value.as_f64().map(|value| value as f32).ok_or("Invalid embedding value"))
+                .collect::<Result<Vec<f32>, _>>()?;
+            embeddings.push(embedding);
+        }
+        Ok(embeddings)
+    }
+}
+
+/// Embedding model of the OpenAI API.
+pub struct OpenAiEmbedder {
+    client: HttpClient,
+    url: String,
+    model: String,
+    api_key: String,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbeddingResponse {
+    data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+impl OpenAiEmbedder {
+    pub fn new(url: String, model: String, api_key: String) -> Self {
+        OpenAiEmbedder { client: HttpClient::new(), url, model, api_key }
+    }
+}
+
+#[async_trait]
+impl Embedder for OpenAiEmbedder {
+    fn name(&self) -> String {
+        self.model.clone()
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Error> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(OPENAI_BATCH_SIZE) {
+            let body = json!({ "model": self.model, "input": batch });
+            let response = self
+                .client
+                .post(&self.url)
+                .header("Authorization", format!("Bearer {}", self.api_key))
+                .json(&body)
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                return Err(format!("OpenAI API error {}: {}", status, error_text).into());
+            }
+
+            // Put the embeddings back in the order of the batch
+            let response: OpenAiEmbeddingResponse = response.json().await?;
+            let mut batch_embeddings = vec![None; batch.len()];
+            for item in response.data {
+                if let Some(embedding) = batch_embeddings.get_mut(item.index) {
+                    *embedding = Some(item.embedding);
+                }
+            }
+            let offset = embeddings.len();
+            for (index, embedding) in batch_embeddings.into_iter().enumerate() {
+                embeddings.push(embedding.ok_or_else(|| format!("No embedding for text {}", offset + index))?);
+            }
+        }
+        Ok(embeddings)
+    }
+}
+
+#[cfg(feature = "local-embedding")]
+mod local {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use candle_core::{Device, Tensor};
+    use candle_nn::VarBuilder;
+    use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+    use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+    use super::Embedder;
+    use crate::Error;
+
+    /// Longest input of BERT models, longer texts are truncated.
+    const MAX_TOKENS: usize = 512;
+
+    /// BERT sentence-embedding model run on the CPU: mean of the token embeddings,
+    /// normalised to unit length.
+    pub struct LocalEmbedder {
+        model: String,
+        inner: Arc<LocalModel>,
+    }
+
+    struct LocalModel {
+        bert: BertModel,
+        tokenizer: Tokenizer,
+    }
+
+    impl LocalEmbedder {
+        /// Load the model from the `config.json`, `tokenizer.json` and `model.safetensors`
+        /// of the directory `path`.
+        pub fn load(path: &Path, model: String) -> Result<Self, Error> {
+            let device = Device::Cpu;
+            let config: Config = serde_json::from_str(&std::fs::read_to_string(path.join("config.json"))?)?;
+            let mut tokenizer = Tokenizer::from_file(path.join("tokenizer.json"))?;
+            tokenizer.with_padding(Some(PaddingParams::default()));
+            tokenizer.with_truncation(Some(TruncationParams { max_length: MAX_TOKENS, ..Default::default() }))?;
+
+            // Safety: the weights file is not modified while the model is loaded
+            let weights = unsafe { VarBuilder::from_mmaped_safetensors(&[path.join("model.safetensors")], DTYPE, &device)? };
+            let bert = BertModel::load(weights, &config)?;
+            println!("Loaded local embedding model {} from {}", model, path.display());
+            Ok(LocalEmbedder { model, inner: Arc::new(LocalModel { bert, tokenizer }) })
+        }
+    }
+
+    impl LocalModel {
+        fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+            let encodings = self.tokenizer.encode_batch(texts, true)?;
+            let device = Device::Cpu;
+            let ids = encodings
+                .iter()
+                .map(|encoding| Tensor::new(encoding.get_ids(), &device))
+                .collect::<Result<Vec<_>, _>>()?;
+            let masks = encodings
+                .iter()
+                .map(|encoding| Tensor::new(encoding.get_attention_mask(), &device))
+                .collect::<Result<Vec<_>, _>>()?;
+            let token_ids = Tensor::stack(&ids, 0)?;
+            let attention_mask = Tensor::stack(&masks, 0)?;
+            let token_type_ids = token_ids.zeros_like()?;
+
+            // (texts, tokens, hidden) -> mean over the tokens that are not padding
+            let output = self.bert.forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
+            let mask = attention_mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+            let pooled = output.broadcast_mul(&mask)?.sum(1)?.broadcast_div(&mask.sum(1)?)?;
+            let normalized = pooled.broadcast_div(&pooled.sqr()?.sum_keepdim(1)?.sqrt()?)?;
+            Ok(normalized.to_vec2::<f32>()?)
+        }
+    }
+
+    #[async_trait]
+    impl Embedder for LocalEmbedder {
+        fn name(&self) -> String {
+            self.model.clone()
+        }
+
+        async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Error> {
+            if texts.is_empty() {
+                return Ok(Vec::new());
+            }
+            // Inference keeps the CPU busy, run it outside of the async workers
+            let inner = self.inner.clone();
+            let texts = texts.to_vec();
+            tokio::task::spawn_blocking(move || inner.embed(texts)).await?
+        }
+    }
+}
+
+/// How two embeddings are compared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceMetric {
+    /// Euclidean distance, `<->`.
+    L2,
+    /// Cosine distance, `<=>`, one minus the cosine similarity.
+    Cosine,
+    /// Negative inner product, `<#>`. Equal to the cosine for normalised embeddings.
+    InnerProduct,
+}
+
+impl DistanceMetric {
+    /// Parse `l2`, `cosine` or `inner_product`.
+    pub fn parse(metric: &str) -> Option<Self> {
+        match metric.trim().to_lowercase().as_str() {
+            "l2" | "euclidean" => Some(DistanceMetric::L2),
+            "cosine" => Some(DistanceMetric::Cosine),
+            "inner_product" | "ip" => Some(DistanceMetric::InnerProduct),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DistanceMetric::L2 => "l2",
+            DistanceMetric::Cosine => "cosine",
+            DistanceMetric::InnerProduct => "inner_product",
+        }
+    }
+
+    /// The pgvector operator, smaller is closer for all three.
+    pub fn operator(self) -> &'static str {
+        match self {
+            DistanceMetric::L2 => "<->",
+            DistanceMetric::Cosine => "<=>",
+            DistanceMetric::InnerProduct => "<#>",
+        }
+    }
+
+    /// SQL expression of the similarity, from 0 (unrelated) to 1 (identical), of the
+    /// `distance` SQL expression computed with [`DistanceMetric::operator`].
+    ///
+    /// - `l2`: `1 / (1 + distance)`.
+    /// - `cosine`: the cosine similarity, negative values counting as 0.
+    /// - `inner_product`: the inner product bounded to 0..1, the cosine for normalised embeddings.
+    pub fn similarity_sql(self, distance: &str) -> String {
+        match self {
+            DistanceMetric::L2 => format!("(1.0 / (1.0 + ({})))", distance),
+            DistanceMetric::Cosine => format!("GREATEST(0.0, 1.0 - ({}))", distance),
+            DistanceMetric::InnerProduct => format!("LEAST(1.0, GREATEST(0.0, -({})))", distance),
+        }
+    }
+}
@@ -0,0 +1,677 @@
+//! Document filters compiled to SQL with `$n` bind parameters.
+//!
+//! The handlers turn the request into a list of [`Filter`] and compile it with a
+//! [`QueryParams`]: the SQL text only ever contains placeholders, every value coming
+//! from the request is bound. The compiled conditions expect the documents table to
+//! be aliased `d`, and the chunks table `dc` for the chunk kind.
+//!
+//! Requests give the filters either as the legacy flat `document_filters` list, or as
+//! a nested [`FilterExpr`] in their `filter` field:
+//!
+//! ```json
+//! {"and": [
+//!     {"or": [
+//!         {"metadata_uuid": "<type>", "operator": "eq", "value": "A"},
+//!         {"metadata_uuid": "<type>", "operator": "eq", "value": "B"}
+//!     ]},
+//!     {"metadata_uuid": "<signature date>", "operator": "gte", "value": "2023-01-01"},
+//!     {"not": {"tags": {"any": ["archived"]}}}
+//! ]}
+//! ```
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Client;
+
+use crate::chunking::ChunkKind;
+
+/// Values bound to the `$n` placeholders of a query, in order.
+#[derive(Default)]
+pub struct QueryParams {
+    values: Vec<Box<dyn ToSql + Sync + Send>>,
+}
+
+impl QueryParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind `value` and return its placeholder (`$1`, `$2`...).
+    pub fn bind<T: ToSql + Sync + Send + 'static>(&mut self, value: T) -> String {
+        self.values.push(Box::new(value));
+        format!("${}", self.values.len())
+    }
+
+    /// The bound values, to pass to `client.query`.
+    pub fn as_refs(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.values
+            .iter()
+            .map(|value| value.as_ref() as &(dyn ToSql + Sync))
+            .collect()
+    }
+}
+
+/// Comparison operators accepted in the metadata filters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Neq,
+    Gt,
+    Lt,
+    Gte,
+    Lte,
+    Contains,
+    NotContains,
+}
+
+impl CompareOp {
+    /// Parse the operator names used by the front end (`eq`, `neq`, `gt`...).
+    pub fn parse(operator: &str) -> Option<Self> {
+        match operator {
+            "eq" => Some(CompareOp::Eq),
+            "neq" => Some(CompareOp::Neq),
+            "gt" => Some(CompareOp::Gt),
+            "lt" => Some(CompareOp::Lt),
+            "gte" => Some(CompareOp::Gte),
+            "lte" => Some(CompareOp::Lte),
+            "contains" => Some(CompareOp::Contains),
+            "not_contains" => Some(CompareOp::NotContains),
+            _ => None,
+        }
+    }
+
+    fn sql(self) -> &'static str {
+        match self {
+            CompareOp::Eq => "=",
+            CompareOp::Neq => "!=",
+            CompareOp::Gt => ">",
+            CompareOp::Lt => "<",
+            CompareOp::Gte => ">=",
+            CompareOp::Lte => "<=",
+            CompareOp::Contains => "ILIKE",
+            CompareOp::NotContains => "NOT ILIKE",
+        }
+    }
+
+    fn is_ordering(self) -> bool {
+        matches!(self, CompareOp::Gt | CompareOp::Lt | CompareOp::Gte | CompareOp::Lte)
+    }
+
+    fn is_pattern(self) -> bool {
+        matches!(self, CompareOp::Contains | CompareOp::NotContains)
+    }
+}
+
+/// The `metadata_type` of a metadata, which tells in which column its values are stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetadataType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Date,
+}
+
+impl MetadataType {
+    /// Parse the types stored in `metadatas.metadata_type`.
+    pub fn parse(metadata_type: &str) -> Option<Self> {
+        match metadata_type {
+            "STRING" => Some(MetadataType::String),
+            "INTEGER" => Some(MetadataType::Integer),
+            "NUMBER" => Some(MetadataType::Number),
+            "BOOLEAN" => Some(MetadataType::Boolean),
+            "DATE" => Some(MetadataType::Date),
+            _ => None,
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            MetadataType::String => "dm.metadata_value_string",
+            MetadataType::Integer => "dm.metadata_value_int",
+            MetadataType::Number => "dm.metadata_value_float",
+            MetadataType::Boolean => "dm.metadata_value_boolean",
+            MetadataType::Date => "dm.metadata_value_date",
+        }
+    }
+
+    /// Whether `op` makes sense for this type.
+    fn supports(self, op: CompareOp) -> bool {
+        match self {
+            MetadataType::String => !op.is_ordering(),
+            MetadataType::Boolean => matches!(op, CompareOp::Eq | CompareOp::Neq),
+            _ => !op.is_pattern(),
+        }
+    }
+
+    /// Read `value` as a value of this type. Numbers and booleans may also be given as
+    /// strings.
+    pub fn value(self, value: &Value) -> Option<MetadataValue> {
+        match self {
+            MetadataType::String => value.as_str().map(|s| MetadataValue::String(s.to_string())),
+            MetadataType::Integer => match value {
+                Value::Number(n) => n.as_i64().and_then(|n| i32::try_from(n).ok()),
+                Value::String(s) => s.trim().parse::<i32>().ok(),
+                _ => None,
+            }
+            .map(MetadataValue::Int),
+            MetadataType::Number => match value {
+                Value::Number(n) => n.as_f64(),
+                Value::String(s) => s.trim().parse::<f64>().ok(),
+                _ => None,
+            }
+            .map(MetadataValue::Float),
+            MetadataType::Boolean => match value {
+                Value::Bool(b) => Some(*b),
+                Value::String(s) => match s.to_lowercase().as_str() {
+                    "true" | "yes" | "1" => Some(true),
+                    "false" | "no" | "0" => Some(false),
+                    _ => None,
+                },
+                _ => None,
+            }
+            .map(MetadataValue::Boolean),
+            MetadataType::Date => value
+                .as_str()
+                .and_then(|s| NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").ok())
+                .map(MetadataValue::Date),
+        }
+    }
+}
+
+/// A metadata value, typed after the [`MetadataType`] of the metadata.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataValue {
+    String(String),
+    Int(i32),
+    Float(f64),
+    Boolean(bool),
+    Date(NaiveDate),
+}
+
+impl MetadataValue {
+    fn bind(&self, params: &mut QueryParams) -> String {
+        match self {
+            MetadataValue::String(s) => params.bind(s.clone()),
+            MetadataValue::Int(i) => params.bind(*i),
+            MetadataValue::Float(f) => params.bind(*f),
+            MetadataValue::Boolean(b) => params.bind(*b),
+            MetadataValue::Date(d) => params.bind(*d),
+        }
+    }
+}
+
+/// What the value of a metadata must satisfy.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataPredicate {
+    Compare(CompareOp, MetadataValue),
+    /// The value is one of the list.
+    In(Vec<MetadataValue>),
+    /// The value is between the two bounds, included.
+    Between(MetadataValue, MetadataValue),
+    /// The document has no value for the metadata.
+    IsNull,
+}
+
+impl MetadataPredicate {
+    /// Build the predicate of `operator` (`eq`... `not_contains`, `in`, `between`,
+    /// `is_null`) for a metadata of type `metadata_type`.
+    pub fn parse(metadata_type: MetadataType, operator: &str, value: &Value) -> Result<Self, String> {
+        let typed = |value: &Value| {
+            metadata_type
+                .value(value)
+                .ok_or_else(|| format!("Invalid value {} for a {:?} metadata", value, metadata_type))
+        };
+
+        match operator {
+            "is_null" => Ok(MetadataPredicate::IsNull),
+            "in" => {
+                let values = value
+                    .as_array()
+                    .ok_or("The value of an 'in' filter must be a list")?;
+                Ok(MetadataPredicate::In(values.iter().map(typed).collect::<Result<_, _>>()?))
+            }
+            "between" => {
+                if metadata_type == MetadataType::Boolean {
+                    return Err("'between' is not supported for a Boolean metadata".to_string());
+                }
+                match value.as_array().map(Vec::as_slice) {
+                    Some([low, high]) => Ok(MetadataPredicate::Between(typed(low)?, typed(high)?)),
+                    _ => Err("The value of a 'between' filter must be a list of two values".to_string()),
+                }
+            }
+            _ => {
+                let op = CompareOp::parse(operator).ok_or_else(|| format!("Unsupported operator: {}", operator))?;
+                if !metadata_type.supports(op) {
+                    return Err(format!("Operator {} is not supported for a {:?} metadata", operator, metadata_type));
+                }
+                Ok(MetadataPredicate::Compare(op, typed(value)?))
+            }
+        }
+    }
+}
+
+/// A condition on the documents (`d`) of a query, or on their chunks (`dc`) for
+/// [`Filter::ChunkKinds`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// `d.creation_date >= value`
+    CreatedFrom(DateTime<Utc>),
+    /// `d.creation_date <= value`
+    CreatedUntil(DateTime<Utc>),
+    /// The document has all the tags.
+    HasAllTags(Vec<String>),
+    /// The document has at least one of the tags.
+    HasAnyTag(Vec<String>),
+    /// A single document.
+    Document(String),
+    /// The chunk is of one of the kinds, chunks without a kind are text.
+    ChunkKinds(Vec<ChunkKind>),
+    /// The value of the metadata `metadata_uuid` of the document matches.
+    Metadata {
+        metadata_uuid: String,
+        metadata_type: MetadataType,
+        predicate: MetadataPredicate,
+    },
+    /// All the filters match (true when empty).
+    And(Vec<Filter>),
+    /// At least one of the filters matches (false when empty).
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    /// Build a metadata filter from the operator and the JSON value of a request.
+    pub fn metadata(metadata_uuid: &str, metadata_type: &str, operator: &str, value: &Value) -> Result<Self, String> {
+        let metadata_type = MetadataType::parse(metadata_type)
+            .ok_or_else(|| format!("Unsupported metadata type: {}", metadata_type))?;
+        Ok(Filter::Metadata {
+            metadata_uuid: metadata_uuid.to_string(),
+            metadata_type,
+            predicate: MetadataPredicate::parse(metadata_type, operator, value)?,
+        })
+    }
+
+    /// Compile the filter into a SQL condition, binding its values into `params`.
+    pub fn to_sql(&self, params: &mut QueryParams) -> String {
+        match self {
+            Filter::CreatedFrom(date) => format!("d.creation_date >= {}", params.bind(*date)),
+            Filter::CreatedUntil(date) => format!("d.creation_date <= {}", params.bind(*date)),
+            // COALESCE keeps the conditions true or false for documents without tags,
+            // so that a NOT around them does not drop those documents
+            Filter::HasAllTags(tags) => format!("COALESCE(d.tags, '{{}}') @> {}", params.bind(tags.clone())),
+            Filter::HasAnyTag(tags) => format!("COALESCE(d.tags, '{{}}') && {}", params.bind(tags.clone())),
+            Filter::Document(document_uuid) => {
+                format!("d.document_uuid = {}", params.bind(document_uuid.clone()))
+            }
+            Filter::ChunkKinds(kinds) => {
+                let kinds: Vec<String> = kinds.iter().map(|kind| kind.as_str().to_string()).collect();
+                format!("COALESCE(dc.chunck_kind, 'text') = ANY({})", params.bind(kinds))
+            }
+            Filter::Metadata { metadata_uuid, metadata_type, predicate } => {
+                let column = metadata_type.column();
+                let uuid_param = params.bind(metadata_uuid.clone());
+                let (exists, condition) = match predicate {
+                    MetadataPredicate::Compare(op, MetadataValue::String(s)) if op.is_pattern() => {
+                        ("EXISTS", format!("{} {} {} ESCAPE '\\'", column, op.sql(), params.bind(contains_pattern(s))))
+                    }
+                    MetadataPredicate::Compare(op, value) => {
+                        ("EXISTS", format!("{} {} {}", column, op.sql(), value.bind(params)))
+                    }
+                    MetadataPredicate::In(values) if values.is_empty() => ("EXISTS", "FALSE".to_string()),
+                    MetadataPredicate::In(values) => {
+                        let placeholders: Vec<String> = values.iter().map(|value| value.bind(params)).collect();
+                        ("EXISTS", format!("{} IN ({})", column, placeholders.join(", ")))
+                    }
+                    MetadataPredicate::Between(low, high) => {
+                        let low_param = low.bind(params);
+                        let high_param = high.bind(params);
+                        ("EXISTS", format!("{} BETWEEN {} AND {}", column, low_param, high_param))
+                    }
+                    MetadataPredicate::IsNull => ("NOT EXISTS", format!("{} IS NOT NULL", column)),
+                };
+                format!(
+                    "{} (SELECT 1 FROM document_library.document_metadatas dm WHERE dm.document_uuid = d.document_uuid AND dm.metadata_uuid = {} AND {})",
+                    exists, uuid_param, condition
+                )
+            }
+            Filter::And(filters) if filters.is_empty() => "TRUE".to_string(),
+            Filter::Or(filters) if filters.is_empty() => "FALSE".to_string(),
+            Filter::And(filters) => {
+                let conditions: Vec<String> = filters.iter().map(|filter| filter.to_sql(params)).collect();
+                format!("({})", conditions.join(" AND "))
+            }
+            Filter::Or(filters) => {
+                let conditions: Vec<String> = filters.iter().map(|filter| filter.to_sql(params)).collect();
+                format!("({})", conditions.join(" OR "))
+            }
+            Filter::Not(filter) => format!("NOT ({})", filter.to_sql(params)),
+        }
+    }
+}
+
+/// `ILIKE` pattern matching the values containing `s`: its `%`, `_` and `\` are escaped with
+/// the `ESCAPE '\'` of the condition so that they match themselves.
+fn contains_pattern(s: &str) -> String {
+    let mut pattern = String::with_capacity(s.len() + 2);
+    pattern.push('%');
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Compile `filters` into a `WHERE` clause joined with `AND`, or an empty string when
+/// there is no filter.
+pub fn where_clause(filters: &[Filter], params: &mut QueryParams) -> String {
+    if filters.is_empty() {
+        return String::new();
+    }
+    let conditions: Vec<String> = filters.iter().map(|filter| filter.to_sql(params)).collect();
+    format!("WHERE {}", conditions.join(" AND "))
+}
+
+/// Parse a date given in a request: RFC 3339, `YYYY-MM-DD HH:MM:SS` or `YYYY-MM-DD`.
+/// Dates without a time zone are taken as UTC.
+pub fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(date.and_utc());
+        }
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| format!("Invalid date: {}", value))
+}
+
+/// Tag predicates of a [`FilterExpr`]: `{"tags": {"any": ["a", "b"]}}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagPredicate {
+    /// The document has all the tags.
+    All(Vec<String>),
+    /// The document has at least one of the tags.
+    Any(Vec<String>),
+    /// The document has none of the tags.
+    None(Vec<String>),
+}
+
+/// The nested filter grammar of the `filter` field of the requests. The metadata are
+/// given by uuid; their types are looked up when the expression is resolved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FilterExpr {
+    And {
+        and: Vec<FilterExpr>,
+    },
+    Or {
+        or: Vec<FilterExpr>,
+    },
+    Not {
+        not: Box<FilterExpr>,
+    },
+    Tags {
+        tags: TagPredicate,
+    },
+    Metadata {
+        metadata_uuid: String,
+        operator: String,
+        #[serde(default)]
+        value: Value,
+    },
+}
+
+impl FilterExpr {
+    fn collect_metadata_uuids<'a>(&'a self, uuids: &mut HashSet<&'a str>) {
+        match self {
+            FilterExpr::And { and: exprs } | FilterExpr::Or { or: exprs } => {
+                exprs.iter().for_each(|expr| expr.collect_metadata_uuids(uuids))
+            }
+            FilterExpr::Not { not } => not.collect_metadata_uuids(uuids),
+            FilterExpr::Tags { .. } => {}
+            FilterExpr::Metadata { metadata_uuid, .. } => {
+                uuids.insert(metadata_uuid);
+            }
+        }
+    }
+
+    fn to_filter(&self, metadata_types: &HashMap<String, String>) -> Result<Filter, String> {
+        match self {
+            FilterExpr::And { and } => Ok(Filter::And(
+                and.iter().map(|expr| expr.to_filter(metadata_types)).collect::<Result<_, _>>()?,
+            )),
+            FilterExpr::Or { or } => Ok(Filter::Or(
+                or.iter().map(|expr| expr.to_filter(metadata_types)).collect::<Result<_, _>>()?,
+            )),
+            FilterExpr::Not { not } => Ok(Filter::Not(Box::new(not.to_filter(metadata_types)?))),
+            FilterExpr::Tags { tags } => Ok(match tags {
+                TagPredicate::All(tags) => Filter::HasAllTags(tags.clone()),
+                TagPredicate::Any(tags) => Filter::HasAnyTag(tags.clone()),
+                TagPredicate::None(tags) => Filter::Not(Box::new(Filter::HasAnyTag(tags.clone()))),
+            }),
+            FilterExpr::Metadata { metadata_uuid, operator, value } => {
+                let metadata_type = metadata_types
+                    .get(metadata_uuid)
+                    .ok_or_else(|| format!("Unknown metadata: {}", metadata_uuid))?;
+                Filter::metadata(metadata_uuid, metadata_type, operator, value)
+            }
+        }
+    }
+
+    /// The uuids of the metadata the expression filters on.
+    pub fn metadata_uuids(&self) -> HashSet<&str> {
+        let mut uuids = HashSet::new();
+        self.collect_metadata_uuids(&mut uuids);
+        uuids
+    }
+
+    /// Resolve the expression into a [`Filter`], looking up the types of all its
+    /// metadata in one query. Unknown metadata and invalid operators or values are
+    /// errors, to be answered with a 400.
+    pub async fn resolve(&self, client: &Client) -> Result<Filter, String> {
+        let uuids: Vec<String> = self.metadata_uuids().into_iter().map(str::to_string).collect();
+
+        let mut metadata_types = HashMap::new();
+        if !uuids.is_empty() {
+            let query = "SELECT metadata_uuid, metadata_type FROM document_library.metadatas WHERE metadata_uuid = ANY($1)";
+            let rows = client
+                .query(query, &[&uuids])
+                .await
+                .map_err(|e| format!("Failed to fetch the metadata types: {}", e))?;
+            for row in rows {
+                metadata_types.insert(row.get("metadata_uuid"), row.get("metadata_type"));
+            }
+        }
+
+        self.to_filter(&metadata_types)
+    }
+}
+
+/// A filter of the legacy `document_filters` list of the requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentFilter {
+    pub filter_type: String,
+    /// For a `metadata` filter, a JSON string `{"metadata_uuid", "operator", "value"}`.
+    pub filter_value: String,
+}
+
+impl DocumentFilter {
+    /// The uuid of the metadata of a well-formed `metadata` filter.
+    pub fn metadata_uuid(&self) -> Option<String> {
+        if self.filter_type != "metadata" {
+            return None;
+        }
+        let metadata_filter: Value = serde_json::from_str(&self.filter_value).ok()?;
+        metadata_filter["metadata_uuid"].as_str().map(str::to_string)
+    }
+}
+
+// Helper function to get metadata type from database
+async fn get_metadata_type(client: &Client, metadata_uuid: &str) -> String {
+    let query = "SELECT metadata_type FROM document_library.metadatas WHERE metadata_uuid = $1";
+
+    match client.query_one(query, &[&metadata_uuid]).await {
+        Ok(row) => {
+            let metadata_type: String = row.get("metadata_type");
+            println!("Retrieved metadata type for {}: {}", metadata_uuid, metadata_type);
+            metadata_type
+        }
+        Err(e) => {
+            eprintln!("Error fetching metadata type for {}: {}", metadata_uuid, e);
+            // Default to STRING if we can't determine the type
+            "STRING".to_string()
+        }
+    }
+}
+
+/// Turn the `metadata` entries of `document_filters` into [`Filter`]s, looking up the
+/// type of each metadata. Malformed or unsupported entries are skipped.
+pub async fn metadata_filters(client: &Client, document_filters: &[DocumentFilter]) -> Vec<Filter> {
+    let mut filters = Vec::new();
+
+    for filter in document_filters {
+        if filter.filter_type != "metadata" {
+            continue; // Skip unsupported filter types
+        }
+        let metadata_filter: Value = match serde_json::from_str(&filter.filter_value) {
+            Ok(value) => value,
+            Err(e) => {
+                println!("Warning: Invalid metadata filter {}: {}", filter.filter_value, e);
+                continue;
+            }
+        };
+        let (Some(metadata_uuid), Some(operator)) = (
+            metadata_filter["metadata_uuid"].as_str(),
+            metadata_filter["operator"].as_str(),
+        ) else {
+            continue;
+        };
+        let value = &metadata_filter["value"];
+
+        let metadata_type = get_metadata_type(client, metadata_uuid).await;
+        match Filter::metadata(metadata_uuid, &metadata_type, operator, value) {
+            Ok(filter) => filters.push(filter),
+            Err(e) => println!(
+                "Warning: Could not create filter condition for metadata {} with type {}, operator {}, and value {:?}: {}",
+                metadata_uuid, metadata_type, operator, value, e
+            ),
+        }
+    }
+
+    filters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn binds_the_values_in_order_of_their_placeholders() {
+        let filter = Filter::And(vec![
+            Filter::Document("doc-1".to_string()),
+            Filter::Or(vec![
+                Filter::HasAnyTag(vec!["hr".to_string()]),
+                Filter::metadata("meta-1", "NUMBER", "between", &json!([1, 5])).unwrap(),
+            ]),
+            Filter::metadata("meta-2", "STRING", "in", &json!(["a", "b"])).unwrap(),
+        ]);
+        let mut params = QueryParams::new();
+        let sql = filter.to_sql(&mut params);
+
+        assert_eq!(
+            sql,
+            "(d.document_uuid = $1 AND (COALESCE(d.tags, '{}') && $2 OR EXISTS (SELECT 1 FROM document_library.document_metadatas dm \
+             WHERE dm.document_uuid = d.document_uuid AND dm.metadata_uuid = $3 AND dm.metadata_value_float BETWEEN $4 AND $5)) \
+             AND EXISTS (SELECT 1 FROM document_library.document_metadatas dm WHERE dm.document_uuid = d.document_uuid \
+             AND dm.metadata_uuid = $6 AND dm.metadata_value_string IN ($7, $8)))"
+        );
+        assert_eq!(params.as_refs().len(), 8);
+    }
+
+    #[test]
+    fn numbering_goes_on_after_the_params_already_bound() {
+        let mut params = QueryParams::new();
+        params.bind("user".to_string());
+        let sql = where_clause(&[Filter::Document("doc-1".to_string()), Filter::ChunkKinds(vec![ChunkKind::Table])], &mut params);
+
+        assert_eq!(sql, "WHERE d.document_uuid = $2 AND COALESCE(dc.chunck_kind, 'text') = ANY($3)");
+        assert_eq!(params.as_refs().len(), 3);
+    }
+
+    #[test]
+    fn empty_and_is_true_and_empty_or_is_false() {
+        let mut params = QueryParams::new();
+
+        assert_eq!(Filter::And(Vec::new()).to_sql(&mut params), "TRUE");
+        assert_eq!(Filter::Or(Vec::new()).to_sql(&mut params), "FALSE");
+        assert_eq!(
+            Filter::Not(Box::new(Filter::Or(Vec::new()))).to_sql(&mut params),
+            "NOT (FALSE)"
+        );
+        assert!(params.as_refs().is_empty());
+    }
+
+    #[test]
+    fn not_wraps_the_condition_it_negates() {
+        let filter = Filter::Not(Box::new(Filter::And(vec![
+            Filter::HasAllTags(vec!["archived".to_string()]),
+            Filter::Not(Box::new(Filter::Document("doc-1".to_string()))),
+        ])));
+        let mut params = QueryParams::new();
+
+        assert_eq!(
+            filter.to_sql(&mut params),
+            "NOT ((COALESCE(d.tags, '{}') @> $1 AND NOT (d.document_uuid = $2)))"
+        );
+        assert_eq!(params.as_refs().len(), 2);
+    }
+
+    #[test]
+    fn is_null_is_a_not_exists_without_value() {
+        let filter = Filter::metadata("meta-1", "DATE", "is_null", &Value::Null).unwrap();
+        let mut params = QueryParams::new();
+
+        assert_eq!(
+            filter.to_sql(&mut params),
+            "NOT EXISTS (SELECT 1 FROM document_library.document_metadatas dm WHERE dm.document_uuid = d.document_uuid \
+             AND dm.metadata_uuid = $1 AND dm.metadata_value_date IS NOT NULL)"
+        );
+        assert_eq!(params.as_refs().len(), 1);
+    }
+
+    #[test]
+    fn contains_matches_the_wildcards_literally() {
+        let filter = Filter::metadata("meta-1", "STRING", "not_contains", &json!("50%_off")).unwrap();
+        let mut params = QueryParams::new();
+
+        assert_eq!(
+            filter.to_sql(&mut params),
+            "EXISTS (SELECT 1 FROM document_library.document_metadatas dm WHERE dm.document_uuid = d.document_uuid \
+             AND dm.metadata_uuid = $1 AND dm.metadata_value_string NOT ILIKE $2 ESCAPE '\\')"
+        );
+        assert_eq!(contains_pattern("50%_off"), "%50\\%\\_off%");
+        assert_eq!(contains_pattern("C:\\docs"), "%C:\\\\docs%");
+        assert_eq!(contains_pattern("préavis"), "%préavis%");
+    }
+
+    #[test]
+    fn operators_must_fit_the_metadata_type() {
+        assert!(Filter::metadata("meta-1", "STRING", "gt", &json!("a")).is_err());
+        assert!(Filter::metadata("meta-1", "BOOLEAN", "between", &json!([true, false])).is_err());
+        assert!(Filter::metadata("meta-1", "INTEGER", "in", &json!(1)).is_err());
+        assert!(Filter::metadata("meta-1", "INTEGER", "eq", &json!("42")).is_ok());
+    }
+}
@@ -0,0 +1,184 @@
+//! Query ledger: every search and answer recorded in `document_library.query_ledgers`.
+//!
+//! [`record`] writes a [`LedgerEntry`] with its chunks (`query_answer_chunks`), their
+//! documents (`query_answer_documents`) and the metadata its filters are on
+//! (`query_metadatas`) in one statement, so that an entry is never left without its
+//! chunks. An entry has a single answer, whose `query_answer_uuid` is the uuid of the entry.
+
+use std::time::Duration;
+
+use serde_json::json;
+use tokio_postgres::Client;
+use uuid::Uuid;
+
+use crate::chat::ChatUsage;
+use crate::citations::GroundedAnswer;
+use crate::filter::parse_timestamp;
+use crate::search::{Chunk, SearchRequest};
+use crate::Error;
+
+/// The API a ledger entry was recorded by, stored in `query_type`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryType {
+    /// Chunks searched by rust_get_chunks.
+    Search,
+    /// Answer of rust_openai_answer, from chunks given by the client.
+    Answer,
+    /// Search and answer of rust_ask.
+    Ask,
+}
+
+impl QueryType {
+    pub fn parse(query_type: &str) -> Option<Self> {
+        match query_type.trim().to_lowercase().as_str() {
+            "search" => Some(QueryType::Search),
+            "answer" => Some(QueryType::Answer),
+            "ask" => Some(QueryType::Ask),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QueryType::Search => "search",
+            QueryType::Answer => "answer",
+            QueryType::Ask => "ask",
+        }
+    }
+}
+
+/// A search or answer to record.
+#[derive(Debug, Clone)]
+pub struct LedgerEntry<'a> {
+    pub query_type: QueryType,
+    /// The question as asked.
+    pub question: &'a str,
+    /// Email of the caller, the `created_by` of the rows by which its history is listed.
+    pub user_email: &'a str,
+    /// Identifier of the caller in the security groups, recorded as its `user_uuid` when
+    /// it is a registered user.
+    pub user_identifier: &'a str,
+    pub search: Option<&'a SearchRequest>,
+    /// The question the search ran on when it differs from the question: a follow-up question
+    /// of a chat session rewritten without the conversation, or expanded with the synonyms by rust_ask.
+    pub search_query: Option<&'a str>,
+    pub embedding_model: Option<&'a str>,
+    pub chat_model: Option<&'a str>,
+    pub answer: Option<&'a GroundedAnswer>,
+    pub usage: Option<&'a ChatUsage>,
+    /// Time from the request to the response.
+    pub latency: Duration,
+    /// The chunks found, or given as the context of the answer, in their order.
+    pub chunks: &'a [Chunk],
+}
+
+/// Record `entry` and return the uuid of its ledger entry.
+pub async fn record(client: &Client, entry: &LedgerEntry<'_>) -> Result<String, Error> {
+    let query_ledger_uuid = Uuid::new_v4().to_string();
+
+    let tags = entry
+        .search
+        .and_then(|search| search.tags.as_ref())
+        .filter(|tags| !tags.is_empty())
+        .map(|tags| tags.join(","));
+    let document_date = |date: Option<&String>| {
+        date.and_then(|date| parse_timestamp(date).ok()).map(|timestamp| timestamp.date_naive())
+    };
+    let start_date = document_date(entry.search.and_then(|search| search.start_date.as_ref()));
+    let end_date = document_date(entry.search.and_then(|search| search.end_date.as_ref()));
+    let filters = entry.search.map(SearchRequest::to_json);
+    let citations = entry.answer.map(|answer| {
+        json!({ "citations": answer.citations, "rejected_citations": answer.rejected_citations })
+    });
+    let input_tokens = entry.usage.map(|usage| usage.input_tokens as i32);
+    let output_tokens = entry.usage.map(|usage| usage.output_tokens as i32);
+    let latency_ms = entry.latency.as_millis().min(i32::MAX as u128) as i32;
+
+    // The chunks given by the client of an answer were not scored by this search
+    let scored = entry.query_type != QueryType::Answer;
+    let chunk_rows: Vec<String> = entry.chunks.iter().map(|_| Uuid::new_v4().to_string()).collect();
+    let chunk_uuids: Vec<String> = entry.chunks.iter().map(|chunk| chunk.document_chunk_uuid.clone()).collect();
+    let chunk_ranks: Vec<i32> = (1..=entry.chunks.len() as i32).collect();
+    let similarities: Vec<Option<f64>> = entry.chunks.iter().map(|chunk| Some(chunk.similarity).filter(|_| scored)).collect();
+    let rerank_scores: Vec<Option<f64>> = entry.chunks.iter().map(|chunk| chunk.rerank_score).collect();
+
+    let mut document_uuids: Vec<String> = Vec::new();
+    for chunk in entry.chunks {
+        if !document_uuids.contains(&chunk.document_uuid) {
+            document_uuids.push(chunk.document_uuid.clone());
+        }
+    }
+    let document_rows: Vec<String> = document_uuids.iter().map(|_| Uuid::new_v4().to_string()).collect();
+    let metadata_uuids = entry.search.map(SearchRequest::metadata_uuids).unwrap_or_default();
+    let metadata_rows: Vec<String> = metadata_uuids.iter().map(|_| Uuid::new_v4().to_string()).collect();
+
+    // Unregistered users, unknown documents and unknown metadata are left out rather than
+    // breaking their foreign keys
+    let query = "WITH ledger AS (
+            INSERT INTO document_library.query_ledgers
+            (query_ledger_uuid, query_type, query_content, user_uuid, query_tags, query_start_document_date,
+             query_end_document_date, query_answer, search_query, query_filters, embedding_model_name, chat_model_name,
+             input_tokens, output_tokens, latency_ms, query_citations, creation_date, created_by, updated_date, updated_by)
+            VALUES ($1, $2, $3, (SELECT user_uuid FROM document_library.users WHERE user_uuid = $4), $5, $6,
+                    $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, now(), $17, now(), $17)
+            RETURNING query_ledger_uuid
+        ), chunks AS (
+            INSERT INTO document_library.query_answer_chunks
+            (query_answer_chunk_uuid, query_answer_uuid, query_ledger_uuid, chunk_uuid, chunk_rank, similarity, rerank_score,
+             creation_date, created_by, updated_date, updated_by)
+            SELECT c.row_uuid, ledger.query_ledger_uuid, ledger.query_ledger_uuid, c.chunk_uuid, c.chunk_rank, c.similarity,
+                   c.rerank_score, now(), $17, now(), $17
+            FROM ledger, unnest($18::text[], $19::text[], $20::int4[], $21::float8[], $22::float8[])
+                AS c(row_uuid, chunk_uuid, chunk_rank, similarity, rerank_score)
+        ), documents AS (
+            INSERT INTO document_library.query_answer_documents
+            (query_answer_document_uuid, query_answer_uuid, query_ledger_uuid, document_uuid, creation_date, created_by,
+             updated_date, updated_by)
+            SELECT d.row_uuid, ledger.query_ledger_uuid, ledger.query_ledger_uuid, d.document_uuid, now(), $17, now(), $17
+            FROM ledger, unnest($23::text[], $24::text[]) AS d(row_uuid, document_uuid)
+            WHERE EXISTS (SELECT 1 FROM document_library.documents WHERE document_uuid = d.document_uuid)
+        ), metadatas AS (
+            INSERT INTO document_library.query_metadatas
+            (query_metadata_uuid, query_ledger_uuid, metadata_uuid, creation_date, created_by, updated_date, updated_by)
+            SELECT m.row_uuid, ledger.query_ledger_uuid, m.metadata_uuid, now(), $17, now(), $17
+            FROM ledger, unnest($25::text[], $26::text[]) AS m(row_uuid, metadata_uuid)
+            WHERE EXISTS (SELECT 1 FROM document_library.metadatas WHERE metadata_uuid = m.metadata_uuid)
+        )
+        SELECT query_ledger_uuid FROM ledger";
+
+    client
+        .execute(
+            query,
+            &[
+                &query_ledger_uuid,
+                &entry.query_type.as_str(),
+                &entry.question,
+                &entry.user_identifier,
+                &tags,
+                &start_date,
+                &end_date,
+                &entry.answer.map(|answer| answer.answer.as_str()),
+                &entry.search_query,
+                &filters,
+                &entry.embedding_model,
+                &entry.chat_model,
+                &input_tokens,
+                &output_tokens,
+                &latency_ms,
+                &citations,
+                &entry.user_email,
+                &chunk_rows,
+                &chunk_uuids,
+                &chunk_ranks,
+                &similarities,
+                &rerank_scores,
+                &document_rows,
+                &document_uuids,
+                &metadata_rows,
+                &metadata_uuids,
+            ],
+        )
+        .await?;
+
+    Ok(query_ledger_uuid)
+}
@@ -0,0 +1,54 @@
+//! Shared building blocks for the RAG Lambda functions.
+//!
+//! - [`secrets`]: read secrets from AWS Secrets Manager (region taken from `REGION`).
+//! - [`db`]: typed database credentials and the PostgreSQL connection pool.
+//! - [`api`]: error responses and pagination shared by the HTTP handlers.
+//! - [`auth`]: Cognito ID token verification into an [`auth::AuthenticatedUser`].
+//! - [`filter`]: document filters compiled to SQL with bind parameters.
+//! - [`chunking`]: UTF-8-safe, sentence-aware text chunking for the ingestion.
+//! - [`embedding`]: embedding backends, distance metrics of the embeddings and their similarity.
+//! - [`search`]: vector, keyword or hybrid search of the chunks a user may read.
+//! - [`synonyms`]: expansion of the questions with the synonyms of the database.
+//! - [`chat`]: chat model backends generating the answers, at once or streamed.
+//! - [`citations`]: numbered sources of the answers and their `[n]` citations.
+//! - [`context`]: context of the answers within a token budget, without duplicated chunks
+//!   and with the neighbouring chunks merged.
+//! - [`answer`]: prompt of the answers grounded in retrieved chunks, and their body.
+//! - [`ledger`]: record of the searches and answers with their chunks and documents.
+//! - [`conversation`]: chat sessions, follow-up questions made standalone and the previous
+//!   turns given to the chat model.
+//! - [`registry`]: registry of the embedding models and their validated tables, and of
+//!   the chat models.
+//! - [`rerank`]: reranking of retrieved chunks by a cross-encoder or a chat model.
+//!
+//! The secrets, the pool, the Cognito `KeySet`, the embedders and the chat models are
+//! created lazily and kept in process-wide statics, so they are reused across warm
+//! invocations of a Lambda.
+//!
+//! Every function reads its configuration from the environment variables described
+//! in `documentation/code_rules/code_rules.md`; none of them has a default value.
+
+pub mod answer;
+pub mod api;
+pub mod auth;
+pub mod chat;
+pub mod citations;
+pub mod chunking;
+pub mod context;
+pub mod conversation;
+pub mod db;
+pub mod embedding;
+pub mod filter;
+pub mod ledger;
+pub mod registry;
+pub mod rerank;
+pub mod search;
+pub mod secrets;
+pub mod synonyms;
+
+#[cfg(test)]
+mod stub_server;
+
+/// Error type used by the library. It is the same boxed error as `lambda_http::Error`,
+/// so `?` works directly inside the handlers.
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
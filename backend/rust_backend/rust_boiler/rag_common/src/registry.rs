@@ -0,0 +1,288 @@
+//! Registry of the embedding and chat models.
+//!
+//! Each row of `document_library.embedding_models` describes one model: the backend and
+//! endpoint computing its embeddings, their dimension, the distance metric of its search
+//! and the table storing them. Searches choose a model by name, or use the default one,
+//! and the vectorisation embeds the chunks for every active model. A new embedder can be
+//! tried next to the current one by inserting a row, without redeploying.
+//!
+//! Embedding tables end up in the SQL of the queries, so a table is only accepted when
+//! its name is a plain identifier of a table of `document_library` with an `embedding`
+//! column.
+//!
+//! Each row of `document_library.chat_models` describes a chat model: its backend,
+//! endpoint, key and generation settings. A function uses the model named by its
+//! `CHAT_MODEL` environment variable, and a request may name another active one.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use tokio_postgres::{Client, Row};
+
+use crate::chat::{new_chat_model, ChatModel, ChatSettings};
+use crate::db::DbCredentials;
+use crate::embedding::{new_embedder, DistanceMetric, Embedder};
+use crate::Error;
+
+/// Models already built by this Lambda instance, by model name, with the row of the model
+/// they were built from.
+type ModelCache<C, M> = Mutex<HashMap<String, (C, M)>>;
+
+static EMBEDDERS: OnceLock<ModelCache<EmbeddingModel, Arc<dyn Embedder>>> = OnceLock::new();
+
+fn embedders() -> &'static ModelCache<EmbeddingModel, Arc<dyn Embedder>> {
+    EMBEDDERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+static CHAT_MODELS: OnceLock<ModelCache<ChatModelConfig, Arc<dyn ChatModel>>> = OnceLock::new();
+
+fn chat_models() -> &'static ModelCache<ChatModelConfig, Arc<dyn ChatModel>> {
+    CHAT_MODELS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+const MODEL_COLUMNS: &str = "embedding_model_name, embedding_backend, embedding_model, embedding_endpoint, \
+    embedding_dimension, distance_metric, embedding_table, is_default";
+
+/// An embedding model of the registry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddingModel {
+    /// Name chosen by the requests, stored as the `embeder_type` of the embeddings.
+    pub name: String,
+    /// `ollama`, `openai` or `local`.
+    pub backend: String,
+    /// Model of the backend, such as `nomic-embed-text`.
+    pub model: String,
+    /// URL of the API, or directory of a local model.
+    pub endpoint: Option<String>,
+    pub dimension: usize,
+    /// Metric of the search when the request does not choose one, the one of the ANN index.
+    pub metric: DistanceMetric,
+    /// Table of `document_library` storing the embeddings.
+    pub table: String,
+    pub is_default: bool,
+}
+
+impl EmbeddingModel {
+    /// The active model named `name`, `None` if there is no such model or it is inactive.
+    pub async fn by_name(client: &Client, name: &str) -> Result<Option<Self>, Error> {
+        let query = format!(
+            "SELECT {} FROM document_library.embedding_models WHERE embedding_model_name = $1 AND is_active",
+            MODEL_COLUMNS
+        );
+        match client.query_opt(&query, &[&name]).await? {
+            Some(row) => Ok(Some(Self::from_row(client, &row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The default model, used by the searches that do not name one.
+    pub async fn default_model(client: &Client) -> Result<Self, Error> {
+        let query = format!(
+            "SELECT {} FROM document_library.embedding_models WHERE is_default AND is_active",
+            MODEL_COLUMNS
+        );
+        let row = client
+            .query_opt(&query, &[])
+            .await?
+            .ok_or("No active default model in document_library.embedding_models")?;
+        Self::from_row(client, &row).await
+    }
+
+    /// The model named `name`, or the default one.
+    pub async fn select(client: &Client, name: Option<&str>) -> Result<Option<Self>, Error> {
+        match name {
+            Some(name) => Self::by_name(client, name).await,
+            None => Ok(Some(Self::default_model(client).await?)),
+        }
+    }
+
+    /// Every active model, the default one first.
+    pub async fn active(client: &Client) -> Result<Vec<Self>, Error> {
+        let query = format!(
+            "SELECT {} FROM document_library.embedding_models WHERE is_active ORDER BY is_default DESC, embedding_model_name",
+            MODEL_COLUMNS
+        );
+        let mut models = Vec::new();
+        for row in client.query(&query, &[]).await? {
+            models.push(Self::from_row(client, &row).await?);
+        }
+        Ok(models)
+    }
+
+    async fn from_row(client: &Client, row: &Row) -> Result<Self, Error> {
+        let name: String = row.get("embedding_model_name");
+        let metric: String = row.get("distance_metric");
+        let dimension: i32 = row.get("embedding_dimension");
+        let table: String = row.get("embedding_table");
+
+        let metric = DistanceMetric::parse(&metric)
+            .ok_or_else(|| format!("Invalid distance_metric '{}' of embedding model {}", metric, name))?;
+        let dimension = usize::try_from(dimension)
+            .ok()
+            .filter(|dimension| *dimension > 0)
+            .ok_or_else(|| format!("Invalid embedding_dimension {} of embedding model {}", dimension, name))?;
+        validate_table(client, &table)
+            .await
+            .map_err(|e| format!("Embedding model {}: {}", name, e))?;
+
+        Ok(EmbeddingModel {
+            name,
+            backend: row.get("embedding_backend"),
+            model: row.get("embedding_model"),
+            endpoint: row.get("embedding_endpoint"),
+            dimension,
+            metric,
+            table,
+            is_default: row.get("is_default"),
+        })
+    }
+
+    /// The embedder of the model, built on the first call and kept for the lifetime of
+    /// the Lambda instance, or until the row of the model changes.
+    pub async fn embedder(&self) -> Result<Arc<dyn Embedder>, Error> {
+        if let Some((_, embedder)) = embedders().lock().unwrap().get(&self.name).filter(|(model, _)| model == self) {
+            return Ok(embedder.clone());
+        }
+
+        let embedder: Arc<dyn Embedder> = Arc::from(new_embedder(&self.backend, &self.model, self.endpoint.as_deref()).await?);
+        embedders().lock().unwrap().insert(self.name.clone(), (self.clone(), embedder.clone()));
+        Ok(embedder)
+    }
+
+    /// Embed `texts` and check that the embeddings have the dimension of the model.
+    pub async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Error> {
+        let embeddings = self.embedder().await?.embed(texts).await?;
+        if let Some(embedding) = embeddings.iter().find(|embedding| embedding.len() != self.dimension) {
+            return Err(format!(
+                "Embedding model {} returned {} dimensions instead of {}",
+                self.name,
+                embedding.len(),
+                self.dimension
+            )
+            .into());
+        }
+        Ok(embeddings)
+    }
+
+    /// Embedding of a single text, such as a question.
+    pub async fn embed_one(&self, text: &str) -> Result<Vec<f32>, Error> {
+        self.embed(&[text.to_string()])
+            .await?
+            .pop()
+            .ok_or_else(|| format!("Embedding model {} returned no embedding", self.name).into())
+    }
+}
+
+/// Check that `table` is a plain identifier naming a table of `document_library` with an
+/// `embedding` column, so that it can be written in SQL.
+pub async fn validate_table(client: &Client, table: &str) -> Result<(), Error> {
+    let plain = !table.is_empty()
+        && table.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && !table.starts_with(|c: char| c.is_ascii_digit());
+    if !plain {
+        return Err(format!("Invalid embedding table name '{}'", table).into());
+    }
+
+    let query = "SELECT COUNT(*) FROM information_schema.columns
+        WHERE table_schema = 'document_library' AND table_name = $1 AND column_name = 'embedding'";
+    let count: i64 = client.query_one(query, &[&table]).await?.get(0);
+    if count == 0 {
+        return Err(format!("document_library.{} is not a table with an embedding column", table).into());
+    }
+    Ok(())
+}
+
+const CHAT_MODEL_COLUMNS: &str = "chat_model_name, chat_backend, chat_model, chat_endpoint, chat_api_key, temperature, max_tokens";
+
+/// A chat model of the registry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatModelConfig {
+    /// Name chosen by the requests and by `CHAT_MODEL`.
+    pub name: String,
+    /// `openai`, `azure`, `ollama` or `anthropic`.
+    pub backend: String,
+    /// URL of the chat API.
+    pub endpoint: String,
+    /// Field of the database secret holding the key, `None` for a server without key.
+    pub api_key_field: Option<String>,
+    /// Model and generation settings.
+    pub settings: ChatSettings,
+}
+
+impl ChatModelConfig {
+    /// The active chat model named `name`, `None` if there is no such model or it is inactive.
+    pub async fn by_name(client: &Client, name: &str) -> Result<Option<Self>, Error> {
+        let query = format!(
+            "SELECT {} FROM document_library.chat_models WHERE chat_model_name = $1 AND is_active",
+            CHAT_MODEL_COLUMNS
+        );
+        match client.query_opt(&query, &[&name]).await? {
+            Some(row) => Ok(Some(Self::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The chat model of the function, named by `CHAT_MODEL`.
+    pub async fn from_env(client: &Client) -> Result<Self, Error> {
+        let name = env::var("CHAT_MODEL").expect("CHAT_MODEL environment variable not set");
+        Self::by_name(client, &name)
+            .await?
+            .ok_or_else(|| format!("CHAT_MODEL '{}' is not an active model of document_library.chat_models", name).into())
+    }
+
+    /// The chat model named `name`, or the one of the function.
+    pub async fn select(client: &Client, name: Option<&str>) -> Result<Option<Self>, Error> {
+        match name {
+            Some(name) => Self::by_name(client, name).await,
+            None => Ok(Some(Self::from_env(client).await?)),
+        }
+    }
+
+    fn from_row(row: &Row) -> Result<Self, Error> {
+        let name: String = row.get("chat_model_name");
+        let temperature: f64 = row.get("temperature");
+        let max_tokens: i32 = row.get("max_tokens");
+
+        if !(0.0..=2.0).contains(&temperature) {
+            return Err(format!("Invalid temperature {} of chat model {}", temperature, name).into());
+        }
+        let max_tokens = u32::try_from(max_tokens)
+            .ok()
+            .filter(|max_tokens| *max_tokens > 0)
+            .ok_or_else(|| format!("Invalid max_tokens {} of chat model {}", max_tokens, name))?;
+
+        Ok(ChatModelConfig {
+            name,
+            backend: row.get("chat_backend"),
+            endpoint: row.get("chat_endpoint"),
+            api_key_field: row.get("chat_api_key"),
+            settings: ChatSettings {
+                model: row.get("chat_model"),
+                temperature,
+                max_tokens,
+            },
+        })
+    }
+
+    /// The chat model, built on the first call and kept for the lifetime of the Lambda
+    /// instance, or until the row of the model changes.
+    pub async fn chat_model(&self) -> Result<Arc<dyn ChatModel>, Error> {
+        if let Some((_, chat_model)) = chat_models().lock().unwrap().get(&self.name).filter(|(config, _)| config == self) {
+            return Ok(chat_model.clone());
+        }
+
+        let api_key = match &self.api_key_field {
+            Some(field) => Some(
+                DbCredentials::secret_field(field)
+                    .await?
+                    .ok_or_else(|| format!("{} of chat model {} is missing from the database secret", field, self.name))?,
+            ),
+            None => None,
+        };
+        let chat_model: Arc<dyn ChatModel> =
+            Arc::from(new_chat_model(&self.backend, self.settings.clone(), self.endpoint.clone(), api_key)?);
+        chat_models().lock().unwrap().insert(self.name.clone(), (self.clone(), chat_model.clone()));
+        Ok(chat_model)
+    }
+}
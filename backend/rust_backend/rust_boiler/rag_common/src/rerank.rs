@@ -0,0 +1,301 @@
+//! Reranking of retrieved chunks.
+//!
+//! The search overfetches candidates, then a [`Reranker`] scores each of them against the
+//! question and [`rerank`] keeps the best ones. Two backends are available, chosen by
+//! `RERANK_BACKEND`:
+//!
+//! - `http`: a cross-encoder behind the rerank API of Jina and Cohere, which Ollama does not
+//!   have: `llama-server --reranking` of llama.cpp serves it at `/v1/rerank`, as does
+//!   Infinity. `RERANK_API_URL` receives `{"model", "query", "documents"}` and answers
+//!   `{"results": [{"index", "relevance_score"}]}`.
+//! - `llm`: a chat model behind the Ollama `/api/chat` API at `RERANK_API_URL`, asked to
+//!   grade every passage from 0 to 10 in a single prompt. Grades are scaled to 0..1.
+//!
+//! Handlers only see the trait, so tests can plug a stub backend, or point
+//! `RERANK_API_URL` at a local stub server.
+
+use std::env;
+
+use async_trait::async_trait;
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::Error;
+
+/// Scores passages against a query, higher is more relevant.
+#[async_trait]
+pub trait Reranker: Send + Sync {
+    /// Name of the backend and model, for the logs and the responses.
+    fn name(&self) -> String;
+
+    /// One score per passage, in the order of `passages`.
+    async fn score(&self, query: &str, passages: &[String]) -> Result<Vec<f64>, Error>;
+}
+
+/// Build the reranker configured by `RERANK_BACKEND` (`http` or `llm`), `RERANK_API_URL`
+/// and `RERANK_MODEL`. A deployment without them fails the requests asking for reranking only.
+pub fn reranker_from_env() -> Result<Box<dyn Reranker>, Error> {
+    let var = |name: &str| env::var(name).map_err(|_| format!("{} environment variable not set", name));
+    let backend = var("RERANK_BACKEND")?;
+    let url = var("RERANK_API_URL")?;
+    let model = var("RERANK_MODEL")?;
+
+    match backend.trim().to_lowercase().as_str() {
+        "http" => Ok(Box::new(HttpReranker::new(url, model))),
+        "llm" => Ok(Box::new(LlmReranker::new(url, model))),
+        _ => Err(format!("Invalid RERANK_BACKEND '{}', expected http or llm", backend).into()),
+    }
+}
+
+/// Score `items` with `reranker` and keep the `top_k` best, with their score, best first.
+pub async fn rerank<T>(
+    reranker: &dyn Reranker,
+    query: &str,
+    items: Vec<T>,
+    text: impl Fn(&T) -> &str,
+    top_k: usize,
+) -> Result<Vec<(T, f64)>, Error> {
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let passages: Vec<String> = items.iter().map(|item| text(item).to_string()).collect();
+    let scores = reranker.score(query, &passages).await?;
+    if scores.len() != items.len() {
+        return Err(format!("{} returned {} scores for {} passages", reranker.name(), scores.len(), items.len()).into());
+    }
+
+    let mut scored: Vec<(T, f64)> = items.into_iter().zip(scores).collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored.truncate(top_k);
+    Ok(scored)
+}
+
+/// Cross-encoder behind a Jina/Cohere-compatible rerank endpoint.
+pub struct HttpReranker {
+    client: HttpClient,
+    url: String,
+    model: String,
+}
+
+#[derive(Deserialize)]
+struct RerankResponse {
+    results: Vec<RerankResult>,
+}
+
+#[derive(Deserialize)]
+struct RerankResult {
+    index: usize,
+    relevance_score: f64,
+}
+
+impl HttpReranker {
+    pub fn new(url: String, model: String) -> Self {
+        HttpReranker { client: HttpClient::new(), url, model }
+    }
+}
+
+#[async_trait]
+impl Reranker for HttpReranker {
+    fn name(&self) -> String {
+        format!("http:{}", self.model)
+    }
+
+    async fn score(&self, query: &str, passages: &[String]) -> Result<Vec<f64>, Error> {
+        let body = json!({
+            "model": self.model,
+            "query": query,
+            "documents": passages,
+        });
+        let response = self.client.post(&self.url).json(&body).send().await?.error_for_status()?;
+        let response: RerankResponse = response.json().await?;
+
+        // Results may come sorted by relevance, put them back in the order of the passages
+        let mut scores = vec![None; passages.len()];
+        for result in response.results {
+            if let Some(score) = scores.get_mut(result.index) {
+                *score = Some(result.relevance_score);
+            }
+        }
+        scores
+            .into_iter()
+            .enumerate()
+            .map(|(index, score)| score.ok_or_else(|| format!("No rerank score for passage {}", index).into()))
+            .collect()
+    }
+}
+
+/// Chat model asked to grade the passages, through the Ollama `/api/chat` API.
+pub struct LlmReranker {
+    client: HttpClient,
+    url: String,
+    model: String,
+}
+
+const LLM_RERANK_PROMPT: &str = "You grade how well passages answer a question. \
+For each passage give a grade from 0 (unrelated) to 10 (answers the question directly). \
+Answer only with JSON of the form {\"scores\": [grade of passage 1, grade of passage 2, ...]}, \
+with exactly one grade per passage, in order.";
+
+impl LlmReranker {
+    pub fn new(url: String, model: String) -> Self {
+        LlmReranker { client: HttpClient::new(), url, model }
+    }
+}
+
+#[async_trait]
+impl Reranker for LlmReranker {
+    fn name(&self) -> String {
+        format!("llm:{}", self.model)
+    }
+
+    async fn score(&self, query: &str, passages: &[String]) -> Result<Vec<f64>, Error> {
+        let passages_text: Vec<String> = passages
+            .iter()
+            .enumerate()
+            .map(|(index, passage)| format!("Passage {}:\n{}", index + 1, passage))
+            .collect();
+        let body = json!({
+            "model": self.model,
+            "stream": false,
+            "format": "json",
+            "options": { "temperature": 0 },
+            "messages": [
+                { "role": "system", "content": LLM_RERANK_PROMPT },
+                { "role": "user", "content": format!("Question: {}\n\n{}", query, passages_text.join("\n\n")) },
+            ],
+        });
+        let response: Value = self.client.post(&self.url).json(&body).send().await?.error_for_status()?.json().await?;
+
+        let content = response["message"]["content"]
+            .as_str()
+            .ok_or_else(|| format!("Unexpected chat response: {}", response))?;
+        let grades: Value = serde_json::from_str(content).map_err(|e| format!("Invalid grades '{}': {}", content, e))?;
+        grades["scores"]
+            .as_array()
+            .ok_or_else(|| format!("No scores in '{}'", content))?
+            .iter()
+            .map(|grade| {
+                grade
+                    .as_f64()
+                    .map(|grade| grade.clamp(0.0, 10.0) / 10.0)
+                    .ok_or_else(|| format!("Invalid grade {} in '{}'", grade, content).into())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub_server::StubServer;
+
+    /// Reranker giving the scores it was built with.
+    struct FixedScores(Vec<f64>);
+
+    #[async_trait]
+    impl Reranker for FixedScores {
+        fn name(&self) -> String {
+            "fixed".to_string()
+        }
+
+        async fn score(&self, _query: &str, _passages: &[String]) -> Result<Vec<f64>, Error> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn passages(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn keeps_the_best_items_first() {
+        let reranker = FixedScores(vec![0.2, 0.9, 0.5, 0.7]);
+        let items = vec!["a", "b", "c", "d"];
+
+        let scored = rerank(&reranker, "question", items, |item| item, 3).await.unwrap();
+
+        assert_eq!(scored, vec![("b", 0.9), ("d", 0.7), ("c", 0.5)]);
+    }
+
+    #[tokio::test]
+    async fn keeps_every_item_when_there_are_fewer_than_top_k() {
+        let reranker = FixedScores(vec![0.2, 0.9]);
+
+        let scored = rerank(&reranker, "question", vec!["a", "b"], |item| item, 10).await.unwrap();
+
+        assert_eq!(scored, vec![("b", 0.9), ("a", 0.2)]);
+        assert!(rerank(&reranker, "question", Vec::<&str>::new(), |item| item, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn needs_one_score_per_item() {
+        let reranker = FixedScores(vec![0.2, 0.9]);
+
+        let error = rerank(&reranker, "question", vec!["a", "b", "c"], |item| item, 3).await.unwrap_err();
+
+        assert_eq!(error.to_string(), "fixed returned 2 scores for 3 passages");
+    }
+
+    #[tokio::test]
+    async fn http_scores_come_back_in_the_order_of_the_passages() {
+        let server = StubServer::json(
+            200,
+            &json!({"results": [{"index": 1, "relevance_score": 0.9}, {"index": 0, "relevance_score": 0.1}]}),
+        );
+        let reranker = HttpReranker::new(format!("{}/v1/rerank", server.url), "bge-reranker-v2-m3".to_string());
+
+        let scores = reranker.score("notice period", &passages(&["overtime", "notice"])).await.unwrap();
+
+        assert_eq!(scores, vec![0.1, 0.9]);
+        let (path, request) = server.request();
+        assert_eq!(path, "/v1/rerank");
+        assert_eq!(
+            request,
+            json!({"model": "bge-reranker-v2-m3", "query": "notice period", "documents": ["overtime", "notice"]})
+        );
+    }
+
+    #[tokio::test]
+    async fn http_fails_without_a_score_for_every_passage() {
+        let server = StubServer::json(200, &json!({"results": [{"index": 0, "relevance_score": 0.1}]}));
+        let reranker = HttpReranker::new(server.url.clone(), "model".to_string());
+
+        let error = reranker.score("question", &passages(&["a", "b"])).await.unwrap_err();
+
+        assert_eq!(error.to_string(), "No rerank score for passage 1");
+    }
+
+    #[tokio::test]
+    async fn http_fails_on_an_error_status() {
+        let server = StubServer::json(404, &json!({"error": "not found"}));
+        let reranker = HttpReranker::new(server.url.clone(), "model".to_string());
+
+        assert!(reranker.score("question", &passages(&["a"])).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn llm_grades_are_scaled_to_0_1() {
+        let server = StubServer::json(200, &json!({"message": {"role": "assistant", "content": "{\"scores\": [3, 12, -1]}"}}));
+        let reranker = LlmReranker::new(format!("{}/api/chat", server.url), "mistral".to_string());
+
+        let scores = reranker.score("notice period", &passages(&["a", "b", "c"])).await.unwrap();
+
+        assert_eq!(scores, vec![0.3, 1.0, 0.0]);
+        let (path, request) = server.request();
+        assert_eq!(path, "/api/chat");
+        assert_eq!(request["model"], "mistral");
+        assert_eq!(request["messages"][1]["content"], "Question: notice period\n\nPassage 1:\na\n\nPassage 2:\nb\n\nPassage 3:\nc");
+    }
+
+    #[tokio::test]
+    async fn llm_fails_on_an_answer_without_grades() {
+        let server = StubServer::json(200, &json!({"message": {"role": "assistant", "content": "The first passage"}}));
+        let reranker = LlmReranker::new(server.url.clone(), "mistral".to_string());
+
+        let error = reranker.score("question", &passages(&["a"])).await.unwrap_err();
+
+        assert!(error.to_string().starts_with("Invalid grades 'The first passage'"), "{}", error);
+    }
+}
@@ -0,0 +1,777 @@
+//! Retrieval of the chunks a user may read for a question.
+//!
+//! [`SearchRequest`] holds the search parameters shared by the APIs returning chunks:
+//! filters, search mode, distance metric, reranking and embedding model. [`search`]
+//! validates them, embeds the question with the model of the registry, runs the vector,
+//! keyword or hybrid search through the security groups of the user and, when asked,
+//! lets the reranker keep the best of the overfetched candidates. [`readable_chunks`]
+//! reads again, through the same security groups, the chunks a client gives back by uuid.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio_postgres::Client;
+
+use crate::chunking::ChunkKind;
+use crate::embedding::DistanceMetric;
+use crate::filter::{metadata_filters, parse_timestamp, DocumentFilter, Filter, FilterExpr, QueryParams};
+use crate::registry::EmbeddingModel;
+use crate::rerank::{rerank, reranker_from_env};
+use crate::Error;
+
+/// Number of chunks returned when the request does not say.
+const DEFAULT_NUM_RESULTS: i64 = 20;
+/// Most chunks a search returns.
+const MAX_NUM_RESULTS: i64 = 200;
+/// Constant of reciprocal rank fusion, 60 as in the original paper: ranks far down a list still count.
+const RRF_K: i64 = 60;
+/// In hybrid mode each ranking proposes this many times `num_results` candidates to the fusion.
+const HYBRID_CANDIDATE_FACTOR: i64 = 4;
+/// With reranking, the search fetches this many times `num_results` candidates for the reranker.
+const RERANK_CANDIDATE_FACTOR: i64 = 4;
+/// Largest `hnsw.ef_search` pgvector accepts.
+const MAX_EF_SEARCH: i32 = 1000;
+
+/// Search parameters of a request, next to its question.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchRequest {
+    pub document_filters: Option<Vec<DocumentFilter>>,
+    /// Nested and/or/not filter expression.
+    pub filter: Option<FilterExpr>,
+    pub num_results: Option<i64>,
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub document_uuid: Option<String>,
+    /// Only chunks of these kinds: text, table.
+    pub chunk_kinds: Option<Vec<String>>,
+    /// vector (default), keyword or hybrid.
+    pub search_mode: Option<String>,
+    /// Rerank overfetched candidates with the reranker of `RERANK_BACKEND`.
+    pub rerank: Option<bool>,
+    /// l2, cosine or inner_product, the metric of the embedding model by default.
+    pub distance_metric: Option<String>,
+    /// Only chunks with a similarity of at least this value, from 0 to 1.
+    pub min_score: Option<f64>,
+    /// HNSW candidate list size for this search, higher is more accurate and slower. It is
+    /// raised to the number of candidates of the vector ranking when lower.
+    pub ef_search: Option<i32>,
+    /// Name of an active model of the registry, the default model otherwise.
+    pub embedding_model: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentMetadata {
+    pub metadata_uuid: String,
+    pub metadata_name: String,
+    /// Type of the metadata: STRING, INTEGER, NUMBER, BOOLEAN or DATE.
+    #[serde(default)]
+    pub metadata_type: String,
+    pub metadata_value_string: Option<String>,
+    pub metadata_value_int: Option<i32>,
+    pub metadata_value_float: Option<f64>,
+    pub metadata_value_boolean: Option<bool>,
+    pub metadata_value_date: Option<chrono::NaiveDate>,
+}
+
+impl DocumentMetadata {
+    /// The value of the metadata, whatever its type, "N/A" when it has none.
+    pub fn value_string(&self) -> String {
+        if let Some(s) = &self.metadata_value_string {
+            s.clone()
+        } else if let Some(i) = self.metadata_value_int {
+            i.to_string()
+        } else if let Some(f) = self.metadata_value_float {
+            f.to_string()
+        } else if let Some(b) = self.metadata_value_boolean {
+            b.to_string()
+        } else if let Some(d) = self.metadata_value_date {
+            d.format("%Y-%m-%d").to_string()
+        } else {
+            "N/A".to_string()
+        }
+    }
+}
+
+/// A retrieved chunk with its document, embedding and scores. The embedding and scores are
+/// left empty for a chunk given back by a client, as to the answer API, and read again by
+/// [`readable_chunks`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    pub document_uuid: String,
+    pub document_name: String,
+    pub document_location: String,
+    pub document_hash: String,
+    pub document_type: String,
+    pub document_status: String,
+    pub document_chunk_uuid: String,
+    pub embebed_text: String,
+    /// text, or table for the rows of a table with their header row.
+    #[serde(default = "text_chunk_kind")]
+    pub chunk_kind: String,
+    /// Pages of the chunk, only for paged formats such as PDF.
+    pub page_start: Option<i32>,
+    pub page_end: Option<i32>,
+    /// Char offsets of the chunk in the extracted text of the document.
+    pub char_start: Option<i32>,
+    pub char_end: Option<i32>,
+    pub document_embeding_uuid: String,
+    pub embeder_type: String,
+    /// Tokens and seconds spent embedding the chunk, unknown when the vectorisation did not
+    /// record them.
+    pub embedding_token: Option<i32>,
+    pub embedding_time: Option<f64>,
+    /// Distance between the embeddings of the question and of the chunk, for the distance metric.
+    /// 0 in keyword mode, where the question is not embedded.
+    #[serde(default)]
+    pub vector_distance: f64,
+    /// Similarity from 0 to 1 derived from the distance.
+    #[serde(default)]
+    pub similarity: f64,
+    /// Full-text rank of the chunk for the words of the question, 0 when none matches.
+    #[serde(default)]
+    pub keyword_score: f64,
+    /// Reciprocal rank fusion of both rankings, only in hybrid mode.
+    pub hybrid_score: Option<f64>,
+    /// Relevance given by the reranker, only when reranking.
+    pub rerank_score: Option<f64>,
+    pub document_metadata: Vec<DocumentMetadata>,
+}
+
+fn text_chunk_kind() -> String {
+    ChunkKind::Text.as_str().to_string()
+}
+
+impl SearchRequest {
+    /// The parameters given by the request, without those left out.
+    pub fn to_json(&self) -> Value {
+        let mut parameters = serde_json::to_value(self).unwrap_or_default();
+        if let Value::Object(fields) = &mut parameters {
+            fields.retain(|_, value| !value.is_null());
+        }
+        parameters
+    }
+
+    /// The uuids of the metadata the filters of the request filter on, sorted.
+    pub fn metadata_uuids(&self) -> Vec<String> {
+        let mut uuids: Vec<String> = self
+            .document_filters
+            .iter()
+            .flatten()
+            .filter_map(DocumentFilter::metadata_uuid)
+            .collect();
+        if let Some(filter) = &self.filter {
+            uuids.extend(filter.metadata_uuids().into_iter().map(str::to_string));
+        }
+        uuids.sort_unstable();
+        uuids.dedup();
+        uuids
+    }
+}
+
+/// Chunks found for a question, with how they were found.
+#[derive(Debug, Clone)]
+pub struct SearchResults {
+    pub chunks: Vec<Chunk>,
+    pub metric: DistanceMetric,
+    /// Name of the embedding model of the registry that embedded the question.
+    pub embedding_model: String,
+    pub timings: SearchTimings,
+}
+
+/// Time spent in each step of a search.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchTimings {
+    pub embedding: Duration,
+    pub retrieval: Duration,
+    /// `None` when the candidates were not reranked.
+    pub rerank: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub enum SearchError {
+    /// A parameter of the request is invalid or names an unknown model.
+    InvalidRequest(String),
+    /// The embedding model failed to embed the question.
+    Embedding(String),
+    /// The reranker failed to score the candidates.
+    Rerank(String),
+    /// A query of the search failed.
+    Database(Error),
+}
+
+impl SearchError {
+    /// HTTP status to answer with: 400 for a client problem, 502 for the reranker, 500 otherwise.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            SearchError::InvalidRequest(_) => 400,
+            SearchError::Rerank(_) => 502,
+            SearchError::Embedding(_) | SearchError::Database(_) => 500,
+        }
+    }
+
+    /// Standard error body of the APIs.
+    pub fn to_json(&self) -> Value {
+        json!({"statusAPI": "ERROR", "message": self.to_string()})
+    }
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::InvalidRequest(e) => write!(f, "{}", e),
+            SearchError::Embedding(_) => write!(f, "Failed to generate embedding"),
+            SearchError::Rerank(e) => write!(f, "Reranking failed: {}", e),
+            SearchError::Database(e) => write!(f, "Search failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+impl From<Error> for SearchError {
+    fn from(e: Error) -> Self {
+        SearchError::Database(e)
+    }
+}
+
+impl From<tokio_postgres::Error> for SearchError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        SearchError::Database(Box::new(e))
+    }
+}
+
+/// How chunks are retrieved: by embedding, by full-text search, or both fused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SearchMode {
+    Vector,
+    Keyword,
+    Hybrid,
+}
+
+impl SearchMode {
+    fn parse(mode: &str) -> Option<Self> {
+        match mode.trim().to_lowercase().as_str() {
+            "vector" => Some(SearchMode::Vector),
+            "keyword" => Some(SearchMode::Keyword),
+            "hybrid" => Some(SearchMode::Hybrid),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SearchMode::Vector => "vector",
+            SearchMode::Keyword => "keyword",
+            SearchMode::Hybrid => "hybrid",
+        }
+    }
+
+    fn candidate_factor(self) -> i64 {
+        match self {
+            SearchMode::Hybrid => HYBRID_CANDIDATE_FACTOR,
+            _ => 1,
+        }
+    }
+}
+
+/// How the chunks are searched and which ones are kept.
+struct SearchOptions {
+    mode: SearchMode,
+    metric: DistanceMetric,
+    min_score: Option<f64>,
+    ef_search: Option<i32>,
+    /// Embedding table of the model, validated by the registry.
+    embedding_table: String,
+}
+
+/// Identifier of the user of `email` in the security groups: its `user_uuid`, or the
+/// email itself when the user is not registered.
+pub async fn user_identifier(client: &Client, email: &str) -> Result<String, Error> {
+    let check_users_table = client
+        .query("SELECT EXISTS (SELECT FROM information_schema.tables WHERE table_schema = 'document_library' AND table_name = 'users')", &[])
+        .await?;
+    let users_table_exists: bool = check_users_table[0].get(0);
+    if !users_table_exists {
+        println!("Users table not found. Using email as identifier.");
+        return Ok(email.to_string());
+    }
+
+    let rows = client
+        .query("SELECT user_uuid FROM document_library.users WHERE sso_unique_id = $1", &[&email])
+        .await?;
+    match rows.first() {
+        Some(row) => Ok(row.get(0)),
+        None => {
+            println!("User with email {} not found. Using email as identifier instead.", email);
+            Ok(email.to_string())
+        }
+    }
+}
+
+/// Filters of the request, every value of them is bound as a query parameter.
+async fn request_filters(client: &Client, request: &SearchRequest) -> Result<Vec<Filter>, SearchError> {
+    let mut filters = Vec::new();
+
+    for (date, is_start) in [(&request.start_date, true), (&request.end_date, false)] {
+        if let Some(date) = date.as_deref().filter(|d| !d.is_empty()) {
+            let timestamp = parse_timestamp(date).map_err(SearchError::InvalidRequest)?;
+            filters.push(if is_start { Filter::CreatedFrom(timestamp) } else { Filter::CreatedUntil(timestamp) });
+        }
+    }
+
+    if let Some(tags) = request.tags.as_ref().filter(|tags| !tags.is_empty()) {
+        filters.push(Filter::HasAllTags(tags.clone()));
+    }
+
+    if let Some(document_filters) = &request.document_filters {
+        filters.extend(metadata_filters(client, document_filters).await);
+    }
+
+    if let Some(filter) = &request.filter {
+        filters.push(filter.resolve(client).await.map_err(SearchError::InvalidRequest)?);
+    }
+
+    if let Some(doc_uuid) = &request.document_uuid {
+        println!("Filtering by document UUID: {}", doc_uuid);
+        filters.push(Filter::Document(doc_uuid.clone()));
+    }
+
+    if let Some(chunk_kinds) = request.chunk_kinds.as_ref().filter(|kinds| !kinds.is_empty()) {
+        let kinds = chunk_kinds
+            .iter()
+            .map(|kind| {
+                ChunkKind::parse(kind)
+                    .ok_or_else(|| SearchError::InvalidRequest(format!("Invalid chunk kind '{}', expected text or table", kind)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        filters.push(Filter::ChunkKinds(kinds));
+    }
+
+    Ok(filters)
+}
+
+/// Search the chunks `user_identifier` may read for `question`, with the parameters of `request`.
+pub async fn search(
+    client: &mut Client,
+    user_identifier: &str,
+    question: &str,
+    request: &SearchRequest,
+) -> Result<SearchResults, SearchError> {
+    // The question is embedded with the model chosen by the request, the one of the ingestion
+    let embedding_model = EmbeddingModel::select(client, request.embedding_model.as_deref())
+        .await?
+        .ok_or_else(|| {
+            SearchError::InvalidRequest(format!(
+                "Unknown or inactive embedding_model '{}'",
+                request.embedding_model.as_deref().unwrap_or_default()
+            ))
+        })?;
+
+    let mode = match request.search_mode.as_deref() {
+        None => SearchMode::Vector,
+        Some(mode) => SearchMode::parse(mode).ok_or_else(|| {
+            SearchError::InvalidRequest(format!("Invalid search_mode '{}', expected vector, keyword or hybrid", mode))
+        })?,
+    };
+    let metric = match request.distance_metric.as_deref() {
+        None => embedding_model.metric,
+        Some(metric) => DistanceMetric::parse(metric).ok_or_else(|| {
+            SearchError::InvalidRequest(format!("Invalid distance_metric '{}', expected l2, cosine or inner_product", metric))
+        })?,
+    };
+    let num_results = request.num_results.unwrap_or(DEFAULT_NUM_RESULTS);
+    if !(1..=MAX_NUM_RESULTS).contains(&num_results) {
+        return Err(SearchError::InvalidRequest(format!(
+            "Invalid num_results {}, expected a value between 1 and {}",
+            num_results, MAX_NUM_RESULTS
+        )));
+    }
+    if let Some(min_score) = request.min_score.filter(|min_score| !(0.0..=1.0).contains(min_score)) {
+        return Err(SearchError::InvalidRequest(format!(
+            "Invalid min_score {}, expected a value between 0 and 1",
+            min_score
+        )));
+    }
+    if let Some(ef_search) = request.ef_search.filter(|ef_search| !(1..=MAX_EF_SEARCH).contains(ef_search)) {
+        return Err(SearchError::InvalidRequest(format!(
+            "Invalid ef_search {}, expected a value between 1 and {}",
+            ef_search, MAX_EF_SEARCH
+        )));
+    }
+    let filters = request_filters(client, request).await?;
+    let options = SearchOptions {
+        mode,
+        metric,
+        min_score: request.min_score,
+        ef_search: request.ef_search,
+        embedding_table: embedding_model.table.clone(),
+    };
+
+    if mode == SearchMode::Keyword && request.min_score.is_some() {
+        return Err(SearchError::InvalidRequest(
+            "min_score needs the embedding of the question, it is not allowed in keyword search_mode".to_string(),
+        ));
+    }
+
+    // The keyword search ranks the chunks by their words only, without embedding the question
+    let mut timings = SearchTimings::default();
+    let embedding: Option<Vec<f64>> = if mode == SearchMode::Keyword {
+        None
+    } else {
+        let started = Instant::now();
+        let embedding: Vec<f64> = match embedding_model.embed_one(question).await {
+            Ok(emb) => emb.into_iter().map(f64::from).collect(),
+            Err(e) => {
+                eprintln!("Error generating embedding with {}: {}", embedding_model.name, e);
+                return Err(SearchError::Embedding(e.to_string()));
+            }
+        };
+        timings.embedding = started.elapsed();
+        println!("Got {} embedding with {} dimensions", embedding_model.name, embedding.len());
+        Some(embedding)
+    };
+
+    // With reranking, fetch more candidates than asked and let the reranker keep the best ones
+    let rerank_results = request.rerank.unwrap_or(false);
+    let candidate_count = if rerank_results { num_results * RERANK_CANDIDATE_FACTOR } else { num_results };
+
+    let started = Instant::now();
+    let mut chunks = query_documents(question, client, embedding, candidate_count, &filters, user_identifier, &options).await?;
+    timings.retrieval = started.elapsed();
+
+    if rerank_results {
+        let started = Instant::now();
+        let reranker = reranker_from_env().map_err(|e| SearchError::Rerank(e.to_string()))?;
+        println!("Reranking {} candidates with {}", chunks.len(), reranker.name());
+        let scored = rerank(reranker.as_ref(), question, chunks, |chunk| chunk.embebed_text.as_str(), num_results as usize)
+            .await
+            .map_err(|e| SearchError::Rerank(e.to_string()))?;
+        chunks = scored
+            .into_iter()
+            .map(|(mut chunk, score)| {
+                chunk.rerank_score = Some(score);
+                chunk
+            })
+            .collect();
+        timings.rerank = Some(started.elapsed());
+    }
+
+    Ok(SearchResults { chunks, metric, embedding_model: embedding_model.name, timings })
+}
+
+/// Chunks similar to a question among those the user may read through its security groups.
+async fn query_documents(
+    question: &str,
+    client: &mut Client,
+    embedding: Option<Vec<f64>>,
+    n_results: i64,
+    filters: &[Filter],
+    user_uuid: &str,
+    options: &SearchOptions,
+) -> Result<Vec<Chunk>, Error> {
+    let mode = options.mode;
+    println!("Executing {} search with {} distance for question: {}", mode.as_str(), options.metric.as_str(), question);
+    println!("User UUID: {}", user_uuid);
+
+    let target_chunk_table = &options.embedding_table;
+
+    // First check if user exists in the security system
+    let sec_check_query = "SELECT COUNT(*) FROM document_library.user_security_groups WHERE user_uuid = $1";
+    let sec_check_result = client.query(sec_check_query, &[&user_uuid]).await?;
+    let user_group_count: i64 = sec_check_result[0].get(0);
+    println!("User belongs to {} security groups", user_group_count);
+
+    // Every value of the request is bound, in the order of the placeholders
+    let mut params = QueryParams::new();
+    let user_param = params.bind(user_uuid.to_string());
+    let mut conditions = vec!["emb.embedding IS NOT NULL".to_string(), readable_by("d.document_uuid", &user_param)];
+    conditions.extend(filters.iter().map(|filter| filter.to_sql(&mut params)));
+    let embedding_param = embedding.map(|embedding| params.bind(embedding));
+    let question_param = params.bind(question.to_string());
+    let limit_param = params.bind(n_results);
+    let candidates_param = params.bind(n_results * mode.candidate_factor());
+    let min_score_param = options.min_score.map(|min_score| params.bind(min_score));
+
+    // Embedded chunks the user may read, through the security groups, that pass the filters.
+    // The chunks waiting for their embedding have a row without one and are left out, and
+    // the security groups are checked with EXISTS to get a single row per chunk, whatever the
+    // number of groups sharing its document with the user
+    let from_clause = format!(
+        "FROM document_library.\"{}\" emb
+        INNER JOIN document_library.document_chunks dc ON dc.document_chunk_uuid = emb.document_chunk_uuid
+        INNER JOIN document_library.documents d ON d.document_uuid = dc.document_uuid
+        WHERE {}",
+        target_chunk_table,
+        conditions.join(" AND ")
+    );
+
+    // Any word of the question matches, ts_rank_cd normalised by the length of the chunk (1) and
+    // saturated to rank / (rank + 1) (32) scores the chunks like BM25
+    let vector_distance = match &embedding_param {
+        Some(embedding_param) => format!("emb.embedding {} {}::float8[]::vector", options.metric.operator(), embedding_param),
+        None => "0::float8".to_string(),
+    };
+    let similarity = match &embedding_param {
+        Some(_) => options.metric.similarity_sql(&vector_distance),
+        None => "0".to_string(),
+    };
+    let keyword_query = format!("replace(plainto_tsquery('simple', {})::text, ' & ', ' | ')::tsquery", question_param);
+    let keyword_score = format!("ts_rank_cd(dc.embebed_tsv, {}, 1 | 32)", keyword_query);
+
+    // The nearest chunks by embedding and the best chunks by keywords, each ranked from 1
+    let vector_hits = format!(
+        "vector_hits AS (
+            SELECT id, ROW_NUMBER() OVER (ORDER BY distance) AS rank
+            FROM (SELECT emb.document_embeding_uuid AS id, {} AS distance {} ORDER BY distance LIMIT {}) v
+        )",
+        vector_distance, from_clause, candidates_param
+    );
+    let keyword_hits = format!(
+        "keyword_hits AS (
+            SELECT id, ROW_NUMBER() OVER (ORDER BY score DESC) AS rank
+            FROM (SELECT emb.document_embeding_uuid AS id, {} AS score {} AND dc.embebed_tsv @@ {} ORDER BY score DESC LIMIT {}) k
+        )",
+        keyword_score, from_clause, keyword_query, candidates_param
+    );
+
+    // Reciprocal rank fusion: a chunk scores 1 / (k + rank) in each ranking it appears in
+    let hits = match mode {
+        SearchMode::Vector => format!(
+            "{}, hits AS (SELECT id, 1.0 / ({} + rank) AS fused_score FROM vector_hits)",
+            vector_hits, RRF_K
+        ),
+        SearchMode::Keyword => format!(
+            "{}, hits AS (SELECT id, 1.0 / ({} + rank) AS fused_score FROM keyword_hits)",
+            keyword_hits, RRF_K
+        ),
+        SearchMode::Hybrid => format!(
+            "{}, {}, hits AS (
+                SELECT COALESCE(v.id, k.id) AS id,
+                    COALESCE(1.0 / ({k} + v.rank), 0) + COALESCE(1.0 / ({k} + k.rank), 0) AS fused_score
+                FROM vector_hits v FULL OUTER JOIN keyword_hits k ON k.id = v.id
+            )",
+            vector_hits, keyword_hits, k = RRF_K
+        ),
+    };
+
+    let query = format!(
+        "WITH {}
+        SELECT
+        d.document_uuid as document_uuid,
+        d.document_name as document_name,
+        d.document_location as document_location,
+        d.document_hash as document_hash,
+        d.document_type as document_type,
+        d.document_status as document_status,
+        dc.document_chunk_uuid as document_chunk_uuid,
+        dc.embebed_text as embebed_text,
+        COALESCE(dc.chunck_kind, 'text') as chunk_kind,
+        dc.chunck_page_start as page_start,
+        dc.chunck_page_end as page_end,
+        dc.chunck_char_start as char_start,
+        dc.chunck_char_end as char_end,
+        emb.document_embeding_uuid as document_embeding_uuid,
+        emb.embeder_type as embeder_type,
+        emb.embedding_token as embedding_token,
+        emb.embedding_time as embedding_time,
+        {} as vector_distance,
+        {}::float8 as similarity,
+        {}::float8 as keyword_score,
+        hits.fused_score::float8 as fused_score
+        FROM hits
+        INNER JOIN document_library.\"{}\" emb ON emb.document_embeding_uuid = hits.id
+        INNER JOIN document_library.document_chunks dc ON dc.document_chunk_uuid = emb.document_chunk_uuid
+        INNER JOIN document_library.documents d ON d.document_uuid = dc.document_uuid
+        {}
+        ORDER BY hits.fused_score DESC
+        LIMIT {};",
+        hits,
+        vector_distance,
+        similarity,
+        keyword_score,
+        target_chunk_table,
+        min_score_param.map(|param| format!("WHERE {} >= {}", similarity, param)).unwrap_or_default(),
+        limit_param
+    );
+
+    println!("Debug Query: {}", query);
+
+    // An HNSW index scan returns at most hnsw.ef_search rows, 40 by default, before the
+    // security groups and filters: it is raised to the candidates of the vector ranking.
+    // The setting is local to the transaction of the search, pooled connections being
+    // reused by other requests, and a dropped transaction is rolled back with it
+    let vector_candidates = (n_results * mode.candidate_factor()).min(MAX_EF_SEARCH as i64) as i32;
+    let ef_search = options.ef_search.unwrap_or(0).max(vector_candidates);
+    let transaction = client.transaction().await?;
+    transaction.batch_execute(&format!("SET LOCAL hnsw.ef_search = {}", ef_search)).await?;
+    let rows = transaction.query(&query, &params.as_refs()).await?;
+    transaction.commit().await?;
+    println!("Query returned {} rows", rows.len());
+
+    let mut chunks: Vec<Chunk> = Vec::new();
+    for row in rows {
+        chunks.push(Chunk {
+            document_uuid: row.get("document_uuid"),
+            document_name: row.get("document_name"),
+            document_location: row.get("document_location"),
+            document_hash: row.get("document_hash"),
+            document_type: row.get("document_type"),
+            document_status: row.get("document_status"),
+            document_chunk_uuid: row.get("document_chunk_uuid"),
+            embebed_text: row.get("embebed_text"),
+            chunk_kind: row.get("chunk_kind"),
+            page_start: row.get("page_start"),
+            page_end: row.get("page_end"),
+            char_start: row.get("char_start"),
+            char_end: row.get("char_end"),
+            document_embeding_uuid: row.get("document_embeding_uuid"),
+            embeder_type: row.get("embeder_type"),
+            embedding_token: row.get("embedding_token"),
+            embedding_time: row.get("embedding_time"),
+            vector_distance: row.get("vector_distance"),
+            similarity: row.get("similarity"),
+            keyword_score: row.get("keyword_score"),
+            hybrid_score: if mode == SearchMode::Hybrid { Some(row.get("fused_score")) } else { None },
+            rerank_score: None,
+            document_metadata: Vec::new(),
+        });
+    }
+
+    attach_metadata(client, &mut chunks).await;
+    Ok(chunks)
+}
+
+/// Attach the metadata of their document to `chunks`, fetched for all the documents in a
+/// single query. Chunks are left without metadata if it cannot be fetched.
+async fn attach_metadata(client: &Client, chunks: &mut [Chunk]) {
+    let mut document_uuids: Vec<String> = Vec::new();
+    for chunk in chunks.iter() {
+        if !document_uuids.contains(&chunk.document_uuid) {
+            document_uuids.push(chunk.document_uuid.clone());
+        }
+    }
+    if document_uuids.is_empty() {
+        return;
+    }
+
+    let metadata_query = "SELECT
+            dm.document_uuid,
+            dm.metadata_uuid,
+            m.metadata_name,
+            m.metadata_type,
+            dm.metadata_value_string,
+            dm.metadata_value_int,
+            dm.metadata_value_float,
+            dm.metadata_value_boolean,
+            dm.metadata_value_date
+         FROM document_library.document_metadatas dm
+         JOIN document_library.metadatas m ON dm.metadata_uuid = m.metadata_uuid
+         WHERE dm.document_uuid = ANY($1)";
+
+    let metadata_rows = match client.query(metadata_query, &[&document_uuids]).await {
+        Ok(rows) => {
+            println!("Found {} metadata entries", rows.len());
+            rows
+        }
+        Err(e) => {
+            println!("Error fetching metadata: {}", e);
+            Vec::new()
+        }
+    };
+
+    let mut metadata_map: HashMap<String, Vec<DocumentMetadata>> = HashMap::new();
+    for row in metadata_rows {
+        let doc_uuid: String = row.get("document_uuid");
+        metadata_map.entry(doc_uuid).or_default().push(DocumentMetadata {
+            metadata_uuid: row.get("metadata_uuid"),
+            metadata_name: row.get("metadata_name"),
+            metadata_type: row.get("metadata_type"),
+            metadata_value_string: row.get("metadata_value_string"),
+            metadata_value_int: row.get("metadata_value_int"),
+            metadata_value_float: row.get("metadata_value_float"),
+            metadata_value_boolean: row.get("metadata_value_boolean"),
+            metadata_value_date: row.get("metadata_value_date"),
+        });
+    }
+
+    for chunk in chunks.iter_mut() {
+        if let Some(metadata_list) = metadata_map.get(&chunk.document_uuid) {
+            chunk.document_metadata = metadata_list.clone();
+        }
+    }
+}
+
+/// SQL condition that the user bound as `user_param` may read the document `document_uuid`
+/// through its security groups, as in the search.
+pub fn readable_by(document_uuid: &str, user_param: &str) -> String {
+    format!(
+        "EXISTS (
+            SELECT 1 FROM document_library.document_security_groups dsg
+            INNER JOIN document_library.security_groups sg ON sg.security_group_uuid = dsg.security_group_uuid
+            INNER JOIN document_library.user_security_groups usg ON usg.security_group_uuid = sg.security_group_uuid
+            INNER JOIN document_library.users u ON u.user_uuid = usg.user_uuid
+            WHERE dsg.document_uuid = {} AND u.user_uuid = {}
+        )",
+        document_uuid, user_param
+    )
+}
+
+/// The chunks of `chunk_uuids` that `user_identifier` may read, in the order of
+/// `chunk_uuids`, read from the database for the APIs given chunks by their client. Unknown
+/// chunks and those of documents the user may not read are left out. They come without
+/// embedding and scores, as they were not searched.
+pub async fn readable_chunks(client: &Client, user_identifier: &str, chunk_uuids: &[String]) -> Result<Vec<Chunk>, Error> {
+    if chunk_uuids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let query = format!(
+        "SELECT d.document_uuid, d.document_name, d.document_location, d.document_hash, d.document_type,
+                d.document_status, dc.document_chunk_uuid, dc.embebed_text, COALESCE(dc.chunck_kind, 'text') as chunk_kind,
+                dc.chunck_page_start as page_start, dc.chunck_page_end as page_end,
+                dc.chunck_char_start as char_start, dc.chunck_char_end as char_end
+         FROM document_library.document_chunks dc
+         INNER JOIN document_library.documents d ON d.document_uuid = dc.document_uuid
+         WHERE dc.document_chunk_uuid = ANY($2) AND {}",
+        readable_by("d.document_uuid", "$1")
+    );
+    let rows = client.query(query.as_str(), &[&user_identifier, &chunk_uuids]).await?;
+
+    let mut found: HashMap<String, Chunk> = HashMap::new();
+    for row in rows {
+        let chunk = Chunk {
+            document_uuid: row.get("document_uuid"),
+            document_name: row.get("document_name"),
+            document_location: row.get("document_location"),
+            document_hash: row.get("document_hash"),
+            document_type: row.get("document_type"),
+            document_status: row.get("document_status"),
+            document_chunk_uuid: row.get("document_chunk_uuid"),
+            embebed_text: row.get("embebed_text"),
+            chunk_kind: row.get("chunk_kind"),
+            page_start: row.get("page_start"),
+            page_end: row.get("page_end"),
+            char_start: row.get("char_start"),
+            char_end: row.get("char_end"),
+            document_embeding_uuid: String::new(),
+            embeder_type: String::new(),
+            embedding_token: None,
+            embedding_time: None,
+            vector_distance: 0.0,
+            similarity: 0.0,
+            keyword_score: 0.0,
+            hybrid_score: None,
+            rerank_score: None,
+            document_metadata: Vec::new(),
+        };
+        found.insert(chunk.document_chunk_uuid.clone(), chunk);
+    }
+
+    let mut chunks: Vec<Chunk> = chunk_uuids.iter().filter_map(|uuid| found.get(uuid).cloned()).collect();
+    attach_metadata(client, &mut chunks).await;
+    Ok(chunks)
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Mutex, OnceLock};
+
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_secretsmanager::Client as SecretManagerClient;
+use serde::de::DeserializeOwned;
+use tokio::sync::OnceCell;
+
+use crate::Error;
+
+static CLIENT: OnceCell<SecretManagerClient> = OnceCell::const_new();
+
+/// Secret values already fetched by this Lambda instance, by secret name.
+static CACHE: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+
+fn cache() -> &'static Mutex<HashMap<String, String>> {
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Return the Secrets Manager client for the region given by the `REGION` environment
+/// variable. It is built once and reused across warm invocations.
+pub async fn secrets_client() -> &'static SecretManagerClient {
+    CLIENT
+        .get_or_init(|| async {
+            let region_name = env::var("REGION").expect("REGION environment variable not set");
+            let config = aws_config::defaults(BehaviorVersion::latest())
+                .region(Region::new(region_name))
+                .load()
+                .await;
+            SecretManagerClient::new(&config)
+        })
+        .await
+}
+
+/// Fetch the string value of the secret `name`. The value is cached for the lifetime
+/// of the Lambda instance.
+pub async fn get_secret(name: &str) -> Result<String, Error> {
+    if let Some(secret) = cache().lock().unwrap().get(name) {
+        return Ok(secret.clone());
+    }
+
+    let resp = secrets_client()
+        .await
+        .get_secret_value()
+        .secret_id(name)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to retrieve secret '{}': {}", name, e))?;
+
+    match resp.secret_string() {
+        Some(secret) => {
+            cache().lock().unwrap().insert(name.to_string(), secret.to_string());
+            Ok(secret.to_string())
+        }
+        None => Err(format!("Secret '{}' has no string value", name).into()),
+    }
+}
+
+/// Fetch the secret `name` and deserialize its JSON content.
+pub async fn get_secret_json<T: DeserializeOwned>(name: &str) -> Result<T, Error> {
+    let secret = get_secret(name).await?;
+    serde_json::from_str(&secret)
+        .map_err(|e| format!("Failed to parse secret '{}': {}", name, e).into())
+}
@@ -0,0 +1,85 @@
+//! HTTP server on a local port for the tests of the HTTP backends.
+//!
+//! [`StubServer::start`] answers every request with the same status and body, written in
+//! the given parts so that the clients receive a streamed body in pieces. The bodies of the
+//! requests are kept for the tests to check what the backend sent.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+pub struct StubServer {
+    /// Base URL of the server, `http://127.0.0.1:<port>`.
+    pub url: String,
+    requests: Receiver<(String, Vec<u8>)>,
+}
+
+impl StubServer {
+    /// Answer every request with `status` and the concatenation of `parts`.
+    pub fn start(status: u16, content_type: &str, parts: Vec<&[u8]>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let head = format!(
+            "HTTP/1.1 {} Stub\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            status,
+            content_type,
+            parts.iter().map(|part| part.len()).sum::<usize>()
+        );
+        let parts: Vec<Vec<u8>> = parts.into_iter().map(<[u8]>::to_vec).collect();
+        let (sender, requests) = channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { return };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                // Request line and headers, then a body of content-length bytes
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+                if sender.send((path, body)).is_err() {
+                    return;
+                }
+
+                stream.write_all(head.as_bytes()).unwrap();
+                for part in &parts {
+                    stream.write_all(part).unwrap();
+                    stream.flush().unwrap();
+                    thread::sleep(Duration::from_millis(5));
+                }
+            }
+        });
+
+        StubServer { url, requests }
+    }
+
+    /// Answer every request with `status` and the JSON `body`.
+    pub fn json(status: u16, body: &Value) -> Self {
+        Self::start(status, "application/json", vec![body.to_string().as_bytes()])
+    }
+
+    /// Path and JSON body of the next request received.
+    pub fn request(&self) -> (String, Value) {
+        let (path, body) = self.requests.recv_timeout(Duration::from_secs(5)).expect("no request received");
+        (path, serde_json::from_slice(&body).unwrap())
+    }
+}
@@ -0,0 +1,48 @@
+//! Synonym expansion of the questions.
+//!
+//! Every whole word of a question that is the name of a synonym of
+//! `document_library.synonyms` is replaced by `<name> or <value>`, so that the search
+//! also finds the chunks using the other word.
+
+use regex::Regex;
+use tokio_postgres::Client;
+
+use crate::Error;
+
+/// A synonym of the database: `synonym_name` may also be written `synonym_value`.
+#[derive(Debug, Clone)]
+pub struct Synonym {
+    pub synonym_name: String,
+    pub synonym_value: String,
+}
+
+/// All the synonyms of the database.
+pub async fn synonyms(client: &Client) -> Result<Vec<Synonym>, Error> {
+    let rows = client
+        .query("SELECT synonym_name, synonym_value FROM document_library.synonyms", &[])
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| Synonym { synonym_name: row.get("synonym_name"), synonym_value: row.get("synonym_value") })
+        .collect())
+}
+
+/// `query` with the whole words that are names of `synonyms` followed by `or <value>`.
+pub fn expand(query: &str, synonyms: &[Synonym]) -> Result<String, Error> {
+    let mut processed_query = query.to_string();
+    for synonym in synonyms {
+        let re = Regex::new(&format!(r"\b{}\b", regex::escape(&synonym.synonym_name)))?;
+        if re.is_match(&processed_query) {
+            let replacement = format!("{} or {}", synonym.synonym_name, synonym.synonym_value);
+            processed_query = re.replace_all(&processed_query, replacement).to_string();
+        }
+    }
+    Ok(processed_query)
+}
+
+/// `query` expanded with the synonyms of the database.
+pub async fn expand_synonyms(client: &Client, query: &str) -> Result<String, Error> {
+    let synonyms = synonyms(client).await?;
+    println!("Total synonyms: {}", synonyms.len());
+    expand(query, &synonyms)
+}
@@ -0,0 +1,100 @@
+use lambda_http::{run, service_fn, Body, Error, Request, Response};
+use serde::Deserialize;
+use serde_json::json;
+
+use rag_common::auth::authenticate;
+use rag_common::conversation::create_session;
+use rag_common::db::get_client;
+
+// New chat session of the caller, to send follow-up questions to rust_ask or rust_openai_answer
+#[derive(Debug, Default, Deserialize)]
+struct AddChatSessionRequest {
+    // Title of the session, its first question by default
+    session_title: Option<String>,
+}
+
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    // Verify the Cognito token of the caller, who owns the session
+    let auth_header = event.headers().get("Authorization").and_then(|h| h.to_str().ok());
+    let user_email = match authenticate(auth_header).await {
+        Ok(user) => user.email,
+        Err(e) => {
+            println!("Failed to authenticate the request: {}", e);
+            return Ok(Response::builder()
+                .status(e.status_code())
+                .header("content-type", "application/json")
+                .body(e.to_json().to_string().into())
+                .map_err(Box::new)?);
+        }
+    };
+
+    // An empty body creates a session without title
+    let req: AddChatSessionRequest = if event.body().is_empty() {
+        AddChatSessionRequest::default()
+    } else {
+        match serde_json::from_slice(event.body().as_ref()) {
+            Ok(req) => req,
+            Err(e) => {
+                eprintln!("Failed to parse request body: {}", e);
+                return Ok(Response::builder()
+                    .status(400)
+                    .header("content-type", "application/json")
+                    .body(json!({"statusAPI": "ERROR", "message": "Invalid request body"}).to_string().into())
+                    .map_err(Box::new)?);
+            }
+        }
+    };
+    let session_title = req.session_title.as_deref().map(str::trim).filter(|title| !title.is_empty());
+
+    // Connect to the database
+    let client = match get_client().await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(Response::builder()
+                .status(500)
+                .header("content-type", "application/json")
+                .body(json!({"statusAPI": "ERROR", "message": "Database connection failed"}).to_string().into())
+                .map_err(Box::new)?);
+        }
+    };
+
+    let chat_session_uuid = match create_session(&client, &user_email, session_title).await {
+        Ok(chat_session_uuid) => chat_session_uuid,
+        Err(e) => {
+            eprintln!("Failed to create the chat session: {}", e);
+            return Ok(Response::builder()
+                .status(500)
+                .header("content-type", "application/json")
+                .body(json!({"statusAPI": "ERROR", "message": format!("Failed to create the chat session: {}", e)}).to_string().into())
+                .map_err(Box::new)?);
+        }
+    };
+    println!("Created chat session {} for {}", chat_session_uuid, user_email);
+
+    let response_body = json!({
+        "statusAPI": "OK",
+        "chat_session_uuid": chat_session_uuid,
+        "session_title": session_title
+    });
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(response_body.to_string().into())
+        .map_err(Box::new)?)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or(tracing_subscriber::EnvFilter::new("INFO")),
+        )
+        .with_target(false)
+        .without_time()
+        .init();
+
+    run(service_fn(function_handler)).await
+}
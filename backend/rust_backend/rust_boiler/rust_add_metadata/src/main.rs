@@ -1,19 +1,13 @@
 use lambda_http::{run, service_fn, Body, Error, Request, Response};
 use serde::{Serialize, Deserialize};
 use serde_json::json;
-use std::env;
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use uuid::Uuid;
-use jsonwebtokens_cognito::KeySet;
 use dotenv::dotenv;
+use std::time::Instant;
 
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_secretsmanager::Client as SecretManagerClient;
-
-use tokio_postgres::{Client, Error as PostgresError, Transaction, Statement};
-use postgres_native_tls::MakeTlsConnector;
-use native_tls::TlsConnector;
-use std::time::{Instant, Duration};
+use rag_common::auth::authenticate;
+use rag_common::db::get_client;
 
 // Request struct to deserialize incoming data
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,78 +20,12 @@ struct MetadataRequest {
 // Response struct for API
 #[derive(Debug, Serialize, Deserialize)]
 struct MetadataResponse {
-    statusAPI: String,
+    #[serde(rename = "statusAPI")]
+    status_api: String,
     message: String,
     metadata_uuid: String,
 }
 
-async fn show_secret(client: &SecretManagerClient, name: &str) -> Result<String, Error> {
-    let resp = client.get_secret_value().secret_id(name).send().await?;
-    match resp.secret_string() {
-        Some(secret) => Ok(secret.into()),
-        None => panic!("Error getting the secret: {:?}", name),
-    }
-}
-
-// Extract email from Authorization token
-async fn extract_email_from_token(event: &Request) -> Result<String, Error> {
-    let key_to_check = "Authorization";
-    if !event.headers().contains_key(key_to_check) {
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "No Authorization header found")));
-    }
-
-    let bearer_str = event.headers()[key_to_check].to_str()?;
-    if !bearer_str.starts_with("Bearer ") {
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid Authorization format")));
-    }
-
-    let token_str = &bearer_str[7..];
-    
-    // Get AWS region and user pool ID from environment variables or use defaults
-    let region = env::var("COGNITO_REGION").expect("COGNITO_REGION environment variable not set");
-    let user_pool_id = env::var("COGNITO_USER_POOL_ID").expect("COGNITO_USER_POOL_ID environment variable not set");
-    let client_id = env::var("COGNITO_CLIENT_ID").expect("COGNITO_CLIENT_ID environment variable not set");
-
-    let keyset = KeySet::new(&region, &user_pool_id);
-    
-    match keyset {
-        Ok(key_set) => {
-            let verifier = key_set.new_id_token_verifier(&[&client_id]).build()?;
-            let verification_result = key_set.verify(token_str, &verifier).await;
-            
-            match verification_result {
-                Ok(claims) => {
-                    // Extract email from claims
-                    match claims.get("email") {
-                        Some(email_value) => {
-                            if let Some(email_str) = email_value.as_str() {
-                                return Ok(email_str.to_string());
-                            }
-                        }
-                        None => {}
-                    }
-                    
-                    // Fallback if email not found in primary location
-                    if let Some(email) = claims.get("email").and_then(|v| v.as_str()) {
-                        Ok(email.to_string())
-                    } else {
-                        println!("No email found in token claims.");
-                        Ok("unknown@user.com".to_string())
-                    }
-                },
-                Err(e) => {
-                    println!("Token verification error: {}", e);
-                    Err(Box::new(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Invalid token")))
-                }
-            }
-        },
-        Err(e) => {
-            println!("KeySet creation error: {}", e);
-            Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Failed to create KeySet")))
-        }
-    }
-}
-
 async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     let function_start_time = Instant::now();
     println!("\n====================================================================================");
@@ -107,22 +35,23 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     println!("🔍 Request method: {:?}", event.method());
     println!("🔍 Request URI: {:?}", event.uri());
     
-    // Extract user email from token
-    println!("\n📝 [STEP 1] Extracting user email from authorization token...");
+    // Verify the Cognito token of the caller
+    println!("\n📝 [STEP 1] Verifying the authorization token...");
     let auth_start_time = Instant::now();
-    let user_email = match extract_email_from_token(&event).await {
-        Ok(email) => {
-            println!("✅ User email extracted successfully: {}", email);
-            println!("⏱️  Token extraction took: {:?}", auth_start_time.elapsed());
-            email
+    let auth_header = event.headers().get("Authorization").and_then(|h| h.to_str().ok());
+    let user_email = match authenticate(auth_header).await {
+        Ok(user) => {
+            println!("✅ User email extracted successfully: {}", user.email);
+            println!("⏱️  Token verification took: {:?}", auth_start_time.elapsed());
+            user.email
         },
         Err(e) => {
-            println!("❌ Failed to extract email from token: {}", e);
-            println!("⏱️  Failed token extraction attempt took: {:?}", auth_start_time.elapsed());
+            println!("❌ Failed to authenticate the request: {}", e);
+            println!("⏱️  Failed token verification took: {:?}", auth_start_time.elapsed());
             return Ok(Response::builder()
-                .status(401)
+                .status(e.status_code())
                 .header("content-type", "application/json")
-                .body(json!({"statusAPI": "ERROR", "message": format!("Unauthorized: Invalid token - {}", e)}).to_string().into())
+                .body(e.to_json().to_string().into())
                 .map_err(Box::new)?);
         }
     };
@@ -167,97 +96,27 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     }
     println!("✅ All required fields are present and valid");
 
-    // Load environment variables for database connection
-    println!("\n📝 [STEP 4] Loading database connection parameters from environment...");
-     // Decode secret
-    let db_secret_name = env::var("DATABASE_CONECTION_STRING").expect("DATABASE_CONECTION_STRING environment variable not set");
-    let db_secret = show_secret(&client_secret, &db_secret_name).await?;
-    let db_credentials: serde_json::Value = serde_json::from_str(&db_secret)?;
-    println!("Decoded secret");
-
-    let db_server = db_credentials["DB_HOST"].as_str().unwrap();
-    let database = db_credentials["DB_NAME"].as_str().unwrap();
-    let db_username = db_credentials["DB_USER"].as_str().unwrap();
-    let db_password = db_credentials["DB_PASSWORD"].as_str().unwrap();
-    let db_port = db_credentials["DB_PORT"].as_str().unwrap();
-    
-    println!("✅ Database connection parameters loaded:");
-    println!("   - DB_HOST: {}", db_server);
-    println!("   - DB_NAME: {}", database);
-    println!("   - DB_USER: {}", db_username);
-    println!("   - DB_PORT: {}", db_port);
-
-    // Configure TLS for database connection
-    println!("\n📝 [STEP 5] Setting up TLS connector for database connection...");
-    let tls_start_time = Instant::now();
-    let tls_connector_result = TlsConnector::builder()
-        .danger_accept_invalid_certs(true) // Disable certificate validation for development
-        .build();
-    
-    let tls = match tls_connector_result {
-        Ok(connector) => {
-            println!("✅ TLS connector built successfully");
-            MakeTlsConnector::new(connector)
-        },
-        Err(e) => {
-            println!("❌ Failed to build TLS connector: {}", e);
-            return Ok(Response::builder()
-                .status(500)
-                .header("content-type", "application/json")
-                .body(json!({"statusAPI": "ERROR", "message": format!("Failed to build TLS connector: {}", e)}).to_string().into())
-                .map_err(Box::new)?);
-        }
-    };
-    println!("⏱️  TLS setup took: {:?}", tls_start_time.elapsed());
-
     // Connect to the database
-    println!("\n📝 [STEP 6] Connecting to PostgreSQL database...");
+    println!("\n📝 [STEP 4] Connecting to PostgreSQL database...");
     let db_connect_start_time = Instant::now();
-    let connection_string = format!(
-        "host={} port={} user={} password={} dbname={} application_name=rust_add_metadata_lambda", 
-        db_server, db_port, db_username, db_password, database
-    );
-    println!("🔗 Using connection string: host={} port={} user={} dbname={} application_name=rust_add_metadata_lambda", 
-        db_server, db_port, db_username, database);
-    
-    let connection_result = tokio_postgres::connect(&connection_string, tls).await;
-    let (client, connection) = match connection_result {
-        Ok((client, connection)) => {
-            println!("✅ Successfully connected to database");
+    let mut client = match get_client().await {
+        Ok(client) => {
             println!("⏱️  Database connection took: {:?}", db_connect_start_time.elapsed());
-            (client, connection)
+            client
         },
         Err(e) => {
-            println!("❌ Failed to connect to database: {}", e);
+            println!("❌ {}", e);
             println!("⏱️  Failed connection attempt took: {:?}", db_connect_start_time.elapsed());
-            
-            // Try to get more details about the connection error
-            let error_details = match e.source() {
-                Some(source) => format!(" (Source: {})", source),
-                None => "".to_string()
-            };
-            
             return Ok(Response::builder()
                 .status(500)
                 .header("content-type", "application/json")
-                .body(json!({
-                    "statusAPI": "ERROR", 
-                    "message": format!("Database connection failed: {}{}", e, error_details)
-                }).to_string().into())
+                .body(json!({"statusAPI": "ERROR", "message": format!("Database connection failed: {}", e)}).to_string().into())
                 .map_err(Box::new)?);
         }
     };
-    
-    // Spawn a task to manage the connection
-    tokio::spawn(async move {
-        println!("📝 Background task: Managing database connection...");
-        if let Err(e) = connection.await {
-            eprintln!("❌ CONNECTION ERROR in background task: {}", e);
-        }
-    });
 
     // Begin transaction
-    println!("\n📝 [STEP 7] Starting database transaction...");
+    println!("\n📝 [STEP 5] Starting database transaction...");
     let transaction_start_time = Instant::now();
     let transaction_result = client.transaction().await;
     let transaction = match transaction_result {
@@ -280,12 +139,12 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     // Generate a new UUID and prepare timestamp for the metadata
     let metadata_uuid = Uuid::new_v4().to_string();
     let current_time = Utc::now();
-    println!("\n📝 [STEP 8] Preparing data for insertion...");
+    println!("\n📝 [STEP 6] Preparing data for insertion...");
     println!("🔑 Generated metadata UUID: {}", metadata_uuid);
     println!("⏰ Current timestamp: {}", current_time);
 
     // First check if schema and table exist
-    println!("\n📝 [STEP 9] Verifying database schema and table existence...");
+    println!("\n📝 [STEP 7] Verifying database schema and table existence...");
     let schema_check_query = "SELECT EXISTS (SELECT 1 FROM information_schema.schemata WHERE schema_name = 'document_library')";
     let table_check_query = "SELECT EXISTS (SELECT 1 FROM information_schema.tables WHERE table_schema = 'document_library' AND table_name = 'metadatas')";
     
@@ -325,7 +184,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     }
 
     // Insert new metadata record into the database
-    println!("\n📝 [STEP 10] Inserting metadata record into database...");
+    println!("\n📝 [STEP 8] Inserting metadata record into database...");
     let insert_start_time = Instant::now();
     let insert_query = "INSERT INTO document_library.metadatas 
         (metadata_uuid, metadata_name, metadata_description, metadata_type, creation_date, created_by) 
@@ -358,7 +217,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
             println!("⏱️  Insert operation took: {:?}", insert_start_time.elapsed());
             
             // Verify the insert worked by querying the database
-            println!("\n📝 [STEP 11] Verifying successful insertion with SELECT query...");
+            println!("\n📝 [STEP 9] Verifying successful insertion with SELECT query...");
             let verify_start_time = Instant::now();
             let verify_query = "SELECT * FROM document_library.metadatas WHERE metadata_uuid = $1";
             
@@ -384,7 +243,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
             }
             
             // SQL query to add missing metadata to document_metadatas
-            println!("\n📝 [STEP 12] Adding missing metadata entries to document_metadatas...");
+            println!("\n📝 [STEP 10] Adding missing metadata entries to document_metadatas...");
             let add_missing_start_time = Instant::now();
             
             // First check if document_metadatas table exists
@@ -472,7 +331,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                             // Try to get additional database error info
                             let pg_error = match e.as_db_error() {
                                 Some(db_err) => format!("PostgreSQL error: Code={}, Detail={}", 
-                                                       db_err.code(), db_err.detail().unwrap_or("No details")),
+                                                       db_err.code().code(), db_err.detail().unwrap_or("No details")),
                                 None => "Not a database error".to_string()
                             };
                             println!("⚠️ Error details: {}", pg_error);
@@ -488,7 +347,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
             println!("⏱️ Missing metadata operation took: {:?}", add_missing_start_time.elapsed());
             
             // Commit the transaction
-            println!("\n📝 [STEP 13] Committing the database transaction...");
+            println!("\n📝 [STEP 11] Committing the database transaction...");
             let commit_start_time = Instant::now();
             match transaction.commit().await {
                 Ok(_) => {
@@ -496,13 +355,13 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                     println!("⏱️ Commit operation took: {:?}", commit_start_time.elapsed());
                     
                     // Success response
+                    println!("\n✅ SUCCESS: Metadata added with UUID: {}", metadata_uuid);
                     let response_body = MetadataResponse {
-                        statusAPI: "OK".to_string(),
+                        status_api: "OK".to_string(),
                         message: "Metadata added successfully".to_string(),
-                        metadata_uuid: metadata_uuid,
+                        metadata_uuid,
                     };
                     
-                    println!("\n✅ SUCCESS: Metadata added with UUID: {}", metadata_uuid);
                     println!("⏱️ Total function execution time: {:?}", function_start_time.elapsed());
                     println!("====================================================================================");
                     println!("==================== FUNCTION HANDLER END (SUCCESS) ====================");
@@ -521,7 +380,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                     // Get detailed error information
                     let pg_error = match e.as_db_error() {
                         Some(db_err) => format!("PostgreSQL error: Code={}, Detail={}", 
-                                               db_err.code(), db_err.detail().unwrap_or("No details")),
+                                               db_err.code().code(), db_err.detail().unwrap_or("No details")),
                         None => "Not a database error".to_string()
                     };
                     println!("❌ Error details: {}", pg_error);
@@ -549,14 +408,14 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
             // Get detailed error information
             let pg_error = match e.as_db_error() {
                 Some(db_err) => {
-                    println!("❌ PostgreSQL error code: {}", db_err.code());
+                    println!("❌ PostgreSQL error code: {}", db_err.code().code());
                     println!("❌ PostgreSQL error message: {}", db_err.message());
                     println!("❌ PostgreSQL error detail: {}", db_err.detail().unwrap_or("No details"));
                     println!("❌ PostgreSQL error hint: {}", db_err.hint().unwrap_or("No hint"));
-                    println!("❌ PostgreSQL error position: {}", db_err.position().unwrap_or(-1));
+                    println!("❌ PostgreSQL error position: {:?}", db_err.position());
                     
                     format!("PostgreSQL error: Code={}, Message={}, Detail={}", 
-                           db_err.code(), db_err.message(), db_err.detail().unwrap_or("No details"))
+                           db_err.code().code(), db_err.message(), db_err.detail().unwrap_or("No details"))
                 },
                 None => {
                     println!("❌ Not a database error");
@@ -601,17 +460,6 @@ async fn main() -> Result<(), Error> {
         Err(e) => println!("ℹ️ No .env file loaded: {} (This is normal in production)", e),
     }
     
-    // Print some environment variables for debugging (hiding sensitive values)
-    println!("🔧 Environment configuration:");
-    println!("   - DB_HOST: {}", env::var("DB_HOST").expect("DB_HOST environment variable not set"));
-    println!("   - DB_NAME: {}", env::var("DB_NAME").expect("DB_NAME environment variable not set"));
-    println!("   - DB_USER: {}", env::var("DB_USER").expect("DB_USER environment variable not set"));
-    println!("   - DB_PORT: {}", env::var("DB_PORT").expect("DB_PORT environment variable not set"));
-    println!("   - DB_PASSWORD: {}", "********"); // Password value hidden for security
-    println!("   - COGNITO_REGION: {}", env::var("COGNITO_REGION").expect("COGNITO_REGION environment variable not set"));
-    println!("   - COGNITO_USER_POOL_ID: {}", env::var("COGNITO_USER_POOL_ID").expect("COGNITO_USER_POOL_ID environment variable not set"));
-    println!("   - COGNITO_CLIENT_ID: {}", env::var("COGNITO_CLIENT_ID").expect("COGNITO_CLIENT_ID environment variable not set"));
-    
     // Initialize the tracing subscriber for structured logging
     tracing_subscriber::fmt()
         .with_env_filter(
@@ -0,0 +1,212 @@
+use std::time::{Duration, Instant};
+
+use lambda_http::{run, service_fn, Body, Error, Request, Response};
+use serde::Deserialize;
+use serde_json::json;
+
+use rag_common::answer::{answer_json, chat_messages, ground};
+use rag_common::api::{auth_error_response, error_response, json_response};
+use rag_common::auth::authenticate;
+use rag_common::context::{assemble, context_token_budget};
+use rag_common::conversation::{
+    append_turn, history_messages, history_token_budget, session_turns, standalone_question, ChatTurn,
+};
+use rag_common::db::get_client;
+use rag_common::ledger::{record, LedgerEntry, QueryType};
+use rag_common::registry::ChatModelConfig;
+use rag_common::search::{search, user_identifier, SearchRequest};
+use rag_common::synonyms::expand_synonyms;
+
+// Question answered from the documents of the caller, retrieved server side
+#[derive(Deserialize)]
+struct AskRequest {
+    question: String,
+    // Chat model of document_library.chat_models answering, the one of CHAT_MODEL by default
+    chat_model: Option<String>,
+    // Search with the question expanded by the synonyms of the database, true by default
+    use_synonyms: Option<bool>,
+    // Chat session the question follows up on, whose previous turns are used to rewrite it for
+    // the search and given to the chat model. The question and its answer are added to it
+    chat_session_uuid: Option<String>,
+    // Filters, search mode, reranking and embedding model of the search
+    #[serde(flatten)]
+    search: SearchRequest,
+}
+
+// Durations of the timings of the response, in milliseconds
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    let started = Instant::now();
+
+    let req: AskRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(req) => req,
+        Err(e) => return error_response(400, format!("Invalid request format: {}", e)),
+    };
+    if req.question.trim().is_empty() {
+        return error_response(400, "Missing required question parameter");
+    }
+
+    // Verify the Cognito token of the caller
+    let auth_header = event.headers().get("Authorization").and_then(|h| h.to_str().ok());
+    let user_email = match authenticate(auth_header).await {
+        Ok(user) => user.email,
+        Err(e) => return auth_error_response(&e),
+    };
+
+    // Connect to the database
+    let mut client = match get_client().await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("{}", e);
+            return error_response(500, "Database connection failed");
+        }
+    };
+
+    // Chat model of the request, or the one of the function, checked before any search
+    let chat_model_config = match ChatModelConfig::select(&client, req.chat_model.as_deref()).await? {
+        Some(config) => config,
+        None => {
+            return error_response(
+                400,
+                format!("Unknown or inactive chat model '{}'", req.chat_model.as_deref().unwrap_or_default()),
+            )
+        }
+    };
+    let chat_model = chat_model_config.chat_model().await?;
+
+    // The user_uuid of the caller, or its email if it is not registered
+    let user_identifier = user_identifier(&client, &user_email).await?;
+    println!("User identifier: {}", user_identifier);
+
+    // Previous turns of the session, within the token budget of the history
+    let history = match &req.chat_session_uuid {
+        Some(chat_session_uuid) => match session_turns(&client, chat_session_uuid, &user_email).await? {
+            Some(turns) => history_messages(&turns, history_token_budget()?),
+            None => return error_response(404, format!("Chat session '{}' not found", chat_session_uuid)),
+        },
+        None => Vec::new(),
+    };
+
+    // A follow-up question is searched rewritten without the conversation
+    let rewrite_started = Instant::now();
+    let standalone_question = match standalone_question(chat_model.as_ref(), &history, &req.question).await {
+        Ok(question) => question,
+        Err(e) => {
+            eprintln!("Failed to rewrite the follow-up question with {}: {}", chat_model_config.name, e);
+            return error_response(502, format!("Question rewriting failed: {}", e));
+        }
+    };
+    let rewrite_time = Some(rewrite_started.elapsed()).filter(|_| !history.is_empty());
+
+    // The search runs on the standalone question expanded by the synonyms, the answer on the question
+    let synonyms_started = Instant::now();
+    let search_query = if req.use_synonyms.unwrap_or(true) {
+        expand_synonyms(&client, &standalone_question).await?
+    } else {
+        standalone_question.clone()
+    };
+    let synonyms_time = synonyms_started.elapsed();
+    println!("Search query: {}", search_query);
+
+    // Only the chunks the caller may read, through its security groups
+    let results = match search(&mut client, &user_identifier, &search_query, &req.search).await {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("Search failed: {}", e);
+            return json_response(e.status_code(), &e.to_json());
+        }
+    };
+    println!("Found {} chunks with {}", results.chunks.len(), results.embedding_model);
+
+    // The chunks that fit in the budget of the context, without duplicates and with their neighbours merged
+    let context = assemble(&results.chunks, context_token_budget()?);
+    println!("Context of {} sources in {} tokens", context.sources.len(), context.tokens);
+
+    // Ask the chat model for the answer, from the numbered sources and after the previous turns
+    let generation_started = Instant::now();
+    let answer = match chat_model.complete(&chat_messages(&req.question, &context.text, &history)).await {
+        Ok(answer) => answer,
+        Err(e) => {
+            eprintln!("Answer generation with {} failed: {}", chat_model_config.name, e);
+            return error_response(502, format!("Answer generation failed: {}", e));
+        }
+    };
+    let generation_time = generation_started.elapsed();
+
+    let grounded = ground(&answer.content, &context);
+
+    // Keep a trace of the question and its answer, without failing them if the ledger cannot be written
+    let entry = LedgerEntry {
+        query_type: QueryType::Ask,
+        question: &req.question,
+        user_email: &user_email,
+        user_identifier: &user_identifier,
+        search: Some(&req.search),
+        search_query: Some(&search_query),
+        embedding_model: Some(&results.embedding_model),
+        chat_model: Some(&chat_model_config.name),
+        answer: Some(&grounded),
+        usage: answer.usage.as_ref(),
+        latency: started.elapsed(),
+        chunks: &results.chunks,
+    };
+    let query_ledger_uuid = match record(&client, &entry).await {
+        Ok(uuid) => Some(uuid),
+        Err(e) => {
+            eprintln!("Failed to record the question in the query ledger: {}", e);
+            None
+        }
+    };
+
+    // Add the question and its answer to the session, which still answers if they cannot be saved
+    if let Some(chat_session_uuid) = &req.chat_session_uuid {
+        let turn = ChatTurn {
+            question: req.question.clone(),
+            standalone_question: Some(standalone_question.clone()).filter(|question| *question != req.question),
+            answer: grounded.answer.clone(),
+            query_ledger_uuid: query_ledger_uuid.clone(),
+        };
+        if let Err(e) = append_turn(&client, chat_session_uuid, &user_email, &turn).await {
+            eprintln!("Failed to add the question to the chat session {}: {}", chat_session_uuid, e);
+        }
+    }
+
+    let mut response_body = answer_json(&grounded, &context, &answer.usage, &chat_model_config.name);
+    response_body["statusAPI"] = json!("OK");
+    response_body["query_ledger_uuid"] = json!(query_ledger_uuid);
+    response_body["chat_session_uuid"] = json!(req.chat_session_uuid);
+    response_body["question"] = json!(req.question);
+    response_body["standalone_question"] = json!(standalone_question);
+    response_body["search_query"] = json!(search_query);
+    response_body["embedding_model"] = json!(results.embedding_model);
+    response_body["distance_metric"] = json!(results.metric.as_str());
+    response_body["chunk_count"] = json!(results.chunks.len());
+    response_body["timings"] = json!({
+        "rewrite_ms": rewrite_time.map(millis),
+        "synonyms_ms": millis(synonyms_time),
+        "embedding_ms": millis(results.timings.embedding),
+        "retrieval_ms": millis(results.timings.retrieval),
+        "rerank_ms": results.timings.rerank.map(millis),
+        "generation_ms": millis(generation_time),
+        "total_ms": millis(started.elapsed())
+    });
+
+    json_response(200, &response_body)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or(tracing_subscriber::EnvFilter::new("INFO")),
+        )
+        .with_target(false)
+        .without_time()
+        .init();
+
+    run(service_fn(function_handler)).await
+}
@@ -1,31 +1,29 @@
 use lambda_http::{run, service_fn, Body, Error, Request, Response};
 use serde::{Serialize, Deserialize};
-use serde_json::{json, Value};
-use std::env;
-use std::time::{Instant, Duration};
-use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::time::Instant;
 use uuid::Uuid;
-use jsonwebtokens_cognito::KeySet;
 
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_secretsmanager::Client as SecretManagerClient;
+use tokio_postgres::Client;
 
-use tokio_postgres::{Client, Error as PostgresError};
-use postgres_native_tls::MakeTlsConnector;
-use native_tls::TlsConnector;
+use rag_common::auth::authenticate;
+use rag_common::chat::{ChatMessage, ChatModel};
+use rag_common::db::get_client;
+use rag_common::registry::ChatModelConfig;
 
 // Request struct to deserialize incoming data
 #[derive(Debug, Serialize, Deserialize)]
 struct ComputeMetadataRequest {
     metadata_uuid: Option<String>,
     document_uuid: Option<String>,
-    llm_provider: Option<String>,  // Add this field to select between "openai" and "ollama"
+    chat_model: Option<String>,  // Chat model of document_library.chat_models, the one of CHAT_MODEL by default
 }
 
 // Response structs for API
 #[derive(Debug, Serialize, Deserialize)]
 struct ComputeMetadataResponse {
-    statusAPI: String,
+    #[serde(rename = "statusAPI")]
+    status_api: String,
     message: String,
     results: Vec<MetadataResult>,
 }
@@ -41,182 +39,13 @@ struct MetadataResult {
     processing_time: f64,
 }
 
-// OllamaRequest struct for sending requests to Ollama server
-#[derive(Debug, Serialize)]
-struct OllamaRequest {
-    model: String,
-    messages: Vec<OllamaMessage>,
-}
-
-#[derive(Debug, Serialize)]
-struct OllamaMessage {
-    role: String,
-    content: String,
-}
-
-// OllamaResponse struct for parsing Ollama server responses
-#[derive(Debug, Deserialize)]
-struct OllamaResponse {
-    model: String,
-    message: OllamaResponseMessage,
-}
-
-#[derive(Debug, Deserialize)]
-struct OllamaResponseMessage {
-    role: String,
-    content: String,
-}
-
-// OpenAI request struct for sending requests to OpenAI API
-#[derive(Debug, Serialize)]
-struct OpenAIRequest {
-    model: String,
-    messages: Vec<OpenAIMessage>,
-    temperature: f32,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct OpenAIMessage {
-    role: String,
-    content: String,
-}
-
-// OpenAI response structs for parsing OpenAI API responses
-#[derive(Debug, Deserialize)]
-struct OpenAIResponse {
-    id: String,
-    object: String,
-    created: u64,
-    model: String,
-    choices: Vec<OpenAIChoice>,
-}
-
-#[derive(Debug, Deserialize)]
-struct OpenAIChoice {
-    index: u32,
-    message: OpenAIMessage,
-    finish_reason: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct OpenAIUsage {
-    prompt_tokens: u32,
-    completion_tokens: u32,
-    total_tokens: u32,
-}
-
-async fn show_secret(client: &SecretManagerClient, name: &str) -> Result<String, Error> {
-    let resp = client.get_secret_value().secret_id(name).send().await?;
-    match resp.secret_string() {
-        Some(secret) => Ok(secret.into()),
-        None => panic!("Error getting the secret: {:?}", name),
-    }
-}
-
-// Extract email from Authorization token
-async fn extract_email_from_token(event: &Request) -> Result<String, Error> {
-    let key_to_check = "Authorization";
-    if !event.headers().contains_key(key_to_check) {
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "No Authorization header found")));
-    }
-
-    let bearer_str = event.headers()[key_to_check].to_str()?;
-    if (!bearer_str.starts_with("Bearer ")) {
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid Authorization format")));
-    }
-
-    let token_str = &bearer_str[7..];
-    
-    // Get AWS region and user pool ID from environment variables or use defaults
-    let region = env::var("COGNITO_REGION").expect("COGNITO_REGION environment variable not set");
-    let user_pool_id = env::var("COGNITO_USER_POOL_ID").expect("COGNITO_USER_POOL_ID environment variable not set");
-    let client_id = env::var("COGNITO_CLIENT_ID").expect("COGNITO_CLIENT_ID environment variable not set");
-
-    let keyset = KeySet::new(&region, &user_pool_id);
-    
-    match keyset {
-        Ok(key_set) => {
-            let verifier = key_set.new_id_token_verifier(&[&client_id]).build()?;
-            let verification_result = key_set.verify(token_str, &verifier).await;
-            
-            match verification_result {
-                Ok(claims) => {
-                    // Extract email from claims
-                    match claims.get("email") {
-                        Some(email_value) => {
-                            if let Some(email_str) = email_value.as_str() {
-                                return Ok(email_str.to_string());
-                            }
-                        }
-                        None => {}
-                    }
-                    
-                    // Fallback if email not found in primary location
-                    if let Some(email) = claims.get("email").and_then(|v| v.as_str()) {
-                        Ok(email.to_string())
-                    } else {
-                        println!("No email found in token claims.");
-                        Ok("unknown@user.com".to_string())
-                    }
-                },
-                Err(e) => {
-                    println!("Token verification error: {}", e);
-                    Err(Box::new(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Invalid token")))
-                }
-            }
-        },
-        Err(e) => {
-            println!("KeySet creation error: {}", e);
-            Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Failed to create KeySet")))
-        }
-    }
-}
-
-// Generate embedding using Ollama API
-async fn get_ollama_embedding(text: &str, model: &str) -> Result<Vec<f64>, Error> {
-    let ollama_api_url = env::var("OLLAMA_API_URL").expect("OLLAMA_API_URL environment variable not set");
-    let client = reqwest::Client::new();
-    
-    // Prepare the request body
-    let body = json!({
-        "model": model,
-        "prompt": text
-    });
-    
-    println!("Sending embedding request to Ollama server at: {}", ollama_api_url);
-    
-    // Send the request to the Ollama API
-    let response = client
-        .post(&ollama_api_url)
-        .json(&body)
-        .send()
-        .await?
-        .json::<Value>()
-        .await?;
-    
-    // Extract the embedding from the response
-    if let Some(embedding) = response["embedding"].as_array() {
-        let embedding_vec: Vec<f64> = embedding
-            .iter()
-            .filter_map(|v| v.as_f64())
-            .collect();
-        
-        Ok(embedding_vec)
-    } else {
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            format!("Failed to parse embedding from response: {:?}", response),
-        )))
-    }
-}
-
 // Function to query documents similar to a question
 async fn query_documents(
     question: &str,
     client: &Client,
     username: &str,
     n_results: i32,
-    model: &str,
+    _model: &str,
     document_uuid: Option<&str>,
     query_ledger_uuid: &str,
 ) -> Result<Vec<serde_json::Value>, Error> {
@@ -256,17 +85,17 @@ async fn query_documents(
     "#, target_chunk_table);
     
     // Add document filter if UUID is provided
-    if let Some(doc_uuid) = document_uuid {
+    if let Some(_doc_uuid) = document_uuid {
         query += r#"
             WHERE d.document_uuid = $2
         "#;
     }
     
     // Add limit
-    if let Some(_) = document_uuid {
-        query += &format!("\nLIMIT $3");
+    if document_uuid.is_some() {
+        query += "\nLIMIT $3";
     } else {
-        query += &format!("\nLIMIT $2");
+        query += "\nLIMIT $2";
     }
     
     println!("Executing query for document chunks...");
@@ -302,11 +131,11 @@ async fn query_documents(
         results.push(result);
         
         // Insert into query_answer_chunks table
-        let insert_query = format!(r#"
+        let insert_query = r#"
             INSERT INTO document_library.query_answer_chunks
             (query_answer_chunk_uuid, query_answer_uuid, query_ledger_uuid, chunk_uuid, creation_date, created_by, updated_date, updated_by, "comments")
             VALUES ($1, 'to_be_managed_if_needed', $2, $3, now(), 'system', now(), 'system', 'chunk used for the answer')
-        "#);
+        "#.to_string();
         
         let query_answer_chunk_uuid = Uuid::new_v4().to_string();
         match client.execute(&insert_query, &[&query_answer_chunk_uuid, &query_ledger_uuid, &document_chunk_uuid]).await {
@@ -331,7 +160,7 @@ async fn get_metadata_fields(client: &Client, target_metadata_uuid: Option<&str>
         FROM document_library.metadatas
     "#);
     
-    if let Some(metadata_uuid) = target_metadata_uuid {
+    if let Some(_metadata_uuid) = target_metadata_uuid {
         query += " WHERE metadata_uuid = $1";
     }
     
@@ -392,18 +221,19 @@ async fn get_metadata_fields(client: &Client, target_metadata_uuid: Option<&str>
     Ok(metadata_fields)
 }
 
-// Function to extract metadata using Ollama or OpenAI
-async fn extract_metadata_ollama(
+// Function to extract metadata with the chat model
+async fn extract_metadata(
     document_uuid: &str,
     client: &Client,
     username: &str,
     target_metadata_uuid: Option<&str>,
-    openai_api_key: &str,
+    chat_model_config: &ChatModelConfig,
+    chat_model: &dyn ChatModel,
 ) -> Result<Vec<MetadataResult>, Error> {
     println!("Starting metadata extraction for document: {}", document_uuid);
     
     // Get metadata fields
-    let metadata_fields = get_metadata_fields(&client, target_metadata_uuid.clone()).await?;
+    let metadata_fields = get_metadata_fields(client, target_metadata_uuid).await?;
     
     let mut results: Vec<MetadataResult> = Vec::new();
     
@@ -416,14 +246,14 @@ async fn extract_metadata_ollama(
         println!("Processing metadata field: {} (UUID: {})", field_name, metadata_uuid);
         
         // Insert query ledger entry
-        let query_ledger_sql = format!(r#"
+        let query_ledger_sql = r#"
             INSERT INTO document_library.query_ledgers_extended
             (query_ledger_uuid, query_type, query_content, metadata_uuid, user_uuid, query_tags, 
              query_start_document_date, query_end_document_date, query_answer, creation_date, 
              created_by, updated_date, updated_by, "comments")
             VALUES ($1, 'metadata_extraction', $2, $3, NULL, 'metadata_extraction', 
                    now(), now(), 'to_be_managed_if_needed', now(), 'system', now(), 'system', 'metadata extraction')
-        "#);
+        "#.to_string();
         
         match client.execute(
             &query_ledger_sql, 
@@ -438,7 +268,7 @@ async fn extract_metadata_ollama(
         // Get document chunks for the document
         let chunks = query_documents(
             prompt_template, 
-            &client, 
+            client, 
             username,
             3, // n_results_per_document
             "nomic-embed-text", // default model
@@ -462,113 +292,25 @@ async fn extract_metadata_ollama(
         // Format the prompt with the document text
         let prompt = prompt_template.replace("{text}", &all_text);
         
-        // Use the OpenAI API key from database credentials
-        let openai_api_url = env::var("OPENAI_API_URL").expect("OPENAI_API_URL environment variable not set");
-        let openai_model = env::var("OPENAI_MODEL").expect("OPENAI_MODEL environment variable not set");
-        let model = env::var("OPENAI_MODEL").expect("OPENAI_MODEL environment variable not set");
-        let client_http = reqwest::Client::new();
-
-        // Create OpenAI request
-        let openai_request = OpenAIRequest {
-            model: openai_model.clone(),
-            messages: vec![
-                OpenAIMessage {
-                    role: "user".to_string(),
-                    content: prompt,
-                }
-            ],
-            temperature: 0.0, // Use lower temperature for more deterministic responses
-        };
-        
-        println!("Sending request to OpenAI API at: {}", openai_api_url);
-
-        // Send the request to OpenAI
-        let response = match client_http
-            .post(&openai_api_url)
-            .header("Authorization", format!("Bearer {}", openai_api_key))
-            .header("Content-Type", "application/json")
-            .json(&openai_request)
-            .send()
-            .await {
-                Ok(resp) => resp,
-                Err(e) => {
-                    println!("Error sending request to OpenAI: {:?}", e);
-                    return Err(Box::new(e));
-                }
-            };
+        let model = &chat_model_config.settings.model;
+        println!("Sending request to chat model: {}", chat_model.name());
 
-        // Check if the response status is successful
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unable to read error response".to_string());
-            println!("OpenAI API error: {} - {}", status, error_text);
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("OpenAI API error: {}", status)
-            )));
-        }
-
-        let openai_response: OpenAIResponse = match response.json().await {
-            Ok(resp) => resp,
+        let completion = match chat_model.complete(&[ChatMessage::user(prompt)]).await {
+            Ok(completion) => completion,
             Err(e) => {
-                println!("Error parsing OpenAI response: {:?}", e);
-                return Err(Box::new(e));
+                println!("Error calling the chat model: {:?}", e);
+                return Err(e);
             }
         };
 
         // Extract content from the response
-        let raw_value = if !openai_response.choices.is_empty() {
-            openai_response.choices[0].message.content.trim().to_string()
-        } else {
-            "Not found".to_string()
-        };
-
-        // Use a higher confidence for OpenAI compared to Ollama
-        let confidence = 0.95; // Default confidence for OpenAI
-
-        /* 
-        // Use Ollama to extract metadata
-        let ollama_api_url = env::var("OLLAMA_API_URL").expect("OLLAMA_API_URL environment variable not set");
-        let model = env::var("OLLAMA_MODEL").expect("OLLAMA_MODEL environment variable not set");
-        let client_http = reqwest::Client::new();
-        
-        let ollama_request = OllamaRequest {
-            model: model.clone(),
-            messages: vec![
-                OllamaMessage {
-                    role: "system".to_string(),
-                    content: prompt,
-                }
-            ],
+        let raw_value = match completion.content.trim() {
+            "" => "Not found".to_string(),
+            content => content.to_string(),
         };
 
-        println!("Sending request to Ollama server at: {}", ollama_api_url);
-        
-        
-        // Send the request to Ollama
-        let response = match client_http
-            .post(&ollama_api_url)
-            .json(&ollama_request)
-            .send()
-            .await {
-                Ok(resp) => resp,
-                Err(e) => {
-                    println!("Error sending request to Ollama: {:?}", e);
-                    return Err(Box::new(e));
-                }
-            };
-        
-        let ollama_response: OllamaResponse = match response.json().await {
-            Ok(resp) => resp,
-            Err(e) => {
-                println!("Error parsing Ollama response: {:?}", e);
-                return Err(Box::new(e));
-            }
-        };
-        
-        let raw_value = ollama_response.message.content.trim().to_string();
-        let confidence = 0.85; // Default confidence for Ollama
-        */
+        // Use a lower confidence for the local Ollama models
+        let confidence = if chat_model_config.backend.trim().eq_ignore_ascii_case("ollama") { 0.85 } else { 0.95 };
         
         // Log the raw LLM response and value type for debugging
         let value_type = field_config["value_type"].as_str().unwrap();
@@ -582,7 +324,7 @@ async fn extract_metadata_ollama(
                 } else {
                     // Extract numeric characters and decimal point
                     let numeric_str: String = raw_value.chars()
-                        .filter(|c| c.is_digit(10) || *c == '.' || *c == '-')
+                        .filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
                         .collect();
                     
                     match numeric_str.parse::<f64>() {
@@ -600,7 +342,7 @@ async fn extract_metadata_ollama(
                 } else {
                     // Extract numeric characters and minus sign
                     let numeric_str: String = raw_value.chars()
-                        .filter(|c| c.is_digit(10) || *c == '-')
+                        .filter(|c| c.is_ascii_digit() || *c == '-')
                         .collect();
                     
                     match numeric_str.parse::<i32>() {
@@ -626,7 +368,7 @@ async fn extract_metadata_ollama(
                         
                         if let (Ok(d), Ok(m), Ok(y)) = (day, month, year) {
                             // Simple range check
-                            if d >= 1 && d <= 31 && m >= 1 && m <= 12 && y >= 1900 && y <= 2100 {
+                            if (1..=31).contains(&d) && (1..=12).contains(&m) && (1900..=2100).contains(&y) {
                                 // Convert to chrono::NaiveDate object that can be directly used with PostgreSQL
                                 match chrono::NaiveDate::from_ymd_opt(y, m, d) {
                                     Some(_) => Some(raw_value.clone()),
@@ -680,12 +422,12 @@ async fn extract_metadata_ollama(
         let processing_time = field_start_time.elapsed().as_secs_f64();
         
         // Check if metadata already exists for this document and field
-        let check_query = format!(r#"
+        let check_query = r#"
             SELECT document_metadata_uuid 
             FROM document_library.document_metadatas 
             WHERE document_uuid = $1 
             AND metadata_uuid = $2
-        "#);
+        "#.to_string();
         
         let existing_metadata_rows = client.query(&check_query, &[&document_uuid, &metadata_uuid]).await?;
         
@@ -701,11 +443,11 @@ async fn extract_metadata_ollama(
                     // Update existing record
                     let document_metadata_uuid: String = existing_metadata_rows[0].get("document_metadata_uuid");
                     
-                    let update_query = format!(r#"
+                    let update_query = r#"
                         UPDATE document_library.document_metadatas 
                         SET metadata_value_boolean = $1, updated_date = now(), updated_by = $2, query_ledger_uuid = $3, comments = $4
                         WHERE document_metadata_uuid = $5
-                    "#);
+                    "#.to_string();
                     
                     match client.execute(
                         &update_query, 
@@ -721,13 +463,13 @@ async fn extract_metadata_ollama(
                     // Insert new record
                     let document_metadata_uuid = Uuid::new_v4().to_string();
                     
-                    let insert_query = format!(r#"
+                    let insert_query = r#"
                         INSERT INTO document_library.document_metadatas 
                         (document_metadata_uuid, document_uuid, metadata_uuid, metadata_value_boolean, 
                          creation_date, created_by, updated_date, updated_by, 
                          query_ledger_uuid, comments)
                         VALUES ($1, $2, $3, $4, now(), $5, now(), $6, $7, $8)
-                    "#);
+                    "#.to_string();
                     
                     match client.execute(
                         &insert_query, 
@@ -943,7 +685,7 @@ async fn extract_metadata_ollama(
             metadata_uuid: metadata_uuid.to_string(),
             metadata_name: field_name.to_string(),
             raw_value: raw_value.clone(),
-            processed_value: processed_value,
+            processed_value,
             confidence,
             processing_time,
         });
@@ -956,15 +698,16 @@ async fn extract_metadata_ollama(
 async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     println!("Request received: {:?}", event);
     
-    // Extract user email from token
-    let user_email = match extract_email_from_token(&event).await {
-        Ok(email) => email,
+    // Verify the Cognito token of the caller
+    let auth_header = event.headers().get("Authorization").and_then(|h| h.to_str().ok());
+    let user_email = match authenticate(auth_header).await {
+        Ok(user) => user.email,
         Err(e) => {
-            println!("Failed to extract email from token: {}", e);
+            println!("Failed to authenticate the request: {}", e);
             return Ok(Response::builder()
-                .status(401)
+                .status(e.status_code())
                 .header("content-type", "application/json")
-                .body(json!({"statusAPI": "ERROR", "message": "Unauthorized: Invalid token"}).to_string().into())
+                .body(e.to_json().to_string().into())
                 .map_err(Box::new)?);
         }
     };
@@ -985,60 +728,11 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
         }
     };
     
-    // Determine which LLM provider to use
-    let llm_provider = request_data.llm_provider.as_deref().unwrap_or("ollama");
-    if llm_provider != "ollama" {
-        return Ok(Response::builder()
-            .status(400)
-            .header("content-type", "application/json")
-            .body(json!({"statusAPI": "ERROR", "message": "Unsupported LLM provider. Only 'ollama' is supported."}).to_string().into())
-            .map_err(Box::new)?);
-    }
-    println!("Using LLM provider: {}", llm_provider);
-    
-    // Access environment variables
-    let region_provider = RegionProviderChain::default_provider().or_else("ap-southeast-1");
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let client_secret = aws_sdk_secretsmanager::Client::new(&config);
-    println!("Access environment variables");
-
-    // Decode secret
-    let db_secret_name = env::var("DATABASE_CONECTION_STRING").expect("DATABASE_CONECTION_STRING environment variable not set");
-    let db_secret = show_secret(&client_secret, &db_secret_name).await?;
-    let db_credentials: serde_json::Value = serde_json::from_str(&db_secret)?;
-    println!("Decoded secret");
-
-    // Extract OpenAI API key from secrets
-    let openai_api_key = db_credentials["OPENAI_API_KEY"].as_str()
-        .ok_or_else(|| Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "OpenAI API key not found in database credentials")))?;
-
-    let db_server = db_credentials["DB_HOST"].as_str().unwrap();
-    let database = db_credentials["DB_NAME"].as_str().unwrap();
-    let db_username = db_credentials["DB_USER"].as_str().unwrap();
-    let db_password = db_credentials["DB_PASSWORD"].as_str().unwrap();
-    let db_port = db_credentials["DB_PORT"].as_str().unwrap();
-
-    let tls_connector = TlsConnector::builder()
-        .danger_accept_invalid_certs(true) // Disable certificate validation for development
-        .build();
-    let tls = MakeTlsConnector::new(tls_connector.expect("Failed to build TLS connector"));
-
     // Connect to the database
-    let connection_string = format!(
-        "host={} port={} user={} password={} dbname={}", 
-        db_server, db_port, db_username, db_password, database
-    );
-    println!("Attempting to connect to database...");
-    
-    let connection_result = tokio_postgres::connect(&connection_string, tls).await;
-
-    let (client, connection) = match connection_result {
-        Ok((client, connection)) => {
-            println!("Successfully connected to database");
-            (client, connection)
-        },
+    let client = match get_client().await {
+        Ok(client) => client,
         Err(e) => {
-            eprintln!("Failed to connect to database: {}", e);
+            eprintln!("{}", e);
             return Ok(Response::builder()
                 .status(500)
                 .header("content-type", "application/json")
@@ -1046,13 +740,20 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                 .map_err(Box::new)?);
         }
     };
-    
-    // Spawn a new task to manage the connection
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
+
+    // Determine which chat model to use
+    let chat_model_config = match ChatModelConfig::select(&client, request_data.chat_model.as_deref()).await? {
+        Some(config) => config,
+        None => {
+            return Ok(Response::builder()
+                .status(400)
+                .header("content-type", "application/json")
+                .body(json!({"statusAPI": "ERROR", "message": format!("Unknown or inactive chat model '{}'", request_data.chat_model.as_deref().unwrap_or_default())}).to_string().into())
+                .map_err(Box::new)?);
         }
-    });
+    };
+    let chat_model = chat_model_config.chat_model().await?;
+    println!("Using chat model: {}", chat_model_config.name);
 
     // Process the request based on parameters
     let start_time = Instant::now();
@@ -1062,12 +763,13 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
         // Process a single document
         println!("Processing document with UUID: {}", document_uuid);
         
-        let results = extract_metadata_ollama(
+        let results = extract_metadata(
             document_uuid,
             &client,
             &user_email,
             request_data.metadata_uuid.as_deref(),
-            openai_api_key
+            &chat_model_config,
+            chat_model.as_ref()
         ).await;
         
         match results {
@@ -1108,12 +810,13 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
             
             println!("Processing document: {} ({})", document_name, document_uuid);
             
-            let results = extract_metadata_ollama(
+            let results = extract_metadata(
                 &document_uuid,
                 &client,
                 &user_email,
                 request_data.metadata_uuid.as_deref(),
-                openai_api_key
+                &chat_model_config,
+                chat_model.as_ref()
             ).await;
             
             match results {
@@ -1132,8 +835,8 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     
     // Create the response
     let response_body = ComputeMetadataResponse {
-        statusAPI: "OK".to_string(),
-        message: format!("Successfully processed {} metadata entries using {} provider", all_results.len(), llm_provider),
+        status_api: "OK".to_string(),
+        message: format!("Successfully processed {} metadata entries using {} chat model", all_results.len(), chat_model_config.name),
         results: all_results,
     };
     
@@ -1,15 +1,9 @@
 use lambda_http::{run, service_fn, Body, Error, Request, Response};
 use serde::{Serialize, Deserialize};
 use serde_json::json;
-use std::env;
-use regex::Regex;
 
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_secretsmanager::Client as SecretManagerClient;
-
-use tokio_postgres::{Client, Error as PostgresError};
-use postgres_native_tls::MakeTlsConnector;
-use native_tls::TlsConnector;
+use rag_common::db::get_client;
+use rag_common::synonyms::expand_synonyms;
 
 // Request struct to deserialize incoming data
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,26 +14,12 @@ struct ComputeSynonymRequest {
 // Response struct for API
 #[derive(Debug, Serialize, Deserialize)]
 struct ComputeSynonymResponse {
-    statusAPI: String,
+    #[serde(rename = "statusAPI")]
+    status_api: String,
     original_query: String,
     processed_query: String,
 }
 
-// Synonym struct to map query results
-#[derive(Debug, Serialize, Deserialize)]
-struct Synonym {
-    synonym_name: String,
-    synonym_value: String,
-}
-
-async fn show_secret(client: &SecretManagerClient, name: &str) -> Result<String, Error> {
-    let resp = client.get_secret_value().secret_id(name).send().await?;
-    match resp.secret_string() {
-        Some(secret) => Ok(secret.into()),
-        None => panic!("Error getting the secret: {:?}", name),
-    }
-}
-
 async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     // Parse request body to get the query string
     let body = event.body();
@@ -64,45 +44,11 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
             .map_err(Box::new)?);
     }
 
-    // Access environment variables
-    let region_provider = RegionProviderChain::default_provider().or_else("ap-southeast-1");
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let client_secret = aws_sdk_secretsmanager::Client::new(&config);
-    println!("Access environment variables");
-
-    // Decode secret
-    let db_secret_name = env::var("DATABASE_CONECTION_STRING").expect("DATABASE_CONECTION_STRING environment variable not set");
-    let db_secret = show_secret(&client_secret, &db_secret_name).await?;
-    let db_credentials: serde_json::Value = serde_json::from_str(&db_secret)?;
-    println!("Decoded secret");
-
-    let db_server = db_credentials["DB_HOST"].as_str().unwrap();
-    let database = db_credentials["DB_NAME"].as_str().unwrap();
-    let db_username = db_credentials["DB_USER"].as_str().unwrap();
-    let db_password = db_credentials["DB_PASSWORD"].as_str().unwrap();
-    let db_port = db_credentials["DB_PORT"].as_str().unwrap();
-
-    let tls_connector = TlsConnector::builder()
-        .danger_accept_invalid_certs(true) // Disable certificate validation for development
-        .build();
-    let tls = MakeTlsConnector::new(tls_connector.expect("Failed to build TLS connector"));
-
-    // Connect to the database with better error handling
-    let connection_string = format!(
-        "host={} port={} user={} password={} dbname={}", 
-        db_server, db_port, db_username, db_password, database
-    );
-    println!("Attempting to connect to database...");
-    
-    let connection_result = tokio_postgres::connect(&connection_string, tls).await;
-
-    let (client, connection) = match connection_result {
-        Ok((client, connection)) => {
-            println!("Successfully connected to database");
-            (client, connection)
-        },
+    // Connect to the database
+    let client = match get_client().await {
+        Ok(client) => client,
         Err(e) => {
-            eprintln!("Failed to connect to database: {}", e);
+            eprintln!("{}", e);
             return Ok(Response::builder()
                 .status(500)
                 .header("content-type", "application/json")
@@ -110,50 +56,13 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                 .map_err(Box::new)?);
         }
     };
-    
-    // Spawn a new task to manage the connection
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
 
-    // Get all synonyms from the database
-    let query = "SELECT synonym_name, synonym_value FROM document_library.synonyms";
-    let rows = client.query(query, &[]).await?;
-    
-    // Parse rows into Synonym structs
-    let mut synonyms: Vec<Synonym> = Vec::new();
-    for row in rows {
-        let synonym = Synonym {
-            synonym_name: row.get("synonym_name"),
-            synonym_value: row.get("synonym_value"),
-        };
-        
-        println!("Synonym Name: {}, Value: {}", synonym.synonym_name, synonym.synonym_value);
-        synonyms.push(synonym);
-    }
-    
-    println!("Total synonyms: {}", synonyms.len());
-
-    // Process the query string by replacing keywords with their synonyms
-    let mut processed_query = request_data.query.clone();
-    
-    for synonym in synonyms {
-        // Create a pattern that matches the whole word only
-        let pattern = format!(r"\b{}\b", regex::escape(&synonym.synonym_name));
-        let re = Regex::new(&pattern).unwrap();
-        
-        // Replace whole word matches with keyword + OR + synonym value
-        if re.is_match(&processed_query) {
-            let replacement = format!("{} or {}", synonym.synonym_name, synonym.synonym_value);
-            processed_query = re.replace_all(&processed_query, replacement).to_string();
-        }
-    }
+    // Replace the keywords of the query by themselves or their synonyms
+    let processed_query = expand_synonyms(&client, &request_data.query).await?;
 
     // Create response
     let response_body = ComputeSynonymResponse {
-        statusAPI: "OK".to_string(),
+        status_api: "OK".to_string(),
         original_query: request_data.query,
         processed_query,
     };
@@ -178,4 +87,4 @@ async fn main() -> Result<(), Error> {
         .init();
 
     run(service_fn(function_handler)).await
-}
\ No newline at end of file
+}
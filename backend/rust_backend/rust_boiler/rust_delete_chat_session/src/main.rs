@@ -0,0 +1,106 @@
+use lambda_http::{run, service_fn, Body, Error, Request, Response};
+use serde::Deserialize;
+use serde_json::json;
+
+use rag_common::auth::authenticate;
+use rag_common::conversation::delete_session;
+use rag_common::db::get_client;
+
+// Chat session of the caller to delete with its messages
+#[derive(Debug, Deserialize)]
+struct DeleteChatSessionRequest {
+    chat_session_uuid: String,
+}
+
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    // Verify the Cognito token of the caller, who can only delete its own sessions
+    let auth_header = event.headers().get("Authorization").and_then(|h| h.to_str().ok());
+    let user_email = match authenticate(auth_header).await {
+        Ok(user) => user.email,
+        Err(e) => {
+            println!("Failed to authenticate the request: {}", e);
+            return Ok(Response::builder()
+                .status(e.status_code())
+                .header("content-type", "application/json")
+                .body(e.to_json().to_string().into())
+                .map_err(Box::new)?);
+        }
+    };
+
+    let req: DeleteChatSessionRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(req) => req,
+        Err(e) => {
+            eprintln!("Failed to parse request body: {}", e);
+            return Ok(Response::builder()
+                .status(400)
+                .header("content-type", "application/json")
+                .body(json!({"statusAPI": "ERROR", "message": "Invalid request body"}).to_string().into())
+                .map_err(Box::new)?);
+        }
+    };
+
+    // Connect to the database
+    let client = match get_client().await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(Response::builder()
+                .status(500)
+                .header("content-type", "application/json")
+                .body(json!({"statusAPI": "ERROR", "message": "Database connection failed"}).to_string().into())
+                .map_err(Box::new)?);
+        }
+    };
+
+    // The ledger entries of the answers of the session are kept
+    match delete_session(&client, &req.chat_session_uuid, &user_email).await {
+        Ok(true) => {
+            println!("Deleted chat session {}", req.chat_session_uuid);
+            let response_body = json!({
+                "statusAPI": "OK",
+                "result": {
+                    "chat_session_uuid": req.chat_session_uuid,
+                    "message": "Chat session deleted successfully"
+                }
+            });
+            Ok(Response::builder()
+                .status(200)
+                .header("content-type", "application/json")
+                .body(response_body.to_string().into())
+                .map_err(Box::new)?)
+        }
+        Ok(false) => Ok(Response::builder()
+            .status(404)
+            .header("content-type", "application/json")
+            .body(json!({
+                "statusAPI": "ERROR",
+                "message": format!("Chat session '{}' not found", req.chat_session_uuid)
+            }).to_string().into())
+            .map_err(Box::new)?),
+        Err(e) => {
+            eprintln!("Failed to delete the chat session: {}", e);
+            Ok(Response::builder()
+                .status(500)
+                .header("content-type", "application/json")
+                .body(json!({
+                    "statusAPI": "ERROR",
+                    "message": format!("Failed to delete the chat session: {}", e)
+                }).to_string().into())
+                .map_err(Box::new)?)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or(tracing_subscriber::EnvFilter::new("INFO")),
+        )
+        .with_target(false)
+        .without_time()
+        .init();
+
+    run(service_fn(function_handler)).await
+}
@@ -3,16 +3,13 @@ use serde::{Serialize, Deserialize};
 use serde_json::json;
 use std::env;
 use std::time::Duration;
-use jsonwebtokens_cognito::KeySet;
 
 use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_secretsmanager::Client as SecretManagerClient;
 use aws_sdk_s3::presigning::PresigningConfig;
 use aws_sdk_s3::Client as S3Client;
 
-use tokio_postgres::{Client, NoTls};
-use postgres_native_tls::MakeTlsConnector;
-use native_tls::TlsConnector;
+use rag_common::auth::authenticate;
+use rag_common::db::get_client;
 
 // Request structure for getting a presigned URL
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,6 +17,8 @@ struct PresignedUrlRequest {
     document_uuid: String,
     #[serde(default = "default_expiration")]
     expiration: u64,
+    page: Option<i32>, // Page to open, added to the URL as #page=N
+    document_chunk_uuid: Option<String>, // Or the chunk whose first page to open
 }
 
 fn default_expiration() -> u64 {
@@ -41,78 +40,20 @@ struct PresignedUrlResponse {
     presigned_url: String,
     document_name: String,
     expiration: u64,
-}
-
-async fn show_secret(client: &SecretManagerClient, name: &str) -> Result<String, Error> {
-    let resp = client.get_secret_value().secret_id(name).send().await?;
-    match resp.secret_string() {
-        Some(secret) => Ok(secret.into()),
-        None => {
-            eprintln!("Error retrieving secret: {}", name);
-            Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("Error retrieving secret: {}", name))))
-        }
-    }
-}
-
-// Extract email from Authorization token
-async fn extract_email_from_token(event: &Request) -> Result<String, Error> {
-    let key_to_check = "Authorization";
-    if !event.headers().contains_key(key_to_check) {
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "No Authorization header found")));
-    }
-
-    let bearer_str = event.headers()[key_to_check].to_str()?;
-    if !bearer_str.starts_with("Bearer ") {
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid Authorization format")));
-    }
-
-    let token_str = &bearer_str[7..];
-    
-    // Get AWS region and user pool ID from environment variables or use defaults
-    let region = env::var("COGNITO_REGION").expect("COGNITO_REGION environment variable not set");
-    let user_pool_id = env::var("COGNITO_USER_POOL_ID").expect("COGNITO_USER_POOL_ID environment variable not set");
-    let client_id = env::var("COGNITO_CLIENT_ID").expect("COGNITO_CLIENT_ID environment variable not set");
-
-    let keyset = KeySet::new(&region, &user_pool_id);
-    
-    match keyset {
-        Ok(key_set) => {
-            let verifier = key_set.new_id_token_verifier(&[&client_id]).build()?;
-            let verification_result = key_set.verify(token_str, &verifier).await;
-            
-            match verification_result {
-                Ok(claims) => {
-                    // Extract email from claims
-                    if let Some(email) = claims.get("email").and_then(|v| v.as_str()) {
-                        return Ok(email.to_string());
-                    } else {
-                        println!("No email found in token claims.");
-                        return Ok("unknown@user.com".to_string());
-                    }
-                },
-                Err(e) => {
-                    println!("Token verification error: {}", e);
-                    return Err(Box::new(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Invalid token")));
-                }
-            }
-        },
-        Err(e) => {
-            println!("KeySet creation error: {}", e);
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Failed to create KeySet")));
-        }
-    }
+    page: Option<i32>,
 }
 
 async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
-    // Extract user email from token
-    let user_email = match extract_email_from_token(&event).await {
-        Ok(email) => email,
+    // Verify the Cognito token of the caller
+    let auth_header = event.headers().get("Authorization").and_then(|h| h.to_str().ok());
+    let user_email = match authenticate(auth_header).await {
+        Ok(user) => user.email,
         Err(e) => {
-            println!("Failed to extract email from token: {}", e);
+            println!("Failed to authenticate the request: {}", e);
             return Ok(Response::builder()
-                .status(401)
+                .status(e.status_code())
                 .header("content-type", "application/json")
-                .body(json!({"status": "error", "message": "Unauthorized: Invalid token"}).to_string().into())
+                .body(json!({"status": "error", "message": e.to_string()}).to_string().into())
                 .map_err(Box::new)?);
         }
     };
@@ -135,62 +76,30 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     
     println!("Request data parsed: Document UUID: {}, Expiration: {} seconds", request_data.document_uuid, request_data.expiration);
     
+    if let Some(page) = request_data.page {
+        if page < 1 {
+            return Ok(Response::builder()
+                .status(400)
+                .header("content-type", "application/json")
+                .body(json!({"status": "error", "message": format!("Bad request: invalid page {}", page)}).to_string().into())
+                .map_err(Box::new)?);
+        }
+    }
+    
     // Access environment variables and AWS resources
     let region_provider = RegionProviderChain::default_provider().or_else("ap-southeast-1");
     let config = aws_config::from_env().region(region_provider).load().await;
-    let client_secret = aws_sdk_secretsmanager::Client::new(&config);
     
     // Get bucket name from environment variable with default
     let s3_bucket_name = env::var("S3BUCKET_IMPORT_FOLDER").expect("S3BUCKET_IMPORT_FOLDER environment variable not set");
     
     println!("S3 bucket name: {}", s3_bucket_name);
 
-    // Decode database secret
-    let db_secret_name = env::var("DATABASE_CONECTION_STRING").expect("DATABASE_CONECTION_STRING environment variable not set");
-    let db_secret = match show_secret(&client_secret, &db_secret_name).await {
-        Ok(secret) => secret,
-        Err(e) => {
-            println!("Error getting database secret: {}", e);
-            return Ok(Response::builder()
-                .status(500)
-                .header("content-type", "application/json")
-                .body(json!({"status": "error", "message": "Server configuration error: Database secret not available"}).to_string().into())
-                .map_err(Box::new)?);
-        }
-    };
-    
-    let db_credentials: serde_json::Value = serde_json::from_str(&db_secret).map_err(|e| {
-        println!("Error parsing database credentials: {}", e);
-        Box::new(e) as Error
-    })?;
-
-    let db_server = db_credentials["DB_HOST"].as_str().unwrap();
-    let database = db_credentials["DB_NAME"].as_str().unwrap();
-    let db_username = db_credentials["DB_USER"].as_str().unwrap();
-    let db_password = db_credentials["DB_PASSWORD"].as_str().unwrap();
-    let db_port = db_credentials["DB_PORT"].as_str().unwrap();
-    
-    // Setup TLS connection
-    let tls_connector = TlsConnector::builder()
-        .danger_accept_invalid_certs(true) // Disable certificate validation for development
-        .build();
-    let tls = MakeTlsConnector::new(tls_connector.expect("Failed to build TLS connector"));
-
     // Connect to the database
-    println!("Connecting to database...");
-    let connection_result = tokio_postgres::connect(
-        &format!("host={} port={} user={} password={} dbname={}", 
-        db_server, db_port, db_username, db_password, database),
-        tls
-    ).await;
-
-    let (pg_client, connection) = match connection_result {
-        Ok((client, connection)) => {
-            println!("Successfully connected to database");
-            (client, connection)
-        },
+    let pg_client = match get_client().await {
+        Ok(client) => client,
         Err(e) => {
-            eprintln!("Failed to connect to database: {}", e);
+            eprintln!("{}", e);
             return Ok(Response::builder()
                 .status(500)
                 .header("content-type", "application/json")
@@ -198,13 +107,6 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                 .map_err(Box::new)?);
         }
     };
-    
-    // Spawn the connection task
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Database connection error: {}", e);
-        }
-    });
 
     // Query to get the document information
     let query = "SELECT document_uuid, document_name, document_location 
@@ -233,6 +135,29 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     
     println!("Document found: {}, Location: {}", document.document_name, document.document_location);
     
+    // Page to deep-link to, given directly or taken from the chunk
+    let page = match (request_data.page, &request_data.document_chunk_uuid) {
+        (Some(page), _) => Some(page),
+        (None, Some(chunk_uuid)) => {
+            let page_query = "SELECT chunck_page_start FROM document_library.document_chunks WHERE document_chunk_uuid = $1 AND document_uuid = $2";
+            match pg_client.query_opt(page_query, &[chunk_uuid, &document.document_uuid]).await {
+                Ok(Some(row)) => row.get::<_, Option<i32>>("chunck_page_start"),
+                Ok(None) => {
+                    return Ok(Response::builder()
+                        .status(404)
+                        .header("content-type", "application/json")
+                        .body(json!({"status": "error", "message": "Chunk not found in this document"}).to_string().into())
+                        .map_err(Box::new)?);
+                }
+                Err(e) => {
+                    println!("Error querying chunk page: {}", e);
+                    None
+                }
+            }
+        }
+        (None, None) => None,
+    };
+    
     // Create S3 client
     let s3_client = S3Client::new(&config);
     
@@ -267,8 +192,11 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
         }
     };
     
-    // Extract the presigned URL as a string
-    let presigned_url = presigned_req.uri().to_string();
+    // Extract the presigned URL as a string, the page fragment is read by the PDF viewer and not signed
+    let mut presigned_url = presigned_req.uri().to_string();
+    if let Some(page) = page {
+        presigned_url.push_str(&format!("#page={}", page));
+    }
     println!("Presigned URL generated: {}", presigned_url);
     
     // Create the response
@@ -277,6 +205,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
         presigned_url,
         document_name: document.document_name,
         expiration: request_data.expiration,
+        page,
     };
     
     let resp = Response::builder()
@@ -1,7 +1,6 @@
 use lambda_http::{run, service_fn, Body, Error, Request, Response};
 use rusoto_core::Region;
 use rusoto_dynamodb::{DynamoDb, DynamoDbClient, ScanInput};
-use serde_json;
 use serde_json::Value;
 
 
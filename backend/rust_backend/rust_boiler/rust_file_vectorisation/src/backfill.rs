@@ -0,0 +1,268 @@
+// Backfill of the embeddings of one model over the whole corpus
+//
+// Invoked with {"backfill": {"embedding_model": "<name>", "reembed": false}}, typically after
+// registering a new model: every chunk of document_library.document_chunks is embedded into
+// the table of the model, with the batches, concurrency and retries of the vectorisation.
+// Searches keep using their model until the new one is made the default, so they go on
+// serving from the old table during the backfill.
+//
+// Chunks are processed in document_chunk_uuid order, by pages, and the last chunk of each
+// page is checkpointed in document_library.embedding_backfills. An invocation stops before
+// the timeout of the Lambda, invoking it again resumes after the checkpoint until the status
+// of the backfill is "completed". Chunks that already have an embedding of the model are
+// skipped, unless "reembed" is true (e.g. the model changed but not its table).
+
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::Utc;
+use lambda_runtime::Error;
+use serde_json::{json, Value};
+use tokio_postgres::Client;
+use uuid::Uuid;
+
+use rag_common::registry::EmbeddingModel;
+
+use crate::batch::{vectorise_chunks, BatchConfig};
+use crate::DocumentChunk;
+
+// Time left for the last page and its checkpoint when the invocation stops
+const DEADLINE_MARGIN_MS: u64 = 60_000;
+// Rounds of EMBEDDING_CONCURRENCY batches between two checkpoints
+const ROUNDS_PER_PAGE: usize = 4;
+
+// A backfill, row of document_library.embedding_backfills
+struct Backfill {
+    uuid: String,
+    reembed: bool,
+    // Checkpoint, chunks up to this one are done
+    last_chunk_uuid: String,
+    total_chunks: i32,
+    processed_chunks: i32,
+    skipped_chunks: i32,
+    failed_chunks: i32,
+    // Seconds spent by all the invocations
+    backfill_time: f64,
+}
+
+// Run the backfill requested by the `backfill` field of the event, until it completes or
+// the invocation nears its deadline (milliseconds since the epoch)
+pub async fn run_backfill(client: &Client, request: &Value, deadline_ms: u64) -> Result<Value, Error> {
+    let model_name = match request["embedding_model"].as_str() {
+        Some(name) => name,
+        None => {
+            return Ok(json!({
+                "statusCode": 400,
+                "body": "backfill.embedding_model is required"
+            }));
+        }
+    };
+    let model = match EmbeddingModel::by_name(client, model_name).await? {
+        Some(model) => model,
+        None => {
+            return Ok(json!({
+                "statusCode": 400,
+                "body": format!("Unknown or inactive embedding model '{}'", model_name)
+            }));
+        }
+    };
+    let reembed = request["reembed"].as_bool().unwrap_or(false);
+    let config = BatchConfig::from_env()?;
+
+    let mut backfill = running_or_new_backfill(client, &model, reembed).await?;
+    if backfill.reembed != reembed {
+        println!("Resuming backfill {} with reembed {} as it was started", backfill.uuid, backfill.reembed);
+    }
+
+    // One invocation at a time per backfill, the lock goes with the connection if the Lambda dies
+    let locked: bool = client
+        .query_one("SELECT pg_try_advisory_lock(hashtext($1))", &[&backfill.uuid])
+        .await?
+        .get(0);
+    if !locked {
+        return Ok(json!({
+            "statusCode": 409,
+            "body": format!("Backfill {} of {} is already running in another invocation", backfill.uuid, model.name)
+        }));
+    }
+    let start_time = Instant::now();
+    let result = process_pages(client, &model, &config, &mut backfill, deadline_ms).await;
+    client.execute("SELECT pg_advisory_unlock(hashtext($1))", &[&backfill.uuid]).await?;
+    let (completed, invocation_processed) = result?;
+    let invocation_seconds = start_time.elapsed().as_secs_f64();
+
+    let remaining: i64 = client
+        .query_one(
+            "SELECT count(*) FROM document_library.document_chunks WHERE document_chunk_uuid > $1",
+            &[&backfill.last_chunk_uuid],
+        )
+        .await?
+        .get(0);
+
+    Ok(json!({
+        "statusCode": 200,
+        "body": json!({
+            "message": if completed { "Backfill completed" } else { "Backfill in progress, invoke again to resume" },
+            "embedding_backfill_uuid": backfill.uuid,
+            "embedding_model": model.name,
+            "status": if completed { "completed" } else { "running" },
+            "reembed": backfill.reembed,
+            "total_chunks": backfill.total_chunks,
+            "processed_chunks": backfill.processed_chunks,
+            "skipped_chunks": backfill.skipped_chunks,
+            "failed_chunks": backfill.failed_chunks,
+            "remaining_chunks": remaining,
+            "invocation_processed_chunks": invocation_processed,
+            "invocation_time_seconds": invocation_seconds,
+            "chunks_per_second": throughput(invocation_processed as f64, invocation_seconds),
+            "overall_chunks_per_second": throughput(backfill.processed_chunks as f64, backfill.backfill_time)
+        })
+    }))
+}
+
+// The running backfill of the model, or a new one
+async fn running_or_new_backfill(client: &Client, model: &EmbeddingModel, reembed: bool) -> Result<Backfill, Error> {
+    let query = "SELECT embedding_backfill_uuid, reembed, last_chunk_uuid, total_chunks, processed_chunks, skipped_chunks, failed_chunks, backfill_time
+        FROM document_library.embedding_backfills WHERE embedding_model_name = $1 AND backfill_status = 'running'";
+    if let Some(row) = client.query_opt(query, &[&model.name]).await? {
+        let backfill = Backfill {
+            uuid: row.get(0),
+            reembed: row.get(1),
+            last_chunk_uuid: row.get::<_, Option<String>>(2).unwrap_or_default(),
+            total_chunks: row.get::<_, Option<i32>>(3).unwrap_or_default(),
+            processed_chunks: row.get(4),
+            skipped_chunks: row.get(5),
+            failed_chunks: row.get(6),
+            backfill_time: row.get(7),
+        };
+        println!(
+            "Resuming backfill {} of {} after chunk '{}' ({} processed, {} skipped, {} failed)",
+            backfill.uuid, model.name, backfill.last_chunk_uuid, backfill.processed_chunks, backfill.skipped_chunks, backfill.failed_chunks
+        );
+        return Ok(backfill);
+    }
+
+    let total_chunks: i64 = client
+        .query_one("SELECT count(*) FROM document_library.document_chunks", &[])
+        .await?
+        .get(0);
+    let backfill = Backfill {
+        uuid: Uuid::new_v4().to_string(),
+        reembed,
+        last_chunk_uuid: String::new(),
+        total_chunks: total_chunks as i32,
+        processed_chunks: 0,
+        skipped_chunks: 0,
+        failed_chunks: 0,
+        backfill_time: 0.0,
+    };
+    let now = Utc::now();
+    client
+        .execute(
+            "INSERT INTO document_library.embedding_backfills (embedding_backfill_uuid, embedding_model_name, backfill_status, reembed, last_chunk_uuid, total_chunks, creation_date, created_by, updated_date, updated_by) VALUES ($1, $2, 'running', $3, $4, $5, $6, $7, $8, $9)",
+            &[&backfill.uuid, &model.name, &reembed, &backfill.last_chunk_uuid, &backfill.total_chunks, &now, &"system", &now, &"system"],
+        )
+        .await?;
+    println!("Started backfill {} of {} over {} chunks (reembed: {})", backfill.uuid, model.name, total_chunks, reembed);
+    Ok(backfill)
+}
+
+// Embed the pages of chunks after the checkpoint, checkpointing each of them. Returns
+// whether the backfill completed and the chunks embedded by this invocation.
+async fn process_pages(
+    client: &Client,
+    model: &EmbeddingModel,
+    config: &BatchConfig,
+    backfill: &mut Backfill,
+    deadline_ms: u64,
+) -> Result<(bool, usize), Error> {
+    let page_size = config.batch_size * config.concurrency * ROUNDS_PER_PAGE;
+    let mut invocation_processed = 0;
+
+    loop {
+        if now_ms() + DEADLINE_MARGIN_MS > deadline_ms {
+            println!("Stopping backfill {} before the deadline, at chunk '{}'", backfill.uuid, backfill.last_chunk_uuid);
+            return Ok((false, invocation_processed));
+        }
+
+        let rows = client
+            .query(
+                "SELECT document_chunk_uuid, embebed_text FROM document_library.document_chunks
+                 WHERE document_chunk_uuid > $1 ORDER BY document_chunk_uuid LIMIT $2",
+                &[&backfill.last_chunk_uuid, &(page_size as i64)],
+            )
+            .await?;
+        if rows.is_empty() {
+            let now = Utc::now();
+            client
+                .execute(
+                    "UPDATE document_library.embedding_backfills SET backfill_status = 'completed', completion_date = $2, updated_date = $2, updated_by = 'system' WHERE embedding_backfill_uuid = $1",
+                    &[&backfill.uuid, &now],
+                )
+                .await?;
+            println!("Backfill {} of {} completed", backfill.uuid, model.name);
+            return Ok((true, invocation_processed));
+        }
+        let chunks: Vec<DocumentChunk> = rows
+            .iter()
+            .map(|row| DocumentChunk {
+                document_chunk_uuid: row.get(0),
+                embebed_text: row.get(1),
+            })
+            .collect();
+
+        let page_start = Instant::now();
+        let summary = vectorise_chunks(client, model, &chunks, config, backfill.reembed).await?;
+        let page_seconds = page_start.elapsed().as_secs_f64();
+
+        backfill.last_chunk_uuid = chunks[chunks.len() - 1].document_chunk_uuid.clone();
+        backfill.processed_chunks += summary.processed_chunks as i32;
+        backfill.skipped_chunks += summary.skipped_chunks as i32;
+        backfill.failed_chunks += summary.failed_chunk_uuids.len() as i32;
+        backfill.backfill_time += page_seconds;
+        invocation_processed += summary.processed_chunks;
+
+        let now = Utc::now();
+        client
+            .execute(
+                "UPDATE document_library.embedding_backfills SET last_chunk_uuid = $2, processed_chunks = $3, skipped_chunks = $4, failed_chunks = $5,
+                 failed_chunk_uuids = failed_chunk_uuids || $6, backfill_time = $7, updated_date = $8, updated_by = 'system'
+                 WHERE embedding_backfill_uuid = $1",
+                &[
+                    &backfill.uuid,
+                    &backfill.last_chunk_uuid,
+                    &backfill.processed_chunks,
+                    &backfill.skipped_chunks,
+                    &backfill.failed_chunks,
+                    &summary.failed_chunk_uuids,
+                    &backfill.backfill_time,
+                    &now,
+                ],
+            )
+            .await?;
+        println!(
+            "Backfill {}: {} chunks up to '{}' in {:.1} seconds ({:.1} chunks/s), {} processed, {} skipped, {} failed of {}",
+            backfill.uuid,
+            chunks.len(),
+            backfill.last_chunk_uuid,
+            page_seconds,
+            throughput(summary.processed_chunks as f64, page_seconds),
+            backfill.processed_chunks,
+            backfill.skipped_chunks,
+            backfill.failed_chunks,
+            backfill.total_chunks
+        );
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_millis() as u64).unwrap_or_default()
+}
+
+// Chunks embedded per second
+fn throughput(chunks: f64, seconds: f64) -> f64 {
+    if seconds > 0.0 {
+        chunks / seconds
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,227 @@
+// Batched vectorisation of the chunks of a document
+//
+// Chunks are embedded by batches of EMBEDDING_BATCH_SIZE texts, with at most
+// EMBEDDING_CONCURRENCY batches in flight so that the embedding server is not flooded.
+// A failed embedding request is retried up to EMBEDDING_MAX_RETRIES times with an
+// exponential backoff, after which the chunks of the batch are reported as failed and the
+// other batches go on. The embeddings of a batch are written with a single upsert, and
+// the chunks that already have an embedding are found with a single query.
+
+use std::collections::HashSet;
+use std::env;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use futures::stream::{self, StreamExt};
+use lambda_runtime::Error;
+use tokio_postgres::Client;
+use uuid::Uuid;
+
+use rag_common::registry::EmbeddingModel;
+
+use crate::{DocumentChunk, PgVector};
+
+// Delay before the first retry, doubled on each following one
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(20);
+
+// Settings of the batched vectorisation
+pub struct BatchConfig {
+    // Chunks embedded per request to the embedding backend
+    pub batch_size: usize,
+    // Batches embedded at the same time
+    pub concurrency: usize,
+    // Retries of a failed embedding request before giving up on its batch
+    pub max_retries: u32,
+}
+
+impl BatchConfig {
+    // Read EMBEDDING_BATCH_SIZE, EMBEDDING_CONCURRENCY and EMBEDDING_MAX_RETRIES
+    pub fn from_env() -> Result<Self, Error> {
+        let batch_size = env::var("EMBEDDING_BATCH_SIZE").expect("EMBEDDING_BATCH_SIZE environment variable not set");
+        let concurrency = env::var("EMBEDDING_CONCURRENCY").expect("EMBEDDING_CONCURRENCY environment variable not set");
+        let max_retries = env::var("EMBEDDING_MAX_RETRIES").expect("EMBEDDING_MAX_RETRIES environment variable not set");
+
+        let batch_size: usize = batch_size
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid EMBEDDING_BATCH_SIZE '{}': {}", batch_size, e))?;
+        let concurrency: usize = concurrency
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid EMBEDDING_CONCURRENCY '{}': {}", concurrency, e))?;
+        let max_retries = max_retries
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid EMBEDDING_MAX_RETRIES '{}': {}", max_retries, e))?;
+        if batch_size == 0 || concurrency == 0 {
+            return Err("EMBEDDING_BATCH_SIZE and EMBEDDING_CONCURRENCY must be greater than 0".into());
+        }
+        Ok(BatchConfig { batch_size, concurrency, max_retries })
+    }
+}
+
+// Outcome of the vectorisation of a document with one model
+#[derive(Debug, Default)]
+pub struct VectorisationSummary {
+    pub processed_chunks: usize,
+    // Chunks that already had an embedding of the model
+    pub skipped_chunks: usize,
+    pub failed_chunk_uuids: Vec<String>,
+    // Error of the last failed batch, to know why without going through the logs
+    pub last_error: Option<String>,
+}
+
+// Embed the chunks that have no embedding of `model` yet, or all of them with `reembed`,
+// and store their embeddings
+pub async fn vectorise_chunks(
+    client: &Client,
+    model: &EmbeddingModel,
+    chunks: &[DocumentChunk],
+    config: &BatchConfig,
+    reembed: bool,
+) -> Result<VectorisationSummary, Error> {
+    let mut summary = VectorisationSummary::default();
+
+    // Chunks without text have nothing to embed
+    let with_text: Vec<(&str, &str)> = chunks
+        .iter()
+        .filter_map(|chunk| {
+            chunk
+                .embebed_text
+                .as_deref()
+                .filter(|text| !text.is_empty())
+                .map(|text| (chunk.document_chunk_uuid.as_str(), text))
+        })
+        .collect();
+    if with_text.len() < chunks.len() {
+        println!("Skipping {} chunks with empty text", chunks.len() - with_text.len());
+    }
+
+    let chunk_uuids: Vec<String> = with_text.iter().map(|(uuid, _)| uuid.to_string()).collect();
+    let existing = if reembed {
+        HashSet::new()
+    } else {
+        existing_embeddings(client, model, &chunk_uuids).await?
+    };
+    let pending: Vec<(&str, &str)> = with_text.into_iter().filter(|(uuid, _)| !existing.contains(*uuid)).collect();
+    summary.skipped_chunks = chunk_uuids.len() - pending.len();
+    println!(
+        "{} chunks to embed with {}, {} already embedded, in batches of {}",
+        pending.len(),
+        model.name,
+        summary.skipped_chunks,
+        config.batch_size
+    );
+
+    let mut results = stream::iter(pending.chunks(config.batch_size))
+        .map(|batch| async move { (batch, embed_and_store(client, model, batch, config.max_retries).await) })
+        .buffer_unordered(config.concurrency);
+    while let Some((batch, result)) = results.next().await {
+        match result {
+            Ok(()) => summary.processed_chunks += batch.len(),
+            Err(e) => {
+                println!("Failed to vectorise a batch of {} chunks with {}: {}", batch.len(), model.name, e);
+                summary.failed_chunk_uuids.extend(batch.iter().map(|(uuid, _)| uuid.to_string()));
+                summary.last_error = Some(e.to_string());
+            }
+        }
+    }
+    // Batches end in any order
+    summary.failed_chunk_uuids.sort();
+
+    Ok(summary)
+}
+
+// Chunks among `chunk_uuids` that already have an embedding in the table of the model
+async fn existing_embeddings(client: &Client, model: &EmbeddingModel, chunk_uuids: &[String]) -> Result<HashSet<String>, Error> {
+    let query = format!(
+        "SELECT document_chunk_uuid FROM document_library.\"{}\" WHERE document_chunk_uuid = ANY($1) AND embedding IS NOT NULL",
+        model.table
+    );
+    let rows = client.query(&query, &[&chunk_uuids]).await.map_err(|e| {
+        println!("Database query error: {}", e);
+        format!("Database query error: {}", e)
+    })?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+// Embed one batch of (chunk uuid, text) and store the embeddings
+async fn embed_and_store(client: &Client, model: &EmbeddingModel, batch: &[(&str, &str)], max_retries: u32) -> Result<(), Error> {
+    let texts: Vec<String> = batch.iter().map(|(_, text)| text.to_string()).collect();
+
+    let start_time = Instant::now();
+    let embeddings = embed_with_retry(model, &texts, max_retries).await?;
+    // Each chunk is given its share of the time of the batch
+    let embedding_time = start_time.elapsed().as_secs_f64() / batch.len() as f64;
+
+    let chunk_uuids: Vec<String> = batch.iter().map(|(uuid, _)| uuid.to_string()).collect();
+    upsert_embeddings(client, model, &chunk_uuids, embeddings, embedding_time).await
+}
+
+// Embed `texts`, retrying with an exponential backoff when the backend fails or is rate limited
+async fn embed_with_retry(model: &EmbeddingModel, texts: &[String], max_retries: u32) -> Result<Vec<Vec<f32>>, Error> {
+    let mut attempt = 0;
+    loop {
+        match model.embed(texts).await {
+            Ok(embeddings) => return Ok(embeddings),
+            Err(e) if attempt < max_retries => {
+                let delay = RETRY_BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt)).min(RETRY_MAX_DELAY);
+                println!(
+                    "{} embedding of {} chunks failed (attempt {}), retrying in {:?}: {}",
+                    model.name,
+                    texts.len(),
+                    attempt + 1,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(format!("{} embedding failed after {} attempts: {}", model.name, attempt + 1, e).into()),
+        }
+    }
+}
+
+// Write the embeddings of a batch in one statement: the rows of the chunks already in the
+// table (such as the placeholders of the integration) are updated, the others inserted. The
+// token count of a chunk is not known in a batch, both branches record -1
+async fn upsert_embeddings(
+    client: &Client,
+    model: &EmbeddingModel,
+    chunk_uuids: &[String],
+    embeddings: Vec<Vec<f32>>,
+    embedding_time: f64,
+) -> Result<(), Error> {
+    let embeding_uuids: Vec<String> = chunk_uuids.iter().map(|_| Uuid::new_v4().to_string()).collect();
+    let embeddings: Vec<PgVector> = embeddings.into_iter().map(PgVector).collect();
+    let embedding_times: Vec<f64> = vec![embedding_time; chunk_uuids.len()];
+    let now = Utc::now();
+
+    let query = format!(
+        "WITH input AS (
+            SELECT * FROM unnest($1::text[], $2::text[], $3::vector[], $4::float8[])
+                AS i(document_embeding_uuid, document_chunk_uuid, embedding, embedding_time)
+        ), updated AS (
+            UPDATE document_library.\"{table}\" e
+            SET embedding = i.embedding, embedding_token = -1, embedding_time = i.embedding_time, embeder_type = $5, updated_date = $6, updated_by = 'system'
+            FROM input i
+            WHERE e.document_chunk_uuid = i.document_chunk_uuid
+            RETURNING e.document_chunk_uuid
+        )
+        INSERT INTO document_library.\"{table}\" (document_embeding_uuid, document_chunk_uuid, embeder_type, embedding_token, embedding_time, embedding, creation_date, created_by, updated_date, updated_by)
+        SELECT i.document_embeding_uuid, i.document_chunk_uuid, $5, -1, i.embedding_time, i.embedding, $6, 'system', $6, 'system'
+        FROM input i
+        WHERE i.document_chunk_uuid NOT IN (SELECT document_chunk_uuid FROM updated)",
+        table = model.table
+    );
+
+    client
+        .execute(&query, &[&embeding_uuids, &chunk_uuids, &embeddings, &embedding_times, &model.name, &now])
+        .await
+        .map_err(|e| {
+            println!("Database upsert error: {}", e);
+            format!("Database upsert error: {}", e)
+        })?;
+    Ok(())
+}
@@ -1,31 +1,26 @@
-use aws_config::meta::region::RegionProviderChain;
 use aws_lambda_events::s3::S3Event;
 use lambda_runtime::{service_fn, Error, LambdaEvent};
-use aws_sdk_secretsmanager::Client as SecretManagerClient;
 use std::env;
 use chrono::Utc;
 use tokio_postgres::Client;
 use tokio_postgres::types::{Type, ToSql, IsNull};
-use postgres_native_tls::MakeTlsConnector;
-use native_tls::TlsConnector;
 use uuid::Uuid;
 use serde_json::{json, Value};
-use reqwest;
-use std::sync::Arc;
 use bytes::BytesMut;
 
-// Define data structures for representing database entities
-#[derive(Debug)]
-struct Document {
-    document_uuid: String,
-    document_name: Option<String>,
-    document_location: Option<String>,
-}
+use rag_common::db::get_client;
+use rag_common::registry::EmbeddingModel;
+
+mod backfill;
+mod batch;
 
+use backfill::run_backfill;
+use batch::{vectorise_chunks, BatchConfig, VectorisationSummary};
+
+// Chunk of a document to vectorise
 #[derive(Debug)]
 struct DocumentChunk {
     document_chunk_uuid: String,
-    document_uuid: String,
     embebed_text: Option<String>,
 }
 
@@ -65,94 +60,9 @@ impl ToSql for PgVector {
     }
 }
 
-// Function to retrieve secrets from AWS Secrets Manager
-async fn show_secret(secret_name: &str) -> Result<String, Error> {
-    let region_provider = RegionProviderChain::default_provider();
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let client = SecretManagerClient::new(&config);
-    
-    let resp = client.get_secret_value().secret_id(secret_name).send().await?;
-    match resp.secret_string() {
-        Some(secret) => Ok(secret.into()),
-        None => {
-            println!("Error retrieving secret: {}", secret_name);
-            Err("Error retrieving secret".into())
-        }
-    }
-}
-
-// Function to establish connection to the database
-async fn connect_to_db() -> Result<Client, Error> {
-    // Get DB connection string from environment variable
-    let secret_name = env::var("DATABASE_CONECTION_STRING").expect("DATABASE_CONECTION_STRING environment variable not set");
-    
-    // Get the secret from AWS Secrets Manager
-    let db_secret = show_secret(&secret_name).await?;
-    
-    // Parse the JSON secret
-    let db_credentials: serde_json::Value = match serde_json::from_str(&db_secret) {
-        Ok(creds) => creds,
-        Err(e) => {
-            println!("Failed to parse database credentials JSON: {}", e);
-            return Err(format!("JSON parsing error: {}", e).into());
-        }
-    };
-    
-    // Extract connection parameters
-    let db_server = db_credentials["DB_HOST"].as_str()
-        .ok_or("DB_HOST not found in secret")?;
-    let db_port = db_credentials["DB_PORT"].as_str()
-        .ok_or("DB_PORT not found in secret")?;
-    let database = db_credentials["DB_NAME"].as_str()
-        .ok_or("DB_NAME not found in secret")?;
-    let db_username = db_credentials["DB_USER"].as_str()
-        .ok_or("DB_USER not found in secret")?;
-    let db_password = db_credentials["DB_PASSWORD"].as_str()
-        .ok_or("DB_PASSWORD not found in secret")?;
-    
-    println!("Database connection parameters retrieved successfully");
-    
-    // Set up TLS for PostgreSQL connection
-    let tls_connector = TlsConnector::builder()
-        .danger_accept_invalid_certs(true)
-        .build()
-        .map_err(|e| {
-            println!("Failed to create TLS connector: {}", e);
-            format!("TLS error: {}", e)
-        })?;
-        
-    let postgres_tls = MakeTlsConnector::new(tls_connector);
-    
-    // Create a properly formatted connection string
-    let connection_string = format!(
-        "host={} port={} user={} password={} dbname={}",
-        db_server, db_port, db_username, db_password, database
-    );
-    
-    println!("Attempting to connect to database...");
-    
-    // Connect to PostgreSQL
-    let (client, connection) = tokio_postgres::connect(&connection_string, postgres_tls)
-        .await
-        .map_err(|e| {
-            println!("Failed to connect to database: {}", e);
-            format!("Database connection error: {}", e)
-        })?;
-        
-    // The connection object performs the actual communication with the database,
-    // so spawn it off to run on its own
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            println!("Database connection error: {}", e);
-        }
-    });
-    
-    Ok(client)
-}
-
-// Function to find document by name
-async fn find_document_by_name(client: &Client, name: &str) -> Result<Option<Document>, Error> {
-    let query = "SELECT document_uuid, document_name, document_location FROM document_library.documents WHERE document_name = $1";
+// Function to find the UUID of a document by name
+async fn find_document_by_name(client: &Client, name: &str) -> Result<Option<String>, Error> {
+    let query = "SELECT document_uuid FROM document_library.documents WHERE document_name = $1";
     
     let rows = client.query(query, &[&name]).await.map_err(|e| {
         println!("Database query error: {}", e);
@@ -163,19 +73,12 @@ async fn find_document_by_name(client: &Client, name: &str) -> Result<Option<Doc
         return Ok(None);
     }
     
-    let row = &rows[0];
-    let doc = Document {
-        document_uuid: row.get(0),
-        document_name: row.get(1),
-        document_location: row.get(2),
-    };
-    
-    Ok(Some(doc))
+    Ok(Some(rows[0].get(0)))
 }
 
-// Function to find document by location
-async fn find_document_by_location(client: &Client, location: &str) -> Result<Option<Document>, Error> {
-    let query = "SELECT document_uuid, document_name, document_location FROM document_library.documents WHERE document_location = $1";
+// Function to find the UUID of a document by location
+async fn find_document_by_location(client: &Client, location: &str) -> Result<Option<String>, Error> {
+    let query = "SELECT document_uuid FROM document_library.documents WHERE document_location = $1";
     
     let rows = client.query(query, &[&location]).await.map_err(|e| {
         println!("Database query error: {}", e);
@@ -186,19 +89,12 @@ async fn find_document_by_location(client: &Client, location: &str) -> Result<Op
         return Ok(None);
     }
     
-    let row = &rows[0];
-    let doc = Document {
-        document_uuid: row.get(0),
-        document_name: row.get(1),
-        document_location: row.get(2),
-    };
-    
-    Ok(Some(doc))
+    Ok(Some(rows[0].get(0)))
 }
 
 // Function to get document chunks for a document
 async fn get_document_chunks(client: &Client, document_uuid: &str) -> Result<Vec<DocumentChunk>, Error> {
-    let query = "SELECT document_chunk_uuid, document_uuid, embebed_text FROM document_library.document_chunks WHERE document_uuid = $1";
+    let query = "SELECT document_chunk_uuid, embebed_text FROM document_library.document_chunks WHERE document_uuid = $1";
     
     let rows = client.query(query, &[&document_uuid]).await.map_err(|e| {
         println!("Database query error: {}", e);
@@ -208,135 +104,47 @@ async fn get_document_chunks(client: &Client, document_uuid: &str) -> Result<Vec
     let chunks: Vec<DocumentChunk> = rows.iter().map(|row| {
         DocumentChunk {
             document_chunk_uuid: row.get(0),
-            document_uuid: row.get(1),
-            embebed_text: row.get(2),
+            embebed_text: row.get(1),
         }
     }).collect();
     
     Ok(chunks)
 }
 
-// Function to check if embedding exists for a document chunk
-async fn check_embedding_exists(client: &Client, document_chunk_uuid: &str) -> Result<bool, Error> {
-    let query = "SELECT count(*) FROM document_library.document_embeding_mistral_generic WHERE document_chunk_uuid = $1 AND embedding IS NOT NULL";
-    
-    let rows = client.query(query, &[&document_chunk_uuid]).await.map_err(|e| {
-        println!("Database query error: {}", e);
-        format!("Database query error: {}", e)
-    })?;
-    
-    let count: i64 = rows[0].get(0);
-    
-    Ok(count > 0)
-}
-
-// Function to generate embedding using Ollama API
-async fn generate_embedding(text: &str) -> Result<(Vec<f32>, f64), Error> {
-    let ollama_api_url = env::var("OLLAMA_API_URL").expect("OLLAMA_API_URL environment variable not set");
-    
-    let start_time = std::time::Instant::now();
-    
-    let client = reqwest::Client::new();
-    let response = client.post(&ollama_api_url)
-        .json(&json!({
-            "model": "nomic-embed-text",
-            "prompt": text
-        }))
-        .send()
-        .await
-        .map_err(|e| {
-            println!("Failed to make request to Ollama API: {}", e);
-            format!("Ollama API request error: {}", e)
-        })?;
-    
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        println!("Ollama API returned error: {}", error_text);
-        return Err(format!("Ollama API error: {}", error_text).into());
-    }
-    
-    let embedding_result = response.json::<Value>().await.map_err(|e| {
-        println!("Failed to parse Ollama API response: {}", e);
-        format!("Ollama API response parsing error: {}", e)
-    })?;
-    
-    let embedding = match embedding_result["embedding"].as_array() {
-        Some(arr) => arr.iter()
-                      .map(|v| v.as_f64().unwrap_or(0.0) as f32)
-                      .collect::<Vec<f32>>(),
-        None => {
-            println!("Embedding not found in response");
-            return Err("Embedding not found in response".into());
-        }
-    };
-    
-    let elapsed = start_time.elapsed();
-    let elapsed_seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
-    
-    Ok((embedding, elapsed_seconds))
-}
-
-// Function to insert embedding into the database
-async fn insert_or_update_embedding(
+// Function to persist the outcome of the vectorisation of a document with one model
+async fn record_vectorisation(
     client: &Client,
-    document_chunk_uuid: &str,
-    embedding: &Vec<f32>,
-    embedding_time: f64
+    document_uuid: &str,
+    model: &EmbeddingModel,
+    total_chunks: usize,
+    summary: &VectorisationSummary,
+    vectorisation_time: f64
 ) -> Result<(), Error> {
-    // Check if embedding already exists for this chunk
-    let check_query = "SELECT document_embeding_uuid FROM document_library.document_embeding_mistral_generic WHERE document_chunk_uuid = $1";
-    let rows = client.query(check_query, &[&document_chunk_uuid]).await.map_err(|e| {
-        println!("Database query error: {}", e);
-        format!("Database query error: {}", e)
-    })?;
-    
+    let query = "INSERT INTO document_library.document_vectorisations (document_vectorisation_uuid, document_uuid, embedding_model_name, total_chunks, processed_chunks, skipped_chunks, failed_chunks, failed_chunk_uuids, last_error, vectorisation_time, creation_date, created_by, updated_date, updated_by) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)";
     let now = Utc::now();
     
-    // Convert the Vec<f32> to our custom PgVector type that implements ToSql
-    let pg_vector = PgVector(embedding.clone());
-    
-    if rows.is_empty() {
-        // Insert new embedding
-        let document_embeding_uuid = Uuid::new_v4().to_string();
-        let query = "INSERT INTO document_library.document_embeding_mistral_generic (document_embeding_uuid, document_chunk_uuid, embeder_type, embedding_token, embedding_time, embedding, creation_date, created_by, updated_date, updated_by) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)";
-        
-        client.execute(
-            query, 
-            &[
-                &document_embeding_uuid,
-                &document_chunk_uuid,
-                &"nomic-embed-text",
-                &(-1), // Ollama doesn't provide token count
-                &embedding_time,
-                &pg_vector, // Use our custom PgVector type
-                &now,
-                &"system",
-                &now,
-                &"system"
-            ]
-        ).await.map_err(|e| {
-            println!("Database insert error: {}", e);
-            format!("Database insert error: {}", e)
-        })?;
-    } else {
-        // Update existing embedding
-        let document_embeding_uuid = rows[0].get::<_, String>(0);
-        let query = "UPDATE document_library.document_embeding_mistral_generic SET embedding = $1, embedding_time = $2, updated_date = $3, updated_by = $4 WHERE document_embeding_uuid = $5";
-        
-        client.execute(
-            query, 
-            &[
-                &pg_vector, // Use our custom PgVector type
-                &embedding_time,
-                &now,
-                &"system",
-                &document_embeding_uuid
-            ]
-        ).await.map_err(|e| {
-            println!("Database update error: {}", e);
-            format!("Database update error: {}", e)
-        })?;
-    }
+    client.execute(
+        query,
+        &[
+            &Uuid::new_v4().to_string(),
+            &document_uuid,
+            &model.name,
+            &(total_chunks as i32),
+            &(summary.processed_chunks as i32),
+            &(summary.skipped_chunks as i32),
+            &(summary.failed_chunk_uuids.len() as i32),
+            &summary.failed_chunk_uuids,
+            &summary.last_error,
+            &vectorisation_time,
+            &now,
+            &"system",
+            &now,
+            &"system"
+        ]
+    ).await.map_err(|e| {
+        println!("Database insert error: {}", e);
+        format!("Database insert error: {}", e)
+    })?;
     
     Ok(())
 }
@@ -346,7 +154,34 @@ async fn handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
     let start_time = std::time::Instant::now();
     
     // Initialize the database connection
-    let client = connect_to_db().await?;
+    let client = get_client().await?;
+    
+    // Backfill of the embeddings of a model over the whole corpus, invoked directly
+    if let Some(request) = event.payload.get("backfill") {
+        return run_backfill(&client, request, event.context.deadline).await;
+    }
+    
+    // The model named by the event, or every active model of the registry so that each
+    // of them can be searched
+    let model_name = event.payload["queryStringParameters"]["embedding_model"]
+        .as_str()
+        .or_else(|| event.payload["embedding_model"].as_str());
+    let models = match model_name {
+        Some(name) => match EmbeddingModel::by_name(&client, name).await? {
+            Some(model) => vec![model],
+            None => {
+                return Ok(json!({
+                    "statusCode": 400,
+                    "body": format!("Unknown or inactive embedding model '{}'", name)
+                }));
+            }
+        },
+        None => EmbeddingModel::active(&client).await?,
+    };
+    if models.is_empty() {
+        return Err("No active model in document_library.embedding_models".into());
+    }
+    println!("Embedding models: {:?}", models.iter().map(|model| &model.name).collect::<Vec<_>>());
     
     // Detect event type (HTTP API Gateway event or direct S3 event)
     let event_type = detect_event_type(&event.payload);
@@ -397,10 +232,10 @@ async fn handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
     println!("Extracted file name: {}", file_name);
     
     // First try to find document by name
-    let document = match find_document_by_name(&client, &file_name).await? {
-        Some(doc) => {
+    let document_uuid = match find_document_by_name(&client, file_name).await? {
+        Some(document_uuid) => {
             println!("Found document by name: {}", file_name);
-            doc
+            document_uuid
         },
         None => {
             // If not found by name, try with the full path but remove prefix if exists
@@ -414,7 +249,7 @@ async fn handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
             println!("Searching for document with location: {}", cleaned_object_key);
             
             match find_document_by_location(&client, &cleaned_object_key).await? {
-                Some(doc) => doc,
+                Some(document_uuid) => document_uuid,
                 None => {
                     println!("Document not found in database for location: {}", cleaned_object_key);
                     return Ok(json!({
@@ -426,67 +261,53 @@ async fn handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
         }
     };
     
-    println!("Found document with UUID: {}", document.document_uuid);
+    println!("Found document with UUID: {}", document_uuid);
     
     // Get all document chunks for this document
-    let chunks = get_document_chunks(&client, &document.document_uuid).await?;
+    let chunks = get_document_chunks(&client, &document_uuid).await?;
     
     println!("Found {} document chunks", chunks.len());
     
-    // Process each chunk
+    // Embed the chunks by batches for each model, and keep the outcome of each model
+    let batch_config = BatchConfig::from_env()?;
     let mut processed_chunks = 0;
     let mut skipped_chunks = 0;
+    let mut failed_chunk_uuids = serde_json::Map::new();
     
-    for chunk in &chunks {
-        // Skip chunks without text
-        if chunk.embebed_text.is_none() || chunk.embebed_text.as_ref().unwrap().is_empty() {
-            println!("Skipping chunk {} with empty text", chunk.document_chunk_uuid);
-            continue;
-        }
+    for model in &models {
+        let model_start_time = std::time::Instant::now();
+        let summary = vectorise_chunks(&client, model, &chunks, &batch_config, false).await?;
+        let model_seconds = model_start_time.elapsed().as_secs_f64();
         
-        // Check if embedding already exists
-        let embedding_exists = check_embedding_exists(&client, &chunk.document_chunk_uuid).await?;
-        if embedding_exists {
-            println!("Skipping chunk {} as embedding already exists", chunk.document_chunk_uuid);
-            skipped_chunks += 1;
-            continue;
-        }
-        
-        // Generate embedding
-        let (embedding, embedding_time) = match generate_embedding(chunk.embebed_text.as_ref().unwrap()).await {
-            Ok(result) => result,
-            Err(e) => {
-                println!("Failed to generate embedding for chunk {}: {}", chunk.document_chunk_uuid, e);
-                continue;
-            }
-        };
+        println!("{}: processed {}, skipped {}, failed {} in {} seconds",
+              model.name, summary.processed_chunks, summary.skipped_chunks, summary.failed_chunk_uuids.len(), model_seconds);
+        record_vectorisation(&client, &document_uuid, model, chunks.len(), &summary, model_seconds).await?;
         
-        // Store embedding in the database
-        match insert_or_update_embedding(&client, &chunk.document_chunk_uuid, &embedding, embedding_time).await {
-            Ok(_) => {
-                println!("Successfully processed chunk {}", chunk.document_chunk_uuid);
-                processed_chunks += 1;
-            },
-            Err(e) => {
-                println!("Failed to insert embedding for chunk {}: {}", chunk.document_chunk_uuid, e);
-            }
+        processed_chunks += summary.processed_chunks;
+        skipped_chunks += summary.skipped_chunks;
+        if !summary.failed_chunk_uuids.is_empty() {
+            failed_chunk_uuids.insert(model.name.clone(), json!(summary.failed_chunk_uuids));
         }
     }
+    let failed_chunks: usize = failed_chunk_uuids.values().filter_map(|uuids| uuids.as_array()).map(|uuids| uuids.len()).sum();
     
     let elapsed = start_time.elapsed();
     let elapsed_seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
     
-    println!("Vectorization process completed. Processed: {}, Skipped: {}, Total time: {} seconds", 
-          processed_chunks, skipped_chunks, elapsed_seconds);
+    println!("Vectorization process completed. Processed: {}, Skipped: {}, Failed: {}, Total time: {} seconds", 
+          processed_chunks, skipped_chunks, failed_chunks, elapsed_seconds);
     
     Ok(json!({
         "statusCode": 200,
         "body": json!({
             "message": "Vectorization process completed",
-            "document_uuid": document.document_uuid,
+            "document_uuid": document_uuid,
             "processed_chunks": processed_chunks,
             "skipped_chunks": skipped_chunks,
+            "failed_chunks": failed_chunks,
+            "failed_chunk_uuids": failed_chunk_uuids,
             "total_chunks": chunks.len(),
+            "embedding_models": models.iter().map(|model| model.name.clone()).collect::<Vec<_>>(),
             "processing_time_seconds": elapsed_seconds
         })
     }))
@@ -514,11 +335,10 @@ impl std::fmt::Display for EventType {
 fn detect_event_type(payload: &Value) -> EventType {
     // Check if it's a direct S3 event (has Records array with s3 object)
     if let Some(records) = payload.get("Records") {
-        if records.is_array() && !records.as_array().unwrap().is_empty() {
-            if records.as_array().unwrap()[0].get("s3").is_some() {
+        if records.is_array() && !records.as_array().unwrap().is_empty()
+            && records.as_array().unwrap()[0].get("s3").is_some() {
                 return EventType::DirectS3;
             }
-        }
     }
     
     // Check if it's an HTTP API Gateway event (has body, headers, etc.)
@@ -598,31 +418,19 @@ fn parse_s3_event(payload: Value, event_type: EventType) -> Result<S3Event, Erro
                         Ok(s3_event) => return Ok(s3_event),
                         Err(_) => {
                             // If it's not a valid JSON string, try parsing it as a JSON value
-                            match serde_json::from_str::<Value>(body_str) {
-                                Ok(body_json) => match serde_json::from_value::<S3Event>(body_json) {
-                                    Ok(s3_event) => return Ok(s3_event),
-                                    Err(_) => {}
-                                },
-                                Err(_) => {}
-                            }
+                            if let Ok(body_json) = serde_json::from_str::<Value>(body_str) { if let Ok(s3_event) = serde_json::from_value::<S3Event>(body_json) { return Ok(s3_event) } }
                         }
                     }
                 }
                 
                 // Try parsing the body as a JSON object
-                match serde_json::from_value::<S3Event>(body.clone()) {
-                    Ok(s3_event) => return Ok(s3_event),
-                    Err(_) => {}
-                }
+                if let Ok(s3_event) = serde_json::from_value::<S3Event>(body.clone()) { return Ok(s3_event) }
             }
             
             // Try parsing from "Records" directly if it exists
             if let Some(records) = payload.get("Records") {
                 let constructed_event = json!({ "Records": records });
-                match serde_json::from_value::<S3Event>(constructed_event) {
-                    Ok(s3_event) => return Ok(s3_event),
-                    Err(_) => {}
-                }
+                if let Ok(s3_event) = serde_json::from_value::<S3Event>(constructed_event) { return Ok(s3_event) }
             }
             
             // Last resort - log detailed information and report failure
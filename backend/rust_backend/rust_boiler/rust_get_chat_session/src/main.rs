@@ -0,0 +1,188 @@
+use chrono::{DateTime, Utc};
+use lambda_http::{run, service_fn, Body, Error, Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio_postgres::Client;
+
+use rag_common::api::{auth_error_response, error_response, json_response, Page};
+use rag_common::auth::authenticate;
+use rag_common::db::get_client;
+
+// Chat sessions of the caller, or one of them with its messages
+#[derive(Debug, Default, Deserialize)]
+struct GetChatSessionRequest {
+    // Session to return with its messages
+    chat_session_uuid: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+// Chat session, as listed
+#[derive(Debug, Serialize)]
+struct ChatSession {
+    chat_session_uuid: String,
+    session_title: Option<String>,
+    message_count: i64,
+    creation_date: Option<DateTime<Utc>>,
+    updated_date: Option<DateTime<Utc>>,
+}
+
+// Message of a session, in its order
+#[derive(Debug, Serialize)]
+struct ChatMessage {
+    message_index: i32,
+    message_role: String,
+    message_content: String,
+    standalone_question: Option<String>,
+    query_ledger_uuid: Option<String>,
+    creation_date: Option<DateTime<Utc>>,
+}
+
+// Sessions of the caller, last updated first, with the number of sessions of the caller
+async fn list_sessions(client: &Client, user_email: &str, page: Page) -> Result<Value, Error> {
+    let sessions: Vec<ChatSession> = client
+        .query(
+            "SELECT s.chat_session_uuid, s.session_title, s.creation_date, s.updated_date,
+                    (SELECT count(*) FROM document_library.chat_messages m
+                     WHERE m.chat_session_uuid = s.chat_session_uuid) AS message_count
+             FROM document_library.chat_sessions s
+             WHERE s.created_by = $1
+             ORDER BY s.updated_date DESC
+             LIMIT $2 OFFSET $3",
+            &[&user_email, &page.limit, &page.offset],
+        )
+        .await?
+        .iter()
+        .map(|row| ChatSession {
+            chat_session_uuid: row.get("chat_session_uuid"),
+            session_title: row.get("session_title"),
+            message_count: row.get("message_count"),
+            creation_date: row.get("creation_date"),
+            updated_date: row.get("updated_date"),
+        })
+        .collect();
+
+    let total: i64 = client
+        .query_one("SELECT count(*) FROM document_library.chat_sessions WHERE created_by = $1", &[&user_email])
+        .await?
+        .get(0);
+
+    println!("Listed {} of {} chat sessions", sessions.len(), total);
+    Ok(page.listing("chat_sessions", sessions, total))
+}
+
+// Session of the caller with its messages, None if the caller has no such session
+async fn session_messages(client: &Client, user_email: &str, chat_session_uuid: &str) -> Result<Option<Value>, Error> {
+    let row = match client
+        .query_opt(
+            "SELECT chat_session_uuid, session_title, creation_date, updated_date
+             FROM document_library.chat_sessions WHERE chat_session_uuid = $1 AND created_by = $2",
+            &[&chat_session_uuid, &user_email],
+        )
+        .await?
+    {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    let messages: Vec<ChatMessage> = client
+        .query(
+            "SELECT message_index, message_role, message_content, standalone_question, query_ledger_uuid, creation_date
+             FROM document_library.chat_messages
+             WHERE chat_session_uuid = $1
+             ORDER BY message_index",
+            &[&chat_session_uuid],
+        )
+        .await?
+        .iter()
+        .map(|row| ChatMessage {
+            message_index: row.get("message_index"),
+            message_role: row.get("message_role"),
+            message_content: row.get("message_content"),
+            standalone_question: row.get("standalone_question"),
+            query_ledger_uuid: row.get("query_ledger_uuid"),
+            creation_date: row.get("creation_date"),
+        })
+        .collect();
+
+    let session = ChatSession {
+        chat_session_uuid: row.get("chat_session_uuid"),
+        session_title: row.get("session_title"),
+        message_count: messages.len() as i64,
+        creation_date: row.get("creation_date"),
+        updated_date: row.get("updated_date"),
+    };
+    println!("Chat session {} has {} messages", chat_session_uuid, messages.len());
+
+    let mut session = json!(session);
+    session["messages"] = json!(messages);
+    Ok(Some(session))
+}
+
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    // An empty body lists the latest sessions
+    let req: GetChatSessionRequest = if event.body().is_empty() {
+        GetChatSessionRequest::default()
+    } else {
+        match serde_json::from_slice(event.body().as_ref()) {
+            Ok(req) => req,
+            Err(e) => return error_response(400, format!("Invalid request format: {}", e)),
+        }
+    };
+
+    let page = match Page::new(req.limit, req.offset) {
+        Ok(page) => page,
+        Err(message) => return error_response(400, message),
+    };
+
+    // Verify the Cognito token of the caller, who only sees its own sessions
+    let auth_header = event.headers().get("Authorization").and_then(|h| h.to_str().ok());
+    let user_email = match authenticate(auth_header).await {
+        Ok(user) => user.email,
+        Err(e) => return auth_error_response(&e),
+    };
+
+    // Connect to the database
+    let client = match get_client().await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("{}", e);
+            return error_response(500, "Database connection failed");
+        }
+    };
+
+    let mut response_body = match &req.chat_session_uuid {
+        Some(chat_session_uuid) => match session_messages(&client, &user_email, chat_session_uuid).await {
+            Ok(Some(session)) => json!({ "chat_session": session }),
+            Ok(None) => return error_response(404, format!("Chat session '{}' not found", chat_session_uuid)),
+            Err(e) => {
+                eprintln!("Query error: {}", e);
+                return error_response(500, "Query execution failed");
+            }
+        },
+        None => match list_sessions(&client, &user_email, page).await {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                eprintln!("Query error: {}", e);
+                return error_response(500, "Query execution failed");
+            }
+        },
+    };
+    response_body["statusAPI"] = json!("OK");
+
+    json_response(200, &response_body)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or(tracing_subscriber::EnvFilter::new("INFO")),
+        )
+        .with_target(false)
+        .without_time()
+        .init();
+
+    run(service_fn(function_handler)).await
+}
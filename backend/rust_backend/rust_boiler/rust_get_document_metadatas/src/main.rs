@@ -1,16 +1,10 @@
 use lambda_http::{run, service_fn, Body, Error, Request, Response};
 use serde::{Serialize, Deserialize};
 use serde_json::json;
-use std::env;
 use std::collections::HashMap;
 use chrono::{DateTime, Utc, NaiveDate};
 
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_secretsmanager::Client as SecretManagerClient;
-
-use tokio_postgres::{Client, Error as OtherError};
-use postgres_native_tls::MakeTlsConnector;
-use native_tls::TlsConnector;
+use rag_common::db::get_client;
 
 // Document metadata struct to map query results
 #[derive(Debug, Serialize, Deserialize)]
@@ -37,22 +31,8 @@ struct DocumentMetadatasResponse {
     metadata_values: HashMap<String, serde_json::Value>,
 }
 
-async fn show_secret(client: &SecretManagerClient, name: &str) -> Result<String, Error> {
-    let resp = client.get_secret_value().secret_id(name).send().await?;
-    match resp.secret_string() {
-        Some(secret) => Ok(secret.into()),
-        None => panic!("Error to get the secret: {:?}", name),
-    }
-}
-
 async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     println!("Request received: {:?}", event);
-    // Access environment variables
-    let region_provider = RegionProviderChain::default_provider().or_else("ap-southeast-1");
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let client_secret = aws_sdk_secretsmanager::Client::new(&config);
-    println!("Access environment variables!");
-
     // Parse request body if available to get specific document_uuid
     let body_str = match event.body() {
         Body::Text(s) => Some(s.to_string()),
@@ -77,59 +57,19 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
         None
     };
 
-    // Decode secret
-    let db_secret_name = env::var("DATABASE_CONECTION_STRING").expect("DATABASE_CONECTION_STRING environment variable not set");
-    let db_secret = show_secret(&client_secret, &db_secret_name).await.unwrap();
-    let db_credentials: serde_json::Value = serde_json::from_str(&db_secret).unwrap();
-    println!("Decoded secret!");
-
-    let db_server = db_credentials["DB_HOST"].as_str().unwrap();
-    println!("Server: {}", db_server);
-    let database = db_credentials["DB_NAME"].as_str().unwrap();
-    println!("Database: {}", database);
-    let db_username = db_credentials["DB_USER"].as_str().unwrap();
-    let db_password = db_credentials["DB_PASSWORD"].as_str().unwrap();
-    let db_port = db_credentials["DB_PORT"].as_str().unwrap();
-    println!("End of get info from secret!");
-
-    let tls_connector = TlsConnector::builder()
-        .danger_accept_invalid_certs(true) // Disable certificate validation for development
-        .build();
-    let tls = MakeTlsConnector::new(tls_connector.expect("Failed to create TLS connector"));
-
-    println!("TLS connector created");
-
-    // Connect to the database with better error handling
-    let connection_string = format!(
-        "host={} port={} user={} password={} dbname={}", 
-        db_server, db_port, db_username, db_password, database
-    );
-    println!("Attempting to connect to database...");
-    
-    let connection_result = tokio_postgres::connect(&connection_string, tls).await;
-
-    let (client, connection) = match connection_result {
-        Ok((client, connection)) => {
-            println!("Successfully connected to database");
-            (client, connection)
-        },
+    // Connect to the database
+    let client = match get_client().await {
+        Ok(client) => client,
         Err(e) => {
-            eprintln!("Failed to connect to database: {}", e);
+            eprintln!("{}", e);
             return Ok(Response::builder()
                 .status(500)
                 .header("content-type", "application/json")
-                .body(json!({"statusAPI": "CRASH", "error": "Database connection failed"}).to_string().into())
+                .body(json!({"statusAPI": "ERROR", "message": "Database connection failed"}).to_string().into())
                 .map_err(Box::new)?);
         }
     };
 
-    // Spawn a new task to manage the connection
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
-
     // Query to fetch document metadata values joined with metadata information
     let query = if document_uuid_filter.is_some() {
         // Query with document_uuid filter
@@ -196,7 +136,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
             "DATE" => {
                 if let Ok(value) = row.try_get::<_, NaiveDate>("metadata_value_date") {
                     println!("Found DATE metadata '{}' with value: {} (as string: {})", 
-                             metadata_name, value, value.format("%Y-%m-%d").to_string());
+                             metadata_name, value, value.format("%Y-%m-%d"));
                 } else {
                     println!("DATE metadata '{}' found but value could not be retrieved", metadata_name);
                 }
@@ -228,7 +168,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     let mut documents_map: HashMap<String, HashMap<String, serde_json::Value>> = HashMap::new();
     
     for metadata in document_metadatas {
-        let metadata_values = documents_map.entry(metadata.document_uuid).or_insert_with(HashMap::new);
+        let metadata_values = documents_map.entry(metadata.document_uuid).or_default();
         
         // Determine which value to use based on metadata_type
         let value = match metadata.metadata_type.as_str() {
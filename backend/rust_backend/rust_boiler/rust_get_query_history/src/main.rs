@@ -0,0 +1,376 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use lambda_http::{run, service_fn, Body, Error, Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio_postgres::{Client, Row};
+
+use rag_common::api::{auth_error_response, error_response, json_response, Page};
+use rag_common::auth::authenticate;
+use rag_common::db::get_client;
+use rag_common::ledger::QueryType;
+use rag_common::search::{readable_by, user_identifier};
+
+// Past questions of the caller, or one of them to reopen
+#[derive(Debug, Default, Deserialize)]
+struct GetQueryHistoryRequest {
+    // Entry to reopen with its filters, citations, chunks, documents and metadata
+    query_ledger_uuid: Option<String>,
+    // Only list the entries of rust_get_chunks (search), rust_openai_answer (answer) or rust_ask (ask)
+    query_type: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+// Entry of the query ledger, as listed
+#[derive(Debug, Serialize)]
+struct QueryLedger {
+    query_ledger_uuid: String,
+    query_type: Option<String>,
+    question: Option<String>,
+    search_query: Option<String>,
+    answer: Option<String>,
+    // The answer is withheld when the caller may no longer read a document it cites
+    answer_withheld: bool,
+    embedding_model: Option<String>,
+    chat_model: Option<String>,
+    token_usage: Value,
+    latency_ms: Option<i32>,
+    creation_date: Option<DateTime<Utc>>,
+    // Documents cited by the answer
+    #[serde(skip)]
+    cited_documents: HashSet<String>,
+}
+
+// Chunk of a reopened entry, in the order it was found or given in
+#[derive(Debug, Serialize)]
+struct QueryChunk {
+    chunk_rank: Option<i32>,
+    document_chunk_uuid: Option<String>,
+    document_uuid: Option<String>,
+    document_name: Option<String>,
+    page_start: Option<i32>,
+    page_end: Option<i32>,
+    embebed_text: Option<String>,
+    similarity: Option<f64>,
+    rerank_score: Option<f64>,
+}
+
+// Document of the chunks of a reopened entry
+#[derive(Debug, Serialize)]
+struct QueryDocument {
+    document_uuid: Option<String>,
+    document_name: Option<String>,
+}
+
+// Metadata the filters of a reopened entry were on
+#[derive(Debug, Serialize)]
+struct QueryMetadata {
+    metadata_uuid: Option<String>,
+    metadata_name: Option<String>,
+}
+
+impl QueryLedger {
+    fn from_row(row: &Row) -> Self {
+        let input_tokens: Option<i32> = row.get("input_tokens");
+        let output_tokens: Option<i32> = row.get("output_tokens");
+        // Same format as the token_usage of the answers
+        let token_usage = match (input_tokens, output_tokens) {
+            (Some(input_tokens), Some(output_tokens)) => json!({
+                "input_tokens": input_tokens,
+                "output_tokens": output_tokens,
+                "total_tokens": input_tokens + output_tokens
+            }),
+            _ => json!(null),
+        };
+        let citations: Option<Value> = row.get("query_citations");
+        let cited_documents = citations
+            .as_ref()
+            .and_then(|citations| citations.get("citations"))
+            .and_then(Value::as_array)
+            .map(|citations| {
+                citations
+                    .iter()
+                    .filter_map(|citation| citation.get("document_uuid").and_then(Value::as_str))
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        QueryLedger {
+            query_ledger_uuid: row.get("query_ledger_uuid"),
+            query_type: row.get("query_type"),
+            question: row.get("query_content"),
+            search_query: row.get("search_query"),
+            answer: row.get("query_answer"),
+            answer_withheld: false,
+            embedding_model: row.get("embedding_model_name"),
+            chat_model: row.get("chat_model_name"),
+            token_usage,
+            latency_ms: row.get("latency_ms"),
+            creation_date: row.get("creation_date"),
+            cited_documents,
+        }
+    }
+
+    // Withhold the answer if a document it cites is not among the `readable` ones
+    fn withhold_answer(&mut self, readable: &HashSet<String>) {
+        if !self.cited_documents.is_subset(readable) {
+            self.answer = None;
+            self.answer_withheld = true;
+        }
+    }
+}
+
+// The documents of `document_uuids` the user bound as `user_identifier` may read
+async fn readable_documents<'a>(
+    client: &Client,
+    user_identifier: &str,
+    document_uuids: impl Iterator<Item = &'a String>,
+) -> Result<HashSet<String>, Error> {
+    let document_uuids: Vec<&String> = document_uuids.collect();
+    if document_uuids.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let query = format!(
+        "SELECT d.document_uuid FROM document_library.documents d WHERE d.document_uuid = ANY($2) AND {}",
+        readable_by("d.document_uuid", "$1")
+    );
+    let rows = client.query(query.as_str(), &[&user_identifier, &document_uuids]).await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+// Columns of the entries, listed or reopened
+const LEDGER_COLUMNS: &str = "query_ledger_uuid, query_type, query_content, search_query, query_answer, \
+    embedding_model_name, chat_model_name, input_tokens, output_tokens, latency_ms, creation_date, \
+    query_filters, query_citations";
+
+// Entries of the caller, newest first, with the number of entries of the caller
+async fn list_entries(
+    client: &Client,
+    user_email: &str,
+    query_type: Option<QueryType>,
+    page: Page,
+) -> Result<Value, Error> {
+    let query_type = query_type.map(QueryType::as_str);
+
+    let query = format!(
+        "SELECT {} FROM document_library.query_ledgers
+         WHERE created_by = $1 AND ($2::text IS NULL OR query_type = $2)
+         ORDER BY creation_date DESC
+         LIMIT $3 OFFSET $4",
+        LEDGER_COLUMNS
+    );
+    let rows = client.query(query.as_str(), &[&user_email, &query_type, &page.limit, &page.offset]).await?;
+    let mut entries: Vec<QueryLedger> = rows.iter().map(QueryLedger::from_row).collect();
+
+    // The security groups of the caller are checked again, access may have been revoked since the entries
+    let user_identifier = user_identifier(client, user_email).await?;
+    let readable = readable_documents(client, &user_identifier, entries.iter().flat_map(|entry| &entry.cited_documents)).await?;
+    for entry in &mut entries {
+        entry.withhold_answer(&readable);
+    }
+
+    let total: i64 = client
+        .query_one(
+            "SELECT count(*) FROM document_library.query_ledgers
+             WHERE created_by = $1 AND ($2::text IS NULL OR query_type = $2)",
+            &[&user_email, &query_type],
+        )
+        .await?
+        .get(0);
+
+    println!("Listed {} of {} entries", entries.len(), total);
+    Ok(page.listing("queries", entries, total))
+}
+
+// Entry of the caller with what it was answered from, None if the caller has no such entry
+async fn reopen_entry(client: &Client, user_email: &str, query_ledger_uuid: &str) -> Result<Option<Value>, Error> {
+    // The security groups of the caller are checked again, access may have been revoked since the entry
+    let user_identifier = user_identifier(client, user_email).await?;
+
+    let query = format!(
+        "SELECT {} FROM document_library.query_ledgers WHERE query_ledger_uuid = $1 AND created_by = $2",
+        LEDGER_COLUMNS
+    );
+    let row = match client.query_opt(query.as_str(), &[&query_ledger_uuid, &user_email]).await? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let filters: Option<Value> = row.get("query_filters");
+    let mut citations: Value = row.get::<_, Option<Value>>("query_citations").unwrap_or_else(|| json!({}));
+    let mut ledger = QueryLedger::from_row(&row);
+
+    // Citations of documents the caller may no longer read are kept without their name and
+    // quote, and the answer and its spans are withheld with them
+    let readable = readable_documents(client, &user_identifier, ledger.cited_documents.iter()).await?;
+    ledger.withhold_answer(&readable);
+    if let Some(citations) = citations.get_mut("citations").and_then(Value::as_array_mut) {
+        for citation in citations.iter_mut().filter_map(Value::as_object_mut) {
+            if ledger.answer_withheld {
+                citation.remove("answer_span");
+            }
+            let document_uuid = citation.get("document_uuid").and_then(Value::as_str).unwrap_or_default();
+            if !readable.contains(document_uuid) {
+                citation.remove("document_name");
+                citation.remove("quote");
+            }
+        }
+    }
+    let mut entry = json!(ledger);
+
+    // Chunks re-vectorised since the entry, and those of documents the caller may no longer
+    // read, are kept with their scores, without their text
+    let query = format!(
+        "SELECT qac.chunk_rank, qac.chunk_uuid, dc.document_uuid, d.document_name, dc.chunck_page_start,
+                dc.chunck_page_end, dc.embebed_text, qac.similarity, qac.rerank_score
+         FROM document_library.query_answer_chunks qac
+         LEFT JOIN document_library.document_chunks dc ON dc.document_chunk_uuid = qac.chunk_uuid
+             AND {}
+         LEFT JOIN document_library.documents d ON d.document_uuid = dc.document_uuid
+         WHERE qac.query_ledger_uuid = $1
+         ORDER BY qac.chunk_rank",
+        readable_by("dc.document_uuid", "$2")
+    );
+    let chunks: Vec<QueryChunk> = client
+        .query(query.as_str(), &[&query_ledger_uuid, &user_identifier])
+        .await?
+        .iter()
+        .map(|row| QueryChunk {
+            chunk_rank: row.get("chunk_rank"),
+            document_chunk_uuid: row.get("chunk_uuid"),
+            document_uuid: row.get("document_uuid"),
+            document_name: row.get("document_name"),
+            page_start: row.get("chunck_page_start"),
+            page_end: row.get("chunck_page_end"),
+            embebed_text: row.get("embebed_text"),
+            similarity: row.get("similarity"),
+            rerank_score: row.get("rerank_score"),
+        })
+        .collect();
+
+    // Same for the names of the documents
+    let query = format!(
+        "SELECT qad.document_uuid, d.document_name
+         FROM document_library.query_answer_documents qad
+         LEFT JOIN document_library.documents d ON d.document_uuid = qad.document_uuid AND {}
+         WHERE qad.query_ledger_uuid = $1
+         ORDER BY d.document_name",
+        readable_by("d.document_uuid", "$2")
+    );
+    let documents: Vec<QueryDocument> = client
+        .query(query.as_str(), &[&query_ledger_uuid, &user_identifier])
+        .await?
+        .iter()
+        .map(|row| QueryDocument { document_uuid: row.get("document_uuid"), document_name: row.get("document_name") })
+        .collect();
+
+    let metadatas: Vec<QueryMetadata> = client
+        .query(
+            "SELECT qm.metadata_uuid, m.metadata_name
+             FROM document_library.query_metadatas qm
+             LEFT JOIN document_library.metadatas m ON m.metadata_uuid = qm.metadata_uuid
+             WHERE qm.query_ledger_uuid = $1
+             ORDER BY m.metadata_name",
+            &[&query_ledger_uuid],
+        )
+        .await?
+        .iter()
+        .map(|row| QueryMetadata { metadata_uuid: row.get("metadata_uuid"), metadata_name: row.get("metadata_name") })
+        .collect();
+
+    println!(
+        "Reopened entry {} with {} chunks and {} documents",
+        query_ledger_uuid,
+        chunks.len(),
+        documents.len()
+    );
+    entry["filters"] = json!(filters);
+    entry["citations"] = citations.get("citations").cloned().unwrap_or_else(|| json!([]));
+    entry["rejected_citations"] = citations.get("rejected_citations").cloned().unwrap_or_else(|| json!([]));
+    entry["chunks"] = json!(chunks);
+    entry["documents"] = json!(documents);
+    entry["metadatas"] = json!(metadatas);
+    Ok(Some(entry))
+}
+
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    // An empty body lists the latest entries
+    let req: GetQueryHistoryRequest = if event.body().is_empty() {
+        GetQueryHistoryRequest::default()
+    } else {
+        match serde_json::from_slice(event.body().as_ref()) {
+            Ok(req) => req,
+            Err(e) => return error_response(400, format!("Invalid request format: {}", e)),
+        }
+    };
+
+    let query_type = match req.query_type.as_deref() {
+        Some(query_type) => match QueryType::parse(query_type) {
+            Some(query_type) => Some(query_type),
+            None => {
+                return error_response(
+                    400,
+                    format!("Unknown query_type '{}', expected search, answer or ask", query_type),
+                )
+            }
+        },
+        None => None,
+    };
+    let page = match Page::new(req.limit, req.offset) {
+        Ok(page) => page,
+        Err(message) => return error_response(400, message),
+    };
+
+    // Verify the Cognito token of the caller, who only sees its own entries
+    let auth_header = event.headers().get("Authorization").and_then(|h| h.to_str().ok());
+    let user_email = match authenticate(auth_header).await {
+        Ok(user) => user.email,
+        Err(e) => return auth_error_response(&e),
+    };
+
+    // Connect to the database
+    let client = match get_client().await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("{}", e);
+            return error_response(500, "Database connection failed");
+        }
+    };
+
+    let mut response_body = match &req.query_ledger_uuid {
+        Some(query_ledger_uuid) => match reopen_entry(&client, &user_email, query_ledger_uuid).await {
+            Ok(Some(entry)) => json!({ "query": entry }),
+            Ok(None) => return error_response(404, format!("Query '{}' not found", query_ledger_uuid)),
+            Err(e) => {
+                eprintln!("Query error: {}", e);
+                return error_response(500, "Query execution failed");
+            }
+        },
+        None => match list_entries(&client, &user_email, query_type, page).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Query error: {}", e);
+                return error_response(500, "Query execution failed");
+            }
+        },
+    };
+    response_body["statusAPI"] = json!("OK");
+
+    json_response(200, &response_body)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or(tracing_subscriber::EnvFilter::new("INFO")),
+        )
+        .with_target(false)
+        .without_time()
+        .init();
+
+    run(service_fn(function_handler)).await
+}
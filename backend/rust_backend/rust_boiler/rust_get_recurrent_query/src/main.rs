@@ -1,15 +1,9 @@
 use lambda_http::{run, service_fn, Body, Error, Request, Response};
 use serde::{Serialize, Deserialize};
 use serde_json::json;
-use std::env;
 use chrono::{DateTime, Utc};
 
-use aws_sdk_secretsmanager::config::Region;
-use aws_sdk_secretsmanager::Client as SecretManagerClient;
-
-use tokio_postgres::types::ToSql;
-use postgres_native_tls::MakeTlsConnector;
-use native_tls::TlsConnector;
+use rag_common::db::get_client;
 
 // RecurrentQuery struct to map query results
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,14 +32,6 @@ struct GetRecurrentQueryRequest {
     user_uuid: Option<String>,
 }
 
-async fn show_secret(client: &SecretManagerClient, name: &str) -> Result<String, Error> {
-    let resp = client.get_secret_value().secret_id(name).send().await?;
-    match resp.secret_string() {
-        Some(secret) => Ok(secret.into()),
-        None => panic!("Error to get the secret: {:?}", name),
-    }
-}
-
 async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     
     // Parse request body if any
@@ -60,69 +46,19 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
         }
     }
     
-    // Get region from environment variable
-    let region_name = env::var("REGION").expect("REGION environment variable not set");
-    
-    // Initialize AWS SDK configuration
-    let region = Region::new(region_name.clone());
-    let config = aws_config::from_env().region(region).load().await;
-    let client_secret = SecretManagerClient::new(&config);
-    println!("AWS SDK initialized!");
-
-    // Get PostgreSQL configuration from environment variables
-    // Decode secret
-    let db_secret_name = env::var("DATABASE_CONECTION_STRING").expect("DATABASE_CONECTION_STRING environment variable not set");
-    let db_secret = show_secret(&client_secret, &db_secret_name).await?;
-    let db_credentials: serde_json::Value = serde_json::from_str(&db_secret)?;
-    println!("Decoded secret!");
-
-    let db_server = db_credentials["DB_HOST"].as_str().unwrap();
-    println!("Server: {}", db_server);
-    let database = db_credentials["DB_NAME"].as_str().unwrap();
-    println!("Database: {}", database);
-    let db_username = db_credentials["DB_USER"].as_str().unwrap();
-    let db_password = db_credentials["DB_PASSWORD"].as_str().unwrap();
-    let db_port = db_credentials["DB_PORT"].as_str().unwrap();
-    println!("End of get info from secret!");
-
-    let tls_connector = TlsConnector::builder()
-        .danger_accept_invalid_certs(true) // Disable certificate validation for development
-        .build();
-    let tls = MakeTlsConnector::new(tls_connector.expect("Failed to create TLS connector"));
-
-    println!("TLS connector created");
-
-    // Connect to the database with better error handling
-    let connection_string = format!(
-        "host={} port={} user={} password={} dbname={}", 
-        db_server, db_port, db_username, db_password, database
-    );
-    println!("Attempting to connect to database...");
-    
-    let connection_result = tokio_postgres::connect(&connection_string, tls).await;
-
-    let (client, connection): (tokio_postgres::Client, _) = match connection_result {
-        Ok((client, connection)) => {
-            println!("Successfully connected to database");
-            (client, connection)
-        },
+    // Connect to the database
+    let client = match get_client().await {
+        Ok(client) => client,
         Err(e) => {
-            eprintln!("Failed to connect to database: {}", e);
+            eprintln!("{}", e);
             return Ok(Response::builder()
                 .status(500)
                 .header("content-type", "application/json")
-                .body(json!({"statusAPI": "CRASH", "error": "Database connection failed"}).to_string().into())
+                .body(json!({"statusAPI": "ERROR", "message": "Database connection failed"}).to_string().into())
                 .map_err(Box::new)?);
         }
     };
 
-    // Spawn a new task to manage the connection
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
-
     // Build query for recurrent queries based on filters
     let mut query = "SELECT * FROM document_library.recurrent_queries_extended".to_string();
     let mut filters = Vec::new();
@@ -210,4 +146,4 @@ async fn main() -> Result<(), Error> {
         .init();
 
     run(service_fn(function_handler)).await
-}
\ No newline at end of file
+}
@@ -1,5 +1,4 @@
 use lambda_http::{run, service_fn, Body, Error, Request, RequestExt, Response};
-use std::env;
 
 // this include is needed to use the current_platform macro
 use current_platform::{COMPILED_ON, CURRENT_PLATFORM};
@@ -1,11 +1,8 @@
-use lambda_http::{run, service_fn, tracing, Body, Error, Request, RequestExt, Response};
+use lambda_http::{run, service_fn, tracing, Body, Error, Request, Response};
 // JSON Schema Validation
 use jsonschema::JSONSchema;
 use serde_json::{json, Value};
 // Standard library imports
-use std::env;
-use std::fs::File;
-use std::io::Read;
 
 
 /// Load JSON schema from file and compile it
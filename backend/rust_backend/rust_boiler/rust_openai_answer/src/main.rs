@@ -1,283 +1,214 @@
-
 use std::env;
-use aws_sdk_secretsmanager::Client as SecretManagerClient;
-use aws_config::Region;
-use lambda_http::{run, service_fn, Body, Error, Request, Response};
-use tracing_subscriber;
-use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
-use reqwest::Client as ReqwestClient;
-use chrono::NaiveDate;
+use std::time::{Duration, Instant};
 
+use bytes::Bytes;
+use lambda_http::{run, run_with_streaming_response, service_fn, Body, Error, Request, Response};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use rag_common::answer::{answer_json, chat_messages, ground};
+use rag_common::api::{auth_error_response, error_response};
+use rag_common::auth::authenticate;
+use rag_common::chat::{ChatCompletion, ChatMessage, ChatModel};
+use rag_common::citations::GroundedAnswer;
+use rag_common::context::{assemble, context_token_budget};
+use rag_common::conversation::{append_turn, history_messages, history_token_budget, session_turns, ChatTurn};
+use rag_common::db::get_client;
+use rag_common::ledger::{record, LedgerEntry, QueryType};
+use rag_common::registry::ChatModelConfig;
+use rag_common::search::{readable_chunks, user_identifier, Chunk};
+
+
+/// Chunk of the context given back by the client, as returned by rust_get_chunks. Only its uuid is read,
+/// its text, document and metadata are read again from the database
+#[derive(Deserialize)]
+struct ChunkReference {
+    document_chunk_uuid: String,
+}
 
 #[derive(Deserialize)]
 struct OpenAIAnswerRequest {
     question: String,
-    chunks: Vec<Chunk>,
-}
-
-#[derive(Debug, Deserialize)]
-struct Chunk {
-    document_uuid: String,
-    document_name: String,
-    document_location: String,
-    document_hash: String,
-
-    document_type: String,
-
-    document_status: String,
-    
-    /// Unique identifier for this specific chunk
-    document_chunk_uuid: String,
-    
-    /// The actual text content extracted from the document
-    embebed_text: String,
-    
-    /// Unique identifier for the embedding of this chunk
-    document_embeding_uuid: String,
-    
-    /// Type of model used for embedding (openai, etc.)
-    embeder_type: String,
-    
-    /// Number of tokens used in the embedding
-    embedding_token: i32,
-    
-    /// Time taken to generate the embedding in seconds
-    embedding_time: f64,
-    
-    /// Collection of metadata properties associated with the document
-    document_metadata: Vec<DocumentMetadata>,
+    /// Chunks as returned by rust_get_chunks
+    chunks: Vec<ChunkReference>,
+    /// Chat model of document_library.chat_models answering, the one of CHAT_MODEL by default
+    #[serde(default)]
+    chat_model: Option<String>,
+    /// Chat session the question follows up on, whose previous turns are given to the chat model.
+    /// The question and its answer are added to it
+    #[serde(default)]
+    chat_session_uuid: Option<String>,
+    /// Question the chunks were searched with, as returned by rust_get_chunks for the session
+    #[serde(default)]
+    standalone_question: Option<String>,
 }
 
-/// Structure for document metadata with various possible data types
+/// Stream the answer of the chat model, sending each piece of it as a `token`
+/// server-sent event as soon as it arrives
 ///
-/// Metadata can be stored in different formats (string, integer, float, boolean, date)
-/// and this structure allows for any of those types to be used flexibly.
-#[derive(Debug, Deserialize)]
-struct DocumentMetadata {
-    /// Unique identifier for this metadata entry
-    metadata_uuid: String,
-    
-    /// Name/key of the metadata property
-    metadata_name: String,
-    
-    /// Value as a string (if applicable)
-    metadata_value_string: Option<String>,
-    
-    /// Value as an integer (if applicable)
-    metadata_value_int: Option<i32>,
-    
-    /// Value as a float (if applicable)
-    metadata_value_float: Option<f64>,
-    
-    /// Value as a boolean (if applicable)
-    metadata_value_boolean: Option<bool>,
+/// # Returns
+/// * `Result<ChatCompletion, Error>` - The whole answer and its token usage, once the stream ended
+async fn stream_answer(
+    chat_model: &dyn ChatModel,
+    messages: &[ChatMessage],
+    sender: &mut hyper::body::Sender
+) -> Result<ChatCompletion, Error> {
+    let (tokens, mut receiver) = mpsc::unbounded_channel();
+    let completion = async move {
+        // The sending half is dropped with the end of the completion, which ends the forwarding
+        chat_model.stream(messages, &tokens).await
+    };
+    // The receiving half is dropped if the client goes away, which stops the completion
+    let forward = async move {
+        while let Some(token) = receiver.recv().await {
+            send_event(sender, "token", &json!({ "content": token })).await?;
+        }
+        Ok::<(), Error>(())
+    };
     
-    /// Value as a date (if applicable)
-    #[serde(with = "naive_date_format")]
-    metadata_value_date: Option<chrono::NaiveDate>,
+    let (completion, forwarded) = tokio::join!(completion, forward);
+    forwarded?;
+    completion
 }
 
-/// Custom serializer for handling NaiveDate formats in JSON
-///
-/// Provides custom deserialization logic for date strings in the format YYYY-MM-DD
-/// into Rust's NaiveDate type, with proper error handling.
-mod naive_date_format {
-    use chrono::NaiveDate;
-    use serde::{self, Deserialize, Deserializer};
-
-    /// Deserialize an Option<String> into an Option<NaiveDate>
-    ///
-    /// # Arguments
-    /// * `deserializer` - The deserializer to use
-    ///
-    /// # Returns
-    /// * `Result<Option<NaiveDate>, D::Error>` - The deserialized date or an error
-    pub fn deserialize<'de, D>(
-        deserializer: D,
-    ) -> Result<Option<NaiveDate>, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let opt = Option::<String>::deserialize(deserializer)?;
-        if let Some(s) = opt {
-            return Ok(Some(NaiveDate::parse_from_str(&s, "%Y-%m-%d")
-                .map_err(serde::de::Error::custom)?));
-        }
-        Ok(None)
-    }
+/// Send one server-sent event to the client
+async fn send_event(sender: &mut hyper::body::Sender, event: &str, data: &Value) -> Result<(), Error> {
+    sender
+        .send_data(Bytes::from(format!("event: {}\ndata: {}\n\n", event, data)))
+        .await
+        .map_err(|_| "The client closed the stream".into())
 }
 
-/// Request structure for OpenAI Chat API
+/// Registry entry of the chat model named by the request, or of the one of CHAT_MODEL
 ///
-/// Contains all parameters needed to make a request to OpenAI's chat completion API.
-#[derive(Serialize)]
-struct OpenAIRequest {
-    /// Model identifier (e.g., "gpt-4o-mini")
-    model: String,
-    
-    /// Array of message objects containing the conversation history
-    messages: Vec<Message>,
-    
-    /// Controls randomness: 0 is deterministic, higher values are more random
-    temperature: f32,
-    
-    /// Maximum number of tokens to generate in the completion
-    max_tokens: u32,
+/// # Returns
+/// * `Result<Option<ChatModelConfig>, Error>` - `None` if the requested model is unknown or inactive
+async fn select_chat_model(name: Option<&str>) -> Result<Option<ChatModelConfig>, Error> {
+    let client = get_client().await?;
+    ChatModelConfig::select(&client, name).await
 }
 
-/// Message structure for OpenAI Chat API
-///
-/// Represents a single message in the conversation context
-#[derive(Serialize)]
-struct Message {
-    /// The role of the message sender (system, user, assistant)
-    role: String,
-    
-    /// The actual text content of the message
-    content: String,
+/// Bad request response for a chat model missing from the registry, for both handlers
+fn unknown_chat_model<B: From<String>>(name: &str) -> Result<Response<B>, Error> {
+    error_response(400, format!("Unknown or inactive chat model '{}'", name))
 }
 
-/// Response structure from OpenAI Chat API
-///
-/// Contains the response data returned by OpenAI's API
-#[derive(Deserialize)]
-struct OpenAIResponse {
-    /// Array of generated completions (usually just one)
-    choices: Vec<Choice>,
-    
-    /// Token usage statistics if provided
-    usage: Option<Usage>,
+/// Not found response for a chat session that is not one of the caller, for both handlers
+fn session_not_found<B: From<String>>(chat_session_uuid: &str) -> Result<Response<B>, Error> {
+    error_response(404, format!("Chat session '{}' not found", chat_session_uuid))
 }
 
-/// Choice structure from OpenAI Chat API response
-///
-/// Contains a single completion choice from the API response
-#[derive(Deserialize)]
-struct Choice {
-    /// The message content of the completion
-    message: ChatMessage,
+/// Bad request response for chunks that are unknown or that the caller may not read, for both handlers
+fn unknown_chunks<B: From<String>>(chunk_uuids: &[String]) -> Result<Response<B>, Error> {
+    error_response(400, format!("Unknown chunks '{}'", chunk_uuids.join("', '")))
 }
 
-/// Message content from OpenAI Chat API response
+/// Chunks of the request read again from the database, through the security groups of the caller
 ///
-/// The actual content of the generated completion
-#[derive(Deserialize)]
-struct ChatMessage {
-    /// The text content of the message
-    content: String,
+/// # Returns
+/// * `Result<Result<Vec<Chunk>, Vec<String>>, Error>` - The chunks in the order of the request, or the uuids
+///   of the request that are unknown or that the caller may not read
+async fn context_chunks(req: &OpenAIAnswerRequest, user_email: &str) -> Result<Result<Vec<Chunk>, Vec<String>>, Error> {
+    let chunk_uuids: Vec<String> = req.chunks.iter().map(|chunk| chunk.document_chunk_uuid.clone()).collect();
+    let client = get_client().await?;
+    let user_identifier = user_identifier(&client, user_email).await?;
+    let chunks = readable_chunks(&client, &user_identifier, &chunk_uuids).await?;
+
+    let unknown: Vec<String> = chunk_uuids
+        .into_iter()
+        .filter(|uuid| !chunks.iter().any(|chunk| chunk.document_chunk_uuid == *uuid))
+        .collect();
+    if !unknown.is_empty() {
+        return Ok(Err(unknown));
+    }
+    Ok(Ok(chunks))
 }
 
-/// Token usage statistics from OpenAI API
+/// Previous turns of the chat session of the request, within the token budget of the history
 ///
-/// Tracks token usage for billing and rate limiting purposes
-#[derive(Debug, Deserialize, Serialize)]
-struct Usage {
-    /// Number of tokens in the prompt (input)
-    prompt_tokens: u32,
-    
-    /// Number of tokens in the completion (output)
-    completion_tokens: u32,
-    
-    /// Total tokens used in the request
-    total_tokens: u32,
+/// # Returns
+/// * `Result<Option<Vec<ChatMessage>>, Error>` - No messages without a session, `None` if the session is not one of the caller
+async fn session_history(req: &OpenAIAnswerRequest, user_email: &str) -> Result<Option<Vec<ChatMessage>>, Error> {
+    let chat_session_uuid = match &req.chat_session_uuid {
+        Some(chat_session_uuid) => chat_session_uuid,
+        None => return Ok(Some(Vec::new())),
+    };
+    let client = get_client().await?;
+    match session_turns(&client, chat_session_uuid, user_email).await? {
+        Some(turns) => Ok(Some(history_messages(&turns, history_token_budget()?))),
+        None => Ok(None),
+    }
 }
 
-/// Simplified response structure for chat completions
+/// Record the answer in the query ledger, without failing the answer if the ledger cannot be written
 ///
-/// Our own simplified structure for passing the API response around
-#[derive(Debug, Serialize)]
-struct ChatResponse {
-    /// The generated text content
-    content: String,
-    
-    /// Token usage statistics if available
-    usage: Option<Usage>,
-}
-
-async fn show_secret(client: &SecretManagerClient, name: &str) -> Result<String, Error> {
-    // Get the secret value from AWS Secrets Manager
-    let resp = match client.get_secret_value().secret_id(name).send().await {
-        Ok(response) => response,
-        Err(e) => return Err(format!("Failed to retrieve secret '{}': {}", name, e).into()),
+/// # Returns
+/// * `Option<String>` - The uuid of the ledger entry, `None` if it could not be recorded
+async fn record_answer(
+    req: &OpenAIAnswerRequest,
+    user_email: &str,
+    chunks: &[Chunk],
+    answer: &ChatCompletion,
+    grounded: &GroundedAnswer,
+    chat_model: &str,
+    latency: Duration
+) -> Option<String> {
+    let recorded = async {
+        let client = get_client().await?;
+        let user_identifier = user_identifier(&client, user_email).await?;
+        let entry = LedgerEntry {
+            query_type: QueryType::Answer,
+            question: &req.question,
+            user_email,
+            user_identifier: &user_identifier,
+            search: None,
+            search_query: None,
+            embedding_model: None,
+            chat_model: Some(chat_model),
+            answer: Some(grounded),
+            usage: answer.usage.as_ref(),
+            latency,
+            chunks,
+        };
+        record(&client, &entry).await
     };
-    
-    // Extract the secret string from the response
-    match resp.secret_string() {
-        Some(secret) => Ok(secret.into()),
-        None => Err(format!("Secret string not found in response for '{}'", name).into())
+    match recorded.await {
+        Ok(uuid) => Some(uuid),
+        Err(e) => {
+            tracing::error!("Failed to record the answer in the query ledger: {}", e);
+            None
+        }
     }
 }
 
-
-async fn call_openai_chat(
-    api_key: &str, 
-    model: &str, 
-    system_prompt: &str, 
-    question: &str, 
-    context: &str
-) -> Result<ChatResponse, reqwest::Error> {
-    let client = ReqwestClient::new();
-    
-    // Format the user's input with context and question
-    let user_content = format!("Context: {}\n\nQuestion: {}", context, question);
-    
-    // Build the message array for the API request
-    let messages = vec![
-        Message {
-            role: "system".to_string(),
-            content: system_prompt.to_string(),
-        },
-        Message {
-            role: "user".to_string(),
-            content: user_content,
-        },
-    ];
-    
-    let request = OpenAIRequest {
-        model: model.to_string(),
-        messages,
-        temperature: 0.7,
-        max_tokens: 2000,
+/// Add the question and its answer to the chat session of the request, if any, without failing the answer
+/// if they cannot be saved
+async fn record_turn(req: &OpenAIAnswerRequest, user_email: &str, grounded: &GroundedAnswer, query_ledger_uuid: &Option<String>) {
+    let chat_session_uuid = match &req.chat_session_uuid {
+        Some(chat_session_uuid) => chat_session_uuid,
+        None => return,
     };
-
-    // Send the request to OpenAI
-    let response_result = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&request)
-        .send()
-        .await;
-    
-    // Handle the HTTP response
-    let response = match response_result {
-        Ok(resp) => resp,
-        Err(e) => return Err(e),
-    };
-    
-    // Parse the JSON response
-    let parsed_response = match response.json::<OpenAIResponse>().await {
-        Ok(parsed) => parsed,
-        Err(e) => return Err(e),
+    let turn = ChatTurn {
+        question: req.question.clone(),
+        standalone_question: req.standalone_question.clone().filter(|question| *question != req.question),
+        answer: grounded.answer.clone(),
+        query_ledger_uuid: query_ledger_uuid.clone(),
     };
-
-    // Extract the generated content
-    let content = if parsed_response.choices.is_empty() {
-        "No answer generated.".to_string()
-    } else {
-        parsed_response.choices[0].message.content.clone()
+    let appended = async {
+        let client = get_client().await?;
+        append_turn(&client, chat_session_uuid, user_email, &turn).await
     };
-
-    Ok(ChatResponse {
-        content,
-        usage: parsed_response.usage,
-    })
+    if let Err(e) = appended.await {
+        tracing::error!("Failed to add the question to the chat session {}: {}", chat_session_uuid, e);
+    }
 }
 
 /// Lambda function handler that processes incoming API requests
 ///
-/// Handles the API gateway request, extracts the question and document chunks,
-/// calls OpenAI API to generate an answer, and returns a formatted response.
+/// Handles the API gateway request, extracts the question and reads the document chunks
+/// the caller may read, asks the chat model to generate an answer after the previous turns of the chat session,
+/// records it in the query ledger and the session, and returns a formatted response.
 ///
 /// # Arguments
 /// * `event` - The Lambda request event from API Gateway
@@ -285,122 +216,154 @@ async fn call_openai_chat(
 /// # Returns
 /// * `Result<Response<Body>, Error>` - HTTP response or error
 async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    let started = Instant::now();
+
     // Parse the incoming request
     let req: OpenAIAnswerRequest = match serde_json::from_slice(event.body().as_ref()) {
         Ok(r) => r,
         Err(e) => return Err(Box::new(e)),
     };
 
-    // Get region from environment variable
-    let region_name = env::var("REGION").expect("REGION environment variable not set");
-    let region = Region::new(region_name.clone());
-    
-    // Initialize AWS SDK configuration
-    let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(region)
-        .load()
-        .await;
-    let client_secret = SecretManagerClient::new(&config);
-
-    // Get database connection string from environment
-    let db_secret_name = env::var("DATABASE_CONECTION_STRING")
-        .expect("DATABASE_CONECTION_STRING environment variable not set");
-    
-    // Retrieve the secret from AWS Secrets Manager
-    let db_secret = match show_secret(&client_secret, &db_secret_name).await {
-        Ok(secret) => secret,
-        Err(e) => return Err(e),
-    };
-    
-    // Parse the secret JSON
-    let db_credentials: serde_json::Value = match serde_json::from_str(&db_secret) {
-        Ok(creds) => creds,
-        Err(e) => return Err(Box::new(e)),
+    // Verify the Cognito token of the caller
+    let auth_header = event.headers().get("Authorization").and_then(|h| h.to_str().ok());
+    let user_email = match authenticate(auth_header).await {
+        Ok(user) => user.email,
+        Err(e) => return auth_error_response(&e),
     };
 
-    // Extract OpenAI API key from secrets
-    let openai_api_key = match db_credentials["OPENAI_API_KEY"].as_str() {
-        Some(key) => key,
-        None => return Err("Failed to extract OpenAI API key from secrets".into()),
+    // The chunks are read again from the database, the caller may only give those it can read
+    let chunks = match context_chunks(&req, &user_email).await? {
+        Ok(chunks) => chunks,
+        Err(chunk_uuids) => return unknown_chunks(&chunk_uuids),
     };
 
-    // Prepare the context by combining all chunks' text and metadata
-    let context = req.chunks
-        .iter()
-        .map(|chunk| {
-            // Convert metadata to string representation
-            let metadata_str = chunk.document_metadata
-                .iter()
-                .map(|meta| {
-                    let value = if let Some(s) = &meta.metadata_value_string {
-                        s.clone()
-                    } else if let Some(i) = meta.metadata_value_int {
-                        i.to_string()
-                    } else if let Some(f) = meta.metadata_value_float {
-                        f.to_string()
-                    } else if let Some(b) = meta.metadata_value_boolean {
-                        b.to_string()
-                    } else if let Some(d) = meta.metadata_value_date {
-                        d.format("%Y-%m-%d").to_string()
-                    } else {
-                        "N/A".to_string()
-                    };
-                    format!("{}: {}", meta.metadata_name, value)
-                })
-                .collect::<Vec<String>>()
-                .join("\n");
-            
-            format!(
-                "Document: {}\nMetadata:\n{}\nContent: {}", 
-                chunk.document_name,
-                metadata_str,
-                chunk.embebed_text
-            )
-        })
-        .collect::<Vec<String>>()
-        .join("\n\n");
-    
-    // Define the system prompt
-    let system_prompt = "You are a helpful assistant that answers questions based on the provided document context and metadata. \
-    Provide clear and concise answers based on both the content and metadata of the provided documents. \
-    If the answer cannot be found in the context or metadata, state that you don't have enough information to answer.";
-    
-    // Call OpenAI to get the answer
-    let model = "gpt-4o-mini"; // or another appropriate model
-    
-    let answer = match call_openai_chat(&openai_api_key, model, system_prompt, &req.question, &context).await {
-        Ok(response) => response,
-        Err(e) => return Err(Box::new(e)),
+    // Previous turns of the chat session, if the question follows up on one
+    let history = match session_history(&req, &user_email).await? {
+        Some(history) => history,
+        None => return session_not_found(req.chat_session_uuid.as_deref().unwrap_or_default()),
     };
 
-    // Format the response with token usage information
-    let token_usage = match answer.usage {
-        Some(usage) => json!({
-            "input_tokens": usage.prompt_tokens,
-            "output_tokens": usage.completion_tokens,
-            "total_tokens": usage.total_tokens
-        }),
-        None => json!(null),
+    // Chat model of the request, or the one of the function
+    let chat_model_config = match select_chat_model(req.chat_model.as_deref()).await? {
+        Some(config) => config,
+        None => return unknown_chat_model(req.chat_model.as_deref().unwrap_or_default()),
     };
+    let chat_model = chat_model_config.chat_model().await?;
 
-    let response_body = json!({
-        "answer": answer.content,
-        "token_usage": token_usage
-    });
+    // Prepare the context from the text and metadata of the chunks that fit in its token budget
+    let context = assemble(&chunks, context_token_budget()?);
+    
+    // Ask the chat model for the answer
+    let answer = chat_model.complete(&chat_messages(&req.question, &context.text, &history)).await?;
+
+    // Format the response with the citations and token usage information, and keep a trace of it
+    let grounded = ground(&answer.content, &context);
+    let query_ledger_uuid = record_answer(&req, &user_email, &chunks, &answer, &grounded, &chat_model_config.name, started.elapsed()).await;
+    record_turn(&req, &user_email, &grounded, &query_ledger_uuid).await;
+    let mut response_body = answer_json(&grounded, &context, &answer.usage, &chat_model_config.name);
+    response_body["query_ledger_uuid"] = json!(query_ledger_uuid);
+    response_body["chat_session_uuid"] = json!(req.chat_session_uuid);
 
     // Build and return the HTTP response
     let resp = Response::builder()
         .status(200)
         .header("content-type", "application/json")
         .body(response_body.to_string().into())
-        .map_err(|e| Box::new(e))?;
+        .map_err(Box::new)?;
     
     Ok(resp)
 }
 
+/// Lambda function handler streaming the answer, behind a function URL in response streaming mode
+///
+/// Answers with server-sent events as the tokens arrive from the chat model:
+/// `token` events with the next piece of the answer, then a `done` event with the whole answer,
+/// its token usage and citations, or an `error` event if the completion failed midway.
+/// Citations of chunks that were not provided are only removed from the answer of the `done` event,
+/// which is sent once the answer is recorded in the query ledger and the chat session.
+///
+/// # Arguments
+/// * `event` - The Lambda request event from the function URL
+///
+/// # Returns
+/// * `Result<Response<hyper::Body>, Error>` - HTTP response whose body is fed as the answer streams
+async fn stream_handler(event: Request) -> Result<Response<hyper::Body>, Error> {
+    let started = Instant::now();
+
+    // Parse the incoming request
+    let req: OpenAIAnswerRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(r) => r,
+        Err(e) => {
+            return error_response(400, format!("Invalid request: {}", e));
+        }
+    };
+
+    // Verify the Cognito token of the caller
+    let auth_header = event.headers().get("Authorization").and_then(|h| h.to_str().ok());
+    let user_email = match authenticate(auth_header).await {
+        Ok(user) => user.email,
+        Err(e) => return auth_error_response(&e),
+    };
+
+    // The chunks are read again from the database, the caller may only give those it can read
+    let chunks = match context_chunks(&req, &user_email).await? {
+        Ok(chunks) => chunks,
+        Err(chunk_uuids) => return unknown_chunks(&chunk_uuids),
+    };
+
+    // Previous turns of the chat session, if the question follows up on one
+    let history = match session_history(&req, &user_email).await? {
+        Some(history) => history,
+        None => return session_not_found(req.chat_session_uuid.as_deref().unwrap_or_default()),
+    };
+
+    // Chat model of the request, or the one of the function
+    let chat_model_config = match select_chat_model(req.chat_model.as_deref()).await? {
+        Some(config) => config,
+        None => return unknown_chat_model(req.chat_model.as_deref().unwrap_or_default()),
+    };
+    let chat_model = chat_model_config.chat_model().await?;
+
+    let context_budget = context_token_budget()?;
+
+    // The response is returned at once, its body is written by this task as the chat model streams
+    let (mut sender, body) = hyper::Body::channel();
+    tokio::spawn(async move {
+        let context = assemble(&chunks, context_budget);
+        let messages = chat_messages(&req.question, &context.text, &history);
+        match stream_answer(chat_model.as_ref(), &messages, &mut sender).await {
+            Ok(answer) => {
+                let grounded = ground(&answer.content, &context);
+                let query_ledger_uuid = record_answer(&req, &user_email, &chunks, &answer, &grounded, &chat_model_config.name, started.elapsed()).await;
+                record_turn(&req, &user_email, &grounded, &query_ledger_uuid).await;
+                let mut done = answer_json(&grounded, &context, &answer.usage, &chat_model_config.name);
+                done["query_ledger_uuid"] = json!(query_ledger_uuid);
+                done["chat_session_uuid"] = json!(req.chat_session_uuid);
+                if let Err(e) = send_event(&mut sender, "done", &done).await {
+                    tracing::warn!("Failed to send the end of the answer: {}", e);
+                }
+            }
+            Err(e) => {
+                tracing::error!("Streaming of the answer failed: {}", e);
+                let _ = send_event(&mut sender, "error", &json!({ "message": e.to_string() })).await;
+            }
+        }
+    });
+
+    let resp = Response::builder()
+        .status(200)
+        .header("content-type", "text/event-stream")
+        .header("cache-control", "no-cache")
+        .body(body)
+        .map_err(Box::new)?;
+
+    Ok(resp)
+}
+
 /// Entry point for the Lambda function
 ///
-/// Sets up tracing and starts the Lambda runtime with our function handler
+/// Sets up tracing and starts the Lambda runtime with our function handler, or with the
+/// streaming one when `ANSWER_STREAMING` is true
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     // Initialize tracing for better observability
@@ -413,6 +376,17 @@ async fn main() -> Result<(), Error> {
         .without_time()
         .init();
 
+    // The same binary serves the API Gateway endpoint and the streaming function URL
+    let streaming = env::var("ANSWER_STREAMING").expect("ANSWER_STREAMING environment variable not set");
+    let streaming: bool = streaming
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid ANSWER_STREAMING '{}': {}", streaming, e))?;
+
     // Start the Lambda runtime with our function handler
-    run(service_fn(function_handler)).await
+    if streaming {
+        run_with_streaming_response(service_fn(stream_handler)).await
+    } else {
+        run(service_fn(function_handler)).await
+    }
 }
\ No newline at end of file
@@ -0,0 +1,668 @@
+// Text extraction of the supported document formats
+//
+// Each format implements `Extractor`, `detect` picks the extractor of a file from its
+// magic bytes, its MIME type, its extension, and finally a look at its content.
+
+use std::io::{Cursor, Read};
+use std::ops::Range;
+
+use lambda_runtime::Error;
+use mail_parser::{MessageParser, MimeHeaders};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use crate::pdf_layout::GlyphCollector;
+
+// A table found in a document, as rows of cells with the header row first
+pub struct ExtractedTable {
+    pub page: Option<i32>,
+    pub rows: Vec<Vec<String>>,
+}
+
+// Text of a document, with the byte offset where each page starts for paged formats, the
+// tables taken out of the text, and for text read by OCR the offset and confidence of each word
+pub struct ExtractedText {
+    pub text: String,
+    pub page_starts: Vec<usize>,
+    pub tables: Vec<ExtractedTable>,
+    pub ocr: bool,
+    pub word_confidences: Vec<(usize, f32)>,
+}
+
+impl ExtractedText {
+    fn plain(text: String) -> Self {
+        ExtractedText {
+            text,
+            page_starts: Vec::new(),
+            tables: Vec::new(),
+            ocr: false,
+            word_confidences: Vec::new(),
+        }
+    }
+
+    // Join the text of each page with a blank line, keeping where each page starts
+    pub fn paged(pages: Vec<String>) -> Self {
+        let mut text = String::new();
+        let mut page_starts = Vec::with_capacity(pages.len());
+        for page in pages {
+            if !text.is_empty() {
+                text.push_str("\n\n");
+            }
+            page_starts.push(text.len());
+            text.push_str(&page);
+        }
+        ExtractedText {
+            text,
+            page_starts,
+            tables: Vec::new(),
+            ocr: false,
+            word_confidences: Vec::new(),
+        }
+    }
+
+    // Page number, from 1, holding the byte at `offset`, None for formats without pages
+    pub fn page_at(&self, offset: usize) -> Option<i32> {
+        if self.page_starts.is_empty() {
+            return None;
+        }
+        Some(self.page_starts.partition_point(|&start| start <= offset).max(1) as i32)
+    }
+
+    // Average OCR confidence, from 0 to 100, of the words starting in `range`, None when the
+    // text was not read by OCR
+    pub fn confidence_in(&self, range: Range<usize>) -> Option<f32> {
+        if !self.ocr {
+            return None;
+        }
+        let first = self.word_confidences.partition_point(|&(offset, _)| offset < range.start);
+        let last = self.word_confidences.partition_point(|&(offset, _)| offset < range.end);
+        let words = &self.word_confidences[first..last];
+        if words.is_empty() {
+            return None;
+        }
+        Some(words.iter().map(|&(_, confidence)| confidence).sum::<f32>() / words.len() as f32)
+    }
+}
+
+pub trait Extractor: Send + Sync {
+    // Value stored in documents.document_type for this format
+    fn document_type(&self) -> &'static str;
+
+    // Extract the text of the file, ready to be chunked
+    fn extract(&self, bytes: &[u8]) -> Result<ExtractedText, Error>;
+}
+
+pub struct PdfExtractor;
+pub struct DocxExtractor;
+pub struct HtmlExtractor;
+pub struct MarkdownExtractor;
+pub struct TextExtractor;
+pub struct CsvExtractor;
+pub struct EmlExtractor;
+pub struct MsgExtractor;
+
+static PDF: PdfExtractor = PdfExtractor;
+static DOCX: DocxExtractor = DocxExtractor;
+static HTML: HtmlExtractor = HtmlExtractor;
+static MARKDOWN: MarkdownExtractor = MarkdownExtractor;
+static TEXT: TextExtractor = TextExtractor;
+static CSV: CsvExtractor = CsvExtractor;
+static EML: EmlExtractor = EmlExtractor;
+static MSG: MsgExtractor = MsgExtractor;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const CFB_MAGIC: &[u8] = b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1";
+
+// Pick the extractor of a file, None when the format is not supported
+pub fn detect(bytes: &[u8], content_type: Option<&str>, file_name: &str) -> Option<&'static dyn Extractor> {
+    // Binary formats are recognised by their magic bytes whatever their name
+    if bytes[..bytes.len().min(1024)].windows(5).any(|w| w == b"%PDF-") {
+        return Some(&PDF);
+    }
+    if bytes.starts_with(ZIP_MAGIC) {
+        return is_docx(bytes).then_some(&DOCX as &dyn Extractor);
+    }
+    if bytes.starts_with(CFB_MAGIC) {
+        return is_msg(bytes).then_some(&MSG as &dyn Extractor);
+    }
+
+    // Then the MIME type given at upload, unless it is a generic one
+    let mime = content_type
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_lowercase())
+        .unwrap_or_default();
+    let by_mime: Option<&'static dyn Extractor> = match mime.as_str() {
+        "application/pdf" => Some(&PDF),
+        "text/html" | "application/xhtml+xml" => Some(&HTML),
+        "text/markdown" | "text/x-markdown" => Some(&MARKDOWN),
+        "text/csv" => Some(&CSV),
+        "message/rfc822" => Some(&EML),
+        _ => None,
+    };
+    if by_mime.is_some() {
+        return by_mime;
+    }
+
+    // Then the extension of the file name
+    let extension = file_name.rsplit_once('.').map(|(_, ext)| ext.to_lowercase()).unwrap_or_default();
+    let by_extension: Option<&'static dyn Extractor> = match extension.as_str() {
+        "pdf" => Some(&PDF),
+        "html" | "htm" | "xhtml" => Some(&HTML),
+        "md" | "markdown" => Some(&MARKDOWN),
+        "csv" => Some(&CSV),
+        "eml" => Some(&EML),
+        "txt" | "text" | "log" => Some(&TEXT),
+        _ => None,
+    };
+    if by_extension.is_some() {
+        return by_extension;
+    }
+
+    // Finally look at the content
+    let head = decode_text(&bytes[..bytes.len().min(1024)]).trim_start().to_lowercase();
+    if head.starts_with("<!doctype html") || head.starts_with("<html") {
+        return Some(&HTML);
+    }
+    if mime == "text/plain" || std::str::from_utf8(bytes).is_ok() {
+        return Some(&TEXT);
+    }
+
+    None
+}
+
+fn is_docx(bytes: &[u8]) -> bool {
+    zip::ZipArchive::new(Cursor::new(bytes))
+        .map(|mut archive| archive.by_name("word/document.xml").is_ok())
+        .unwrap_or(false)
+}
+
+fn is_msg(bytes: &[u8]) -> bool {
+    cfb::CompoundFile::open(Cursor::new(bytes))
+        .map(|file| file.exists("/__properties_version1.0"))
+        .unwrap_or(false)
+}
+
+// Decode a text file: UTF-8 or UTF-16 with a BOM, UTF-8, else Windows-1252
+fn decode_text(bytes: &[u8]) -> String {
+    if let Some((encoding, bom_length)) = encoding_rs::Encoding::for_bom(bytes) {
+        return encoding.decode_without_bom_handling(&bytes[bom_length..]).0.into_owned();
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => encoding_rs::WINDOWS_1252.decode(bytes).0.into_owned(),
+    }
+}
+
+impl Extractor for PdfExtractor {
+    fn document_type(&self) -> &'static str {
+        "PDF"
+    }
+
+    fn extract(&self, bytes: &[u8]) -> Result<ExtractedText, Error> {
+        // pdf-extract panics on some malformed files
+        let layout = std::panic::catch_unwind(|| -> Result<_, pdf_extract::OutputError> {
+            let mut document = pdf_extract::Document::load_mem(bytes)?;
+            if document.is_encrypted() {
+                document.decrypt("")?;
+            }
+            let mut collector = GlyphCollector::default();
+            pdf_extract::output_doc(&document, &mut collector)?;
+            Ok(collector.into_pages())
+        });
+        let pages = match layout {
+            Ok(Ok(pages)) => pages,
+            Ok(Err(e)) => return Err(format!("PDF text extraction failed: {}", e).into()),
+            Err(_) => return Err("PDF text extraction panicked".into()),
+        };
+
+        let mut tables = Vec::new();
+        let mut texts = Vec::with_capacity(pages.len());
+        for (index, page) in pages.into_iter().enumerate() {
+            tables.extend(page.tables.into_iter().map(|rows| ExtractedTable {
+                page: Some(index as i32 + 1),
+                rows,
+            }));
+            texts.push(page.text);
+        }
+
+        let mut extracted = ExtractedText::paged(texts);
+        extracted.tables = tables;
+        Ok(extracted)
+    }
+}
+
+impl Extractor for DocxExtractor {
+    fn document_type(&self) -> &'static str {
+        "DOCX"
+    }
+
+    fn extract(&self, bytes: &[u8]) -> Result<ExtractedText, Error> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+        let mut xml = String::new();
+        archive.by_name("word/document.xml")?.read_to_string(&mut xml)?;
+
+        // Paragraphs are separated by a blank line, table rows are one line with cells separated by " | "
+        let mut reader = Reader::from_str(&xml);
+        let mut text = String::new();
+        let mut cell = String::new();
+        let mut row: Vec<String> = Vec::new();
+        let mut in_text = false;
+        let mut table_depth = 0;
+
+        loop {
+            let event = reader.read_event()?;
+            let out = if table_depth > 0 { &mut cell } else { &mut text };
+            match event {
+                Event::Start(e) => match e.name().as_ref() {
+                    b"w:t" => in_text = true,
+                    b"w:tbl" => table_depth += 1,
+                    _ => {}
+                },
+                Event::End(e) => match e.name().as_ref() {
+                    b"w:t" => in_text = false,
+                    b"w:p" if table_depth > 0 => out.push(' '),
+                    b"w:p" => out.push_str("\n\n"),
+                    b"w:tc" => {
+                        row.push(cell.trim().to_string());
+                        cell.clear();
+                    }
+                    b"w:tr" => {
+                        text.push_str(&row.join(" | "));
+                        text.push('\n');
+                        row.clear();
+                    }
+                    b"w:tbl" => {
+                        table_depth -= 1;
+                        if table_depth == 0 {
+                            text.push('\n');
+                        }
+                    }
+                    _ => {}
+                },
+                Event::Empty(e) => match e.name().as_ref() {
+                    b"w:tab" => out.push('\t'),
+                    b"w:br" | b"w:cr" => out.push('\n'),
+                    _ => {}
+                },
+                Event::Text(e) if in_text => out.push_str(&e.unescape()?),
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        Ok(ExtractedText::plain(text))
+    }
+}
+
+impl Extractor for HtmlExtractor {
+    fn document_type(&self) -> &'static str {
+        "HTML"
+    }
+
+    fn extract(&self, bytes: &[u8]) -> Result<ExtractedText, Error> {
+        // A wide width keeps html2text from wrapping the lines
+        Ok(ExtractedText::plain(html2text::from_read(decode_text(bytes).as_bytes(), 10_000)))
+    }
+}
+
+impl Extractor for MarkdownExtractor {
+    fn document_type(&self) -> &'static str {
+        "MARKDOWN"
+    }
+
+    fn extract(&self, bytes: &[u8]) -> Result<ExtractedText, Error> {
+        // Kept as is so that the markdown chunking strategy sees the headings
+        Ok(ExtractedText::plain(decode_text(bytes)))
+    }
+}
+
+impl Extractor for TextExtractor {
+    fn document_type(&self) -> &'static str {
+        "TXT"
+    }
+
+    fn extract(&self, bytes: &[u8]) -> Result<ExtractedText, Error> {
+        Ok(ExtractedText::plain(decode_text(bytes)))
+    }
+}
+
+impl Extractor for CsvExtractor {
+    fn document_type(&self) -> &'static str {
+        "CSV"
+    }
+
+    fn extract(&self, bytes: &[u8]) -> Result<ExtractedText, Error> {
+        let content = decode_text(bytes);
+
+        // Spreadsheet exports often use ';' as delimiter
+        let first_line = content.lines().next().unwrap_or_default();
+        let delimiter = if first_line.matches(';').count() > first_line.matches(',').count() { b';' } else { b',' };
+
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_reader(content.as_bytes());
+        let headers = reader.headers()?.clone();
+
+        // One line per row, each value prefixed with its column name
+        let mut lines = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            let line = record
+                .iter()
+                .enumerate()
+                .filter(|(_, value)| !value.trim().is_empty())
+                .map(|(i, value)| match headers.get(i) {
+                    Some(header) if !header.trim().is_empty() => format!("{}: {}", header.trim(), value.trim()),
+                    _ => value.trim().to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+
+        Ok(ExtractedText::plain(lines.join("\n")))
+    }
+}
+
+// Header lines of an email, in the order they appear in the extracted text
+fn email_text(subject: Option<&str>, from: Option<String>, to: Option<String>, date: Option<String>, body: &str, attachments: &[String]) -> String {
+    let mut text = String::new();
+    for (name, value) in [("Subject", subject.map(str::to_string)), ("From", from), ("To", to), ("Date", date)] {
+        if let Some(value) = value.filter(|value| !value.trim().is_empty()) {
+            text.push_str(&format!("{}: {}\n", name, value.trim()));
+        }
+    }
+    if !attachments.is_empty() {
+        text.push_str(&format!("Attachments: {}\n", attachments.join(", ")));
+    }
+    text.push('\n');
+    text.push_str(body);
+    text
+}
+
+impl Extractor for EmlExtractor {
+    fn document_type(&self) -> &'static str {
+        "EML"
+    }
+
+    fn extract(&self, bytes: &[u8]) -> Result<ExtractedText, Error> {
+        let message = MessageParser::default().parse(bytes).ok_or("Failed to parse the email")?;
+
+        let addresses = |address: Option<&mail_parser::Address>| {
+            address.map(|address| {
+                address
+                    .iter()
+                    .map(|addr| match (addr.name(), addr.address()) {
+                        (Some(name), Some(email)) => format!("{} <{}>", name, email),
+                        (name, email) => name.or(email).unwrap_or_default().to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+        };
+
+        // body_text converts the HTML part when there is no plain text one
+        let body = (0..message.text_body_count())
+            .filter_map(|i| message.body_text(i))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let attachments: Vec<String> = message
+            .attachments()
+            .filter_map(|part| part.attachment_name().map(str::to_string))
+            .collect();
+
+        Ok(ExtractedText::plain(email_text(
+            message.subject(),
+            addresses(message.from()),
+            addresses(message.to()),
+            message.date().map(|date| date.to_rfc3339()),
+            &body,
+            &attachments,
+        )))
+    }
+}
+
+// Read a string property of an Outlook message, or of one of its attachments, stored as
+// UTF-16 (001F) or 8-bit (001E)
+fn msg_property(file: &mut cfb::CompoundFile<Cursor<&[u8]>>, storage: &str, id: &str) -> Option<String> {
+    let mut data = Vec::new();
+    if let Ok(mut stream) = file.open_stream(format!("{}/__substg1.0_{}001F", storage, id)) {
+        stream.read_to_end(&mut data).ok()?;
+        return Some(encoding_rs::UTF_16LE.decode_without_bom_handling(&data).0.trim_end_matches('\0').to_string());
+    }
+    let mut stream = file.open_stream(format!("{}/__substg1.0_{}001E", storage, id)).ok()?;
+    stream.read_to_end(&mut data).ok()?;
+    Some(encoding_rs::WINDOWS_1252.decode(&data).0.trim_end_matches('\0').to_string())
+}
+
+impl Extractor for MsgExtractor {
+    fn document_type(&self) -> &'static str {
+        "MSG"
+    }
+
+    fn extract(&self, bytes: &[u8]) -> Result<ExtractedText, Error> {
+        let mut file = cfb::CompoundFile::open(Cursor::new(bytes))?;
+
+        // Property ids of PR_SUBJECT, PR_SENDER_NAME, PR_DISPLAY_TO and PR_BODY
+        let subject = msg_property(&mut file, "", "0037");
+        let from = msg_property(&mut file, "", "0C1A");
+        let to = msg_property(&mut file, "", "0E04");
+        let body = msg_property(&mut file, "", "1000").unwrap_or_default();
+
+        // Attachments are storages holding their file name in PR_ATTACH_LONG_FILENAME
+        let attachment_storages: Vec<String> = file
+            .read_root_storage()
+            .filter(|entry| entry.is_storage() && entry.name().starts_with("__attach_version1.0_"))
+            .map(|entry| format!("/{}", entry.name()))
+            .collect();
+        let attachments: Vec<String> = attachment_storages
+            .iter()
+            .filter_map(|storage| msg_property(&mut file, storage, "3707"))
+            .collect();
+
+        Ok(ExtractedText::plain(email_text(subject.as_deref(), from, to, None, &body, &attachments)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn detected(bytes: &[u8], content_type: Option<&str>, file_name: &str) -> Option<&'static str> {
+        detect(bytes, content_type, file_name).map(|extractor| extractor.document_type())
+    }
+
+    fn zip_of(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn docx(body: &str) -> Vec<u8> {
+        let document = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>{}</w:body></w:document>"#,
+            body
+        );
+        zip_of(&[("[Content_Types].xml", "<Types/>"), ("word/document.xml", &document)])
+    }
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    // Outlook message with the given string properties, by storage and property id
+    fn msg(properties: &[(&str, &str, &str)]) -> Vec<u8> {
+        let mut file = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        file.create_stream("/__properties_version1.0").unwrap();
+        for (storage, id, value) in properties {
+            if !storage.is_empty() && !file.exists(storage) {
+                file.create_storage(storage).unwrap();
+            }
+            let mut stream = file.create_stream(format!("{}/__substg1.0_{}001F", storage, id)).unwrap();
+            stream.write_all(&utf16le(value)).unwrap();
+        }
+        file.flush().unwrap();
+        file.into_inner().into_inner()
+    }
+
+    #[test]
+    fn detects_the_binary_formats_by_their_magic_bytes() {
+        let pdf = b"\n%PDF-1.7\n1 0 obj";
+        assert_eq!(detected(pdf, Some("text/plain"), "notes.txt"), Some("PDF"));
+        assert_eq!(detected(&docx("<w:p/>"), None, "report.bin"), Some("DOCX"));
+        assert_eq!(detected(&msg(&[]), None, "mail"), Some("MSG"));
+
+        // Other zip and compound files are not supported, whatever their name
+        assert_eq!(detected(&zip_of(&[("content.xml", "<office/>")]), None, "sheet.docx"), None);
+        let mut xls = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        xls.create_stream("/Workbook").unwrap();
+        assert_eq!(detected(&xls.into_inner().into_inner(), None, "mail.msg"), None);
+    }
+
+    #[test]
+    fn detects_the_text_formats_by_mime_type_then_extension() {
+        assert_eq!(detected(b"<p>Notice</p>", Some("text/html; charset=utf-8"), "notice"), Some("HTML"));
+        assert_eq!(detected(b"a,b\n1,2", Some("TEXT/CSV"), "export.txt"), Some("CSV"));
+        assert_eq!(detected(b"Subject: Notice", Some("message/rfc822"), "notice"), Some("EML"));
+
+        // A generic MIME type leaves the choice to the extension
+        assert_eq!(detected(b"# Notice", Some("application/octet-stream"), "notice.MD"), Some("MARKDOWN"));
+        assert_eq!(detected(b"<p>Notice</p>", None, "archive.2024.htm"), Some("HTML"));
+        assert_eq!(detected(b"Subject: Notice", None, "notice.eml"), Some("EML"));
+        assert_eq!(detected(b"Notice", None, "server.log"), Some("TXT"));
+    }
+
+    #[test]
+    fn detects_html_and_text_by_their_content() {
+        assert_eq!(detected(b"\xEF\xBB\xBF  <!DOCTYPE html><html>", None, "page"), Some("HTML"));
+        assert_eq!(detected(b"<HTML><body>Notice</body></HTML>", None, "page"), Some("HTML"));
+        assert_eq!(detected("Préavis de trois mois".as_bytes(), None, "notice"), Some("TXT"));
+
+        // Bytes that are not UTF-8 are only text when the upload says so
+        assert_eq!(detected(b"Pr\xe9avis", None, "notice"), None);
+        assert_eq!(detected(b"Pr\xe9avis", Some("text/plain"), "notice"), Some("TXT"));
+    }
+
+    #[test]
+    fn decodes_the_text_by_its_bom_or_as_windows_1252() {
+        assert_eq!(decode_text(b"\xEF\xBB\xBFPr\xC3\xA9avis"), "Préavis");
+        assert_eq!(decode_text(&[b"\xFF\xFE".as_slice(), &utf16le("Préavis")].concat()), "Préavis");
+        let utf16be: Vec<u8> = "Préavis".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(decode_text(&[b"\xFE\xFF".as_slice(), &utf16be].concat()), "Préavis");
+        assert_eq!(decode_text("Préavis".as_bytes()), "Préavis");
+        assert_eq!(decode_text(b"Pr\xe9avis \x80"), "Préavis €");
+    }
+
+    #[test]
+    fn extracts_the_paragraphs_and_tables_of_a_docx() {
+        let body = r#"<w:p><w:r><w:t>Notice</w:t></w:r><w:r><w:tab/><w:t xml:space="preserve">period &amp; terms</w:t></w:r></w:p>
+            <w:tbl>
+                <w:tr><w:tc><w:p><w:r><w:t>Role</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>Months</w:t></w:r></w:p></w:tc></w:tr>
+                <w:tr><w:tc><w:p><w:r><w:t>Manager</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>3</w:t></w:r></w:p></w:tc></w:tr>
+            </w:tbl>
+            <w:p><w:r><w:t>Signed</w:t><w:br/><w:t>in Paris</w:t></w:r></w:p>"#;
+
+        let extracted = DocxExtractor.extract(&docx(body)).unwrap();
+
+        assert_eq!(extracted.text, "Notice\tperiod & terms\n\nRole | Months\nManager | 3\n\nSigned\nin Paris\n\n");
+        assert!(extracted.page_starts.is_empty());
+        assert!(DocxExtractor.extract(&zip_of(&[("content.xml", "<office/>")])).is_err());
+    }
+
+    #[test]
+    fn extracts_the_text_of_html_without_its_tags_and_head() {
+        let html = "<html><head><title>Contract</title></head><body><h1>Notice</h1><p>Three <b>months</b>.</p></body></html>";
+
+        let text = HtmlExtractor.extract(html.as_bytes()).unwrap().text;
+
+        assert_eq!(text, "# Notice\n\nThree months.\n");
+    }
+
+    #[test]
+    fn keeps_markdown_and_text_as_they_are() {
+        let markdown = "# Contract\n\n## Notice\n\nThree months.\n";
+        assert_eq!(MarkdownExtractor.extract(markdown.as_bytes()).unwrap().text, markdown);
+        assert_eq!(TextExtractor.extract(b"\xEF\xBB\xBFLine 1\r\nLine 2").unwrap().text, "Line 1\r\nLine 2");
+    }
+
+    #[test]
+    fn extracts_a_line_per_csv_row_with_the_column_names() {
+        let csv = "Name;Role;Notice\nAlice;Manager;3 months\nBob;;1 month\n;;\n";
+
+        let text = CsvExtractor.extract(csv.as_bytes()).unwrap().text;
+
+        assert_eq!(text, "Name: Alice, Role: Manager, Notice: 3 months\nName: Bob, Notice: 1 month");
+    }
+
+    #[test]
+    fn extracts_the_headers_body_and_attachments_of_an_eml() {
+        let eml = "From: Alice Martin <alice@example.com>\r\n\
+            To: bob@example.com\r\n\
+            Subject: Notice period\r\n\
+            Date: Mon, 7 Oct 2024 09:30:00 +0000\r\n\
+            MIME-Version: 1.0\r\n\
+            Content-Type: multipart/mixed; boundary=\"b1\"\r\n\
+            \r\n\
+            --b1\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            \r\n\
+            The notice is three months.\r\n\
+            --b1\r\n\
+            Content-Type: application/pdf; name=\"contract.pdf\"\r\n\
+            Content-Disposition: attachment; filename=\"contract.pdf\"\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            JVBERi0=\r\n\
+            --b1--\r\n";
+
+        let text = EmlExtractor.extract(eml.as_bytes()).unwrap().text;
+
+        assert_eq!(
+            text,
+            "Subject: Notice period\n\
+            From: Alice Martin <alice@example.com>\n\
+            To: bob@example.com\n\
+            Date: 2024-10-07T09:30:00Z\n\
+            Attachments: contract.pdf\n\
+            \n\
+            The notice is three months."
+        );
+    }
+
+    #[test]
+    fn extracts_the_properties_and_attachments_of_a_msg() {
+        let bytes = msg(&[
+            ("", "0037", "Notice period"),
+            ("", "0C1A", "Alice Martin"),
+            ("", "0E04", "Bob"),
+            ("", "1000", "The notice is three months.\0"),
+            ("/__attach_version1.0_#00000000", "3707", "contract.pdf"),
+        ]);
+
+        let text = MsgExtractor.extract(&bytes).unwrap().text;
+
+        assert_eq!(
+            text,
+            "Subject: Notice period\nFrom: Alice Martin\nTo: Bob\nAttachments: contract.pdf\n\nThe notice is three months."
+        );
+    }
+
+    #[test]
+    fn finds_the_page_of_an_offset() {
+        let extracted = ExtractedText::paged(vec!["Page one".to_string(), "Page two".to_string()]);
+
+        assert_eq!(extracted.page_starts, vec![0, 10]);
+        assert_eq!(extracted.page_at(0), Some(1));
+        assert_eq!(extracted.page_at(9), Some(1));
+        assert_eq!(extracted.page_at(10), Some(2));
+        assert_eq!(ExtractedText::plain("Text".to_string()).page_at(0), None);
+    }
+}
@@ -1,23 +1,26 @@
-use aws_config::meta::region::RegionProviderChain;
 use aws_lambda_events::s3::S3Event;
 use lambda_runtime::Error;
 use aws_sdk_s3::{Client as S3Client, config::Region};
-use aws_sdk_secretsmanager::Client as SecretManagerClient;
 use md5::{Digest, Md5};
 use std::env;
 use chrono::Utc;
 use tokio_postgres::Client;
-use postgres_native_tls::MakeTlsConnector;
-use native_tls::TlsConnector;
 use uuid::Uuid;
 use serde_json::{json, Value};
-use pdf_extract; // PDF text extraction library
 use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC}; // For URL encoding/decoding
-use http::{Response, StatusCode, header};
-use lambda_http::{Body, Request, run, service_fn};
+use http::Response;
+use lambda_http::{Body, Request, service_fn};
 
-const CHUNK_SIZE: usize = 1000;
-const CHUNK_OVERLAP: usize = 100;
+use rag_common::chunking::{chunk_table, ChunkConfig, ChunkKind, ChunkStrategy, TableFormat};
+
+mod extract;
+mod ocr;
+mod pdf_layout;
+
+use extract::ExtractedText;
+use ocr::OcrConfig;
+use rag_common::db::get_client;
+use rag_common::registry::EmbeddingModel;
 
 #[derive(Debug)]
 struct Document {
@@ -29,93 +32,11 @@ struct Document {
     document_length: i32,
     document_size: f64,
     document_status: String,
-}
-
-async fn show_secret(secret_name: &str) -> Result<String, Error> {
-    let region_provider = RegionProviderChain::default_provider();
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let client = SecretManagerClient::new(&config);
-    
-    let resp = client.get_secret_value().secret_id(secret_name).send().await?;
-    match resp.secret_string() {
-        Some(secret) => Ok(secret.into()),
-        None => {
-            println!("Error retrieving secret: {}", secret_name);
-            Err("Error retrieving secret".into())
-        }
-    }
-}
-
-async fn connect_to_db() -> Result<Client, Error> {
-    // Get DB connection string from environment variable
-    let secret_name = env::var("DATABASE_CONECTION_STRING")?;
-    
-    // Get the secret from AWS Secrets Manager
-    let db_secret = show_secret(&secret_name).await?;
-    
-    // Parse the JSON secret
-    let db_credentials: serde_json::Value = match serde_json::from_str(&db_secret) {
-        Ok(creds) => creds,
-        Err(e) => {
-            println!("Failed to parse database credentials JSON: {}", e);
-            return Err(format!("JSON parsing error: {}", e).into());
-        }
-    };
-    
-    // Extract connection parameters
-    let db_server = db_credentials["DB_HOST"].as_str()
-        .ok_or("DB_HOST not found in secret")?;
-    let db_port = db_credentials["DB_PORT"].as_str()
-        .ok_or("DB_PORT not found in secret")?;
-    let database = db_credentials["DB_NAME"].as_str()
-        .ok_or("DB_NAME not found in secret")?;
-    let db_username = db_credentials["DB_USER"].as_str()
-        .ok_or("DB_USER not found in secret")?;
-    let db_password = db_credentials["DB_PASSWORD"].as_str()
-        .ok_or("DB_PASSWORD not found in secret")?;
-    
-    println!("Database connection parameters retrieved successfully");
-    
-    // Set up TLS for PostgreSQL connection
-    let tls_connector = TlsConnector::builder()
-        .danger_accept_invalid_certs(true)
-        .build()
-        .map_err(|e| {
-            println!("Failed to create TLS connector: {}", e);
-            format!("TLS error: {}", e)
-        })?;
-        
-    let postgres_tls = MakeTlsConnector::new(tls_connector);
-    
-    // Create a properly formatted connection string
-    let connection_string = format!(
-        "host={} port={} user={} password={} dbname={}",
-        db_server, db_port, db_username, db_password, database
-    );
-    
-    println!("Attempting to connect to database...");
-    
-    // Connect to PostgreSQL
-    let (client, connection) = tokio_postgres::connect(&connection_string, postgres_tls)
-        .await
-        .map_err(|e| {
-            println!("Failed to connect to database: {}", e);
-            format!("Database connection error: {}", e)
-        })?;
-        
-    // The connection object performs the actual communication with the database,
-    // so spawn it off to run on its own
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            println!("Database connection error: {}", e);
-        }
-    });
-    
-    Ok(client)
+    ocr_used: bool,
 }
 
 async fn check_document_exists(client: &Client, doc_hash: &str) -> Result<Option<Document>, Error> {
-    let query = "SELECT document_uuid, document_name, document_location, document_hash, document_type, document_lenght, document_size, document_status FROM document_library.documents WHERE document_hash = $1";
+    let query = "SELECT document_uuid, document_name, document_location, document_hash, document_type, document_lenght, document_size, document_status, COALESCE(ocr_used, false) FROM document_library.documents WHERE document_hash = $1";
     
     let rows = client.query(query, &[&doc_hash]).await.map_err(|e| {
         println!("Database query error: {}", e);
@@ -136,6 +57,7 @@ async fn check_document_exists(client: &Client, doc_hash: &str) -> Result<Option
         document_length: row.get(5),
         document_size: row.get(6),
         document_status: row.get(7),
+        ocr_used: row.get(8),
     };
     
     Ok(Some(doc))
@@ -157,7 +79,7 @@ async fn insert_document(client: &Client, doc: &Document) -> Result<String, Erro
     let doc_uuid = Uuid::new_v4().to_string();
     let now = Utc::now();
     
-    let query = "INSERT INTO document_library.documents (document_uuid, document_name, document_location, document_hash, document_type, document_lenght, document_size, document_status, chunk_time, creation_date, created_by, updated_date, updated_by, comments, tags) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, NULL, NULL)";
+    let query = "INSERT INTO document_library.documents (document_uuid, document_name, document_location, document_hash, document_type, document_lenght, document_size, document_status, ocr_used, chunk_time, creation_date, created_by, updated_date, updated_by, comments, tags) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, NULL, NULL)";
     
     client.execute(
         query, 
@@ -169,7 +91,8 @@ async fn insert_document(client: &Client, doc: &Document) -> Result<String, Erro
             &doc.document_type,
             &doc.document_length,
             &doc.document_size,
-            &"new",
+            &doc.document_status,
+            &doc.ocr_used,
             &0.0,  // chunk_time
             &now,  // creation_date
             &"system", // created_by
@@ -184,18 +107,32 @@ async fn insert_document(client: &Client, doc: &Document) -> Result<String, Erro
     Ok(doc_uuid)
 }
 
+// Where a chunk comes from in its document: its kind, pages for paged formats, char offsets in the
+// extracted text, which tables taken out of the text do not have, and the OCR confidence of its words
+// when the text was read by OCR
+struct ChunkPosition {
+    kind: ChunkKind,
+    page_start: Option<i32>,
+    page_end: Option<i32>,
+    char_start: Option<i32>,
+    char_end: Option<i32>,
+    ocr_confidence: Option<f32>,
+}
+
 async fn insert_chunk(
     client: &Client, 
     document_uuid: &str, 
     chunk_text: &str, 
-    chunk_length: i32, 
-    chunk_overlap: i32, 
+    position: &ChunkPosition, 
+    chunk_config: &ChunkConfig, 
+    chunk_strategy: &ChunkStrategy, 
     chunk_hash: &str
 ) -> Result<String, Error> {
     let chunk_uuid = Uuid::new_v4().to_string();
     let now = Utc::now();
+    let chunk_length = chunk_text.chars().count() as i32;
     
-    let query = "INSERT INTO document_library.document_chunks (document_chunk_uuid, document_uuid, chunck_lenght, chunck_overlap, chunck_hash, embebed_text, creation_date, created_by, updated_date, updated_by, comments) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, NULL)";
+    let query = "INSERT INTO document_library.document_chunks (document_chunk_uuid, document_uuid, chunck_lenght, chunck_overlap, chunck_size, chunck_unit, chunck_strategy, chunck_parameters, chunck_kind, chunck_page_start, chunck_page_end, chunck_char_start, chunck_char_end, chunck_ocr_confidence, chunck_hash, embebed_text, creation_date, created_by, updated_date, updated_by, comments) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, NULL)";
     
     client.execute(
         query, 
@@ -203,7 +140,17 @@ async fn insert_chunk(
             &chunk_uuid, 
             &document_uuid, 
             &chunk_length, 
-            &chunk_overlap, 
+            &(chunk_config.overlap as i32), 
+            &(chunk_config.size as i32), 
+            &chunk_config.unit.as_str(), 
+            &chunk_strategy.name(), 
+            &chunk_strategy.parameters(chunk_config), 
+            &position.kind.as_str(), 
+            &position.page_start, 
+            &position.page_end, 
+            &position.char_start, 
+            &position.char_end, 
+            &position.ocr_confidence, 
             &chunk_hash,
             &chunk_text,
             &now,  // creation_date
@@ -219,39 +166,44 @@ async fn insert_chunk(
     Ok(chunk_uuid)
 }
 
+// Placeholder row of the chunk in the table of every embedding model, filled by rust_file_vectorisation
 async fn prepare_embedding_entry(
     client: &Client, 
+    models: &[EmbeddingModel],
     chunk_uuid: &str
 ) -> Result<(), Error> {
-    println!("Preparing embedding entry for chunk: {}", chunk_uuid);
-    let embedding_uuid = Uuid::new_v4().to_string();
+    println!("Preparing embedding entries for chunk: {}", chunk_uuid);
     let now = Utc::now();
     
-    let query = "INSERT INTO document_library.document_embeding_mistral_generic (document_embeding_uuid, document_chunk_uuid, embeder_type, embedding_token, embedding_time, embedding, creation_date, created_by, updated_date, updated_by, comments) VALUES ($1, $2, $3, NULL, NULL, NULL, $4, $5, $6, $7, NULL)";
-    
-    println!("Executing embedding insertion SQL query for chunk: {}", chunk_uuid);
-    
-    match client.execute(
-        query, 
-        &[
-            &embedding_uuid, 
-            &chunk_uuid, 
-            &"mistral_generic",
-            &now,  // creation_date
-            &"system", // created_by
-            &now,  // updated_date
-            &"system", // updated_by
-        ]
-    ).await {
-        Ok(rows_affected) => {
-            println!("Successfully inserted embedding entry for chunk: {}. Rows affected: {}", chunk_uuid, rows_affected);
-            Ok(())
-        },
-        Err(e) => {
-            println!("Database insert embedding entry error for chunk {}: {}", chunk_uuid, e);
-            Err(format!("Database insert embedding entry error: {}", e).into())
+    for model in models {
+        let embedding_uuid = Uuid::new_v4().to_string();
+        let query = format!("INSERT INTO document_library.\"{}\" (document_embeding_uuid, document_chunk_uuid, embeder_type, embedding_token, embedding_time, embedding, creation_date, created_by, updated_date, updated_by, comments) VALUES ($1, $2, $3, NULL, NULL, NULL, $4, $5, $6, $7, NULL)", model.table);
+        
+        println!("Executing {} embedding insertion SQL query for chunk: {}", model.name, chunk_uuid);
+        
+        match client.execute(
+            &query, 
+            &[
+                &embedding_uuid, 
+                &chunk_uuid, 
+                &model.name,
+                &now,  // creation_date
+                &"system", // created_by
+                &now,  // updated_date
+                &"system", // updated_by
+            ]
+        ).await {
+            Ok(rows_affected) => {
+                println!("Successfully inserted {} embedding entry for chunk: {}. Rows affected: {}", model.name, chunk_uuid, rows_affected);
+            },
+            Err(e) => {
+                println!("Database insert embedding entry error for chunk {}: {}", chunk_uuid, e);
+                return Err(format!("Database insert embedding entry error: {}", e).into());
+            }
         }
     }
+    
+    Ok(())
 }
 
 async fn insert_document_security_group(
@@ -305,113 +257,68 @@ async fn insert_document_security_group(
     }
 }
 
-fn chunk_text(text: &str, chunk_size: usize, chunk_overlap: usize) -> Vec<String> {
-    let mut chunks = Vec::new();
-    let text_len = text.len();
-    
-    if text_len <= chunk_size {
-        chunks.push(text.to_string());
-        return chunks;
-    }
-    
-    let mut start = 0;
-    while start < text_len {
-        let end = if start + chunk_size >= text_len {
-            text_len
-        } else {
-            start + chunk_size
-        };
-        
-        chunks.push(text[start..end].to_string());
-        
-        if end == text_len {
-            break;
-        }
-        
-        start = end - chunk_overlap;
-    }
-    
-    chunks
-}
-
-async fn process_pdf_content(
-    s3_client: &S3Client,
+async fn process_document_content(
     db_client: &Client,
-    bucket: &str,
-    key: &str,
-    document_uuid: &str
+    document_uuid: &str,
+    document_type: &str,
+    extracted: &ExtractedText,
+    upload_strategy: Option<&str>
 ) -> Result<(), Error> {
-    println!("Processing PDF content from bucket: {}, key: {}", bucket, key);
-    
-    // Get the object from S3
-    let resp = s3_client
-        .get_object()
-        .bucket(bucket)
-        .key(key)
-        .send()
-        .await?;
-    
-    // Read the PDF content into a buffer
-    let mut buffer = Vec::new();
-    let mut byte_stream = resp.body.into_async_read();
-    
-    use tokio::io::AsyncReadExt;
-    byte_stream.read_to_end(&mut buffer).await?;
-    
-    // Create a temporary file to use with pdf-extract
-    use std::fs::File;
-    use std::io::Write;
-    let temp_path = format!("/tmp/{}.pdf", Uuid::new_v4());
-    let mut temp_file = File::create(&temp_path).map_err(|e| {
-        println!("Failed to create temporary file: {}", e);
-        format!("File creation error: {}", e)
-    })?;
-    
-    temp_file.write_all(&buffer).map_err(|e| {
-        println!("Failed to write to temporary file: {}", e);
-        format!("File write error: {}", e)
-    })?;
-    
-    // Extract text from the PDF using pdf-extract
-    println!("Extracting text from PDF using pdf-extract");
-    let text = match pdf_extract::extract_text(&temp_path) {
-        Ok(content) => {
-            println!("PDF text extraction successful");
-            content
-        },
-        Err(e) => {
-            println!("PDF text extraction failed: {}", e);
-            
-            // Fallback to raw text conversion if pdf-extract fails
-            println!("Falling back to raw text conversion");
-            String::from_utf8_lossy(&buffer).to_string()
-        }
-    };
-    
-    // Clean up the temporary file
-    if let Err(e) = std::fs::remove_file(&temp_path) {
-        println!("Warning: Failed to remove temporary file {}: {}", temp_path, e);
-    }
+    println!("Processing {} content of document {}", document_type, document_uuid);
     
     // Start timing for chunk processing
     let start_time = std::time::Instant::now();
     
-    // Process the text in chunks
-    let chunks = chunk_text(&text, CHUNK_SIZE, CHUNK_OVERLAP);
+    // Process the text in chunks, with the size and overlap of the deployment and the strategy of the document
+    let chunk_config = ChunkConfig::from_env()?;
+    let chunk_strategy = ChunkStrategy::select(document_type, upload_strategy)?;
+    println!("Chunking with the {} strategy, size {} and overlap {} in {}", chunk_strategy.name(), chunk_config.size, chunk_config.overlap, chunk_config.unit.as_str());
+    let text = extracted.text.as_str();
+    // Every active embedding model gets an entry per chunk to vectorise
+    let embedding_models = EmbeddingModel::active(db_client).await?;
+    // The semantic strategy compares sentences with the default embedding model
+    let chunks = chunk_strategy
+        .chunk(text, &chunk_config, |sentences| async move {
+            println!("Embedding {} sentences for semantic chunking", sentences.len());
+            EmbeddingModel::default_model(db_client).await?.embed(&sentences).await
+        })
+        .await?;
     let chunk_count = chunks.len();
     
     // Insert each chunk into the database
     println!("Starting chunk insertion for document {}, {} chunks to process", document_uuid, chunks.len());
     let mut processed_chunks = 0;
     
+    // Char offsets are counted from those of the previous chunk, chunks come in the order of the text
+    let mut last_byte = 0;
+    let mut last_char = 0;
+    let mut char_offset = |byte: usize| {
+        if byte >= last_byte {
+            last_char += text[last_byte..byte].chars().count();
+        } else {
+            last_char -= text[byte..last_byte].chars().count();
+        }
+        last_byte = byte;
+        last_char as i32
+    };
+    
     for chunk in &chunks {
-        let chunk_hash = format!("{:x}", Md5::digest(chunk.as_bytes()));
+        let chunk_hash = format!("{:x}", Md5::digest(chunk.text.as_bytes()));
+        let position = ChunkPosition {
+            kind: ChunkKind::Text,
+            page_start: extracted.page_at(chunk.range.start),
+            page_end: extracted.page_at(chunk.range.end.saturating_sub(1)),
+            char_start: Some(char_offset(chunk.range.start)),
+            char_end: Some(char_offset(chunk.range.end)),
+            ocr_confidence: extracted.confidence_in(chunk.range.clone()),
+        };
         let chunk_uuid = insert_chunk(
             db_client, 
             document_uuid, 
-            chunk, 
-            chunk.len() as i32,
-            CHUNK_OVERLAP as i32, 
+            &chunk.text, 
+            &position,
+            &chunk_config, 
+            &chunk_strategy, 
             &chunk_hash
         ).await?;
         
@@ -422,10 +329,42 @@ async fn process_pdf_content(
         
         // Prepare embedding entry for this chunk
         println!("Starting embedding preparation for chunk {}", chunk_uuid);
-        prepare_embedding_entry(db_client, &chunk_uuid).await?;
+        prepare_embedding_entry(db_client, &embedding_models, &chunk_uuid).await?;
+    }
+    
+    // Tables taken out of the text get chunks of their own, each starting with the header row
+    let mut table_chunk_count = 0;
+    if !extracted.tables.is_empty() {
+        let table_format = TableFormat::from_env()?;
+        println!("Chunking {} tables of document {} as {}", extracted.tables.len(), document_uuid, table_format.as_str());
+        for table in &extracted.tables {
+            for table_chunk in chunk_table(&table.rows, &chunk_config, table_format) {
+                let chunk_hash = format!("{:x}", Md5::digest(table_chunk.as_bytes()));
+                let position = ChunkPosition {
+                    kind: ChunkKind::Table,
+                    page_start: table.page,
+                    page_end: table.page,
+                    char_start: None,
+                    char_end: None,
+                    ocr_confidence: None,
+                };
+                let chunk_uuid = insert_chunk(
+                    db_client, 
+                    document_uuid, 
+                    &table_chunk, 
+                    &position,
+                    &chunk_config, 
+                    &chunk_strategy, 
+                    &chunk_hash
+                ).await?;
+                prepare_embedding_entry(db_client, &embedding_models, &chunk_uuid).await?;
+                table_chunk_count += 1;
+            }
+        }
     }
+    let chunk_count = chunk_count + table_chunk_count;
     
-    println!("All {} chunks have been inserted for document {}", chunks.len(), document_uuid);
+    println!("All {} chunks have been inserted for document {}", chunk_count, document_uuid);
     println!("All embedding entries have been prepared for document {}", document_uuid);
     
     // // Insert document security group after all embedding entries have been created
@@ -549,8 +488,8 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     // Log the raw event JSON
     let payload = match event.body() {
         Body::Empty => Value::Null,
-        Body::Text(text) => serde_json::from_str(text).unwrap_or_else(|_| Value::Null),
-        Body::Binary(data) => serde_json::from_slice(data).unwrap_or_else(|_| Value::Null),
+        Body::Text(text) => serde_json::from_str(text).unwrap_or(Value::Null),
+        Body::Binary(data) => serde_json::from_slice(data).unwrap_or(Value::Null),
     };
     let raw_json = serde_json::to_string(&payload).unwrap_or_else(|_| "Failed to serialize payload".to_string());
     println!("Received raw JSON: {}", raw_json);
@@ -715,7 +654,13 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
         
     // Get file metadata
     let file_size = resp.content_length as f64;
-    let file_name = key.split('/').last().unwrap_or(&key).to_string();
+    let file_name = key.split('/').next_back().unwrap_or(&key).to_string();
+    let content_type = resp.content_type().map(str::to_string);
+    
+    // Chunking strategy chosen at upload time, if any
+    let upload_strategy = resp.metadata()
+        .and_then(|metadata| metadata.get("chunk-strategy"))
+        .cloned();
     
     // Read the file content
     let mut buffer = Vec::new();
@@ -730,7 +675,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     println!("File hash computed: {}", file_hash);
     
     // Connect to the database
-    let db_client = connect_to_db().await?;
+    let db_client = get_client().await?;
     
     // Check if document already exists
     let existing_doc = check_document_exists(&db_client, &file_hash).await?;
@@ -754,16 +699,54 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
             doc.document_uuid
         },
         None => {
+            // Pick the extractor of the file format and extract its text before creating the record
+            let extractor = extract::detect(&buffer, content_type.as_deref(), &file_name)
+                .ok_or_else(|| format!("Unsupported file type for s3://{}/{} (content type {:?})", bucket, key, content_type))?;
+            println!("Extracting text of {} as {}", file_name, extractor.document_type());
+            let mut extracted = match extractor.extract(&buffer) {
+                Ok(extracted) => extracted,
+                // A PDF whose text layer cannot be read is left to the OCR fallback, without text
+                Err(e) if extractor.document_type() == "PDF" => {
+                    println!("Text extraction failed for {}, falling back to OCR: {}", file_name, e);
+                    ExtractedText::paged(Vec::new())
+                }
+                Err(e) => {
+                    println!("Text extraction failed for {}: {}", file_name, e);
+                    return Err(e);
+                }
+            };
+            
+            // Scanned PDFs have little or no text layer, read their pages with OCR instead
+            if extractor.document_type() == "PDF" {
+                let ocr_config = OcrConfig::from_env()?;
+                if ocr_config.needs_ocr(&extracted) {
+                    println!("Text layer of {} is too sparse ({} usable chars), falling back to OCR", file_name, ocr::usable_chars(&extracted.text));
+                    // pdftoppm and tesseract run for minutes on large scans, out of the async runtime
+                    let pdf = buffer.clone();
+                    match tokio::task::spawn_blocking(move || ocr::ocr_pdf(&pdf, &ocr_config)).await? {
+                        Ok(ocr_text) => extracted = ocr_text,
+                        Err(e) => println!("OCR failed for {}: {}", file_name, e),
+                    }
+                }
+            }
+            
+            // Documents without usable text are left for a person to check instead of producing empty chunks
+            let has_text = ocr::usable_chars(&extracted.text) > 0 || !extracted.tables.is_empty();
+            if !has_text {
+                println!("No usable text found in {}, marking it for review", file_name);
+            }
+            
             // Create a new document record
             let document = Document {
                 document_uuid: String::new(), // Will be generated in insert_document
                 document_name: file_name.clone(),
                 document_location: format!("s3://{}/{}", destination_bucket, destination_key),
                 document_hash: file_hash,
-                document_type: "PDF".to_string(),
+                document_type: extractor.document_type().to_string(),
                 document_length: buffer.len() as i32,
                 document_size: file_size,
-                document_status: "new".to_string(),
+                document_status: if has_text { "new" } else { "needs_review" }.to_string(),
+                ocr_used: extracted.ocr,
             };
             
             println!("Inserting new document: {}", document.document_name);
@@ -771,8 +754,10 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
             // Insert document and get the UUID
             let doc_uuid = insert_document(&db_client, &document).await?;
             
-            // Process PDF and create chunks
-            process_pdf_content(&s3_client, &db_client, bucket, &key, &doc_uuid).await?;
+            // Chunk the extracted text
+            if has_text {
+                process_document_content(&db_client, &doc_uuid, &document.document_type, &extracted, upload_strategy.as_deref()).await?;
+            }
             
             // Insert document security group
             insert_document_security_group(&db_client, &doc_uuid).await?;
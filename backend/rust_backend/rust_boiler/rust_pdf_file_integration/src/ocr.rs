@@ -0,0 +1,221 @@
+// OCR fallback for scanned PDFs
+//
+// Scanned documents carry images instead of text, so pdf-extract finds little or nothing
+// on their pages. Those pages are rasterised with `pdftoppm` (poppler) and read with
+// `tesseract`, both run as local binaries that the Lambda layer puts on the PATH.
+// Tesseract's TSV output gives the confidence of every word, kept with its offset in the
+// text so that each chunk gets the average confidence of its words.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+use lambda_runtime::Error;
+use uuid::Uuid;
+
+use crate::extract::ExtractedText;
+
+// Resolution of the rasterised pages, 300 dpi is what Tesseract is trained on
+const RASTER_DPI: &str = "300";
+// Words read with a lower confidence are noise (speckles, stamps, lines of a form)
+const MIN_WORD_CONFIDENCE: f32 = 30.0;
+
+// Settings of the OCR fallback
+pub struct OcrConfig {
+    // Below this many letters and digits per page, the text of a PDF is considered missing
+    pub min_chars_per_page: usize,
+    // Tesseract languages, such as "eng+fra"
+    pub languages: String,
+}
+
+impl OcrConfig {
+    // Read OCR_MIN_CHARS_PER_PAGE and OCR_LANGUAGES
+    pub fn from_env() -> Result<Self, Error> {
+        let min_chars = env::var("OCR_MIN_CHARS_PER_PAGE").expect("OCR_MIN_CHARS_PER_PAGE environment variable not set");
+        let languages = env::var("OCR_LANGUAGES").expect("OCR_LANGUAGES environment variable not set");
+
+        let min_chars_per_page = min_chars
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid OCR_MIN_CHARS_PER_PAGE '{}': {}", min_chars, e))?;
+        Ok(OcrConfig { min_chars_per_page, languages })
+    }
+
+    // Whether the text pdf-extract found is too sparse to be the real content of the document
+    pub fn needs_ocr(&self, extracted: &ExtractedText) -> bool {
+        let pages = extracted.page_starts.len().max(1);
+        usable_chars(&extracted.text) < self.min_chars_per_page * pages
+    }
+}
+
+// Letters and digits of a text, replacement chars and symbols of a broken font do not count
+pub fn usable_chars(text: &str) -> usize {
+    text.chars().filter(|c| c.is_alphanumeric()).count()
+}
+
+// Rasterise every page of the PDF and read it with Tesseract
+pub fn ocr_pdf(bytes: &[u8], config: &OcrConfig) -> Result<ExtractedText, Error> {
+    let work_dir = env::temp_dir().join(format!("ocr-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&work_dir)?;
+    let result = ocr_in(&work_dir, bytes, config);
+    if let Err(e) = std::fs::remove_dir_all(&work_dir) {
+        println!("Failed to remove OCR work directory {}: {}", work_dir.display(), e);
+    }
+    result
+}
+
+fn ocr_in(work_dir: &Path, bytes: &[u8], config: &OcrConfig) -> Result<ExtractedText, Error> {
+    let pdf_path = work_dir.join("document.pdf");
+    std::fs::write(&pdf_path, bytes)?;
+
+    // One page-N.png per page, N zero padded to the same width so the names sort in page order
+    run(Command::new("pdftoppm")
+        .args(["-r", RASTER_DPI, "-gray", "-png"])
+        .arg(&pdf_path)
+        .arg(work_dir.join("page")))?;
+
+    let mut images: Vec<_> = std::fs::read_dir(work_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+        .collect();
+    images.sort();
+    if images.is_empty() {
+        return Err("pdftoppm produced no page image".into());
+    }
+
+    let mut pages = Vec::with_capacity(images.len());
+    for image in &images {
+        let tsv = run(Command::new("tesseract")
+            .arg(image)
+            .arg("stdout")
+            .args(["-l", &config.languages])
+            .arg("tsv"))?;
+        pages.push(parse_tsv(&tsv));
+    }
+
+    let (texts, words): (Vec<String>, Vec<Vec<(usize, f32)>>) = pages.into_iter().unzip();
+    let mut extracted = ExtractedText::paged(texts);
+    extracted.word_confidences = words
+        .into_iter()
+        .zip(extracted.page_starts.clone())
+        .flat_map(|(words, page_start)| words.into_iter().map(move |(offset, confidence)| (page_start + offset, confidence)))
+        .collect();
+    extracted.ocr = true;
+    Ok(extracted)
+}
+
+// Run a command and return its standard output
+fn run(command: &mut Command) -> Result<String, Error> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed ({}): {}", program, output.status, String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Text of a page from Tesseract's TSV output, with the byte offset and confidence of each word.
+// Blocks and paragraphs are separated by a blank line, lines by a newline.
+fn parse_tsv(tsv: &str) -> (String, Vec<(usize, f32)>) {
+    let mut text = String::new();
+    let mut words = Vec::new();
+    let mut previous: Option<(&str, &str, &str)> = None;
+
+    // level page_num block_num par_num line_num word_num left top width height conf text
+    for line in tsv.lines().skip(1) {
+        let fields: Vec<&str> = line.splitn(12, '\t').collect();
+        if fields.len() < 12 || fields[0] != "5" {
+            continue;
+        }
+        let word = fields[11].trim();
+        let confidence: f32 = fields[10].trim().parse().unwrap_or(-1.0);
+        if word.is_empty() || confidence < MIN_WORD_CONFIDENCE {
+            continue;
+        }
+
+        let position = (fields[2], fields[3], fields[4]);
+        match previous {
+            Some((block, paragraph, _)) if (block, paragraph) != (position.0, position.1) => text.push_str("\n\n"),
+            Some((_, _, line)) if line != position.2 => text.push('\n'),
+            Some(_) => text.push(' '),
+            None => {}
+        }
+        previous = Some(position);
+
+        words.push((text.len(), confidence));
+        text.push_str(word);
+    }
+
+    (text, words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(min_chars_per_page: usize) -> OcrConfig {
+        OcrConfig { min_chars_per_page, languages: "eng+fra".to_string() }
+    }
+
+    #[test]
+    fn needs_ocr_below_the_letters_and_digits_of_every_page() {
+        let two_pages = |page: &str| ExtractedText::paged(vec![page.to_string(), page.to_string()]);
+        let letters = "a".repeat(30);
+
+        assert!(config(50).needs_ocr(&two_pages(&letters)));
+        assert!(!config(30).needs_ocr(&two_pages(&letters)));
+        assert!(!config(50).needs_ocr(&ExtractedText::paged(vec![format!("{} {}", letters, letters)])));
+        assert!(config(1).needs_ocr(&ExtractedText::paged(Vec::new())));
+    }
+
+    #[test]
+    fn symbols_of_a_broken_font_are_not_usable() {
+        assert_eq!(usable_chars("Préavis: 3 mois."), 12);
+        assert_eq!(usable_chars("\u{FFFD}\u{FFFD} ■■ •••  ---"), 0);
+        assert!(config(5).needs_ocr(&ExtractedText::paged(vec!["\u{FFFD}".repeat(100)])));
+    }
+
+    #[test]
+    fn parses_the_words_of_the_tsv_of_tesseract() {
+        let tsv = "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext\n\
+            1\t1\t0\t0\t0\t0\t0\t0\t2480\t3508\t-1\t\n\
+            2\t1\t1\t0\t0\t0\t100\t100\t800\t200\t-1\t\n\
+            5\t1\t1\t1\t1\t1\t100\t100\t200\t40\t96.5\tNotice\n\
+            5\t1\t1\t1\t1\t2\t320\t100\t200\t40\t91\tperiod\n\
+            5\t1\t1\t1\t2\t1\t100\t150\t200\t40\t88\tthree\n\
+            5\t1\t1\t1\t2\t2\t320\t150\t20\t40\t12\t~\n\
+            5\t1\t1\t1\t2\t3\t360\t150\t200\t40\t90\tmonths\n\
+            5\t1\t1\t2\t1\t1\t100\t300\t200\t40\t95\tSigned\n\
+            5\t1\t2\t1\t1\t1\t100\t500\t200\t40\t93\tParis\n\
+            5\t1\t2\t1\t1\t2\t320\t500\t200\t40\t94\t \n\
+            5\t1\t2\t1\t1\t3";
+
+        let (text, words) = parse_tsv(tsv);
+
+        assert_eq!(text, "Notice period\nthree months\n\nSigned\n\nParis");
+        assert_eq!(words, vec![(0, 96.5), (7, 91.0), (14, 88.0), (20, 90.0), (28, 95.0), (36, 93.0)]);
+        for &(offset, _) in &words {
+            assert!(!text[offset..].starts_with(char::is_whitespace));
+        }
+    }
+
+    #[test]
+    fn a_page_without_words_has_no_text() {
+        assert_eq!(parse_tsv(""), (String::new(), Vec::new()));
+        assert_eq!(parse_tsv("level\tpage_num\n1\t1\t0\t0\t0\t0\t0\t0\t2480\t3508\t-1\t"), (String::new(), Vec::new()));
+    }
+
+    #[test]
+    fn averages_the_confidence_of_the_words_of_a_chunk() {
+        let mut extracted = ExtractedText::paged(vec!["Notice period".to_string()]);
+        assert_eq!(extracted.confidence_in(0..13), None);
+
+        extracted.ocr = true;
+        extracted.word_confidences = vec![(0, 90.0), (7, 60.0)];
+        assert_eq!(extracted.confidence_in(0..13), Some(75.0));
+        assert_eq!(extracted.confidence_in(7..13), Some(60.0));
+        assert_eq!(extracted.confidence_in(1..6), None);
+    }
+}
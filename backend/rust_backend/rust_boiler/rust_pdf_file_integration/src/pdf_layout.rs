@@ -0,0 +1,433 @@
+// Layout analysis of PDF pages
+//
+// pdf-extract hands over each character with its position, in the order they are written
+// in the page. The page text follows that order, as the text of pdf-extract does, so that
+// the columns of a page are read one after the other. To find the tables, the characters
+// are also grouped into the lines of the page, the words of a line into cells where they
+// are far apart, and runs of lines whose cells line up in columns and that are written row
+// by row are pulled out of the page text as tables.
+
+use std::ops::Range;
+
+use pdf_extract::{MediaBox, OutputDev, OutputError, Transform};
+
+// Gap between two characters, in font sizes, above which they are separate words
+const WORD_GAP: f64 = 0.1;
+// Gap between two words, in font sizes, above which they are separate cells
+const CELL_GAP: f64 = 1.2;
+// Gap between two lines, in font sizes, above which they are separate paragraphs
+const PARAGRAPH_GAP: f64 = 1.8;
+// Gap between two rows of a table, in font sizes, above which the table ends
+const ROW_GAP: f64 = 2.5;
+// Smallest table, header row included
+const MIN_TABLE_ROWS: usize = 3;
+const MIN_TABLE_COLUMNS: usize = 2;
+// Cells longer than this on average are columns of text rather than a table
+const MAX_AVERAGE_CELL_CHARS: usize = 40;
+
+struct Glyph {
+    x: f64,
+    y: f64,
+    end: f64,
+    size: f64,
+    text: String,
+}
+
+// Characters of each page, positioned from the top left corner of the page
+#[derive(Default)]
+pub struct GlyphCollector {
+    pages: Vec<Vec<Glyph>>,
+    page_height: f64,
+}
+
+impl OutputDev for GlyphCollector {
+    fn begin_page(&mut self, _page_num: u32, media_box: &MediaBox, _art_box: Option<(f64, f64, f64, f64)>) -> Result<(), OutputError> {
+        self.page_height = media_box.ury - media_box.lly;
+        self.pages.push(Vec::new());
+        Ok(())
+    }
+
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn output_character(&mut self, trm: &Transform, width: f64, _spacing: f64, font_size: f64, char: &str) -> Result<(), OutputError> {
+        // Spaces are rebuilt from the gaps between characters
+        if char.trim().is_empty() {
+            return Ok(());
+        }
+
+        // Side of the square with the area of the transformed font box, as pdf-extract does
+        let size_x = font_size * (trm.m11 + trm.m21);
+        let size_y = font_size * (trm.m12 + trm.m22);
+        let size = (size_x * size_y).abs().sqrt();
+
+        if let Some(page) = self.pages.last_mut() {
+            page.push(Glyph {
+                x: trm.m31,
+                y: self.page_height - trm.m32,
+                end: trm.m31 + width * size,
+                size,
+                text: char.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+// Words of a line close enough to be read together
+struct Segment {
+    start: f64,
+    end: f64,
+    text: String,
+}
+
+struct Line {
+    y: f64,
+    size: f64,
+    segments: Vec<Segment>,
+    // Positions of the characters of the line in the order of the page
+    glyphs: Vec<usize>,
+}
+
+// Text of a page without its tables, and its tables as rows of cells, header row first
+pub struct PageLayout {
+    pub text: String,
+    pub tables: Vec<Vec<Vec<String>>>,
+}
+
+impl GlyphCollector {
+    pub fn into_pages(self) -> Vec<PageLayout> {
+        self.pages.into_iter().map(layout_page).collect()
+    }
+}
+
+// Lines of the page from top to bottom, whatever the order their characters are written in
+fn build_lines(glyphs: &[Glyph]) -> Vec<Line> {
+    let mut order: Vec<usize> = (0..glyphs.len()).collect();
+    order.sort_by(|&a, &b| glyphs[a].y.total_cmp(&glyphs[b].y).then(glyphs[a].x.total_cmp(&glyphs[b].x)));
+
+    let mut rows: Vec<Vec<usize>> = Vec::new();
+    for index in order {
+        let glyph = &glyphs[index];
+        match rows.last_mut() {
+            Some(row) if (glyph.y - glyphs[row[0]].y).abs() < glyphs[row[0]].size.max(glyph.size) * 0.5 => row.push(index),
+            _ => rows.push(vec![index]),
+        }
+    }
+
+    rows.into_iter()
+        .map(|mut row| {
+            row.sort_by(|&a, &b| glyphs[a].x.total_cmp(&glyphs[b].x));
+            let size = row.iter().map(|&index| glyphs[index].size).fold(0.0, f64::max);
+
+            let mut segments: Vec<Segment> = Vec::new();
+            for glyph in row.iter().map(|&index| &glyphs[index]) {
+                match segments.last_mut() {
+                    Some(segment) if glyph.x - segment.end <= CELL_GAP * size => {
+                        if glyph.x - segment.end > WORD_GAP * size {
+                            segment.text.push(' ');
+                        }
+                        segment.text.push_str(&glyph.text);
+                        segment.end = segment.end.max(glyph.end);
+                    }
+                    _ => segments.push(Segment {
+                        start: glyph.x,
+                        end: glyph.end,
+                        text: glyph.text.clone(),
+                    }),
+                }
+            }
+
+            Line { y: glyphs[row[0]].y, size, segments, glyphs: row }
+        })
+        .collect()
+}
+
+// Rows of cells of `lines` when their segments line up in columns
+fn table_of(lines: &[Line]) -> Option<Vec<Vec<String>>> {
+    // A table is written row by row. The lines of columns of text side by side take their
+    // characters from far apart in the page, a column being written after the other
+    let mut spans: Vec<(usize, usize)> = lines
+        .iter()
+        .filter_map(|line| Some((*line.glyphs.iter().min()?, *line.glyphs.iter().max()?)))
+        .collect();
+    spans.sort();
+    if spans.windows(2).any(|pair| pair[1].0 < pair[0].1) {
+        return None;
+    }
+
+    // Columns are the spans of the page covered by segments, separated by vertical gutters
+    let mut spans: Vec<(f64, f64)> = lines
+        .iter()
+        .flat_map(|line| line.segments.iter().map(|segment| (segment.start, segment.end)))
+        .collect();
+    spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut columns: Vec<(f64, f64)> = Vec::new();
+    for (start, end) in spans {
+        match columns.last_mut() {
+            Some(column) if start <= column.1 => column.1 = column.1.max(end),
+            _ => columns.push((start, end)),
+        }
+    }
+    if columns.len() < MIN_TABLE_COLUMNS {
+        return None;
+    }
+
+    let rows: Vec<Vec<String>> = lines
+        .iter()
+        .map(|line| {
+            let mut cells = vec![String::new(); columns.len()];
+            for segment in &line.segments {
+                let column = columns
+                    .iter()
+                    .position(|&(start, end)| segment.start >= start && segment.start <= end)
+                    .unwrap_or(0);
+                if !cells[column].is_empty() {
+                    cells[column].push(' ');
+                }
+                cells[column].push_str(&segment.text);
+            }
+            cells
+        })
+        .collect();
+
+    // A table fills most of its cells with short values
+    let filled: Vec<&String> = rows.iter().flatten().filter(|cell| !cell.is_empty()).collect();
+    if filled.len() * 2 < rows.len() * columns.len() {
+        return None;
+    }
+    let average_chars = filled.iter().map(|cell| cell.chars().count()).sum::<usize>() / filled.len().max(1);
+    if average_chars > MAX_AVERAGE_CELL_CHARS {
+        return None;
+    }
+
+    Some(rows)
+}
+
+// Runs of consecutive lines with several cells each that form a table
+fn find_tables(lines: &[Line]) -> Vec<(Range<usize>, Vec<Vec<String>>)> {
+    let mut tables = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        if lines[i].segments.len() < MIN_TABLE_COLUMNS {
+            i += 1;
+            continue;
+        }
+
+        let mut j = i + 1;
+        while j < lines.len()
+            && lines[j].segments.len() >= MIN_TABLE_COLUMNS
+            && lines[j].y - lines[j - 1].y < ROW_GAP * lines[j].size.max(lines[j - 1].size)
+        {
+            j += 1;
+        }
+
+        if j - i >= MIN_TABLE_ROWS {
+            if let Some(rows) = table_of(&lines[i..j]) {
+                tables.push((i..j, rows));
+            }
+        }
+        i = j;
+    }
+
+    tables
+}
+
+fn layout_page(glyphs: Vec<Glyph>) -> PageLayout {
+    let lines = build_lines(&glyphs);
+    let tables = find_tables(&lines);
+
+    let mut in_table = vec![false; glyphs.len()];
+    for (range, _) in &tables {
+        for &index in lines[range.clone()].iter().flat_map(|line| &line.glyphs) {
+            in_table[index] = true;
+        }
+    }
+
+    // The text follows the order of the characters in the page
+    let mut text = String::new();
+    let mut previous: Option<&Glyph> = None;
+    for (glyph, in_table) in glyphs.iter().zip(in_table) {
+        if in_table {
+            // The text around a table reads as separate paragraphs
+            previous = None;
+            if !text.is_empty() && !text.ends_with("\n\n") {
+                text.push_str("\n\n");
+            }
+            continue;
+        }
+
+        if let Some(previous) = previous {
+            let size = glyph.size.max(previous.size);
+            let drop = glyph.y - previous.y;
+            if drop.abs() < size * 0.5 && glyph.x >= previous.x {
+                if glyph.x - previous.end > WORD_GAP * size {
+                    text.push(' ');
+                }
+            } else if drop > PARAGRAPH_GAP * size || drop < 0.0 {
+                // Going back up the page starts another column or block
+                text.push_str("\n\n");
+            } else {
+                text.push('\n');
+            }
+        }
+        text.push_str(&glyph.text);
+        previous = Some(glyph);
+    }
+
+    PageLayout {
+        text,
+        tables: tables.into_iter().map(|(_, rows)| rows).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: f64 = 10.0;
+    const CHAR_WIDTH: f64 = 6.0;
+
+    // Write `text` from `x` on the line at `y`, a character every CHAR_WIDTH, spaces included
+    fn write(glyphs: &mut Vec<Glyph>, x: f64, y: f64, text: &str) {
+        for (i, char) in text.chars().enumerate() {
+            if char == ' ' {
+                continue;
+            }
+            let x = x + i as f64 * CHAR_WIDTH;
+            glyphs.push(Glyph { x, y, end: x + CHAR_WIDTH, size: SIZE, text: char.to_string() });
+        }
+    }
+
+    // Write the cells of each row at the x of their column, a row every 12 points from `y`
+    fn write_table(glyphs: &mut Vec<Glyph>, y: f64, columns: &[f64], rows: &[&[&str]]) {
+        for (i, row) in rows.iter().enumerate() {
+            for (x, cell) in columns.iter().zip(row.iter()) {
+                write(glyphs, *x, y + i as f64 * 12.0, cell);
+            }
+        }
+    }
+
+    fn segments(line: &Line) -> Vec<&str> {
+        line.segments.iter().map(|segment| segment.text.as_str()).collect()
+    }
+
+    #[test]
+    fn builds_the_lines_from_top_to_bottom_whatever_the_writing_order() {
+        let mut glyphs = Vec::new();
+        write(&mut glyphs, 50.0, 112.0, "second line");
+        write(&mut glyphs, 300.0, 100.0, "far cell");
+        write(&mut glyphs, 50.0, 100.0, "first line");
+        // Slightly off the baseline, as a superscript
+        write(&mut glyphs, 110.0, 102.0, "2");
+
+        let lines = build_lines(&glyphs);
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].y, 100.0);
+        assert_eq!(segments(&lines[0]), vec!["first line2", "far cell"]);
+        assert_eq!(segments(&lines[1]), vec!["second line"]);
+        assert_eq!(lines[1].glyphs, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn finds_a_table_written_row_by_row() {
+        let mut glyphs = Vec::new();
+        write_table(
+            &mut glyphs,
+            100.0,
+            &[50.0, 200.0, 300.0],
+            &[&["Role", "Months", "Notice"], &["Manager", "3", "Written"], &["Clerk", "", "Oral"]],
+        );
+
+        let lines = build_lines(&glyphs);
+        let tables = find_tables(&lines);
+
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].0, 0..3);
+        assert_eq!(
+            tables[0].1,
+            vec![
+                vec!["Role".to_string(), "Months".to_string(), "Notice".to_string()],
+                vec!["Manager".to_string(), "3".to_string(), "Written".to_string()],
+                vec!["Clerk".to_string(), String::new(), "Oral".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn two_columns_of_text_are_not_a_table() {
+        // The left column is written before the right one, on the same lines
+        let mut glyphs = Vec::new();
+        for (i, text) in ["The notice is three", "months for managers", "and one month."].iter().enumerate() {
+            write(&mut glyphs, 50.0, 100.0 + i as f64 * 12.0, text);
+        }
+        for (i, text) in ["Overtime is paid", "at 125% of the", "hourly rate."].iter().enumerate() {
+            write(&mut glyphs, 300.0, 100.0 + i as f64 * 12.0, text);
+        }
+
+        let lines = build_lines(&glyphs);
+        assert!(lines.iter().all(|line| line.segments.len() == 2));
+        assert!(table_of(&lines).is_none());
+        assert!(find_tables(&lines).is_empty());
+
+        let page = layout_page(glyphs);
+        assert_eq!(
+            page.text,
+            "The notice is three\nmonths for managers\nand one month.\n\nOvertime is paid\nat 125% of the\nhourly rate."
+        );
+    }
+
+    #[test]
+    fn long_or_sparse_cells_are_not_a_table() {
+        let long = "a cell far too long to be the value of a table";
+        let mut glyphs = Vec::new();
+        write_table(&mut glyphs, 100.0, &[50.0, 400.0], &[&[long, long], &[long, long], &[long, long]]);
+        assert!(table_of(&build_lines(&glyphs)).is_none());
+
+        let mut glyphs = Vec::new();
+        write_table(
+            &mut glyphs,
+            100.0,
+            &[50.0, 150.0, 250.0, 350.0, 450.0],
+            &[&["a", "", "", "", "b"], &["", "c", "", "d"], &["", "", "e", "", "f"]],
+        );
+        assert!(table_of(&build_lines(&glyphs)).is_none());
+    }
+
+    #[test]
+    fn takes_the_tables_out_of_the_text_of_a_mixed_page() {
+        let mut glyphs = Vec::new();
+        write(&mut glyphs, 50.0, 60.0, "Notice periods by role:");
+        write_table(
+            &mut glyphs,
+            100.0,
+            &[50.0, 200.0],
+            &[&["Role", "Months"], &["Manager", "3"], &["Clerk", "1"], &["Intern", "0"]],
+        );
+        write(&mut glyphs, 50.0, 180.0, "Signed in Paris.");
+        write(&mut glyphs, 50.0, 192.0, "On 7 October.");
+
+        let page = layout_page(glyphs);
+
+        assert_eq!(page.text, "Notice periods by role:\n\nSigned in Paris.\nOn 7 October.");
+        assert_eq!(page.tables.len(), 1);
+        assert_eq!(page.tables[0].len(), 4);
+        assert_eq!(page.tables[0][3], vec!["Intern".to_string(), "0".to_string()]);
+    }
+}
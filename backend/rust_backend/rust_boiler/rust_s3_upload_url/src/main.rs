@@ -1,17 +1,17 @@
 use lambda_http::{run, service_fn, Body, Error, Request, Response};
 use serde::{Serialize, Deserialize};
-use serde_json::{json, Value};
+use serde_json::json;
 use std::env;
 use std::time::Duration;
-use jsonwebtokens_cognito::KeySet;
 use uuid::Uuid;
 
 use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_secretsmanager::Client as SecretManagerClient;
-use aws_sdk_secretsmanager::config::Region;
+use aws_sdk_s3::config::Region;
 use aws_sdk_s3::presigning::PresigningConfig;
 use aws_sdk_s3::Client as S3Client;
-use aws_sdk_s3::primitives::ByteStream;
+
+use rag_common::auth::authenticate;
+use rag_common::chunking::ChunkStrategy;
 
 // Request structure for generating a presigned URL for uploading
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,6 +20,8 @@ struct UploadUrlRequest {
     content_type: String,
     #[serde(default = "default_expiration")]
     expiration: u64,
+    // Optional chunking strategy of the file, e.g. "recursive" or "semantic:0.8"
+    chunk_strategy: Option<String>,
 }
 
 fn default_expiration() -> u64 {
@@ -35,89 +37,8 @@ struct UploadUrlResponse {
     expiration: u64,
     bucket: String,
     key: String,
-}
-
-async fn show_secret(client: &SecretManagerClient, name: &str) -> Result<String, Error> {
-    let resp = client.get_secret_value().secret_id(name).send().await?;
-    match resp.secret_string() {
-        Some(secret) => Ok(secret.into()),
-        None => {
-            eprintln!("Error retrieving secret: {}", name);
-            Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("Error retrieving secret: {}", name))))
-        }
-    }
-}
-
-// Extract email from Authorization token
-async fn extract_email_from_token(event: &Request) -> Result<String, Error> {
-    let key_to_check = "Authorization";
-    if !event.headers().contains_key(key_to_check) {
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "No Authorization header found")));
-    }
-
-    let bearer_str = event.headers()[key_to_check].to_str()?;
-    if !bearer_str.starts_with("Bearer ") {
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid Authorization format")));
-    }
-
-    let token_str = &bearer_str[7..];
-
-
-
-    // Get Cognito configuration from AWS Secrets Manager
-    let cognito_secret_name = env::var("COGNITO_SECRET").expect("COGNITO_SECRET environment variable not set");
-    
-    // Get region from environment variable
-    let region_name = env::var("REGION").expect("REGION environment variable not set");
-    
-    // Initialize AWS SDK configuration
-    let region = Region::new(region_name.clone());
-    let config = aws_config::from_env().region(region).load().await;
-    let client_secret = SecretManagerClient::new(&config);
-    println!("AWS SDK initialized!");
-    
-    let secret_content = show_secret(&client_secret, &cognito_secret_name).await?;
-    let cognito_credentials: Value = serde_json::from_str(&secret_content)?;
-    
-    // Extract user_pool_id and client_id from the secret
-    let user_pool_id = cognito_credentials["USER_POOL_ID"].as_str()
-        .ok_or("USER_POOL_ID not found in secret")?;
-    let client_id = cognito_credentials["APP_CLIENT_ID"].as_str()
-        .ok_or("APP_CLIENT_ID not found in secret")?;
-    let cognito_region_name = cognito_credentials["REGION"].as_str()
-        .ok_or("REGION not found in secret")?;
-    
-    println!("Retrieved Cognito configuration from Secrets Manager!");
-
-    // Use the region name string instead of Region struct for KeySet creation
-    let keyset = KeySet::new(cognito_region_name, user_pool_id);
-    
-    match keyset {
-        Ok(key_set) => {
-            let verifier = key_set.new_id_token_verifier(&[&client_id]).build()?;
-            let verification_result = key_set.verify(token_str, &verifier).await;
-            
-            match verification_result {
-                Ok(claims) => {
-                    // Extract email from claims
-                    if let Some(email) = claims.get("email").and_then(|v| v.as_str()) {
-                        return Ok(email.to_string());
-                    } else {
-                        println!("No email found in token claims.");
-                        return Ok("unknown@user.com".to_string());
-                    }
-                },
-                Err(e) => {
-                    println!("Token verification error: {}", e);
-                    return Err(Box::new(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Invalid token")));
-                }
-            }
-        },
-        Err(e) => {
-            println!("KeySet creation error: {}", e);
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("KeySet creation error: {}", e))));
-        }
-    }
+    // Must be sent back as the x-amz-meta-chunk-strategy header of the upload
+    chunk_strategy: Option<String>,
 }
 
 async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
@@ -137,13 +58,14 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     // Load AWS configuration
     let shared_config = aws_config::from_env().region(region_provider).load().await;
 
-    // Extract user email from token for auditing
-    let user_email = match extract_email_from_token(&event).await {
-        Ok(email) => email,
+    // Verify the Cognito token of the caller, the email is kept for auditing
+    let auth_header = event.headers().get("Authorization").and_then(|h| h.to_str().ok());
+    let user_email = match authenticate(auth_header).await {
+        Ok(user) => user.email,
         Err(e) => {
-            println!("Failed to extract email from token: {:?}", e);
+            println!("Failed to authenticate the request: {:?}", e);
             return Ok(Response::builder()
-                .status(401)
+                .status(e.status_code())
                 .header("Content-Type", "application/json")
                 .body(Body::from(json!({
                     "status": "error",
@@ -171,6 +93,20 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
         }
     };
 
+    // Validate the chunking strategy before signing it into the upload
+    if let Some(strategy) = &upload_request.chunk_strategy {
+        if ChunkStrategy::parse(strategy).is_none() {
+            return Ok(Response::builder()
+                .status(400)
+                .header("Content-Type", "application/json")
+                .body(Body::from(json!({
+                    "status": "error",
+                    "message": "Invalid chunk strategy",
+                    "error": format!("Unknown chunk strategy '{}', expected window, recursive, markdown[:max_level] or semantic[:threshold]", strategy)
+                }).to_string()))?);
+        }
+    }
+
     // Get S3 bucket name from environment variable
     let bucket_name = match env::var("S3BUCKET_IMPORT_FOLDER") {
         Ok(name) => name,
@@ -188,9 +124,9 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     };
 
     // Generate a unique key for the file using UUID
-    let file_extension = upload_request.file_name.split('.').last().unwrap_or("");
+    let file_extension = upload_request.file_name.split('.').next_back().unwrap_or("");
     let file_key = format!("uploads/{}-{}.{}", 
-        Uuid::new_v4().to_string(), 
+        Uuid::new_v4(), 
         upload_request.file_name.replace(&format!(".{}", file_extension), ""),
         file_extension
     );
@@ -220,6 +156,9 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
         .bucket(&bucket_name)
         .key(&file_key)
         .content_type(&upload_request.content_type)
+        .set_metadata(upload_request.chunk_strategy.clone().map(|strategy| {
+            [("chunk-strategy".to_string(), strategy)].into_iter().collect()
+        }))
         .presigned(presign_config)
         .await;
 
@@ -238,6 +177,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                 expiration: upload_request.expiration,
                 bucket: bucket_name,
                 key: file_key,
+                chunk_strategy: upload_request.chunk_strategy,
             };
 
             Ok(Response::builder()
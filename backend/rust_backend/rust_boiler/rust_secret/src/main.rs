@@ -1,53 +1,9 @@
 
-use tracing_subscriber::filter::{EnvFilter, LevelFilter};
 use lambda_http::{run, service_fn, Body, Error, Request, Response};
-use aws_sdk_secretsmanager::{Client as SecretManagerClient};
 use std::env;
 use serde_json::{json, Value};
-use aws_sdk_secretsmanager::config::Region;
-use dotenv::dotenv;
 
-/// Retrieves a secret value from AWS Secrets Manager
-///
-/// # Arguments
-///
-/// * `client` - The AWS Secrets Manager client
-/// * `name` - The name of the secret to retrieve
-///
-/// # Returns
-///
-/// The secret value as a string, or an error if the secret cannot be retrieved
-async fn show_secret(client: &SecretManagerClient, name: &str) -> Result<String, Error> {
-    println!("Retrieving secret: {}", name);
-    
-    let resp = client.get_secret_value().secret_id(name).send().await?;
-
-    match resp.secret_string() {
-        Some(secret) => {
-            println!("Successfully retrieved secret of length: {}", secret.len());
-            
-            // Check if the retrieved secret is valid JSON (for logging purposes only)
-            match serde_json::from_str::<Value>(&secret) {
-                Ok(_) => println!("Secret appears to be valid JSON"),
-                Err(e) => println!("Secret is not valid JSON: {}", e)
-            }
-            
-            Ok(secret.into())
-        },
-        None => {
-            // Check if there's binary data instead
-            if let Some(_binary_data) = resp.secret_binary() {
-                let error_msg = format!("Secret '{}' contains binary data, not string data", name);
-                println!("Error: {}", error_msg);
-                return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, error_msg)));
-            }
-            
-            let error_msg = format!("No string or binary value found for secret: {}", name);
-            println!("Error: {}", error_msg);
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, error_msg)));
-        }
-    }
-}
+use rag_common::secrets::get_secret;
 
 /// Lambda function handler
 ///
@@ -59,19 +15,6 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     println!("{:?}", event);
     println!("---------------------------------------- END OF EVENT -----------------------------------------");
     
-    // Get region from environment variable
-    let region_name = env::var("REGION").expect("REGION environment variable not set");
-    println!("Using region: {}", region_name);
-    
-    // Initialize AWS SDK configuration
-    let region = Region::new(region_name.clone());
-    let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(region)
-        .load()
-        .await;
-    let client_secret = SecretManagerClient::new(&config);
-    println!("AWS SDK initialized!");
-    
     // Get the secret name from environment variable
     let default_secret_name = env::var("TEST_SECRET").expect("TEST_SECRET environment variable not set");
     println!("Secret name from env: {}", default_secret_name);
@@ -80,7 +23,7 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     println!("Retrieving secret: {}", default_secret_name);
     
     // Retrieve the secret
-    match show_secret(&client_secret, &default_secret_name).await {
+    match get_secret(&default_secret_name).await {
         Ok(secret_value) => {
             println!("Secret retrieved successfully");
             
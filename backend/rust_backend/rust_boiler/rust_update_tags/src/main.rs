@@ -1,14 +1,8 @@
 use lambda_http::{run, service_fn, Body, Error, Request, Response};
 use serde::{Serialize, Deserialize};
 use serde_json::json;
-use std::env;
 
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_secretsmanager::Client as SecretManagerClient;
-
-use tokio_postgres::{Client, Error as OtherError};
-use postgres_native_tls::MakeTlsConnector;
-use native_tls::TlsConnector;
+use rag_common::db::get_client;
 
 // Request structure for updating tags
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,14 +19,6 @@ struct UpdateTagsResponse {
     document_uuid: String,
 }
 
-async fn show_secret(client: &SecretManagerClient, name: &str) -> Result<String, Error> {
-    let resp = client.get_secret_value().secret_id(name).send().await?;
-    match resp.secret_string() {
-        Some(secret) => Ok(secret.into()),
-        None => panic!("Error to get the secret: {:?}", name),
-    }
-}
-
 async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     println!("Processing update tags request");
     
@@ -70,48 +56,11 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     
     println!("Update request for document: {}, tags: {:?}", update_request.document_uuid, update_request.tags);
     
-    // Access environment variables
-    let region_provider = RegionProviderChain::default_provider().or_else("ap-southeast-1");
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let client_secret = aws_sdk_secretsmanager::Client::new(&config);
-    println!("Access environment variables!");
-
-    // Decode secret
-    let db_secret_name = env::var("DATABASE_CONECTION_STRING").expect("DATABASE_CONECTION_STRING environment variable not set");
-    let db_secret = show_secret(&client_secret, &db_secret_name).await.unwrap();
-    let db_credentials: serde_json::Value = serde_json::from_str(&db_secret).unwrap();
-    println!("Decoded secret!");
-
-    let db_server = db_credentials["DB_HOST"].as_str().unwrap();
-    let database = db_credentials["DB_NAME"].as_str().unwrap();
-    let db_username = db_credentials["DB_USER"].as_str().unwrap();
-    let db_password = db_credentials["DB_PASSWORD"].as_str().unwrap();
-    let db_port = db_credentials["DB_PORT"].as_str().unwrap();
-    println!("End of get info from secret!");
-
-    let tls_connector = TlsConnector::builder()
-        .danger_accept_invalid_certs(true) // Disable certificate validation
-        .build();
-    let tls = MakeTlsConnector::new(tls_connector.expect("Failed to create TLS connector"));
-
     // Connect to the database
-    let connection_string = format!("host={} port={} user={} dbname={}", 
-        db_server, db_port, db_username, database);
-    println!("Attempting to connect to database...");
-    
-    let connection_result = tokio_postgres::connect(
-        &format!("host={} port={} user={} password={} dbname={}", 
-        db_server, db_port, db_username, db_password, database),
-        tls
-    ).await;
-
-    let (client, connection) = match connection_result {
-        Ok((client, connection)) => {
-            println!("Successfully connected to database");
-            (client, connection)
-        },
+    let client = match get_client().await {
+        Ok(client) => client,
         Err(e) => {
-            eprintln!("Failed to connect to database: {}", e);
+            eprintln!("{}", e);
             return Ok(Response::builder()
                 .status(500)
                 .header("content-type", "application/json")
@@ -122,13 +71,6 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                 .map_err(Box::new)?);
         }
     };
-    
-    // Spawn a new task to manage the connection
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
 
     // First, check if the document exists
     let check_query = "SELECT document_uuid FROM document_library.documents WHERE document_uuid = $1";
@@ -170,22 +112,22 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                 document_uuid: update_request.document_uuid,
             };
             
-            return Ok(Response::builder()
+            Ok(Response::builder()
                 .status(200)
                 .header("content-type", "application/json")
                 .body(serde_json::to_string(&response).unwrap().into())
-                .map_err(Box::new)?);
+                .map_err(Box::new)?)
         },
         Err(e) => {
             eprintln!("Database error: {}", e);
-            return Ok(Response::builder()
+            Ok(Response::builder()
                 .status(500)
                 .header("content-type", "application/json")
                 .body(json!({
                     "success": false,
                     "message": format!("Failed to update document tags: {}", e)
                 }).to_string().into())
-                .map_err(Box::new)?);
+                .map_err(Box::new)?)
         }
     }
 }
@@ -202,4 +144,4 @@ async fn main() -> Result<(), Error> {
         .init();
 
     run(service_fn(function_handler)).await
-}
\ No newline at end of file
+}
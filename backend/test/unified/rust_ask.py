@@ -0,0 +1,68 @@
+"""
+Test module for the rust_ask endpoint.
+
+This module tests the questions answered from the documents of the caller,
+retrieved server side, with their citations.
+"""
+import json
+
+import pytest
+
+from shared_function import get_api_credentials, post_api
+
+
+@pytest.fixture(scope="module")
+def api_credentials():
+    """Fixture for API credentials and endpoints"""
+    return get_api_credentials()
+
+
+class TestAsk:
+    """Test class for rust_ask"""
+
+    def test_ask(self, api_credentials):
+        """Test a question answered with its citations"""
+        ask_data = {
+            "question": "What is the notice period?",
+            "num_results": 5
+        }
+
+        response = post_api(api_credentials, 'rust_ask', ask_data)
+
+        assert response.status_code == 200
+
+        result = json.loads(response.text)
+        assert result["statusAPI"] == "OK"
+        assert result["question"] == ask_data["question"]
+        assert "answer" in result, "Response should contain the answer"
+        assert isinstance(result["citations"], list), "Response should contain the citations"
+        assert isinstance(result["rejected_citations"], list)
+        assert result["chunk_count"] <= ask_data["num_results"]
+        assert "total_ms" in result["timings"]
+        assert "query_ledger_uuid" in result, "Response should contain the entry of the query ledger"
+
+    def test_ask_missing_question(self, api_credentials):
+        """Test that a blank question is rejected"""
+        response = post_api(api_credentials, 'rust_ask', {"question": "   "})
+
+        assert response.status_code == 400
+        assert json.loads(response.text)["statusAPI"] == "ERROR"
+
+    @pytest.mark.parametrize("num_results", [0, 201])
+    def test_ask_invalid_num_results(self, api_credentials, num_results):
+        """Test that num_results out of its range is rejected"""
+        response = post_api(api_credentials, 'rust_ask', {"question": "What is the notice period?", "num_results": num_results})
+
+        assert response.status_code == 400
+
+    def test_ask_unknown_chat_session(self, api_credentials):
+        """Test that a chat session of no one is not found"""
+        response = post_api(api_credentials, 'rust_ask', {"question": "And after that?", "chat_session_uuid": "unknown-chat-session"})
+
+        assert response.status_code == 404
+
+    def test_ask_invalid_token(self, api_credentials):
+        """Test that a request with an invalid Cognito token is unauthorized"""
+        response = post_api(api_credentials, 'rust_ask', {"question": "What is the notice period?"}, token="invalid-token")
+
+        assert response.status_code == 401
@@ -0,0 +1,83 @@
+"""
+Test module for the chat session endpoints.
+
+This module tests rust_add_chat_session, rust_get_chat_session and
+rust_delete_chat_session, with follow-up questions asked to rust_ask.
+"""
+import json
+
+import pytest
+
+from shared_function import get_api_credentials, post_api
+
+
+@pytest.fixture(scope="module")
+def api_credentials():
+    """Fixture for API credentials and endpoints"""
+    return get_api_credentials()
+
+
+class TestChatSession:
+    """Test class for the chat sessions, from their creation to their deletion"""
+
+    def test_chat_session(self, api_credentials):
+        """Test a session followed up by rust_ask, listed, read with its messages and deleted"""
+        add_response = post_api(api_credentials, 'rust_add_chat_session', {"session_title": "Test chat session"})
+        assert add_response.status_code == 200
+        add_result = json.loads(add_response.text)
+        assert add_result["session_title"] == "Test chat session"
+        chat_session_uuid = add_result["chat_session_uuid"]
+        print("New chat session UUID:", chat_session_uuid)
+
+        try:
+            # Two turns, the second one following up on the first
+            for question in ["What is the notice period?", "Can it be shortened?"]:
+                ask_response = post_api(api_credentials, 'rust_ask', {"question": question, "chat_session_uuid": chat_session_uuid})
+                assert ask_response.status_code == 200
+                assert json.loads(ask_response.text)["chat_session_uuid"] == chat_session_uuid
+
+            list_response = post_api(api_credentials, 'rust_get_chat_session', {"limit": 100})
+            assert list_response.status_code == 200
+            listed = [session for session in json.loads(list_response.text)["chat_sessions"]
+                      if session["chat_session_uuid"] == chat_session_uuid]
+            assert len(listed) == 1
+            assert listed[0]["message_count"] == 4
+
+            get_response = post_api(api_credentials, 'rust_get_chat_session', {"chat_session_uuid": chat_session_uuid})
+            assert get_response.status_code == 200
+            session = json.loads(get_response.text)["chat_session"]
+            assert [message["message_role"] for message in session["messages"]] == ["user", "assistant", "user", "assistant"]
+            assert session["messages"][2]["message_content"] == "Can it be shortened?"
+        finally:
+            delete_response = post_api(api_credentials, 'rust_delete_chat_session', {"chat_session_uuid": chat_session_uuid})
+            assert delete_response.status_code == 200
+
+        get_response = post_api(api_credentials, 'rust_get_chat_session', {"chat_session_uuid": chat_session_uuid})
+        assert get_response.status_code == 404
+
+    def test_add_chat_session_without_title(self, api_credentials):
+        """Test that a session can be created without title"""
+        add_response = post_api(api_credentials, 'rust_add_chat_session', {})
+        assert add_response.status_code == 200
+        add_result = json.loads(add_response.text)
+        assert add_result["session_title"] is None
+
+        delete_response = post_api(api_credentials, 'rust_delete_chat_session', {"chat_session_uuid": add_result["chat_session_uuid"]})
+        assert delete_response.status_code == 200
+
+    def test_delete_unknown_chat_session(self, api_credentials):
+        """Test that a session of no one is not found"""
+        response = post_api(api_credentials, 'rust_delete_chat_session', {"chat_session_uuid": "unknown-chat-session"})
+
+        assert response.status_code == 404
+
+    @pytest.mark.parametrize("data", [
+        {"limit": 0},
+        {"limit": 101},
+        {"offset": -1},
+    ])
+    def test_invalid_listing(self, api_credentials, data):
+        """Test that an invalid limit or offset is rejected"""
+        response = post_api(api_credentials, 'rust_get_chat_session', data)
+
+        assert response.status_code == 400
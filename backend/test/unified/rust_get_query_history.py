@@ -0,0 +1,88 @@
+"""
+Test module for the rust_get_query_history endpoint.
+
+This module tests the listing of the past questions of the caller and the
+reopening of one of them with its chunks.
+"""
+import json
+
+import pytest
+
+from shared_function import get_api_credentials, post_api
+
+
+@pytest.fixture(scope="module")
+def api_credentials():
+    """Fixture for API credentials and endpoints"""
+    return get_api_credentials()
+
+
+class TestListQueryHistory:
+    """Test class for the listing of rust_get_query_history"""
+
+    def test_list_queries(self, api_credentials):
+        """Test the listing of the latest entries of the caller"""
+        response = post_api(api_credentials, 'rust_get_query_history', {"limit": 5})
+
+        assert response.status_code == 200
+
+        result = json.loads(response.text)
+        assert result["statusAPI"] == "OK"
+        assert len(result["queries"]) <= 5
+        assert result["total"] >= len(result["queries"])
+        assert result["limit"] == 5
+        assert result["offset"] == 0
+        assert all(query["answer"] is None for query in result["queries"] if query["answer_withheld"])
+
+    def test_list_queries_of_a_type(self, api_credentials):
+        """Test the listing of the entries of rust_ask only"""
+        response = post_api(api_credentials, 'rust_get_query_history', {"query_type": "ask"})
+
+        assert response.status_code == 200
+        assert all(query["query_type"] == "ask" for query in json.loads(response.text)["queries"])
+
+    @pytest.mark.parametrize("data", [
+        {"limit": 0},
+        {"limit": 101},
+        {"offset": -1},
+        {"query_type": "unknown"},
+    ])
+    def test_invalid_listing(self, api_credentials, data):
+        """Test that an invalid limit, offset or query_type is rejected"""
+        response = post_api(api_credentials, 'rust_get_query_history', data)
+
+        assert response.status_code == 400
+        assert json.loads(response.text)["statusAPI"] == "ERROR"
+
+
+class TestReopenQuery:
+    """Test class for the entries reopened by rust_get_query_history"""
+
+    def test_reopen_ask(self, api_credentials):
+        """Test that a question of rust_ask is reopened with its chunks"""
+        ask_response = post_api(api_credentials, 'rust_ask', {"question": "What is the notice period?"})
+        assert ask_response.status_code == 200
+        ask_result = json.loads(ask_response.text)
+        query_ledger_uuid = ask_result["query_ledger_uuid"]
+        assert query_ledger_uuid, "The question should be recorded in the query ledger"
+
+        response = post_api(api_credentials, 'rust_get_query_history', {"query_ledger_uuid": query_ledger_uuid})
+
+        assert response.status_code == 200
+
+        query = json.loads(response.text)["query"]
+        assert query["query_ledger_uuid"] == query_ledger_uuid
+        assert query["query_type"] == "ask"
+        assert query["question"] == "What is the notice period?"
+        assert query["answer"] == ask_result["answer"]
+        assert query["answer_withheld"] is False, "The caller still reads the documents cited by the answer"
+        assert len(query["chunks"]) == ask_result["chunk_count"]
+        assert [chunk["chunk_rank"] for chunk in query["chunks"]] == list(range(1, len(query["chunks"]) + 1))
+        assert isinstance(query["citations"], list)
+        assert isinstance(query["documents"], list)
+
+    def test_reopen_unknown_query(self, api_credentials):
+        """Test that an entry of no one is not found"""
+        response = post_api(api_credentials, 'rust_get_query_history', {"query_ledger_uuid": "unknown-query"})
+
+        assert response.status_code == 404
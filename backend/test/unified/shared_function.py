@@ -3,6 +3,8 @@ import hmac
 import hashlib
 import base64
 import os
+import json
+import requests
 from dotenv import load_dotenv
 load_dotenv()
 
@@ -73,4 +75,64 @@ def cognito_connexion(username, password):
     else:
         return {"error": True, 
             "success": False, 
-            "data": None, "message": None}
\ No newline at end of file
+            "data": None, "message": None}
+
+def get_api_credentials():
+    """
+    Credentials and endpoints of the API tests, checking the environment variables they need.
+
+    Returns:
+        dict: Dictionary containing token, api_key, and endpoint generator function.
+    """
+    # Check required environment variables
+    user_name = os.getenv('USER_NAME')
+    password = os.getenv('PASSWORD')
+    api_key = os.getenv('API_KEY')
+
+    assert user_name, "USER_NAME environment variable is required"
+    assert password, "PASSWORD environment variable is required"
+    assert api_key, "API_KEY environment variable is required"
+
+    # Determine API endpoint based on debug flag
+    local_debug_flag = os.getenv('LOCAL_DEBUG_FLAG', 'false').lower() == 'true'
+    api_debug_endpoint_root = os.getenv('API_DEBUG_ENDPOINT_ROOT')
+    api_endpoint_root = os.getenv('API_ENDPOINT_ROOT')
+    api_stage = os.getenv('API_STAGE')
+
+    if local_debug_flag:
+        assert api_debug_endpoint_root, "API_DEBUG_ENDPOINT_ROOT is required when LOCAL_DEBUG_FLAG is true"
+    else:
+        assert api_endpoint_root, "API_ENDPOINT_ROOT environment variable is required"
+        assert api_stage, "API_STAGE environment variable is required when not in debug mode"
+
+    # Authenticate with Cognito to get a token
+    conn_result = cognito_connexion(user_name, password)
+    assert conn_result['success'], f"Authentication failed: {conn_result.get('message', 'No error message provided')}"
+
+    # Build base endpoint for all API calls
+    def get_endpoint(service):
+        if local_debug_flag:
+            return f"{api_debug_endpoint_root}/{service}"
+        return f"{api_endpoint_root}/{api_stage}/{service}"
+
+    return {
+        'token': conn_result['data']['id_token'],
+        'api_key': api_key,
+        'get_endpoint': get_endpoint
+    }
+
+
+def post_api(api_credentials, service, data, token=None):
+    """POST data to a service as the test user, or with another token"""
+    response = requests.post(
+        api_credentials['get_endpoint'](service),
+        data=json.dumps(data),
+        headers={
+            "Content-Type": "application/json",
+            "Authorization": f"Bearer {token or api_credentials['token']}",
+            "x-api-key": api_credentials['api_key']
+        }
+    )
+    print(f'{service} STATUS: {response.status_code} REASON: {response.reason}')
+    print(f'{service} RESPONSE: {response.text}')
+    return response